@@ -0,0 +1,8 @@
+fn main() {
+    // Only the bare metal kernel is linked with the layout of QEMU's virt machine
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        println!("cargo:rustc-link-arg-bin=kernel=-T{dir}/boot/linker.ld");
+    }
+    println!("cargo:rerun-if-changed=boot");
+}
@@ -0,0 +1,260 @@
+//! A terminal front-end like the scheduler's: a heat-map of the used frames, the heap's free list
+//! and a summary of the page tables, redrawn as the scripted workload allocates and frees.
+
+use crate::{
+    memory::{self, alloc, consts::FRAME_SIZE, paging, FrameOwner},
+    workload::Workload,
+};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent},
+    execute,
+    terminal::{Clear, ClearType},
+};
+use std::{
+    io::{self, Stdout},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+use tui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, BorderType, Borders, Cell, Paragraph, Row, Table},
+    Terminal,
+};
+
+pub enum DisplayEvent {
+    Input(KeyEvent),
+    Resize,
+    Tick,
+}
+
+const TICK_RATE: Duration = Duration::from_millis(200);
+
+const HEADER_HEIGHT: u16 = 3;
+/// The cells of the heat-map, from free to full
+const HEAT: [char; 5] = ['·', '░', '▒', '▓', '█'];
+const FREE_LIST_BAR_WIDTH: usize = 20;
+
+/// Where each panel is drawn.
+struct Panels {
+    header: Rect,
+    frames: Rect,
+    free_list: Rect,
+    page_tables: Rect,
+}
+
+impl Panels {
+    fn new(area: Rect) -> Self {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(1)
+            .constraints([Constraint::Length(HEADER_HEIGHT), Constraint::Min(0)])
+            .split(area);
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(rows[1]);
+        let right = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(columns[1]);
+
+        Self {
+            header: rows[0],
+            frames: columns[0],
+            free_list: right[0],
+            page_tables: right[1],
+        }
+    }
+}
+
+pub struct DisplayTerminal {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    input_rx: Receiver<DisplayEvent>,
+}
+
+impl DisplayTerminal {
+    pub fn new() -> Result<Self, io::Error> {
+        crossterm::terminal::enable_raw_mode()?;
+
+        // Set up the input handling thread
+        let (input_tx, input_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut last_tick = Instant::now();
+            loop {
+                let timeout = TICK_RATE
+                    .checked_sub(last_tick.elapsed())
+                    .unwrap_or(Duration::ZERO);
+
+                if event::poll(timeout).expect("Failed to poll events.") {
+                    let event = match event::read().expect("Failed to read events.") {
+                        Event::Key(key) => Some(DisplayEvent::Input(key)),
+                        Event::Resize(_, _) => Some(DisplayEvent::Resize),
+                        _ => None,
+                    };
+                    if let Some(event) = event {
+                        input_tx.send(event).expect("Failed to send input events.");
+                    }
+                }
+
+                if last_tick.elapsed() >= TICK_RATE && input_tx.send(DisplayEvent::Tick).is_ok() {
+                    last_tick = Instant::now();
+                }
+            }
+        });
+
+        // Set up the terminal-user-interface
+        let backend = CrosstermBackend::new(io::stdout());
+        let terminal = Terminal::new(backend)?;
+
+        Ok(Self { terminal, input_rx })
+    }
+
+    pub fn draw(&mut self, workload: &mut Workload, last_step: &str, paused: bool) {
+        let step = workload.step_count();
+        let ranges = paging::mapped_ranges(workload.root());
+
+        self.terminal
+            .draw(|f| {
+                let panels = Panels::new(f.size());
+
+                let header = Paragraph::new(format!("#{step} | {last_step}"))
+                    .style(Style::default().add_modifier(Modifier::BOLD))
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title(if paused {
+                                "Workload | Paused (space to resume, s to step, q to quit)"
+                            } else {
+                                "Workload | Running (space to pause, q to quit)"
+                            })
+                            .border_type(BorderType::Rounded),
+                    );
+                f.render_widget(header, panels.header);
+
+                // A cell per run of frames, as many as fit in the panel
+                let width = panels.frames.width.saturating_sub(2) as usize;
+                let height = panels.frames.height.saturating_sub(2) as usize;
+                let usage = memory::frame_usage(width * height);
+                let stats = memory::frame_stats();
+                let cells: Vec<Span> = usage
+                    .iter()
+                    .map(|&used| {
+                        let heat = (used * (HEAT.len() - 1) as f64).ceil() as usize;
+                        let color = match heat {
+                            0 => Color::DarkGray,
+                            heat if heat < HEAT.len() - 1 => Color::Yellow,
+                            _ => Color::Red,
+                        };
+                        Span::styled(HEAT[heat].to_string(), Style::default().fg(color))
+                    })
+                    .collect();
+                let lines: Vec<Spans> = cells
+                    .chunks(width.max(1))
+                    .map(|line| Spans::from(line.to_vec()))
+                    .collect();
+                let frames = Paragraph::new(lines).block(
+                    Block::default()
+                        .title(format!(
+                            "Frames | {}/{} used, {} at peak | {} per cell",
+                            stats.used_frames,
+                            stats.total_frames,
+                            stats.peak_used_frames,
+                            stats.total_frames / usage.len().max(1)
+                        ))
+                        .borders(Borders::ALL),
+                );
+                f.render_widget(frames, panels.frames);
+
+                // A bar per free region, as long as its part of the heap
+                let heap_size = alloc::heap_frames() * FRAME_SIZE;
+                let heap = alloc::heap_stats();
+                let rows = alloc::free_regions().into_iter().map(|(addr, size)| {
+                    let bar = (size * FREE_LIST_BAR_WIDTH).div_ceil(heap_size.max(1));
+                    Row::new(vec![
+                        Cell::from(format!("{addr:#X}")),
+                        Cell::from(size.to_string()),
+                        Cell::from("█".repeat(bar)).style(Style::default().fg(Color::Green)),
+                    ])
+                });
+                let free_list = Table::new(rows)
+                    .header(
+                        Row::new(vec!["Address", "Size", "Share"])
+                            .style(Style::default().add_modifier(Modifier::BOLD)),
+                    )
+                    .widths(&[
+                        Constraint::Length(16),
+                        Constraint::Length(8),
+                        Constraint::Length(FREE_LIST_BAR_WIDTH as u16),
+                    ])
+                    .block(
+                        Block::default()
+                            .title(format!(
+                                "Heap Free List | {} regions, {}/{} bytes free, {:.0}% fragmented",
+                                heap.free_regions,
+                                heap.free_bytes,
+                                heap_size,
+                                heap.fragmentation() * 100.0
+                            ))
+                            .borders(Borders::ALL),
+                    )
+                    .column_spacing(1);
+                f.render_widget(free_list, panels.free_list);
+
+                let lines: Vec<String> = ranges.iter().map(ToString::to_string).collect();
+                let page_tables = Paragraph::new(lines.join("\n")).block(
+                    Block::default()
+                        .title(format!(
+                            "Page Tables | {} ranges, {} table frames",
+                            ranges.len(),
+                            memory::owned_frames(FrameOwner::PageTable)
+                        ))
+                        .borders(Borders::ALL),
+                );
+                f.render_widget(page_tables, panels.page_tables);
+            })
+            .expect("Failed to draw frame.");
+    }
+
+    pub fn get_input(&self) -> DisplayEvent {
+        self.input_rx
+            .recv()
+            .expect("Failed to recieve input events.")
+    }
+}
+
+impl Drop for DisplayTerminal {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
+
+/// Runs `workload` a step every tick and shows how memory changes, until the user quits.
+pub fn run(mut workload: Workload) -> Result<(), io::Error> {
+    execute!(io::stdout(), Clear(ClearType::All))?;
+    let mut terminal = DisplayTerminal::new()?;
+    let mut last_step = "Nothing yet".to_owned();
+    let mut paused = false;
+
+    loop {
+        terminal.draw(&mut workload, &last_step, paused);
+        match terminal.get_input() {
+            DisplayEvent::Input(key) => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                KeyCode::Char(' ') => paused = !paused,
+                KeyCode::Char('s') if paused => last_step = workload.step(),
+                _ => {}
+            },
+            DisplayEvent::Tick if !paused => last_step = workload.step(),
+            // Returning right away redraws the frame at the new size
+            DisplayEvent::Resize | DisplayEvent::Tick => {}
+        }
+    }
+
+    drop(terminal);
+    execute!(io::stdout(), Clear(ClearType::All))?;
+    Ok(())
+}
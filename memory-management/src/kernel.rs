@@ -0,0 +1,155 @@
+//! The kernel on bare metal (`riscv64gc-unknown-none-elf`), booted by OpenSBI on QEMU's virt
+//! machine with `cargo kernel`. The rest of the crate simulates the hardware on top of `mem.img`;
+//! here the page table is handed to the real MMU: `kmain` maps the kernel, switches `satp` to Sv39
+//! and runs `sfence.vma`, then reads the kernel through its higher half alias to check that the
+//! hardware translates it.
+//!
+//! `boot/entry.S` sets up the stack and the bss before `kmain`, and `boot/linker.ld` places the
+//! sections where OpenSBI expects them.
+
+#![no_std]
+#![no_main]
+
+use core::{
+    arch::{asm, global_asm},
+    fmt::{self, Write},
+    panic::PanicInfo,
+    ptr,
+};
+
+global_asm!(include_str!("../boot/entry.S"));
+
+/// The NS16550A UART of the virt machine
+const UART: usize = 0x1000_0000;
+/// The line status register, and its bit that says the transmitter can take a byte
+const UART_LSR: usize = UART + 5;
+const UART_LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// The SiFive test device of the virt machine, which powers it off
+const TEST_DEVICE: usize = 0x10_0000;
+const TEST_PASS: u32 = 0x5555;
+const TEST_FAIL: u32 = 0x3333;
+
+/// The start of the higher half in Sv39, where the kernel is mapped like in `map_kernel`
+const HIGHER_HALF: usize = 0xFFFF_FFC0_0000_0000;
+const GIB: usize = 1 << 30;
+
+// The flags of a leaf entry, see `PageEntryFlags`
+const VALID: u64 = 1 << 0;
+const READ: u64 = 1 << 1;
+const WRITE: u64 = 1 << 2;
+const EXECUTE: u64 = 1 << 3;
+const GLOBAL: u64 = 1 << 5;
+const ACCESSED: u64 = 1 << 6;
+const DIRTY: u64 = 1 << 7;
+
+/// The MODE of `satp` that selects Sv39 (see `Satp::bits`)
+const SATP_SV39: u64 = 8 << 60;
+
+#[repr(C, align(4096))]
+struct PageTable([u64; 512]);
+
+/// The root table, which maps everything with gigapages so no other table is needed.
+static mut ROOT: PageTable = PageTable([0; 512]);
+
+/// Read through the higher half once paging is on.
+static MAGIC: u64 = 0x5249_5343_594F_5321;
+
+extern "C" {
+    static _text_start: u8;
+    static _text_end: u8;
+    static _rodata_start: u8;
+    static _rodata_end: u8;
+    static _data_start: u8;
+    static _data_end: u8;
+    static _bss_start: u8;
+    static _bss_end: u8;
+    static _stack_start: u8;
+    static _stack_end: u8;
+}
+
+struct Uart;
+
+impl Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            unsafe {
+                while ptr::read_volatile(UART_LSR as *const u8) & UART_LSR_THR_EMPTY == 0 {}
+                ptr::write_volatile(UART as *mut u8, byte);
+            }
+        }
+        Ok(())
+    }
+}
+
+macro_rules! println {
+    ($($arg:tt)*) => {
+        let _ = writeln!(Uart, $($arg)*);
+    };
+}
+
+fn power_off(code: u32) -> ! {
+    unsafe { ptr::write_volatile(TEST_DEVICE as *mut u32, code) };
+    loop {
+        unsafe { asm!("wfi") };
+    }
+}
+
+/// Maps the gigapage of `phys` at `virt` in the root table.
+fn map_gigapage(virt: usize, phys: usize, flags: u64) {
+    let index = (virt >> 30) & 0x1FF;
+    let ppn = (phys >> 12) as u64;
+    unsafe { (*ptr::addr_of_mut!(ROOT)).0[index] = (ppn << 10) | flags | VALID };
+}
+
+#[no_mangle]
+extern "C" fn kmain(hart: usize, dtb: usize) -> ! {
+    println!("* Booted hart {hart} with the device tree at {dtb:#X}.");
+    let sections = [
+        ("text", ptr::addr_of!(_text_start), ptr::addr_of!(_text_end)),
+        (
+            "rodata",
+            ptr::addr_of!(_rodata_start),
+            ptr::addr_of!(_rodata_end),
+        ),
+        ("data", ptr::addr_of!(_data_start), ptr::addr_of!(_data_end)),
+        ("bss", ptr::addr_of!(_bss_start), ptr::addr_of!(_bss_end)),
+        (
+            "stack",
+            ptr::addr_of!(_stack_start),
+            ptr::addr_of!(_stack_end),
+        ),
+    ];
+    for (name, start, end) in sections {
+        println!("* The {name} section is at {start:p}..{end:p}.");
+    }
+
+    // The boot code runs at its physical address until it jumps into the higher half, so the
+    // first gigabyte of memory is mapped at both. The gigapage of the devices is identity mapped.
+    let memory = ptr::addr_of!(_text_start) as usize & !(GIB - 1);
+    let kernel_flags = READ | WRITE | EXECUTE | ACCESSED | DIRTY | GLOBAL;
+    map_gigapage(memory, memory, kernel_flags);
+    map_gigapage(HIGHER_HALF + memory, memory, kernel_flags);
+    map_gigapage(0, 0, READ | WRITE | ACCESSED | DIRTY | GLOBAL);
+
+    let root = ptr::addr_of!(ROOT) as usize;
+    let satp = SATP_SV39 | (root >> 12) as u64;
+    unsafe {
+        asm!("csrw satp, {satp}", "sfence.vma", satp = in(reg) satp);
+    }
+    println!("* Switched satp to {satp:#X} (Sv39, root table at {root:#X}).");
+
+    let alias = (HIGHER_HALF + ptr::addr_of!(MAGIC) as usize) as *const u64;
+    let value = unsafe { ptr::read_volatile(alias) };
+    println!("* Read {value:#X} through the higher half at {alias:p}.");
+    if value != MAGIC {
+        panic!("The higher half doesn't map the kernel");
+    }
+    power_off(TEST_PASS)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("! {info}");
+    power_off(TEST_FAIL | (1 << 16))
+}
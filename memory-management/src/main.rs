@@ -1,21 +1,918 @@
-#![feature(const_mut_refs)]
-
+mod display;
 mod memory;
+mod shell;
+mod workload;
+
+use std::alloc::Layout;
 
 fn main() {
-    let mem_size = 0x800000;
-    let mut mem = memory::virt::init_virtual_memory(mem_size);
+    let image = memory::virt::MemoryImage::from_args().unwrap_or_else(|error| panic!("{error}"));
+    let mem_size = image.size;
+    let mut mem =
+        memory::virt::init_virtual_memory(&image).expect("Failed to map the memory file.");
     let mem_start = mem.as_mut_ptr();
     println!("* Initiated virtual memory at {mem_start:?}.");
 
-    memory::init_frames_allocation(mem_start, mem_size);
+    if let Some(path) = std::env::args().skip_while(|arg| arg != "--layout").nth(1) {
+        let layout = memory::layout::load_layout(&path).unwrap_or_else(|error| panic!("{error}"));
+        memory::layout::set_layout(layout).expect("The layout was validated when it was loaded.");
+        println!("* Loaded the kernel's layout from {path}.");
+    }
+    let image = memory::layout::layout();
+
+    if std::env::args().any(|arg| arg == "--reserve") {
+        // Pretend firmware lives in the second MiB of memory
+        let firmware = memory::addr::PhysAddr::from_ptr(mem_start) + 0x100000;
+        memory::reserve_range(firmware, firmware + 0x100000).expect("Failed to reserve firmware.");
+        println!(
+            "* Reserved {firmware:#X}..{:#X} for firmware.",
+            firmware + 0x100000
+        );
+    }
+
+    let mut restored_root = None;
+    if let Some(path) = std::env::args().skip_while(|arg| arg != "--dtb").nth(1) {
+        let blob = std::fs::read(&path).expect("Failed to read the device tree.");
+        let map = memory::fdt::parse(&blob).unwrap_or_else(|error| panic!("{error}"));
+        for range in &map.memory {
+            println!(
+                "* The device tree has memory at {:#X}..{:#X}.",
+                range.start, range.end
+            );
+        }
+        for range in &map.reserved {
+            println!(
+                "* The device tree reserves {:#X}..{:#X}.",
+                range.start, range.end
+            );
+        }
+        map.init_frames_allocation(mem_start, mem_size)
+            .expect("Failed to reserve the device tree's reserved regions.");
+    } else if let Some(path) = std::env::args()
+        .skip_while(|arg| arg != "--restore-snapshot")
+        .nth(1)
+    {
+        restored_root = memory::restore_snapshot(&path, mem_start, mem_size)
+            .unwrap_or_else(|error| panic!("{error}"));
+        println!("* Restored the memory and the frames from {path}.");
+    } else if std::env::args().any(|arg| arg == "--regions") {
+        // Leave a 1MiB hole after the first 3MiB, like an MMIO window between two RAM banks
+        let start = mem_start as usize;
+        let hole = start + 0x300000..start + 0x400000;
+        memory::init_frames_allocation_regions(&[start..hole.start, hole.end..start + mem_size]);
+        println!("* Left a hole at {:#X}..{:#X}.", hole.start, hole.end);
+    } else {
+        memory::init_frames_allocation(mem_start, mem_size);
+    }
     println!("* Initiated frames allocation.");
 
-    let root_table = memory::paging::create_root_table();
-    println!("* Created root table at: {root_table:?}");
-    memory::map_kernel(unsafe { root_table.as_mut() }.unwrap());
+    if let Some(mode) = std::env::args().find_map(|arg| match arg.as_str() {
+        "--sv48" => Some(memory::paging::PagingMode::Sv48),
+        "--sv57" => Some(memory::paging::PagingMode::Sv57),
+        _ => None,
+    }) {
+        memory::paging::set_mode(mode);
+        println!("* Switched to {mode:?} paging.");
+    }
+
+    let root_table = match restored_root {
+        Some(root) => {
+            println!("* Restored root table at: {root:?}");
+            root
+        }
+        None => {
+            let root =
+                memory::paging::create_root_table().expect("Failed to allocate the root table.");
+            println!("* Created root table at: {root:?}");
+            root
+        }
+    };
+    if std::env::args().any(|arg| arg == "--warn-wx") {
+        memory::paging::set_wx_policy(memory::paging::WxPolicy::Warn);
+    } else if std::env::args().any(|arg| arg == "--allow-wx") {
+        memory::paging::set_wx_policy(memory::paging::WxPolicy::Allow);
+    }
+    if std::env::args().any(|arg| arg == "--verify") {
+        memory::paging::set_verify_mutations(true);
+        println!("* Verifying the page tables after every change.");
+    }
+    memory::map_kernel(unsafe { root_table.as_mut() }.unwrap()).expect("Failed to map the kernel.");
     println!("* Mapped kernel.");
+    if std::env::args().any(|arg| arg == "--dump") {
+        memory::paging::dump(unsafe { root_table.as_ref() }.unwrap());
+    }
+    if std::env::args().any(|arg| arg == "--dot") {
+        memory::paging::write_dot(unsafe { root_table.as_ref() }.unwrap(), "page_tables.dot")
+            .expect("Failed to write the page tables' graph.");
+        println!("* Wrote the page tables' graph to page_tables.dot.");
+    }
+
+    if std::env::args().any(|arg| arg == "--translate") {
+        let root = unsafe { root_table.as_mut() }.unwrap();
+        // The boot code runs at its physical address until it jumps into the higher half
+        memory::identity_map_range(
+            root,
+            image.text.start,
+            image.text.end,
+            memory::paging::PageEntryFlags::READ_EXECUTE,
+            memory::paging::PageEntryLevel::KiB4,
+        )
+        .expect("Failed to identity map the text.");
+        println!("* Identity mapped the text for the boot trampoline.");
+
+        for virt in [
+            image.text.start + 0x123,
+            memory::kernel_address(image.text.start) + 0x123,
+            memory::kernel_address(image.heap.start) + 0x4567,
+        ] {
+            let translation =
+                memory::paging::translate(root, virt).expect("The kernel is not mapped.");
+            println!(
+                "* {virt:#X} -> {:#X} ({:?}, {:?})",
+                translation.phys, translation.level, translation.flags
+            );
+        }
+    }
+
+    if std::env::args().any(|arg| arg == "--unmap") {
+        let root = unsafe { root_table.as_mut() }.unwrap();
+        let tables = memory::owned_frames(memory::FrameOwner::PageTable);
+        let stack = memory::kernel_address(image.stack.start);
+        let stack_size = image.stack.size();
+        let unmapped = memory::paging::unmap_range(root, stack, stack_size)
+            .expect("Failed to unmap the stack.");
+        println!(
+            "* Unmapped {} pages of the stack, freed {} page tables.",
+            unmapped.len(),
+            tables - memory::owned_frames(memory::FrameOwner::PageTable)
+        );
+        let heap = memory::kernel_address(image.heap.start);
+        println!(
+            "* {stack:#X}: {:?}, heap: {:?}",
+            memory::paging::virtual_to_physical(root, memory::addr::VirtAddr::new(stack)),
+            memory::paging::virtual_to_physical(root, memory::addr::VirtAddr::new(heap))
+        );
+    }
+
+    if std::env::args().any(|arg| arg == "--protect") {
+        // Boot is over, make the data read-only
+        let root = unsafe { root_table.as_mut() }.unwrap();
+        let data = memory::kernel_address(image.data.start);
+        memory::paging::protect_range(
+            root,
+            data,
+            image.data.size(),
+            &(memory::paging::PageEntryFlags::READ
+                | memory::paging::PageEntryFlags::ACCESSED
+                | memory::paging::PageEntryFlags::GLOBAL),
+        )
+        .expect("Failed to protect the data.");
+        println!(
+            "* Made the data read-only: {:?}",
+            memory::paging::translate(root, data).unwrap().flags
+        );
+        println!(
+            "* Protecting the unmapped space fails: {:?}",
+            memory::paging::protect_range(
+                root,
+                memory::kernel_address(image.heap.end),
+                0x1000,
+                &memory::paging::PageEntryFlags::READ
+            )
+        );
+        let text = memory::kernel_address(image.text.start);
+        println!(
+            "* Making the text writable: {:?}",
+            memory::paging::protect_range(
+                root,
+                text,
+                image.text.size(),
+                &(memory::paging::PageEntryFlags::READ_WRITE_EXECUTE
+                    | memory::paging::PageEntryFlags::GLOBAL)
+            )
+        );
+    }
+
+    if std::env::args().any(|arg| arg == "--cow") {
+        use memory::fault::{self, Access};
+        use memory::paging::{PageEntryFlags, PageEntryLevel};
+
+        // Share a frame copy-on-write, and write to it through the mapping
+        let root = unsafe { root_table.as_mut() }.unwrap();
+        let frame = memory::alloc_frames_aligned(1, memory::consts::FRAME_SIZE)
+            .expect("Failed to allocate a frame.");
+        unsafe { frame.as_mut_ptr::<u64>().write(0xC0FFEE) };
+        memory::share_frames(frame); // The kernel keeps using it too
+        let virt = 0x4000_0000;
+        memory::paging::map(
+            root,
+            frame,
+            memory::addr::VirtAddr::new(virt),
+            &(PageEntryFlags::READ | PageEntryFlags::COW),
+            PageEntryLevel::KiB4,
+        )
+        .expect("Failed to map the shared frame.");
+
+        println!(
+            "* Reading a CoW page: {:?}, executing it: {:?}, writing to it: {:?}",
+            fault::check_access(root, virt, Access::Read),
+            fault::check_access(root, virt, Access::Execute),
+            fault::check_access(root, virt, Access::Write)
+        );
+        let copy = fault::access(root, virt, Access::Write).expect("Failed to copy the page.");
+        unsafe { (copy as *mut u64).write(0xBEEF) };
+        println!(
+            "* Copied {:#X} to {copy:#X}: shared={:#X}, copy={:#X}",
+            frame,
+            unsafe { frame.as_mut_ptr::<u64>().read() },
+            unsafe { (copy as *const u64).read() }
+        );
+    }
+
+    if std::env::args().any(|arg| arg == "--spaces") {
+        use memory::paging::{PageEntryFlags, PageEntryLevel};
+        use memory::space::{AddressSpace, Backing};
+
+        let used = memory::frame_stats().used_frames;
+        let mut parent = AddressSpace::new().expect("Failed to create an address space.");
+        parent
+            .map(
+                0x1000_0000,
+                0x4000,
+                PageEntryFlags::READ_WRITE,
+                PageEntryLevel::KiB4,
+                Backing::Heap,
+            )
+            .expect("Failed to map the parent's memory.");
+        parent
+            .reserve(
+                0x2000_0000,
+                0x8000,
+                PageEntryFlags::READ_WRITE,
+                PageEntryLevel::KiB4,
+                Backing::Stack,
+            )
+            .expect("Failed to reserve the parent's stack.");
+        for (start, backing) in [
+            (
+                0x3000_0000,
+                Backing::File {
+                    name: "init.bin",
+                    offset: 0x1000,
+                },
+            ),
+            (0x4000_0000, Backing::Anonymous),
+        ] {
+            parent
+                .reserve(
+                    start,
+                    0x2000,
+                    PageEntryFlags::READ,
+                    PageEntryLevel::KiB4,
+                    backing,
+                )
+                .expect("Failed to reserve an area.");
+        }
+        parent
+            .access(0x2000_7FF8, memory::fault::Access::Write)
+            .expect("Failed to fault the stack's top page in.");
+        parent.set_stack_limit(0x10000);
+        parent
+            .access(0x1FFF_FFF8, memory::fault::Access::Write)
+            .expect("Failed to grow the stack.");
+        let mut child = parent.fork().expect("Failed to fork the address space.");
+        child
+            .unmap(0x1000_1000, 0x1000)
+            .expect("Failed to unmap the child's page.");
+
+        for space in [&mut parent, &mut child] {
+            let translation = space.translate(0x1000_0000).unwrap();
+            println!(
+                "* ASID {}: {} areas, 0x10000000 -> {:#X} ({:?})",
+                space.asid(),
+                space.areas().len(),
+                translation.phys,
+                translation.flags
+            );
+        }
+        let used_before_reads = memory::frame_stats().used_frames;
+        for page in (0x4000_0000..0x4000_2000).step_by(memory::consts::FRAME_SIZE) {
+            child
+                .access(page, memory::fault::Access::Read)
+                .expect("Failed to read an anonymous page.");
+        }
+        println!(
+            "* Reading 2 anonymous pages took {} frames, {} pages map the zero frame.",
+            memory::frame_stats().used_frames - used_before_reads,
+            memory::fault::zero_frame_users()
+        );
+        print!("{}", child.maps());
+        drop(parent);
+        drop(child);
+        println!(
+            "* Dropped both address spaces, {} frames are still used (the zero frame is kept).",
+            memory::frame_stats().used_frames - used
+        );
+    }
+
+    if std::env::args().any(|arg| arg == "--brk") {
+        use memory::space::AddressSpace;
+
+        let used = memory::frame_stats().used_frames;
+        let mut space = AddressSpace::new().expect("Failed to create an address space.");
+        space
+            .start_heap(0x1000_0000)
+            .expect("Failed to start the heap.");
+
+        // A bump allocator on top of sbrk, like the first malloc of a user program
+        let objects: Vec<usize> = [24, 100, 4000, 9000]
+            .into_iter()
+            .map(|size| space.sbrk(size).expect("Failed to grow the heap."))
+            .collect();
+        println!(
+            "* Allocated objects at {objects:X?}, the heap ends at {:#X} and took {} frames.",
+            space.sbrk(0).unwrap(),
+            memory::frame_stats().used_frames - used
+        );
+        print!("{}", space.maps());
+
+        space.brk(objects[2]).expect("Failed to shrink the heap.");
+        println!(
+            "* Freed the last 2 objects, the heap takes {} frames.",
+            memory::frame_stats().used_frames - used
+        );
+    }
+
+    if std::env::args().any(|arg| arg == "--mmap") {
+        use memory::bus::{MemoryBus, Privilege};
+        use memory::paging::PageEntryFlags;
+        use memory::space::{AddressSpace, Backing, Placement};
+
+        memory::disk::init_disk("disk.img");
+        memory::disk::create_file("motd.txt", b"Welcome to riscyOS!\n");
+        let mut space = AddressSpace::new().expect("Failed to create an address space.");
+        let flags = PageEntryFlags::READ | PageEntryFlags::USER;
+        let motd = space
+            .mmap(
+                0x20,
+                flags,
+                Backing::File {
+                    name: "motd.txt",
+                    offset: 0,
+                },
+                Placement::Any,
+            )
+            .expect("Failed to map the file.");
+        let buffer = space
+            .mmap(
+                0x2000,
+                PageEntryFlags::READ_WRITE | PageEntryFlags::USER,
+                Backing::Anonymous,
+                Placement::Hint(motd),
+            )
+            .expect("Failed to map the buffer.");
+        let fixed = space
+            .mmap(
+                0x1000,
+                flags,
+                Backing::Anonymous,
+                Placement::Fixed(buffer + 0x1000),
+            )
+            .expect("Failed to map over the buffer.");
+        print!("{}", space.maps());
+
+        let mut bus = MemoryBus::new(&mut space, Privilege::User);
+        let text = bus.read(motd, 20).expect("Failed to read the file.");
+        println!(
+            "* Read {:?} from {motd:#X}, writing to {fixed:#X}: {:?}",
+            String::from_utf8_lossy(&text),
+            bus.write(fixed, b"x")
+        );
+        space
+            .munmap(motd, 0x3000)
+            .expect("Failed to unmap everything.");
+        println!("* Unmapped everything: {} areas left.", space.areas().len());
+    }
+
+    if std::env::args().any(|arg| arg == "--bus") {
+        use memory::bus::{MemoryBus, Privilege};
+        use memory::paging::{PageEntryFlags, PageEntryLevel};
+        use memory::space::{AddressSpace, Backing};
+
+        let mut space = AddressSpace::new().expect("Failed to create an address space.");
+        for (start, flags) in [
+            (
+                0x1000_0000,
+                PageEntryFlags::READ_WRITE | PageEntryFlags::USER,
+            ),
+            (0x2000_0000, PageEntryFlags::READ | PageEntryFlags::USER),
+            (0x3000_0000, PageEntryFlags::READ_WRITE),
+        ] {
+            space
+                .reserve(
+                    start,
+                    0x2000,
+                    flags,
+                    PageEntryLevel::KiB4,
+                    Backing::Anonymous,
+                )
+                .expect("Failed to reserve an area.");
+        }
+
+        let mut bus = MemoryBus::new(&mut space, Privilege::User);
+        bus.write(0x1000_0FFC, b"Hello, riscyOS!")
+            .expect("Failed to write across the page boundary.");
+        let data = bus
+            .read(0x1000_0FFC, 15)
+            .expect("Failed to read across the page boundary.");
+        println!("* User read back {:?}", String::from_utf8_lossy(&data));
+        println!(
+            "* User writing to a read-only page: {:?}",
+            bus.write(0x2000_0000, b"x")
+        );
+        println!(
+            "* User reading a kernel page: {:?}",
+            bus.read(0x3000_0000, 1)
+        );
+        bus.set_privilege(Privilege::Supervisor { sum: false });
+        println!(
+            "* Supervisor reading a user page without SUM: {:?}",
+            bus.read(0x1000_0000, 1)
+        );
+        bus.set_privilege(Privilege::Supervisor { sum: true });
+        println!(
+            "* Supervisor reading it with SUM: {:?}, executing it: {:?}",
+            bus.read(0x1000_0000, 1).map(|data| data[0]),
+            bus.fetch(0x1000_0000, 4)
+        );
+        println!(
+            "* The page that was written to is {:?}",
+            space.translate(0x1000_1000).unwrap().flags
+        );
+    }
+
+    if std::env::args().any(|arg| arg == "--tlb") {
+        use memory::bus::{MemoryBus, Privilege};
+        use memory::paging::{PageEntryFlags, PageEntryLevel};
+        use memory::space::{AddressSpace, Backing};
+        use memory::tlb;
+
+        tlb::configure_tlb(16, 4);
+        let root = unsafe { root_table.as_ref() }.unwrap();
+        let heap = memory::kernel_address(image.heap.start);
+        for _ in 0..4 {
+            for page in (heap..heap + 0x8000).step_by(0x1000) {
+                tlb::translate(root, 0, page).expect("The heap is not mapped.");
+            }
+        }
+        let stats = tlb::tlb_stats();
+        println!(
+            "* Translating 8 heap pages 4 times: {} hits, {} misses ({:.0}%)",
+            stats.hits,
+            stats.misses,
+            stats.hit_rate() * 100.0
+        );
+
+        let mut spaces = [0x1000_0000, 0x1000_0000].map(|start| {
+            let mut space = AddressSpace::new().expect("Failed to create an address space.");
+            space
+                .map(
+                    start,
+                    0x8000,
+                    PageEntryFlags::READ_WRITE | PageEntryFlags::USER,
+                    PageEntryLevel::KiB4,
+                    Backing::Anonymous,
+                )
+                .expect("Failed to map the memory of an address space.");
+            space
+        });
+        // Switch between the address spaces without ASIDs, which flushes the TLB on every switch,
+        // and with them
+        let mut cpu = memory::cpu::Cpu::new();
+        for asid_bits in [0, 16] {
+            memory::space::set_asid_bits(asid_bits);
+            tlb::configure_tlb(16, 4);
+            for _ in 0..100 {
+                for space in &mut spaces {
+                    cpu.switch_to(space);
+                    let mut bus = MemoryBus::new(space, Privilege::User);
+                    for _ in 0..4 {
+                        for page in (0x1000_0000..0x1000_8000).step_by(0x1000) {
+                            bus.read(page, 8).expect("Failed to read a page.");
+                        }
+                    }
+                }
+            }
+            let stats = tlb::tlb_stats();
+            println!(
+                "* {} switches with {asid_bits} ASID bits: {:.1}% hits, {} flushes, satp={:#018X} (generation {})",
+                cpu.switches(),
+                stats.hit_rate() * 100.0,
+                stats.flushes,
+                cpu.satp().bits(),
+                memory::space::asid_generation()
+            );
+        }
+        println!(
+            "* The hart translates 0x10000123 to {:#X}",
+            cpu.translate(0x1000_0123).expect("Failed to translate.")
+        );
+    }
+
+    if std::env::args().any(|arg| arg == "--swap") {
+        use memory::consts::FRAME_SIZE;
+        use memory::fault::Access;
+        use memory::paging::{PageEntryFlags, PageEntryLevel};
+        use memory::space::{AddressSpace, Backing};
+
+        use memory::swap::PolicyKind;
+
+        let policy = std::env::args()
+            .find_map(|arg| match arg.as_str() {
+                "--fifo" => Some(PolicyKind::Fifo),
+                "--lru" => Some(PolicyKind::Lru),
+                "--random" => Some(PolicyKind::Random),
+                _ => None,
+            })
+            .unwrap_or(PolicyKind::Clock);
+        memory::swap::init_swap("swap.img", 1024, policy);
+        let used = memory::frame_stats().used_frames;
+
+        // Touch more pages than there are free frames
+        let pages = memory::frame_stats().free_frames + 64;
+        let start = 0x5000_0000;
+        let mut space = AddressSpace::new().expect("Failed to create an address space.");
+        space
+            .reserve(
+                start,
+                pages * FRAME_SIZE,
+                PageEntryFlags::READ_WRITE,
+                PageEntryLevel::KiB4,
+                Backing::Anonymous,
+            )
+            .expect("Failed to reserve the area.");
+        for (i, page) in (start..start + pages * FRAME_SIZE)
+            .step_by(FRAME_SIZE)
+            .enumerate()
+        {
+            let phys = space
+                .access(page, Access::Write)
+                .expect("Failed to write to a page.");
+            unsafe { (phys as *mut usize).write(i) };
+        }
+        let wrong = (start..start + pages * FRAME_SIZE)
+            .step_by(FRAME_SIZE)
+            .enumerate()
+            .filter(|&(i, page)| {
+                let phys = space
+                    .access(page, Access::Read)
+                    .expect("Failed to read a page.");
+                i != unsafe { (phys as *const usize).read() }
+            })
+            .count();
+
+        let stats = memory::swap::swap_stats();
+        println!(
+            "* {policy:?}: wrote {pages} pages, {wrong} read back wrong. Swapped {} pages out and {} in, {}/{} slots are used.",
+            stats.swapped_out, stats.swapped_in, stats.used_slots, stats.slots
+        );
+        let last = start + (pages - 1) * FRAME_SIZE;
+        space
+            .swap_out(last)
+            .expect("Failed to swap out the last page.");
+        println!(
+            "* Swapped out {last:#X} by hand: {:?}",
+            space.translate(last)
+        );
+        drop(space);
+        println!(
+            "* Dropped the address space, {} frames and {} swap slots are still used.",
+            memory::frame_stats().used_frames - used,
+            memory::swap::swap_stats().used_slots
+        );
+
+        let trace = memory::swap::bench::reference_trace(10000, 64, 0x5EED);
+        for result in memory::swap::bench::compare_policies(8, &trace) {
+            println!(
+                "* {:?} on a trace of {} references with 8 frames: {} faults ({:.1}%)",
+                result.kind,
+                result.references,
+                result.faults,
+                result.fault_rate() * 100.0
+            );
+        }
+    }
+
+    if std::env::args().any(|arg| arg == "--recursive") {
+        let root = unsafe { root_table.as_mut() }.unwrap();
+        memory::recursive::enable(root);
+        println!("* Enabled recursive mapping.");
+
+        // Access the heap's page table entry through the recursive window
+        let level = memory::paging::PageEntryLevel::KiB4;
+        let heap = memory::kernel_address(image.heap.start);
+        let entry_addr = memory::recursive::entry_address(heap, level);
+        let entry = memory::recursive::entry(root, heap, level).unwrap();
+        println!(
+            "* Heap entry at {:#X} (recursive) -> {:#p}: PPN={:#X}",
+            entry_addr,
+            entry as *const _,
+            entry.get_ppn()
+        );
+        let translation = memory::recursive::translate(root, heap + 0x123)
+            .expect("The heap is not mapped through the window.");
+        println!(
+            "* {:#X} -> {:#X} through the window ({:?})",
+            heap + 0x123,
+            translation.phys,
+            translation.level
+        );
+    }
+
+    let heap_frames = image.heap.size() / memory::consts::FRAME_SIZE;
+    let arg = |name: &str| std::env::args().any(|arg| arg == name);
+    let heap_kind = if arg("--fixed") {
+        memory::alloc::HeapKind::FixedSizeBlock
+    } else if arg("--best-fit") {
+        memory::alloc::HeapKind::LinkedList(memory::alloc::FitStrategy::Best)
+    } else if arg("--worst-fit") {
+        memory::alloc::HeapKind::LinkedList(memory::alloc::FitStrategy::Worst)
+    } else {
+        memory::alloc::HeapKind::LinkedList(memory::alloc::FitStrategy::First)
+    };
+    memory::alloc::init(heap_frames, heap_kind).expect("Failed to allocate the heap.");
+    println!("* Initiated the kernel allocator with a {heap_kind:?} heap.");
+
+    if std::env::args().any(|arg| arg == "--tui") {
+        let workload = workload::Workload::new(0x5EED).expect("Failed to create the workload.");
+        display::run(workload).expect("Failed to run the display.");
+        return;
+    }
+
+    if std::env::args().any(|arg| arg == "--shell") {
+        let root = unsafe { root_table.as_mut() }.unwrap();
+        shell::Shell::new(root)
+            .run(std::io::stdin().lock())
+            .expect("Failed to read the shell's input.");
+        return;
+    }
+
+    if std::env::args().any(|arg| arg == "--slab") {
+        // Small objects come from the slab caches, big ones from the linked list
+        let layouts = [(24, 8), (24, 8), (100, 8), (4096, 4096), (10000, 8)]
+            .map(|(size, align)| Layout::from_size_align(size, align).unwrap());
+        let objects = layouts.map(memory::alloc::alloc);
+        for (layout, object) in layouts.iter().zip(objects) {
+            println!("* Allocated {} bytes at {object:?}", layout.size());
+        }
+
+        for cache in memory::alloc::slab_stats() {
+            if cache.slabs > 0 {
+                println!(
+                    "* Slab cache {}: {} objects in {} slabs",
+                    cache.object_size, cache.allocated, cache.slabs
+                );
+            }
+        }
+
+        for (layout, object) in layouts.into_iter().zip(objects) {
+            unsafe { memory::alloc::dealloc(object, layout) };
+        }
+        println!("* Freed the objects.");
+    }
+
+    if std::env::args().any(|arg| arg == "--coalesce") {
+        // Free every other object, then the rest, which merges the holes back into one region
+        let print_stats = |when: &str| {
+            let stats = memory::alloc::heap_stats();
+            println!(
+                "* {when}: {} free regions, {} bytes free, largest {} ({:.0}% fragmented)",
+                stats.free_regions,
+                stats.free_bytes,
+                stats.largest_region,
+                stats.fragmentation() * 100.0
+            );
+        };
+        let layout = Layout::from_size_align(8192, 8).unwrap();
+        let objects: Vec<_> = (0..8).map(|_| memory::alloc::alloc(layout)).collect();
+        print_stats("Allocated 8 objects");
+        for object in objects.iter().step_by(2) {
+            unsafe { memory::alloc::dealloc(*object, layout) };
+        }
+        print_stats("Freed every other object");
+        for object in objects.iter().skip(1).step_by(2) {
+            unsafe { memory::alloc::dealloc(*object, layout) };
+        }
+        print_stats("Freed the rest");
+    }
+
+    if std::env::args().any(|arg| arg == "--realloc") {
+        // Grow an object like a Vec does, it stays in place while the region after it is free
+        let mut layout = Layout::from_size_align(5000, 8).unwrap();
+        let mut object = memory::alloc::alloc(layout);
+        println!("* Allocated {} bytes at {object:?}", layout.size());
+        for new_size in [8000, 16000, 32000, 2000, 100] {
+            let moved = unsafe { memory::alloc::realloc(object, layout, new_size) };
+            println!(
+                "* Resized to {new_size} bytes at {moved:?}{}",
+                if moved == object { " (in place)" } else { "" }
+            );
+            object = moved;
+            layout = Layout::from_size_align(new_size, 8).unwrap();
+        }
+        unsafe { memory::alloc::dealloc(object, layout) };
+        println!("* Freed the object.");
+    }
+
+    if std::env::args().any(|arg| arg == "--grow") {
+        // Allocate more than the heap has, so it has to grow
+        let root = unsafe { root_table.as_mut() }.unwrap();
+        memory::alloc::set_growth_policy(
+            memory::alloc::GrowthPolicy {
+                increment_frames: 16,
+                max_frames: heap_frames * 4,
+            },
+            root,
+        );
+        let layout = Layout::from_size_align(40000, 8).unwrap();
+        let objects: Vec<_> = (0..10).map(|_| memory::alloc::alloc(layout)).collect();
+        let failed = objects.iter().filter(|object| object.is_null()).count();
+        println!(
+            "* Allocated {} objects of {} bytes ({failed} failed), the heap has {} frames",
+            objects.len(),
+            layout.size(),
+            memory::alloc::heap_frames()
+        );
+        for object in objects.into_iter().filter(|object| !object.is_null()) {
+            unsafe { memory::alloc::dealloc(object, layout) };
+        }
+        println!("* Freed the objects.");
+    }
+
+    if std::env::args().any(|arg| arg == "--stack") {
+        // A 4 page kernel stack, aligned to its size so its bottom can be found by masking
+        let stack_frames = 4;
+        let stack_size = stack_frames * memory::consts::FRAME_SIZE;
+        let stack = memory::alloc_frames_aligned(stack_frames, stack_size)
+            .expect("Failed to allocate the stack.");
+        println!("* Allocated a {stack_size:#X} byte stack at {stack:?}");
+        memory::dealloc_frames(stack, stack_frames).expect("Failed to free the stack.");
+        println!("* Freed the stack.");
+    }
+
+    if std::env::args().any(|arg| arg == "--rmap") {
+        // Ask who maps the first frames of the kernel's text and heap
+        for frame in [image.text.start, image.heap.start] {
+            for mapping in memory::rmap::who_maps(frame) {
+                println!(
+                    "* Frame {frame:#X} is mapped at {:#X} ({:?}) by the entry at {:#X}",
+                    mapping.virt, mapping.level, mapping.entry
+                );
+            }
+        }
+    }
+
+    if std::env::args().any(|arg| arg == "--watermarks") {
+        use memory::addr::PhysAddr;
+        use memory::consts::FRAME_SIZE;
+        use memory::{Pressure, Watermarks};
+        use std::sync::Mutex;
+
+        // A cache that gives its frames back when they run low
+        static CACHE: Mutex<Vec<PhysAddr>> = Mutex::new(Vec::new());
+        fn shrink_cache(_pressure: Pressure, wanted: usize) -> usize {
+            let mut cache = CACHE.lock().unwrap();
+            let start = cache.len() - wanted.min(cache.len());
+            let freed: Vec<PhysAddr> = cache.drain(start..).collect();
+            for &frame in &freed {
+                memory::dealloc_frames(frame, 1).expect("Failed to free a cached frame.");
+            }
+            freed.len()
+        }
+        fn report_oom(frames: usize) {
+            println!(
+                "* Out of memory: {frames} frames couldn't be allocated, even after reclaiming."
+            );
+        }
+        memory::register_reclaim_hook("cache", shrink_cache);
+        memory::register_oom_hook("main", report_oom);
+
+        let before = memory::pressure_stats();
+        let free = memory::frame_stats().free_frames;
+        let watermarks = Watermarks {
+            low: free / 4,
+            min: free / 8,
+        };
+        memory::set_watermarks(watermarks).unwrap_or_else(|error| panic!("{error}"));
+        for _ in 0..free / 2 {
+            let frame =
+                memory::alloc_frames_aligned(1, FRAME_SIZE).expect("Failed to fill the cache.");
+            CACHE.lock().unwrap().push(frame);
+        }
+
+        // Allocate everything, the cache shrinks below the low watermark until it's empty
+        let mut frames = Vec::new();
+        let error = loop {
+            match memory::alloc_frames_aligned(1, FRAME_SIZE) {
+                Ok(frame) => frames.push(frame),
+                Err(error) => break error,
+            }
+        };
+        let stats = memory::pressure_stats();
+        println!(
+            "* Watermarks {}/{} (low/min) of {free} free frames: allocated {} frames, {} reclaimed from the cache in {} reclaims, {} OOMs ({error}).",
+            watermarks.low,
+            watermarks.min,
+            frames.len(),
+            stats.reclaimed_frames - before.reclaimed_frames,
+            stats.reclaims - before.reclaims,
+            stats.ooms - before.ooms
+        );
+
+        memory::set_watermarks(Watermarks::default()).unwrap_or_else(|error| panic!("{error}"));
+        for frame in frames {
+            memory::dealloc_frames(frame, 1).expect("Failed to free a frame.");
+        }
+    }
+
+    if std::env::args().any(|arg| arg == "--memstat") {
+        let stats = memory::frame_stats();
+        println!(
+            "* Frames: {} total, {} used ({} reserved), {} free (peak {} used)",
+            stats.total_frames,
+            stats.used_frames,
+            stats.reserved_frames,
+            stats.free_frames,
+            stats.peak_used_frames
+        );
+        println!(
+            "* {} allocations, {} frees, largest free run of {} frames",
+            stats.allocs, stats.frees, stats.largest_free_run
+        );
+        use memory::FrameOwner;
+        for owner in [
+            FrameOwner::Reserved,
+            FrameOwner::Kernel,
+            FrameOwner::PageTable,
+            FrameOwner::Slab,
+            FrameOwner::Heap,
+            FrameOwner::AddressSpace,
+        ] {
+            println!("* {owner:?}: {} frames", memory::owned_frames(owner));
+        }
+        if let Some(info) = memory::frame_info(memory::addr::PhysAddr::from_ptr(root_table)) {
+            println!(
+                "* The root table's frame: {:?}, order {}, refcount {}, head: {}",
+                info.owner,
+                info.order,
+                info.refcount,
+                info.flags.contains(memory::FrameFlags::HEAD)
+            );
+        }
+    }
+
+    if std::env::args().any(|arg| arg == "--bench") {
+        for result in memory::alloc::bench::compare(heap_frames, 10000) {
+            println!(
+                "* {:?}: {} allocations in {:?} ({:?} each, {} failed)",
+                result.kind,
+                result.allocs,
+                result.elapsed,
+                result.per_alloc(),
+                result.failed
+            );
+        }
+
+        for mem_size in [0x800000, 0x40000000] {
+            let result = memory::frames_bench::search(mem_size, 1000);
+            println!(
+                "* Finding the last free frame of {} MiB: {:?} scanning the bitmap, {:?} with the summary",
+                result.mem_size / 0x100000,
+                result.linear,
+                result.summary
+            );
+        }
+
+        let trace = memory::alloc::bench::random_trace(2000, 14, 0x5EED);
+        for result in memory::alloc::bench::compare_strategies(heap_frames, &trace) {
+            println!(
+                "* {:?} fit: {} failed, {} regions searched in {:?}, {} free regions at the end ({:.0}% fragmented)",
+                result.strategy,
+                result.failed,
+                result.stats.regions_searched,
+                result.elapsed,
+                result.stats.free_regions,
+                result.stats.fragmentation() * 100.0
+            );
+        }
+    }
 
-    memory::alloc::init(mem_start, mem_size - 4096);
-    println!("* Initiated paging.");
+    if let Some(path) = std::env::args()
+        .skip_while(|arg| arg != "--save-snapshot")
+        .nth(1)
+    {
+        memory::save_snapshot(&path, unsafe { root_table.as_ref() })
+            .unwrap_or_else(|error| panic!("{error}"));
+        println!("* Saved the memory and the frames to {path}.");
+    }
 }
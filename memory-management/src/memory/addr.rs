@@ -0,0 +1,151 @@
+//! Physical and virtual addresses as their own types, so one can't be passed where the other is
+//! expected (like the physical and the virtual address of `paging::map`, which used to be two
+//! `usize`s next to each other).
+
+use super::consts::FRAME_SIZE;
+use core::{fmt, ops};
+
+macro_rules! address {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[repr(transparent)]
+        pub struct $name(usize);
+
+        #[allow(dead_code)] // Not every helper is used with both kinds of addresses yet
+        impl $name {
+            pub const fn new(addr: usize) -> Self {
+                Self(addr)
+            }
+
+            pub const fn as_usize(self) -> usize {
+                self.0
+            }
+
+            /// Rounds the address down to a multiple of `align`.
+            pub const fn align_down(self, align: usize) -> Self {
+                Self(self.0 - self.0 % align)
+            }
+
+            /// Rounds the address up to a multiple of `align`.
+            pub const fn align_up(self, align: usize) -> Self {
+                Self(self.0.next_multiple_of(align))
+            }
+
+            pub const fn is_aligned(self, align: usize) -> bool {
+                self.0.is_multiple_of(align)
+            }
+
+            /// Returns the offset of the address into its 4KiB page.
+            pub const fn page_offset(self) -> usize {
+                self.0 % FRAME_SIZE
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({:#X})", stringify!($name), self.0)
+            }
+        }
+
+        impl fmt::UpperHex for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::UpperHex::fmt(&self.0, f)
+            }
+        }
+
+        impl ops::Add<usize> for $name {
+            type Output = Self;
+
+            fn add(self, rhs: usize) -> Self::Output {
+                Self(self.0 + rhs)
+            }
+        }
+
+        impl ops::AddAssign<usize> for $name {
+            fn add_assign(&mut self, rhs: usize) {
+                self.0 += rhs;
+            }
+        }
+
+        impl ops::Sub<usize> for $name {
+            type Output = Self;
+
+            fn sub(self, rhs: usize) -> Self::Output {
+                Self(self.0 - rhs)
+            }
+        }
+
+        /// The distance between two addresses.
+        impl ops::Sub for $name {
+            type Output = usize;
+
+            fn sub(self, rhs: Self) -> Self::Output {
+                self.0 - rhs.0
+            }
+        }
+    };
+}
+
+address!(
+    /// An address in physical memory (here, in `mem.img`).
+    PhysAddr
+);
+
+address!(
+    /// An address in an address space, which the page tables translate to a `PhysAddr`.
+    VirtAddr
+);
+
+impl PhysAddr {
+    /// Returns the address of the frame numbered `pfn`.
+    pub const fn from_frame_number(pfn: usize) -> Self {
+        Self(pfn * FRAME_SIZE)
+    }
+
+    /// Returns the number of the frame the address is in (its PPN, in a page entry).
+    pub const fn frame_number(self) -> usize {
+        self.0 / FRAME_SIZE
+    }
+
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        Self(ptr as usize)
+    }
+
+    /// Returns a pointer to the address. Physical memory is mapped at the same addresses in the
+    /// simulation, so it can be dereferenced.
+    pub const fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+}
+
+impl VirtAddr {
+    /// Returns the number of the 4KiB page the address is in.
+    pub const fn page_number(self) -> usize {
+        self.0 / FRAME_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_align_and_convert() {
+        let phys = PhysAddr::new(0x1234);
+        assert_eq!(phys.align_down(FRAME_SIZE), PhysAddr::new(0x1000));
+        assert_eq!(phys.align_up(FRAME_SIZE), PhysAddr::new(0x2000));
+        assert!(!phys.is_aligned(FRAME_SIZE));
+        assert_eq!(phys.page_offset(), 0x234);
+        assert_eq!(phys.frame_number(), 1);
+        assert_eq!(PhysAddr::from_frame_number(3) + 0x10, PhysAddr::new(0x3010));
+
+        let virt = VirtAddr::new(0x10000);
+        assert_eq!(virt.page_number(), 0x10);
+        assert_eq!((virt + 0x2000) - virt, 0x2000);
+        assert_eq!(
+            format!("{virt:?} {:#X}", virt - 1),
+            "VirtAddr(0x10000) 0xFFFF"
+        );
+    }
+}
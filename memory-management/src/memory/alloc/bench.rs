@@ -0,0 +1,177 @@
+//! Times the heaps against each other on the same workload.
+
+use super::{FitStrategy, Heap, HeapKind, HeapStats, KernelHeap};
+use crate::memory::{consts::FRAME_SIZE, frames::FRAMES_ALLOCATOR, paging::PageEntryLevel};
+use core::alloc::Layout;
+use std::time::{Duration, Instant};
+
+/// The sizes the workload allocates, all too big for the slabs.
+const SIZES: [usize; 6] = [4097, 5000, 6000, 8192, 12000, 16384];
+
+/// How long a heap took to run the workload.
+#[derive(Debug, Clone, Copy)]
+pub struct BenchResult {
+    pub kind: HeapKind,
+    pub allocs: usize,
+    /// The allocations that returned null
+    pub failed: usize,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    /// Returns the average time of an allocation and its free.
+    pub fn per_alloc(&self) -> Duration {
+        self.elapsed / self.allocs.max(1) as u32
+    }
+}
+
+/// Runs `rounds` rounds of allocating a batch of objects and freeing every other one, then the
+/// rest, on a fresh heap of `kind` with `num_frames` frames.
+pub fn run(kind: HeapKind, num_frames: usize, rounds: usize) -> BenchResult {
+    let start = FRAMES_ALLOCATOR
+        .lock()
+        .alloc(num_frames, PageEntryLevel::KiB4)
+        .expect("Failed to allocate the heap's frames.");
+    let mut heap = KernelHeap::new(kind);
+    heap.init(start as usize, num_frames * FRAME_SIZE);
+
+    let layouts = SIZES.map(|size| Layout::from_size_align(size, 8).unwrap());
+    let mut allocs = 0;
+    let mut failed = 0;
+
+    let started = Instant::now();
+    for _ in 0..rounds {
+        let objects = layouts.map(|layout| heap.alloc(layout));
+        allocs += objects.len();
+        failed += objects.iter().filter(|object| object.is_null()).count();
+
+        // Free out of order, so the linked list has to search
+        for pass in [0, 1] {
+            for (i, (layout, object)) in layouts.iter().zip(objects).enumerate() {
+                if i % 2 == pass && !object.is_null() {
+                    heap.dealloc(object, *layout);
+                }
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+
+    FRAMES_ALLOCATOR
+        .lock()
+        .dealloc(start as usize, num_frames, PageEntryLevel::KiB4)
+        .expect("Failed to free the heap's frames.");
+
+    BenchResult {
+        kind,
+        allocs,
+        failed,
+        elapsed,
+    }
+}
+
+/// Runs the workload on every heap.
+pub fn compare(num_frames: usize, rounds: usize) -> [BenchResult; 2] {
+    [
+        HeapKind::LinkedList(FitStrategy::First),
+        HeapKind::FixedSizeBlock,
+    ]
+    .map(|kind| run(kind, num_frames, rounds))
+}
+
+/// A step of an allocation trace.
+#[derive(Debug, Clone, Copy)]
+pub enum TraceOp {
+    Alloc(Layout),
+    /// Frees the object of the `n`th allocation, if it didn't fail
+    Free(usize),
+}
+
+/// Returns a random trace of `len` steps that allocates and frees objects of mixed sizes, with at
+/// most `max_alive` objects alive at once. The same `seed` gives the same trace.
+pub fn random_trace(len: usize, max_alive: usize, seed: u64) -> Vec<TraceOp> {
+    let mut state = seed | 1;
+    let mut next = move || {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as usize
+    };
+
+    let mut allocated = 0;
+    let mut alive = Vec::new();
+    (0..len)
+        .map(|_| {
+            if alive.len() >= max_alive || (!alive.is_empty() && next() % 2 == 0) {
+                TraceOp::Free(alive.swap_remove(next() % alive.len()))
+            } else {
+                alive.push(allocated);
+                allocated += 1;
+                let size = SIZES[next() % SIZES.len()];
+                TraceOp::Alloc(Layout::from_size_align(size, 8).unwrap())
+            }
+        })
+        .collect()
+}
+
+/// How a search strategy did on a trace.
+#[derive(Debug, Clone, Copy)]
+pub struct StrategyResult {
+    pub strategy: FitStrategy,
+    /// The allocations that returned null
+    pub failed: usize,
+    pub elapsed: Duration,
+    /// The heap at the end of the trace, before the objects that are still alive are freed
+    pub stats: HeapStats,
+}
+
+/// Replays `trace` on a fresh linked list heap with `num_frames` frames for every strategy.
+pub fn compare_strategies(num_frames: usize, trace: &[TraceOp]) -> [StrategyResult; 3] {
+    [FitStrategy::First, FitStrategy::Best, FitStrategy::Worst].map(|strategy| {
+        let start = FRAMES_ALLOCATOR
+            .lock()
+            .alloc(num_frames, PageEntryLevel::KiB4)
+            .expect("Failed to allocate the heap's frames.");
+        let mut heap = KernelHeap::new(HeapKind::LinkedList(strategy));
+        heap.init(start as usize, num_frames * FRAME_SIZE);
+
+        let mut objects = Vec::new();
+        let mut failed = 0;
+        let started = Instant::now();
+        for op in trace {
+            match *op {
+                TraceOp::Alloc(layout) => {
+                    let object = heap.alloc(layout);
+                    if object.is_null() {
+                        failed += 1;
+                        objects.push(None);
+                    } else {
+                        objects.push(Some((object, layout)));
+                    }
+                }
+                TraceOp::Free(n) => {
+                    if let Some((object, layout)) = objects[n].take() {
+                        heap.dealloc(object, layout);
+                    }
+                }
+            }
+        }
+        let elapsed = started.elapsed();
+        let stats = heap.stats();
+
+        for (object, layout) in objects.into_iter().flatten() {
+            heap.dealloc(object, layout);
+        }
+        FRAMES_ALLOCATOR
+            .lock()
+            .dealloc(start as usize, num_frames, PageEntryLevel::KiB4)
+            .expect("Failed to free the heap's frames.");
+
+        StrategyResult {
+            strategy,
+            failed,
+            elapsed,
+            stats,
+        }
+    })
+}
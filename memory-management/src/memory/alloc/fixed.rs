@@ -0,0 +1,113 @@
+use super::{
+    list::{FitStrategy, HeapStats, LinkedListAllocator},
+    Heap,
+};
+use core::{alloc::Layout, mem, ptr};
+
+/// The block sizes, every block is aligned to its size.
+const BLOCK_SIZES: [usize; 13] = [
+    16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+
+/// A free block, which points at the next free block of its size.
+struct FreeBlock {
+    next: Option<&'static mut FreeBlock>,
+}
+
+/// A free list for every power of two block size, so allocating and freeing a block is popping or
+/// pushing the head of its list.
+///
+/// Blocks are cut out of a linked list allocator when their list is empty, and go back to their
+/// list (not to the linked list) when they're freed. Objects bigger than the biggest block go
+/// straight to the linked list.
+pub(super) struct FixedSizeBlockAllocator {
+    free_lists: [Option<&'static mut FreeBlock>; BLOCK_SIZES.len()],
+    fallback: LinkedListAllocator,
+}
+
+impl FixedSizeBlockAllocator {
+    pub(super) const fn new() -> Self {
+        const EMPTY: Option<&'static mut FreeBlock> = None;
+        Self {
+            free_lists: [EMPTY; BLOCK_SIZES.len()],
+            fallback: LinkedListAllocator::new(FitStrategy::First),
+        }
+    }
+
+    /// Returns the index of the smallest block that fits `layout`.
+    fn list_index(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        BLOCK_SIZES.iter().position(|&block| block >= size)
+    }
+
+    /// Returns the free regions that haven't been cut into blocks yet.
+    pub(super) fn stats(&self) -> HeapStats {
+        self.fallback.stats()
+    }
+
+    /// Returns the free regions of the fallback allocator, the blocks in the free lists aren't
+    /// counted.
+    pub(super) fn free_regions(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.fallback.free_regions()
+    }
+}
+
+impl Heap for FixedSizeBlockAllocator {
+    fn init(&mut self, start: usize, size: usize) {
+        self.fallback.init(start, size);
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let Some(index) = FixedSizeBlockAllocator::list_index(layout) else {
+            return self.fallback.alloc(layout);
+        };
+
+        match self.free_lists[index].take() {
+            Some(block) => {
+                self.free_lists[index] = block.next.take();
+                (block as *mut FreeBlock).cast()
+            }
+            None => {
+                // The list is empty, cut a new block
+                let size = BLOCK_SIZES[index];
+                let layout = Layout::from_size_align(size, size).unwrap();
+                self.fallback.alloc(layout)
+            }
+        }
+    }
+
+    fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let Some(index) = FixedSizeBlockAllocator::list_index(layout) else {
+            self.fallback.dealloc(ptr, layout);
+            return;
+        };
+
+        // Every block can hold a free block
+        assert!(mem::size_of::<FreeBlock>() <= BLOCK_SIZES[index]);
+        let block = ptr.cast::<FreeBlock>();
+        unsafe {
+            ptr::write(
+                block,
+                FreeBlock {
+                    next: self.free_lists[index].take(),
+                },
+            );
+            self.free_lists[index] = Some(&mut *block);
+        }
+    }
+
+    fn resize(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> bool {
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return false;
+        };
+        match (
+            FixedSizeBlockAllocator::list_index(layout),
+            FixedSizeBlockAllocator::list_index(new_layout),
+        ) {
+            // The block already fits the new size
+            (Some(index), Some(new_index)) => index == new_index,
+            (None, None) => self.fallback.resize(ptr, layout, new_size),
+            _ => false,
+        }
+    }
+}
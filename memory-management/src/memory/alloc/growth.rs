@@ -0,0 +1,112 @@
+//! Growing the heap when it runs out of memory, with frames from the frames allocator that are
+//! mapped right after the heap's end.
+
+use crate::memory::{
+    addr::{PhysAddr, VirtAddr},
+    consts::FRAME_SIZE,
+    frames::{alloc_with_reclaim, FrameOwner, FRAMES_ALLOCATOR},
+    kernel_address, layout,
+    paging::{self, PageEntryFlags, PageEntryLevel, PageTable},
+};
+use core::alloc::Layout;
+
+/// How the heap grows when an allocation doesn't fit.
+#[derive(Debug, Clone, Copy)]
+pub struct GrowthPolicy {
+    /// The least number of frames to grow by, bigger allocations grow by as much as they need
+    pub increment_frames: usize,
+    /// The number of frames the heap never grows past, including the ones it started with
+    pub max_frames: usize,
+}
+
+/// The heap's size, and where to map the frames it grows by.
+pub(super) struct HeapGrowth {
+    policy: Option<GrowthPolicy>,
+    /// The address of the root page table
+    root: usize,
+    frames: usize,
+    /// The virtual address after the heap
+    end: usize,
+}
+
+impl HeapGrowth {
+    pub(super) const fn new() -> Self {
+        Self {
+            policy: None,
+            root: 0,
+            frames: 0,
+            end: 0,
+        }
+    }
+
+    /// Starts over with a heap of `frames` frames that doesn't grow.
+    pub(super) fn reset(&mut self, frames: usize) {
+        *self = Self {
+            frames,
+            ..Self::new()
+        };
+    }
+
+    pub(super) fn set_policy(&mut self, policy: GrowthPolicy, root: &mut PageTable) {
+        self.policy = Some(policy);
+        self.root = root as *mut PageTable as usize;
+        self.end = kernel_address(layout::layout().heap.end);
+    }
+
+    pub(super) fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Takes enough frames for `layout` from the frames allocator and maps them after the heap.
+    ///
+    /// Returns (start, size) of the new region, or None if the policy doesn't let the heap grow.
+    pub(super) fn grow(&mut self, layout: Layout) -> Option<(usize, usize)> {
+        let policy = self.policy?;
+
+        // Leave room to align the object inside the region
+        let needed = (layout.size() + layout.align()).div_ceil(FRAME_SIZE);
+        let num_frames = needed.max(policy.increment_frames);
+        if self.frames + num_frames > policy.max_frames {
+            return None;
+        }
+
+        let start = alloc_with_reclaim(num_frames, || {
+            FRAMES_ALLOCATOR
+                .lock()
+                .alloc(num_frames, PageEntryLevel::KiB4)
+        })
+        .ok()? as usize;
+        FRAMES_ALLOCATOR
+            .lock()
+            .set_owner(start, num_frames, FrameOwner::Heap);
+
+        let root = unsafe { (self.root as *mut PageTable).as_mut() }.unwrap();
+        let mapped = (0..num_frames).try_for_each(|frame| {
+            paging::map(
+                root,
+                PhysAddr::new(start) + frame * FRAME_SIZE,
+                VirtAddr::new(self.end) + frame * FRAME_SIZE,
+                &(PageEntryFlags::READ_WRITE
+                    | PageEntryFlags::ACCESSED_DIRTY
+                    | PageEntryFlags::GLOBAL),
+                PageEntryLevel::KiB4,
+            )
+        });
+        if mapped.is_err() {
+            // There are no frames left for the page tables, give the heap's frames back
+            FRAMES_ALLOCATOR
+                .lock()
+                .dealloc(start, num_frames, PageEntryLevel::KiB4)
+                .expect("Failed to free the frames that were just allocated.");
+            return None;
+        }
+
+        self.frames += num_frames;
+        self.end += num_frames * FRAME_SIZE;
+        println!(
+            "- Grew the heap by {num_frames} frames to {} frames.",
+            self.frames
+        );
+        Some((start, num_frames * FRAME_SIZE))
+    }
+}
@@ -1,5 +1,6 @@
+use super::Heap;
 use crate::memory::align_up;
-use core::{alloc::Layout, mem};
+use core::{alloc::Layout, mem, ptr};
 
 pub(super) struct Node {
     size: usize,
@@ -20,36 +21,180 @@ impl Node {
     }
 }
 
+/// Which free region an allocation is taken from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FitStrategy {
+    /// The first region that fits, stops searching early
+    First,
+    /// The smallest region that fits, leaves the big regions whole
+    Best,
+    /// The biggest region, leaves remainders that are big enough to be useful
+    Worst,
+}
+
+/// The free regions of a heap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    pub free_regions: usize,
+    pub free_bytes: usize,
+    pub largest_region: usize,
+    /// The number of regions every allocation looked at so far, summed
+    pub regions_searched: usize,
+}
+
+impl HeapStats {
+    /// Returns the part of the free memory that is outside the largest region, from 0 (one region)
+    /// to almost 1 (many tiny regions).
+    pub fn fragmentation(&self) -> f64 {
+        if self.free_bytes == 0 {
+            return 0.0;
+        }
+        1.0 - self.largest_region as f64 / self.free_bytes as f64
+    }
+}
+
 pub(super) struct LinkedListAllocator {
     head: Node,
+    strategy: FitStrategy,
+    regions_searched: usize,
 }
 
 impl LinkedListAllocator {
-    pub(super) const fn new() -> Self {
-        Self { head: Node::new(0) }
-    }
-
-    /// Initializes the allocator inside the given region.
-    pub(super) fn init(&mut self, start: usize, size: usize) {
-        self.add_free_region(start, size);
+    pub(super) const fn new(strategy: FitStrategy) -> Self {
+        Self {
+            head: Node::new(0),
+            strategy,
+            regions_searched: 0,
+        }
     }
 
-    /// Adds a memory region to the front of the list.
+    /// Adds a memory region to the list, which is sorted by address, and merges it with the regions
+    /// right before and after it.
     pub(super) fn add_free_region(&mut self, addr: usize, size: usize) {
         // Check if the address is aligned and the size is big enough
         assert_eq!(align_up(addr, mem::align_of::<Node>()), addr);
         assert!(size >= mem::size_of::<Node>());
 
+        // Find the last region before the new one (or the head)
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < addr)
+        {
+            current = current.next.as_mut().unwrap();
+        }
+
+        // The head's size is 0, so it is never merged
+        assert!(
+            current.size == 0 || current.end_addr() <= addr,
+            "Freed region overlaps a free region."
+        );
+
         let mut node = Node::new(size);
-        node.next = self.head.next.take();
+        node.next = current.next.take();
+        if let Some(next) = node.next.take() {
+            let end = addr + size;
+            assert!(
+                end <= next.start_addr(),
+                "Freed region overlaps a free region."
+            );
+            if end == next.start_addr() {
+                // Merge with the region after
+                node.size += next.size;
+                node.next = next.next.take();
+            } else {
+                node.next = Some(next);
+            }
+        }
+
+        if current.size > 0 && current.end_addr() == addr {
+            // Merge with the region before
+            current.size += node.size;
+            current.next = node.next.take();
+            return;
+        }
+
         let node_ptr = addr as *mut Node;
         unsafe {
             node_ptr.write(node);
-            self.head.next = Some(&mut *node_ptr);
+            current.next = Some(&mut *node_ptr);
         }
     }
 
-    /// Searches the linked list for a free region with `size` ans `align` and removes it from the list.
+    /// Resizes the region at `addr` from `size` to `new_size` (both already adjusted), by giving
+    /// back its end or taking the start of the free region right after it.
+    ///
+    /// Returns false if it can't be done without moving the region.
+    pub(super) fn resize_region(&mut self, addr: usize, size: usize, new_size: usize) -> bool {
+        if new_size <= size {
+            let rest = size - new_size;
+            if rest > 0 && rest < mem::size_of::<Node>() {
+                // The rest can't hold a Node
+                return false;
+            }
+            if rest > 0 {
+                self.add_free_region(addr + new_size, rest);
+            }
+            return true;
+        }
+
+        // Find the region right after this one
+        let end = addr + size;
+        let needed = new_size - size;
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < end)
+        {
+            current = current.next.as_mut().unwrap();
+        }
+        let Some(next) = current.next.as_ref() else {
+            return false;
+        };
+        if next.start_addr() != end || next.size < needed {
+            return false;
+        }
+        let rest = next.size - needed;
+        if rest > 0 && rest < mem::size_of::<Node>() {
+            return false;
+        }
+
+        let next = current.next.take().unwrap();
+        current.next = next.next.take();
+        if rest > 0 {
+            self.add_free_region(end + needed, rest);
+        }
+        true
+    }
+
+    /// Returns how fragmented the free regions are.
+    pub(super) fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats {
+            regions_searched: self.regions_searched,
+            ..Default::default()
+        };
+        for (_, size) in self.free_regions() {
+            stats.free_regions += 1;
+            stats.free_bytes += size;
+            stats.largest_region = stats.largest_region.max(size);
+        }
+        stats
+    }
+
+    /// Returns the (address, size) of every free region, in order of address.
+    pub(super) fn free_regions(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let mut current = self.head.next.as_deref();
+        core::iter::from_fn(move || {
+            let region = current?;
+            current = region.next.as_deref();
+            Some((region.start_addr(), region.size))
+        })
+    }
+
+    /// Searches the linked list for a free region with `size` ans `align`, picked by the
+    /// allocator's strategy, and removes it from the list.
     ///
     /// Returns (node, address) of the region
     pub(super) fn find_region(
@@ -57,26 +202,60 @@ impl LinkedListAllocator {
         size: usize,
         align: usize,
     ) -> Option<(&'static mut Node, usize)> {
+        let region_addr = match self.strategy {
+            FitStrategy::First => self.first_fit(size, align)?,
+            FitStrategy::Best => self.fit_by(size, align, |region, best| region < best)?,
+            FitStrategy::Worst => self.fit_by(size, align, |region, best| region > best)?,
+        };
+
+        // Remove the region's node from the list
         let mut current = &mut self.head;
+        while current.next.as_ref()?.start_addr() != region_addr {
+            current = current.next.as_mut().unwrap();
+        }
+        let region = current.next.take().unwrap();
+        current.next = region.next.take();
+        let start = Self::alloc_from_region(region, size, align).unwrap();
+        Some((region, start))
+    }
 
-        // Search the list for a large enough region
-        while let Some(ref mut region) = current.next {
-            if let Some(alloc_start) = Self::alloc_from_region(region, size, align) {
-                // The region is good, remove it's node from the list
-                let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
-                current.next = next;
-                return ret;
-            } else {
-                // The region is not good, go to the next node
-                current = current.next.as_mut().unwrap();
+    /// Returns the address of the first region that fits.
+    fn first_fit(&mut self, size: usize, align: usize) -> Option<usize> {
+        let mut current = self.head.next.as_deref_mut();
+        while let Some(region) = current {
+            self.regions_searched += 1;
+            if Self::alloc_from_region(region, size, align).is_some() {
+                return Some(region.start_addr());
             }
+            current = region.next.as_deref_mut();
         }
 
         // No good region found :(
         None
     }
 
+    /// Searches the whole list, and returns the address of the region that fits and whose size
+    /// `better` prefers over all the others.
+    fn fit_by(
+        &mut self,
+        size: usize,
+        align: usize,
+        better: impl Fn(usize, usize) -> bool,
+    ) -> Option<usize> {
+        let mut found: Option<(usize, usize)> = None;
+        let mut current = self.head.next.as_deref_mut();
+        while let Some(region) = current {
+            self.regions_searched += 1;
+            if Self::alloc_from_region(region, size, align).is_some()
+                && found.is_none_or(|(_, best)| better(region.size, best))
+            {
+                found = Some((region.start_addr(), region.size));
+            }
+            current = region.next.as_deref_mut();
+        }
+        found.map(|(addr, _)| addr)
+    }
+
     /// Try to allocate this region with `size` and `align`.
     ///
     /// Returns the start address if successful.
@@ -111,3 +290,45 @@ impl LinkedListAllocator {
         (size, layout.align())
     }
 }
+
+impl Heap for LinkedListAllocator {
+    fn init(&mut self, start: usize, size: usize) {
+        self.add_free_region(start, size);
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = LinkedListAllocator::size_align(layout);
+
+        // Allocate a new region
+        if let Some((region, start)) = self.find_region(size, align) {
+            let (region_start, region_end) = (region.start_addr(), region.end_addr());
+            let end = start.checked_add(size).expect("Address addition overflow.");
+            if start - region_start >= mem::size_of::<Node>() {
+                // Give back the gap that aligning the start left
+                self.add_free_region(region_start, start - region_start);
+            }
+            let remaining_size = region_end - end;
+            if remaining_size > 0 {
+                // If there is a remaining region, add it to the list
+                self.add_free_region(end, remaining_size);
+            }
+            start as *mut u8
+        } else {
+            ptr::null_mut() // There are no more regions to allocate
+        }
+    }
+
+    fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = LinkedListAllocator::size_align(layout);
+        self.add_free_region(ptr as usize, size);
+    }
+
+    fn resize(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> bool {
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return false;
+        };
+        let (size, _) = LinkedListAllocator::size_align(layout);
+        let (new_size, _) = LinkedListAllocator::size_align(new_layout);
+        self.resize_region(ptr as usize, size, new_size)
+    }
+}
@@ -1,55 +1,263 @@
+pub mod bench;
+mod fixed;
+mod growth;
 mod list;
+mod slab;
 
-use core::{alloc::GlobalAlloc, ptr};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr,
+};
 
-use self::list::LinkedListAllocator;
+use self::{
+    fixed::FixedSizeBlockAllocator, growth::HeapGrowth, list::LinkedListAllocator,
+    slab::SlabAllocator,
+};
+use super::{
+    consts::FRAME_SIZE,
+    error::MemoryError,
+    frames::{FrameOwner, FRAMES_ALLOCATOR},
+    paging::{PageEntryLevel, PageTable},
+};
+pub use growth::GrowthPolicy;
+pub use list::{FitStrategy, HeapStats};
+pub use slab::{CacheStats, SIZE_CLASSES};
 
+/// Where `KernelAllocator` gets the objects that are too big for the slabs.
+pub trait Heap {
+    /// Makes the region the heap's memory.
+    fn init(&mut self, start: usize, size: usize);
+
+    /// Adds the region to the heap's memory, the way `init` does by default.
+    fn extend(&mut self, start: usize, size: usize) {
+        self.init(start, size);
+    }
+
+    /// Returns null if there isn't enough free memory.
+    fn alloc(&mut self, layout: Layout) -> *mut u8;
+
+    fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// Resizes the object at `ptr` to `new_size` without moving it.
+    ///
+    /// Returns false (and leaves the object as it was) if it can't be done in place.
+    fn resize(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> bool;
+}
+
+/// The heaps `init` can give the kernel's allocator.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeapKind {
+    /// A list of the free regions, searched with the strategy
+    LinkedList(FitStrategy),
+    /// Free lists of power of two blocks, which allocate and free in O(1)
+    FixedSizeBlock,
+}
+
+/// One of the heaps, picked at `init`.
+enum KernelHeap {
+    LinkedList(LinkedListAllocator),
+    FixedSizeBlock(FixedSizeBlockAllocator),
+}
+
+impl KernelHeap {
+    const fn new(kind: HeapKind) -> Self {
+        match kind {
+            HeapKind::LinkedList(strategy) => {
+                KernelHeap::LinkedList(LinkedListAllocator::new(strategy))
+            }
+            HeapKind::FixedSizeBlock => KernelHeap::FixedSizeBlock(FixedSizeBlockAllocator::new()),
+        }
+    }
+
+    fn heap(&mut self) -> &mut dyn Heap {
+        match self {
+            KernelHeap::LinkedList(heap) => heap,
+            KernelHeap::FixedSizeBlock(heap) => heap,
+        }
+    }
+
+    /// Returns the free regions of the linked list under the heap.
+    fn stats(&self) -> HeapStats {
+        match self {
+            KernelHeap::LinkedList(heap) => heap.stats(),
+            KernelHeap::FixedSizeBlock(heap) => heap.stats(),
+        }
+    }
+
+    /// Returns the (address, size) of the free regions of the linked list under the heap.
+    fn free_regions(&self) -> Vec<(usize, usize)> {
+        match self {
+            KernelHeap::LinkedList(heap) => heap.free_regions().collect(),
+            KernelHeap::FixedSizeBlock(heap) => heap.free_regions().collect(),
+        }
+    }
+}
+
+impl Heap for KernelHeap {
+    fn init(&mut self, start: usize, size: usize) {
+        self.heap().init(start, size);
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.heap().alloc(layout)
+    }
+
+    fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        self.heap().dealloc(ptr, layout);
+    }
+
+    fn resize(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> bool {
+        self.heap().resize(ptr, layout, new_size)
+    }
+}
+
+/// Serves small objects from the slab caches, and everything else from `A`, which grows when it
+/// runs out if it has a growth policy.
 pub struct KernelAllocator<A> {
     allocator: spin::Mutex<A>,
+    slabs: spin::Mutex<SlabAllocator>,
+    growth: spin::Mutex<HeapGrowth>,
 }
 
 impl<A> KernelAllocator<A> {
     pub const fn new(allocator: A) -> Self {
         Self {
             allocator: spin::Mutex::new(allocator),
+            slabs: spin::Mutex::new(SlabAllocator::new()),
+            growth: spin::Mutex::new(HeapGrowth::new()),
         }
     }
+
+    /// Returns how much of every slab cache is in use.
+    fn slab_stats(&self) -> [CacheStats; SIZE_CLASSES.len()] {
+        self.slabs.lock().stats()
+    }
 }
 
-unsafe impl GlobalAlloc for KernelAllocator<LinkedListAllocator> {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let (size, align) = LinkedListAllocator::size_align(layout);
+unsafe impl<A: Heap> GlobalAlloc for KernelAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(class) = SlabAllocator::size_class(layout) {
+            return self.slabs.lock().alloc(class);
+        }
+
         let mut allocator = self.allocator.lock();
+        let ptr = allocator.alloc(layout);
+        if !ptr.is_null() {
+            return ptr;
+        }
 
-        // Allocate a new region
-        if let Some((region, start)) = allocator.find_region(size, align) {
-            let end = start.checked_add(size).expect("Address addition overflow.");
-            let remaining_size = region.end_addr() - end;
-            if remaining_size > 0 {
-                // If there is a remaining region, add it to the list
-                allocator.add_free_region(end, remaining_size);
+        // Out of memory, grow the heap and try again
+        match self.growth.lock().grow(layout) {
+            Some((start, size)) => {
+                allocator.extend(start, size);
+                allocator.alloc(layout)
             }
-            start as *mut u8
-        } else {
-            ptr::null_mut() // There are no more regions to allocate
+            None => ptr,
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-        let (size, _) = LinkedListAllocator::size_align(layout);
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(class) = SlabAllocator::size_class(layout) {
+            self.slabs.lock().dealloc(ptr, class);
+            return;
+        }
 
-        self.allocator.lock().add_free_region(ptr as usize, size);
+        self.allocator.lock().dealloc(ptr, layout);
     }
-}
 
-static ALLOCATOR: KernelAllocator<LinkedListAllocator> =
-    KernelAllocator::new(LinkedListAllocator::new());
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+        if !ptr.is_null() {
+            ptr::write_bytes(ptr, 0, layout.size());
+        }
+        ptr
+    }
+
+    /// Keeps the object where it is when it can: in the same slab cache, or in the heap when the
+    /// free region right after it is big enough. Otherwise moves it.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (
+            SlabAllocator::size_class(layout),
+            SlabAllocator::size_class(new_layout),
+        ) {
+            (Some(class), Some(new_class)) if class == new_class => return ptr,
+            (None, None) if self.allocator.lock().resize(ptr, layout, new_size) => return ptr,
+            _ => {}
+        }
 
-pub fn init(mem_start: *mut u8, size: usize) {
-    unsafe {
-        ALLOCATOR
-            .allocator
-            .lock()
-            .init(mem_start.offset(1024) as usize, size);
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
     }
 }
+
+static ALLOCATOR: KernelAllocator<KernelHeap> =
+    KernelAllocator::new(KernelHeap::new(HeapKind::LinkedList(FitStrategy::First)));
+
+/// Gives a heap of `kind` `num_frames` contiguous frames for the objects that are too big for the
+/// slabs. The slabs take their frames from the frames allocator as they grow, so the two never
+/// overlap.
+pub fn init(num_frames: usize, kind: HeapKind) -> Result<(), MemoryError> {
+    let mut frames = FRAMES_ALLOCATOR.lock();
+    let start = frames.alloc(num_frames, PageEntryLevel::KiB4)?;
+    frames.set_owner(start as usize, num_frames, FrameOwner::Heap);
+    drop(frames);
+
+    let mut heap = ALLOCATOR.allocator.lock();
+    *heap = KernelHeap::new(kind);
+    heap.init(start as usize, num_frames * FRAME_SIZE);
+    ALLOCATOR.growth.lock().reset(num_frames);
+    Ok(())
+}
+
+/// Lets the heap grow by `policy` when it runs out, mapping the frames it grows by after the
+/// heap's end in `root`.
+pub fn set_growth_policy(policy: GrowthPolicy, root: &mut PageTable) {
+    ALLOCATOR.growth.lock().set_policy(policy, root);
+}
+
+/// Returns the number of frames the heap has, including the ones it grew by.
+pub fn heap_frames() -> usize {
+    ALLOCATOR.growth.lock().frames()
+}
+
+/// Allocates memory for `layout` from the kernel's allocator.
+pub fn alloc(layout: Layout) -> *mut u8 {
+    unsafe { ALLOCATOR.alloc(layout) }
+}
+
+/// Frees memory that `alloc` returned for `layout`.
+///
+/// # Safety
+/// `ptr` must have been allocated by `alloc` with the same `layout`, and not freed since.
+pub unsafe fn dealloc(ptr: *mut u8, layout: Layout) {
+    ALLOCATOR.dealloc(ptr, layout);
+}
+
+/// Resizes memory that `alloc` returned for `layout` to `new_size`, moving it if it can't grow in
+/// place. Returns null (and leaves the memory as it was) if there isn't enough free memory.
+///
+/// # Safety
+/// `ptr` must have been allocated by `alloc` with the same `layout`, and not freed since.
+pub unsafe fn realloc(ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATOR.realloc(ptr, layout, new_size)
+}
+
+/// Returns how much of every slab cache is in use.
+pub fn slab_stats() -> [CacheStats; SIZE_CLASSES.len()] {
+    ALLOCATOR.slab_stats()
+}
+
+/// Returns how fragmented the kernel's heap is.
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.allocator.lock().stats()
+}
+
+/// Returns the (address, size) of every free region of the kernel's heap, in order of address.
+pub fn free_regions() -> Vec<(usize, usize)> {
+    ALLOCATOR.allocator.lock().free_regions()
+}
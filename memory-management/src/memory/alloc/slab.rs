@@ -0,0 +1,142 @@
+//! A slab allocator for small objects. Every size class has a cache of equally sized objects,
+//! carved out of whole frames, so small objects don't fragment the heap and freeing one only
+//! puts it back in its cache.
+
+use crate::memory::{
+    consts::FRAME_SIZE,
+    error::MemoryError,
+    frames::{FrameOwner, FRAMES_ALLOCATOR},
+    paging::PageEntryLevel,
+};
+use core::{alloc::Layout, ptr};
+
+/// The object sizes of the caches. Every object is aligned to its size.
+pub const SIZE_CLASSES: [usize; 9] = [16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+/// A free object, which points at the next free object of its cache.
+struct FreeObject {
+    next: Option<&'static mut FreeObject>,
+}
+
+/// How much of a cache is in use.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub object_size: usize,
+    /// The number of frames the cache took from the frames allocator
+    pub slabs: usize,
+    pub allocated: usize,
+}
+
+/// The objects of one size class.
+struct SlabCache {
+    object_size: usize,
+    free: Option<&'static mut FreeObject>,
+    slabs: usize,
+    allocated: usize,
+}
+
+impl SlabCache {
+    const fn new(object_size: usize) -> Self {
+        Self {
+            object_size,
+            free: None,
+            slabs: 0,
+            allocated: 0,
+        }
+    }
+
+    fn alloc(&mut self) -> *mut u8 {
+        if self.free.is_none() && self.grow().is_err() {
+            return ptr::null_mut(); // There are no more frames for a new slab
+        }
+
+        let object = self.free.take().unwrap();
+        self.free = object.next.take();
+        self.allocated += 1;
+        (object as *mut FreeObject).cast()
+    }
+
+    fn dealloc(&mut self, ptr: *mut u8) {
+        self.push(ptr);
+        self.allocated -= 1;
+    }
+
+    /// Adds an object to the front of the free list.
+    fn push(&mut self, ptr: *mut u8) {
+        let object = ptr.cast::<FreeObject>();
+        unsafe {
+            object.write(FreeObject {
+                next: self.free.take(),
+            });
+            self.free = Some(&mut *object);
+        }
+    }
+
+    /// Takes a new frame (a slab) and cuts it into free objects.
+    fn grow(&mut self) -> Result<(), MemoryError> {
+        let mut frames = FRAMES_ALLOCATOR.lock();
+        let slab = frames.alloc(1, PageEntryLevel::KiB4)?;
+        frames.set_owner(slab as usize, 1, FrameOwner::Slab);
+        drop(frames);
+
+        // Push the objects backwards, so they're handed out in the order of their addresses
+        for offset in (0..FRAME_SIZE).step_by(self.object_size).rev() {
+            self.push(unsafe { slab.add(offset) });
+        }
+        self.slabs += 1;
+        Ok(())
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            object_size: self.object_size,
+            slabs: self.slabs,
+            allocated: self.allocated,
+        }
+    }
+}
+
+/// A cache for every size class. Slabs are never given back to the frames allocator, a cache
+/// keeps its free objects for the next allocations of its size.
+pub(super) struct SlabAllocator {
+    caches: [SlabCache; SIZE_CLASSES.len()],
+}
+
+impl SlabAllocator {
+    pub(super) const fn new() -> Self {
+        Self {
+            caches: [
+                SlabCache::new(SIZE_CLASSES[0]),
+                SlabCache::new(SIZE_CLASSES[1]),
+                SlabCache::new(SIZE_CLASSES[2]),
+                SlabCache::new(SIZE_CLASSES[3]),
+                SlabCache::new(SIZE_CLASSES[4]),
+                SlabCache::new(SIZE_CLASSES[5]),
+                SlabCache::new(SIZE_CLASSES[6]),
+                SlabCache::new(SIZE_CLASSES[7]),
+                SlabCache::new(SIZE_CLASSES[8]),
+            ],
+        }
+    }
+
+    /// Returns the index of the smallest size class that fits `layout`, or None if `layout` is
+    /// too big for the slabs.
+    pub(super) fn size_class(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        SIZE_CLASSES.iter().position(|&class| class >= size)
+    }
+
+    pub(super) fn alloc(&mut self, class: usize) -> *mut u8 {
+        self.caches[class].alloc()
+    }
+
+    pub(super) fn dealloc(&mut self, ptr: *mut u8, class: usize) {
+        self.caches[class].dealloc(ptr);
+    }
+
+    pub(super) fn stats(&self) -> [CacheStats; SIZE_CLASSES.len()] {
+        self.caches.each_ref().map(SlabCache::stats)
+    }
+}
+
+unsafe impl Send for SlabAllocator {}
@@ -0,0 +1,194 @@
+//! The path every load, store and instruction fetch of a hart takes through an address space:
+//! the page tables are walked, the page's permissions are checked against the access and the
+//! privilege mode, the accessed and dirty bits are set, and the fault handler runs on violations.
+//! Translations are cached in the TLB, and the tables are only walked on a miss.
+
+use super::{
+    consts::FRAME_SIZE, error::MemoryError, fault::Access, paging::PageEntryFlags,
+    space::AddressSpace, tlb::TLB,
+};
+use core::ptr;
+
+/// The privilege mode of the hart that accesses memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Privilege {
+    /// Can only access the pages with the `USER` flag
+    User,
+    /// Can't access the pages with the `USER` flag, unless `sum` (`sstatus.SUM`) is set, and even
+    /// then it can't execute them
+    Supervisor { sum: bool },
+}
+
+impl Privilege {
+    /// Returns true if a page with `flags` can be accessed for `access` in this mode.
+    pub fn allows(self, flags: PageEntryFlags, access: Access) -> bool {
+        let user_page = flags.contains(PageEntryFlags::USER);
+        match self {
+            Privilege::User => user_page,
+            Privilege::Supervisor { sum } => !user_page || (sum && access != Access::Execute),
+        }
+    }
+}
+
+/// Checked accesses to the memory of an address space, as a hart in `privilege` mode.
+pub struct MemoryBus<'a> {
+    space: &'a mut AddressSpace,
+    privilege: Privilege,
+}
+
+impl<'a> MemoryBus<'a> {
+    pub fn new(space: &'a mut AddressSpace, privilege: Privilege) -> Self {
+        Self { space, privilege }
+    }
+
+    pub fn set_privilege(&mut self, privilege: Privilege) {
+        self.privilege = privilege;
+    }
+
+    /// Reads `len` bytes from `addr`.
+    pub fn read(&mut self, addr: usize, len: usize) -> Result<Vec<u8>, MemoryError> {
+        let mut data = vec![0; len];
+        self.transfer(addr, len, Access::Read, |phys, offset, chunk| unsafe {
+            ptr::copy_nonoverlapping(phys as *const u8, data[offset..].as_mut_ptr(), chunk)
+        })?;
+        Ok(data)
+    }
+
+    /// Writes `data` to `addr`.
+    pub fn write(&mut self, addr: usize, data: &[u8]) -> Result<(), MemoryError> {
+        self.transfer(
+            addr,
+            data.len(),
+            Access::Write,
+            |phys, offset, chunk| unsafe {
+                ptr::copy_nonoverlapping(data[offset..].as_ptr(), phys as *mut u8, chunk)
+            },
+        )
+    }
+
+    /// Fetches `len` bytes of instructions from `addr`.
+    pub fn fetch(&mut self, addr: usize, len: usize) -> Result<Vec<u8>, MemoryError> {
+        let mut code = vec![0; len];
+        self.transfer(addr, len, Access::Execute, |phys, offset, chunk| unsafe {
+            ptr::copy_nonoverlapping(phys as *const u8, code[offset..].as_mut_ptr(), chunk)
+        })?;
+        Ok(code)
+    }
+
+    /// Translates `[addr, addr + len)` a page at a time, and calls `copy` with the physical
+    /// address, the offset into the range and the length of every piece. Every page is translated
+    /// before anything is copied, so an access that faults on any page has no effect.
+    fn transfer(
+        &mut self,
+        addr: usize,
+        len: usize,
+        access: Access,
+        mut copy: impl FnMut(usize, usize, usize),
+    ) -> Result<(), MemoryError> {
+        let mut pieces = Vec::new();
+        let mut offset = 0;
+        while offset < len {
+            let virt = addr + offset;
+            let chunk = (FRAME_SIZE - virt % FRAME_SIZE).min(len - offset);
+            pieces.push((self.translate(virt, access)?, offset, chunk));
+            offset += chunk;
+        }
+
+        for (phys, offset, chunk) in pieces {
+            copy(phys, offset, chunk);
+        }
+        Ok(())
+    }
+
+    /// Translates `virt` for `access`. Faults the privilege mode can't access the page in are
+    /// reported without running the fault handler, since it can't fix them.
+    ///
+    /// A cached translation is only used if it allows the access without changing the page's
+    /// entry, so the first write to a page walks the tables to set its dirty bit.
+    fn translate(&mut self, virt: usize, access: Access) -> Result<usize, MemoryError> {
+        let fault = MemoryError::PageFault { virt, access };
+        let asid = self.space.current_asid();
+        if let Some(cached) = TLB.lock().lookup(asid, virt) {
+            let needed = match access {
+                Access::Read => PageEntryFlags::READ,
+                Access::Write => PageEntryFlags::WRITE | PageEntryFlags::DIRTY,
+                Access::Execute => PageEntryFlags::EXECUTE,
+            };
+            if cached.flags.contains(needed) && self.privilege.allows(cached.flags, access) {
+                return Ok(cached.phys);
+            }
+        }
+
+        if let Ok(translation) = self.space.translate(virt) {
+            if !self.privilege.allows(translation.flags, access) {
+                return Err(fault);
+            }
+        }
+
+        // Sets the accessed and dirty bits, and handles the fault if the page is missing
+        let phys = self.space.access(virt, access)?;
+        let translation = self.space.translate(virt)?;
+        if !self.privilege.allows(translation.flags, access) {
+            return Err(fault);
+        }
+        TLB.lock().insert(asid, virt, translation);
+        Ok(phys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{paging::PageEntryLevel, space::Backing, tests::frames};
+
+    #[test]
+    fn accesses_check_permissions_and_set_the_accessed_and_dirty_bits() {
+        let _frames = frames();
+        let mut space = AddressSpace::new().unwrap();
+        let (user, kernel) = (0x1000_0000, 0x2000_0000);
+        for (start, flags) in [
+            (user, PageEntryFlags::READ_WRITE | PageEntryFlags::USER),
+            (kernel, PageEntryFlags::READ_WRITE),
+        ] {
+            space
+                .reserve(
+                    start,
+                    2 * FRAME_SIZE,
+                    flags,
+                    PageEntryLevel::KiB4,
+                    Backing::Anonymous,
+                )
+                .unwrap();
+        }
+
+        // A write across the page boundary faults both pages in
+        let mut bus = MemoryBus::new(&mut space, Privilege::User);
+        let addr = user + FRAME_SIZE - 3;
+        bus.write(addr, b"riscy").unwrap();
+        assert_eq!(bus.read(addr, 5).unwrap(), b"riscy");
+        assert_eq!(
+            bus.read(kernel, 1),
+            Err(MemoryError::PageFault {
+                virt: kernel,
+                access: Access::Read
+            })
+        );
+        assert_eq!(
+            bus.fetch(user, 4),
+            Err(MemoryError::PageFault {
+                virt: user,
+                access: Access::Execute
+            })
+        );
+
+        // The supervisor can only read user pages with SUM
+        bus.set_privilege(Privilege::Supervisor { sum: false });
+        assert!(bus.read(user, 1).is_err());
+        bus.write(kernel, b"kernel").unwrap();
+        bus.set_privilege(Privilege::Supervisor { sum: true });
+        assert_eq!(bus.read(addr + 3, 2).unwrap(), b"cy");
+
+        let flags = space.translate(user + FRAME_SIZE).unwrap().flags;
+        assert!(flags.contains(PageEntryFlags::ACCESSED_DIRTY));
+    }
+}
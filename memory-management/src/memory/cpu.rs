@@ -0,0 +1,158 @@
+//! The state of the hart that matters to memory management: the `satp` register, which selects the
+//! page table (and the ASID) every access is translated with.
+
+use super::{
+    addr::PhysAddr,
+    error::MemoryError,
+    paging::{self, PageTable, PagingMode},
+    space::{asids_disabled, AddressSpace},
+    tlb,
+};
+
+/// The `satp` register: the paging mode, the ASID and the PPN of the root table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Satp {
+    /// None for `Bare`, where virtual addresses are physical addresses
+    pub mode: Option<PagingMode>,
+    pub asid: u16,
+    pub ppn: usize,
+}
+
+impl Satp {
+    pub const BARE: Self = Self {
+        mode: None,
+        asid: 0,
+        ppn: 0,
+    };
+
+    /// Returns the value of the register: MODE in bits 63-60, ASID in bits 59-44 and PPN in bits
+    /// 43-0.
+    pub fn bits(self) -> u64 {
+        let mode = match self.mode {
+            None => 0,
+            Some(PagingMode::Sv39) => 8,
+            Some(PagingMode::Sv48) => 9,
+            Some(PagingMode::Sv57) => 10,
+        };
+        (mode << 60) | ((self.asid as u64) << 44) | (self.ppn as u64 & ((1 << 44) - 1))
+    }
+
+    /// Returns the address of the root table.
+    pub fn root(self) -> usize {
+        PhysAddr::from_frame_number(self.ppn).as_usize()
+    }
+}
+
+/// A hart, which accesses memory through the address space `satp` points at.
+pub struct Cpu {
+    satp: Satp,
+    /// The number of times `satp` was switched to another address space
+    switches: usize,
+}
+
+impl Cpu {
+    /// Returns a hart that starts in `Bare` mode, like after reset.
+    pub const fn new() -> Self {
+        Self {
+            satp: Satp::BARE,
+            switches: 0,
+        }
+    }
+
+    pub fn satp(&self) -> Satp {
+        self.satp
+    }
+
+    pub fn switches(&self) -> usize {
+        self.switches
+    }
+
+    /// Points `satp` at `space`. Translations are tagged with ASIDs, so nothing is flushed unless
+    /// the address space needed a new ASID and the generation rolled over (which flushes the
+    /// whole TLB), or the hart doesn't implement ASIDs (and every address space uses ASID 0).
+    pub fn switch_to(&mut self, space: &mut AddressSpace) {
+        let asid = space.current_asid();
+        let ppn = PhysAddr::from_ptr(space.root()).frame_number();
+        if ppn == self.satp.ppn && asid == self.satp.asid {
+            return; // Already there
+        }
+
+        if asids_disabled() {
+            // Flushes the translations of ASID 0, but not the global (kernel) ones
+            tlb::sfence_vma(Some(0), None);
+        }
+        self.satp = Satp {
+            mode: Some(paging::mode()),
+            asid,
+            ppn,
+        };
+        self.switches += 1;
+    }
+
+    /// Translates `virt` like the hart would, through the TLB and the table `satp` points at.
+    pub fn translate(&self, virt: usize) -> Result<usize, MemoryError> {
+        if self.satp.mode.is_none() {
+            return Ok(virt);
+        }
+        let root = unsafe { &*(self.satp.root() as *const PageTable) };
+        tlb::translate(root, self.satp.asid, virt).map(|translation| translation.phys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{
+        consts::FRAME_SIZE,
+        paging::{PageEntryFlags, PageEntryLevel},
+        space::{set_asid_bits, Backing},
+        tests::frames,
+    };
+
+    #[test]
+    fn switching_keeps_asids_until_they_run_out() {
+        let _frames = frames();
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.translate(0x1234), Ok(0x1234));
+
+        set_asid_bits(2); // ASIDs 1 to 3
+        let mut spaces: Vec<_> = (0..4).map(|_| AddressSpace::new().unwrap()).collect();
+        for space in &mut spaces {
+            space
+                .map(
+                    0x1000_0000,
+                    FRAME_SIZE,
+                    PageEntryFlags::READ,
+                    PageEntryLevel::KiB4,
+                    Backing::Anonymous,
+                )
+                .unwrap();
+        }
+
+        // The fourth address space started a new generation, so the others get new ASIDs
+        let old_asid = spaces[0].asid();
+        cpu.switch_to(&mut spaces[0]);
+        assert_ne!(cpu.satp().asid, old_asid);
+        assert_eq!(
+            cpu.satp().root(),
+            spaces[0].root() as *mut PageTable as usize
+        );
+        assert_eq!(cpu.satp().bits() >> 60, 8);
+        let frame = spaces[0].translate(0x1000_0000).unwrap().phys;
+        assert_eq!(cpu.translate(0x1000_0000), Ok(frame));
+
+        // Switching back and forth keeps the translations of both
+        cpu.switch_to(&mut spaces[3]);
+        cpu.translate(0x1000_0000).unwrap();
+        cpu.switch_to(&mut spaces[0]);
+        cpu.switch_to(&mut spaces[3]);
+        assert_eq!(cpu.switches(), 4);
+        let mut tlb = tlb::TLB.lock();
+        assert!(tlb.lookup(spaces[0].asid(), 0x1000_0000).is_some());
+        assert!(tlb.lookup(spaces[3].asid(), 0x1000_0000).is_some());
+        drop(tlb);
+
+        drop(spaces);
+        set_asid_bits(16);
+    }
+}
@@ -0,0 +1,67 @@
+//! A disk: a file next to `mem.img` that holds the files address spaces can map, one after the
+//! other, with a table of where every file starts and how long it is.
+
+use spin::Mutex;
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+static DISK: Mutex<Option<Disk>> = Mutex::new(None);
+
+struct Disk {
+    file: File,
+    /// The offset and the length of every file
+    files: BTreeMap<&'static str, (usize, usize)>,
+    /// Where the next file is written
+    end: usize,
+}
+
+/// Creates an empty disk at `path`.
+pub fn init_disk(path: &str) {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .expect("Failed to create disk file");
+
+    *DISK.lock() = Some(Disk {
+        file,
+        files: BTreeMap::new(),
+        end: 0,
+    });
+}
+
+/// Writes a file called `name` with `data` to the disk, in place of the file with that name if
+/// there is one.
+pub fn create_file(name: &'static str, data: &[u8]) {
+    let mut disk = DISK.lock();
+    let disk = disk.as_mut().expect("The disk is not initialized");
+    disk.file
+        .seek(SeekFrom::Start(disk.end as u64))
+        .and_then(|_| disk.file.write_all(data))
+        .expect("Failed to write to the disk file");
+
+    disk.files.insert(name, (disk.end, data.len()));
+    disk.end += data.len();
+}
+
+/// Returns `len` bytes of the file called `name` from `offset`. The bytes past the end of the file
+/// are zeros, like the rest of the last page of a mapped file. Returns None if there is no such
+/// file.
+pub fn read_file(name: &str, offset: usize, len: usize) -> Option<Vec<u8>> {
+    let mut disk = DISK.lock();
+    let disk = disk.as_mut()?;
+    let &(start, file_len) = disk.files.get(name)?;
+
+    let mut data = vec![0; len];
+    let available = file_len.saturating_sub(offset).min(len);
+    disk.file
+        .seek(SeekFrom::Start((start + offset) as u64))
+        .and_then(|_| disk.file.read_exact(&mut data[..available]))
+        .expect("Failed to read from the disk file");
+    Some(data)
+}
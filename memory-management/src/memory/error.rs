@@ -0,0 +1,99 @@
+use super::{fault::Access, paging::PageEntryFlags};
+use core::fmt;
+
+/// Why a frame or page table operation failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryError {
+    /// There aren't `frames` free (contiguous) frames
+    OutOfMemory { frames: usize },
+    /// `addr` isn't aligned to the page size of the level it was used at
+    Misaligned { addr: usize, align: usize },
+    /// The virtual address is already mapped, or is covered by a bigger page
+    AlreadyMapped { virt: usize },
+    /// The virtual address isn't mapped
+    NotMapped { virt: usize },
+    /// The `len` bytes at the virtual address run past the end of the address space
+    RangeOverflow { virt: usize, len: usize },
+    /// The frames at `addr` are already free
+    DoubleFree { addr: usize },
+    /// The requested alignment isn't a power of two
+    InvalidAlignment { align: usize },
+    /// Too many ranges were reserved before the frames allocator was initialized
+    TooManyReservations,
+    /// The reverse map has no room for another mapping
+    ReverseMapFull,
+    /// The page that maps the virtual address was written out to swap
+    Swapped { virt: usize },
+    /// There are no free slots in the swap file
+    SwapFull,
+    /// The page that maps the virtual address can't be swapped out (it's shared, bigger than
+    /// 4KiB, or isn't an address space's)
+    NotSwappable { virt: usize },
+    /// Accessing the virtual address faults, and the fault can't be handled
+    PageFault { virt: usize, access: Access },
+    /// There is no free range of `len` bytes left in the address space
+    AddressSpaceFull { len: usize },
+    /// The `len` bytes at the virtual address aren't all in the lower half, where user mappings
+    /// are
+    NotInLowerHalf { virt: usize, len: usize },
+    /// The page at the virtual address would be both writable and executable, which the W^X
+    /// policy denies
+    WritableAndExecutable { virt: usize },
+    /// The flags can't be the flags of a page (they aren't readable, writable or executable, or
+    /// they are writable but not readable)
+    InvalidFlags { flags: PageEntryFlags },
+    /// The min watermark is above the low watermark
+    InvalidWatermarks { low: usize, min: usize },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryError::OutOfMemory { frames } => {
+                write!(f, "Could not find {frames} contiguous free frames")
+            }
+            MemoryError::Misaligned { addr, align } => {
+                write!(f, "Address {addr:#X} is not aligned to {align:#X}")
+            }
+            MemoryError::AlreadyMapped { virt } => write!(f, "Address {virt:#X} is already mapped"),
+            MemoryError::NotMapped { virt } => write!(f, "Address {virt:#X} is not mapped"),
+            MemoryError::RangeOverflow { virt, len } => {
+                write!(f, "{len:#X} bytes at {virt:#X} run past the end of memory")
+            }
+            MemoryError::DoubleFree { addr } => write!(f, "Double free at {addr:#X}"),
+            MemoryError::InvalidAlignment { align } => {
+                write!(f, "Alignment {align:#X} is not a power of two")
+            }
+            MemoryError::TooManyReservations => {
+                write!(f, "Too many ranges were reserved before initialization")
+            }
+            MemoryError::ReverseMapFull => write!(f, "The reverse map is full"),
+            MemoryError::Swapped { virt } => write!(f, "Address {virt:#X} is swapped out"),
+            MemoryError::SwapFull => write!(f, "The swap file is full"),
+            MemoryError::NotSwappable { virt } => {
+                write!(f, "The page at {virt:#X} can't be swapped out")
+            }
+            MemoryError::PageFault { virt, access } => {
+                write!(f, "Page fault on {access:?} access to {virt:#X}")
+            }
+            MemoryError::AddressSpaceFull { len } => {
+                write!(f, "There is no free range of {len:#X} bytes")
+            }
+            MemoryError::NotInLowerHalf { virt, len } => {
+                write!(f, "{len:#X} bytes at {virt:#X} are not in the lower half")
+            }
+            MemoryError::WritableAndExecutable { virt } => {
+                write!(f, "The page at {virt:#X} would be writable and executable")
+            }
+            MemoryError::InvalidFlags { flags } => {
+                write!(f, "A page can't have the flags {flags:?}")
+            }
+            MemoryError::InvalidWatermarks { low, min } => {
+                write!(
+                    f,
+                    "The min watermark {min} is above the low watermark {low}"
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! What the MMU does on an access: it checks the page's flags, and faults if the page is missing or
+//! doesn't allow the access. Some faults are part of managing memory (like copy-on-write), so they
+//! are handled here and the access is retried, instead of being reported.
+
+use super::{
+    consts::FRAME_SIZE,
+    error::MemoryError,
+    frames::{FrameOwner, FRAMES_ALLOCATOR},
+    paging::{self, PageEntry, PageEntryFlags, PageEntryLevel, PageTable},
+    rmap::{Mapping, REVERSE_MAP},
+    swap, tlb,
+};
+use core::ptr;
+use spin::Mutex;
+
+/// The frame of zeros that every untouched page is mapped to until it's written to.
+static ZERO_FRAME: Mutex<Option<usize>> = Mutex::new(None);
+
+/// The kinds of memory accesses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    /// Returns the flag a page needs to allow the access.
+    fn flag(self) -> PageEntryFlags {
+        match self {
+            Access::Read => PageEntryFlags::READ,
+            Access::Write => PageEntryFlags::WRITE,
+            Access::Execute => PageEntryFlags::EXECUTE,
+        }
+    }
+}
+
+/// Returns the shared frame of zeros, which is allocated on first use and never freed. Every page
+/// that maps it holds a reference to it (see `BitmapAllocator::share`).
+pub fn zero_frame() -> Result<usize, MemoryError> {
+    let mut zero_frame = ZERO_FRAME.lock();
+    if let Some(frame) = *zero_frame {
+        return Ok(frame);
+    }
+
+    let frame = FRAMES_ALLOCATOR
+        .lock()
+        .zero_alloc(1, PageEntryLevel::KiB4)? as usize;
+    *zero_frame = Some(frame);
+    Ok(frame)
+}
+
+/// Forgets the zero frame, so the next `zero_frame` allocates another one. Only for when the
+/// memory it's in is replaced (see `restore_snapshot`), and no page maps it.
+pub fn forget_zero_frame() {
+    *ZERO_FRAME.lock() = None;
+}
+
+/// Returns the number of pages that map the zero frame.
+pub fn zero_frame_users() -> usize {
+    match *ZERO_FRAME.lock() {
+        Some(frame) => {
+            let info = FRAMES_ALLOCATOR.lock().info(frame).unwrap();
+            info.refcount as usize - 1
+        }
+        None => 0,
+    }
+}
+
+/// Translates `virt` like the MMU would for `access`, without handling faults.
+pub fn check_access(root: &PageTable, virt: usize, access: Access) -> Result<usize, MemoryError> {
+    let fault = MemoryError::PageFault { virt, access };
+    let translation = paging::translate(root, virt).map_err(|_| fault)?;
+    if !translation.flags.contains(access.flag()) {
+        return Err(fault);
+    }
+    Ok(translation.phys)
+}
+
+/// Translates `virt` for `access`, handling the fault and retrying once if it faults.
+pub fn access(root: &mut PageTable, virt: usize, access: Access) -> Result<usize, MemoryError> {
+    match check_access(root, virt, access) {
+        Err(MemoryError::PageFault { .. }) => {
+            handle_fault(root, virt, access)?;
+            check_access(root, virt, access)
+        }
+        result => result,
+    }
+}
+
+/// Handles a fault on `access` to `virt`. Fails with the fault if nothing can make the access
+/// succeed, or with `Swapped` if the page is in swap.
+pub fn handle_fault(root: &mut PageTable, virt: usize, access: Access) -> Result<(), MemoryError> {
+    let fault = MemoryError::PageFault { virt, access };
+    let (entry, level) = match paging::leaf_entry(root, virt) {
+        Ok(leaf) => leaf,
+        Err(MemoryError::NotMapped { .. }) => return Err(fault),
+        Err(error) => return Err(error),
+    };
+
+    if access == Access::Write && entry.is_cow() {
+        return copy_on_write(entry, level, virt - virt % level.size());
+    }
+    Err(fault)
+}
+
+/// Gives the page at `virt` a private, writable copy of the frame it shares. If nobody else uses
+/// the frame anymore, the page just becomes writable.
+fn copy_on_write(
+    entry: &mut PageEntry,
+    level: PageEntryLevel,
+    virt: usize,
+) -> Result<(), MemoryError> {
+    let shared = entry.get_ppn();
+    let mut flags = entry.flags();
+    flags.remove(PageEntryFlags::COW);
+    flags.insert(PageEntryFlags::WRITE);
+
+    let info = FRAMES_ALLOCATOR.lock().info(shared);
+    if info.is_some_and(|info| info.refcount == 1) {
+        entry.set_flags(&flags);
+        tlb::sfence_vma(None, Some(virt));
+        return Ok(());
+    }
+
+    // The shared frame has other users, so making room can't swap it out
+    let copy = swap::alloc_page(level)?;
+    let mut frames = FRAMES_ALLOCATOR.lock();
+    // Only the pages of address spaces are copy-on-write, even if they shared the zero frame,
+    // which the kernel owns
+    frames.set_owner(
+        copy as usize,
+        level.size() / FRAME_SIZE,
+        FrameOwner::AddressSpace,
+    );
+    if info.is_some() {
+        frames.release(shared, 1, level)?; // Someone else still uses it
+    }
+    drop(frames);
+    unsafe { ptr::copy_nonoverlapping(shared as *const u8, copy, level.size()) };
+
+    entry.set_flags(&flags);
+    entry.set_ppn(copy as usize);
+    tlb::sfence_vma(None, Some(virt));
+
+    let entry_addr = entry as *const PageEntry as usize;
+    let mut rmap = REVERSE_MAP.lock();
+    rmap.remove_entries(entry_addr, entry_addr + size_of::<PageEntry>());
+    rmap.insert(Mapping {
+        phys: copy as usize,
+        virt,
+        level,
+        entry: entry_addr,
+    })
+}
@@ -0,0 +1,296 @@
+//! A minimal parser of flattened device trees, the `.dtb` blobs that firmware (like QEMU's virt
+//! machine) hands the kernel to describe the hardware. It only looks for memory: the ranges of the
+//! `memory` nodes, and the regions that the memory reservation block and the children of the
+//! `/reserved-memory` node reserve. `virt.dtb` is a trimmed down tree of the virt machine with 8MiB
+//! of memory, like `mem.img`.
+
+use super::{
+    addr::PhysAddr,
+    error::MemoryError,
+    frames::{init_frames_allocation_regions, reserve_range},
+};
+use core::{fmt, ops::Range};
+
+const MAGIC: u32 = 0xD00D_FEED;
+
+// The tokens of the structure block
+const BEGIN_NODE: u32 = 1;
+const END_NODE: u32 = 2;
+const PROP: u32 = 3;
+const NOP: u32 = 4;
+const END: u32 = 9;
+
+/// Why a blob couldn't be parsed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FdtError {
+    /// The blob doesn't start with the magic number of device trees
+    BadMagic { magic: u32 },
+    /// Something at the offset runs past the end of the blob
+    Truncated { offset: usize },
+    /// The structure block has a token that isn't one of the five tokens
+    UnknownToken { token: u32, offset: usize },
+    /// The `reg` property at the offset isn't a whole number of (address, size) pairs
+    BadReg { offset: usize },
+    /// No node describes any memory
+    NoMemory,
+}
+
+impl fmt::Display for FdtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FdtError::BadMagic { magic } => write!(f, "{magic:#X} is not a device tree's magic"),
+            FdtError::Truncated { offset } => {
+                write!(f, "The device tree is truncated at {offset:#X}")
+            }
+            FdtError::UnknownToken { token, offset } => {
+                write!(f, "Unknown token {token:#X} at {offset:#X}")
+            }
+            FdtError::BadReg { offset } => {
+                write!(f, "The `reg` property at {offset:#X} is malformed")
+            }
+            FdtError::NoMemory => write!(f, "The device tree has no memory"),
+        }
+    }
+}
+
+/// The memory a device tree describes. Both lists are sorted, and the memory ranges are merged
+/// where they touch.
+#[derive(Debug, Default, PartialEq)]
+pub struct MemoryMap {
+    pub memory: Vec<Range<usize>>,
+    pub reserved: Vec<Range<usize>>,
+}
+
+impl MemoryMap {
+    /// Lets the frames allocator manage the memory, and reserves the reserved regions. The memory
+    /// is simulated: the first `size` bytes from the start of the first range are at `start`, and
+    /// the rest is left out.
+    pub fn init_frames_allocation(&self, start: *mut u8, size: usize) -> Result<(), MemoryError> {
+        let base = self.memory.first().expect("There is no memory").start;
+        let relocate = |addr: usize| start as usize + (addr.max(base) - base).min(size);
+
+        let regions: Vec<Range<usize>> = self
+            .memory
+            .iter()
+            .map(|range| relocate(range.start)..relocate(range.end))
+            .filter(|range| !range.is_empty())
+            .collect();
+        init_frames_allocation_regions(&regions);
+        for range in &self.reserved {
+            let (start, end) = (relocate(range.start), relocate(range.end));
+            reserve_range(PhysAddr::new(start), PhysAddr::new(end))?;
+        }
+        Ok(())
+    }
+}
+
+/// A node that is being parsed, and what its children need to know about it.
+struct Node<'a> {
+    name: &'a str,
+    /// The number of cells in the addresses and the sizes of its children's `reg`s
+    address_cells: usize,
+    size_cells: usize,
+    is_memory: bool,
+    /// The value of its `reg` property, and the value's offset
+    reg: Option<(&'a [u8], usize)>,
+}
+
+impl<'a> Node<'a> {
+    fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            // The defaults the specification gives
+            address_cells: 2,
+            size_cells: 1,
+            is_memory: false,
+            reg: None,
+        }
+    }
+}
+
+/// Finds the memory that the device tree in `blob` describes.
+pub fn parse(blob: &[u8]) -> Result<MemoryMap, FdtError> {
+    let magic = read_u32(blob, 0)?;
+    if magic != MAGIC {
+        return Err(FdtError::BadMagic { magic });
+    }
+    let struct_offset = read_u32(blob, 8)? as usize;
+    let strings_offset = read_u32(blob, 12)? as usize;
+    let reservations_offset = read_u32(blob, 16)? as usize;
+
+    let mut map = MemoryMap::default();
+
+    // The memory reservation block is a list of (address, size) pairs that ends with a zero pair
+    for offset in (reservations_offset..).step_by(16) {
+        let (address, size) = (read_u64(blob, offset)?, read_u64(blob, offset + 8)?);
+        if address == 0 && size == 0 {
+            break;
+        }
+        map.reserved.push(address..address + size);
+    }
+
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut offset = struct_offset;
+    loop {
+        let token = read_u32(blob, offset)?;
+        offset += 4;
+        match token {
+            BEGIN_NODE => {
+                let name = read_str(blob, offset)?;
+                offset = align4(offset + name.len() + 1);
+                let mut node = Node::new(name);
+                node.is_memory =
+                    nodes.len() == 1 && (name == "memory" || name.starts_with("memory@"));
+                nodes.push(node);
+            }
+            END_NODE => {
+                let node = nodes
+                    .pop()
+                    .ok_or(FdtError::UnknownToken { token, offset })?;
+                let Some((reg, reg_offset)) = node.reg else {
+                    continue;
+                };
+                // Only the memory nodes and the reserved regions matter, and both are direct
+                // children of a node right under the root
+                let (parent, grandparent) = match nodes.as_slice() {
+                    [root] => (root, None),
+                    [.., grandparent, parent] => (parent, Some(grandparent)),
+                    [] => continue,
+                };
+                let ranges = parse_reg(reg, reg_offset, parent)?;
+                if node.is_memory {
+                    map.memory.extend(ranges);
+                } else if parent.name == "reserved-memory"
+                    && grandparent.is_some_and(|node| node.name.is_empty())
+                {
+                    map.reserved.extend(ranges);
+                }
+            }
+            PROP => {
+                let len = read_u32(blob, offset)? as usize;
+                let name = read_str(blob, strings_offset + read_u32(blob, offset + 4)? as usize)?;
+                let value_offset = offset + 8;
+                let value =
+                    blob.get(value_offset..value_offset + len)
+                        .ok_or(FdtError::Truncated {
+                            offset: value_offset,
+                        })?;
+                offset = align4(value_offset + len);
+
+                let Some(node) = nodes.last_mut() else {
+                    return Err(FdtError::UnknownToken { token, offset });
+                };
+                match name {
+                    "#address-cells" => node.address_cells = read_u32(value, 0)? as usize,
+                    "#size-cells" => node.size_cells = read_u32(value, 0)? as usize,
+                    "device_type" => node.is_memory |= value == b"memory\0",
+                    "reg" => node.reg = Some((value, value_offset)),
+                    _ => {}
+                }
+            }
+            NOP => {}
+            END => break,
+            _ => {
+                return Err(FdtError::UnknownToken {
+                    token,
+                    offset: offset - 4,
+                })
+            }
+        }
+    }
+
+    if map.memory.is_empty() {
+        return Err(FdtError::NoMemory);
+    }
+    map.memory.sort_by_key(|range| range.start);
+    map.memory.dedup_by(|next, range| {
+        // Merge ranges that touch or overlap
+        if next.start > range.end {
+            return false;
+        }
+        range.end = range.end.max(next.end);
+        true
+    });
+    map.reserved.sort_by_key(|range| range.start);
+    Ok(map)
+}
+
+/// Splits a `reg` property into the ranges it lists, with the address and size cells of `parent`.
+fn parse_reg(reg: &[u8], offset: usize, parent: &Node) -> Result<Vec<Range<usize>>, FdtError> {
+    let (address_cells, size_cells) = (parent.address_cells, parent.size_cells);
+    let pair_size = (address_cells + size_cells) * 4;
+    if pair_size == 0 || !reg.len().is_multiple_of(pair_size) {
+        return Err(FdtError::BadReg { offset });
+    }
+
+    let read_cells = |cells: &[u8]| {
+        cells.chunks(4).fold(0usize, |value, cell| {
+            value << 32 | read_u32(cell, 0).unwrap() as usize
+        })
+    };
+    Ok(reg
+        .chunks(pair_size)
+        .map(|pair| {
+            let (address, size) = pair.split_at(address_cells * 4);
+            let address = read_cells(address);
+            address..address + read_cells(size)
+        })
+        .collect())
+}
+
+fn align4(offset: usize) -> usize {
+    offset.next_multiple_of(4)
+}
+
+// Every number in a device tree is big endian
+
+fn read_u32(blob: &[u8], offset: usize) -> Result<u32, FdtError> {
+    let bytes = blob
+        .get(offset..offset + 4)
+        .ok_or(FdtError::Truncated { offset })?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(blob: &[u8], offset: usize) -> Result<usize, FdtError> {
+    let bytes = blob
+        .get(offset..offset + 8)
+        .ok_or(FdtError::Truncated { offset })?;
+    Ok(u64::from_be_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+/// Reads the null terminated string at `offset`.
+fn read_str(blob: &[u8], offset: usize) -> Result<&str, FdtError> {
+    let bytes = blob.get(offset..).ok_or(FdtError::Truncated { offset })?;
+    let len = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or(FdtError::Truncated { offset })?;
+    core::str::from_utf8(&bytes[..len]).map_err(|_| FdtError::Truncated { offset })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIRT: &[u8] = include_bytes!("../../virt.dtb");
+
+    #[test]
+    fn memory_is_found_in_the_virt_tree() {
+        let map = parse(VIRT).unwrap();
+        assert_eq!(map.memory.len(), 1);
+        assert_eq!(map.memory[0], 0x8000_0000..0x8080_0000);
+        // OpenSBI's region in `/reserved-memory`, and the tree itself in the reservation block
+        assert_eq!(
+            map.reserved,
+            [0x8000_0000..0x8004_0000, 0x807F_F000..0x8080_0000]
+        );
+
+        let mut blob = VIRT.to_vec();
+        blob[0] = 0;
+        assert_eq!(parse(&blob), Err(FdtError::BadMagic { magic: 0x0D_FEED }));
+        assert!(matches!(
+            parse(&VIRT[..VIRT.len() / 2]),
+            Err(FdtError::Truncated { .. })
+        ));
+    }
+}
@@ -1,20 +1,74 @@
+pub mod bench;
+mod info;
+mod journal;
+mod pressure;
+mod snapshot;
+
+pub use info::{FrameFlags, FrameInfo, FrameOwner};
+pub use pressure::{
+    alloc_with_reclaim, pressure_stats, register_oom_hook, register_reclaim_hook, set_watermarks,
+    Pressure, Watermarks,
+};
+pub use snapshot::{restore_snapshot, save_snapshot};
+
 use super::{
-    consts::{FRAME_SIZE, KIB},
-    paging::PageEntryLevel,
+    addr::PhysAddr, align_up, consts::FRAME_SIZE, error::MemoryError, paging::PageEntryLevel,
+};
+use core::{
+    mem::{align_of, size_of},
+    ops::Range,
+    ptr, slice,
 };
-use core::{cmp::Ordering, ptr, slice};
+use journal::{Record, Update};
 use spin::mutex::SpinMutex;
 
 const BITMAP_ENTRY_BITS: usize = 64;
 const BITMAP_ENTRY_SIZE_BYTES: usize = BITMAP_ENTRY_BITS * FRAME_SIZE;
 
+/// The number of ranges that can be reserved before `init`.
+const MAX_PENDING_RESERVATIONS: usize = 8;
+
 pub static FRAMES_ALLOCATOR: SpinMutex<BitmapAllocator> = SpinMutex::new(BitmapAllocator::new());
 
+/// How the frames are used.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    pub total_frames: usize,
+    pub used_frames: usize,
+    pub free_frames: usize,
+    /// The most frames that were used at once
+    pub peak_used_frames: usize,
+    /// The used frames that were reserved and never allocated (the bitmap's frames included)
+    pub reserved_frames: usize,
+    /// The number of successful `alloc` calls
+    pub allocs: usize,
+    /// The number of successful `dealloc` calls
+    pub frees: usize,
+    /// The longest run of contiguous free frames
+    pub largest_free_run: usize,
+}
+
+/// A bit for every frame, and a summary level above it with a bit for every bitmap entry that is
+/// full, so searching for a free frame skips 64 full entries (4096 frames) at a time. Right after
+/// them is a `FrameInfo` for every frame, and then the journal of their updates (see `journal`).
 pub struct BitmapAllocator {
     bitmap: *mut u64,
     size: usize,
+    summary: *mut u64,
+    summary_size: usize,
+    info: *mut FrameInfo,
+    num_frames: usize,
+    journal: *mut Record,
     mem_start: *mut u8,
     mem_end: *mut u8,
+    stats: FrameStats,
+    /// The (start, end) ranges reserved before `init`
+    pending_reservations: [(usize, usize); MAX_PENDING_RESERVATIONS],
+    num_pending_reservations: usize,
+    /// The number of metadata writes after which the next update crashes
+    #[cfg(test)]
+    crash_after: Option<usize>,
 }
 
 impl BitmapAllocator {
@@ -22,8 +76,27 @@ impl BitmapAllocator {
         Self {
             bitmap: ptr::null_mut(),
             size: 0,
+            summary: ptr::null_mut(),
+            summary_size: 0,
+            info: ptr::null_mut(),
+            num_frames: 0,
+            journal: ptr::null_mut(),
             mem_start: ptr::null_mut(),
             mem_end: ptr::null_mut(),
+            stats: FrameStats {
+                total_frames: 0,
+                used_frames: 0,
+                free_frames: 0,
+                peak_used_frames: 0,
+                reserved_frames: 0,
+                allocs: 0,
+                frees: 0,
+                largest_free_run: 0,
+            },
+            pending_reservations: [(0, 0); MAX_PENDING_RESERVATIONS],
+            num_pending_reservations: 0,
+            #[cfg(test)]
+            crash_after: None,
         }
     }
 
@@ -31,61 +104,354 @@ impl BitmapAllocator {
         unsafe { slice::from_raw_parts_mut(self.bitmap, self.size) }
     }
 
-    /// Returns (entry_index, entry_bit)
-    fn bitmap_entry_index_bit(&self, address: usize) -> (usize, usize) {
-        (
-            (address - self.mem_start as usize) / BITMAP_ENTRY_SIZE_BYTES,
-            ((address - self.mem_start as usize) / FRAME_SIZE) % BITMAP_ENTRY_BITS,
-        )
+    fn summary_slice(&mut self) -> &'static mut [u64] {
+        unsafe { slice::from_raw_parts_mut(self.summary, self.summary_size) }
+    }
+
+    fn info_slice(&mut self) -> &'static mut [FrameInfo] {
+        unsafe { slice::from_raw_parts_mut(self.info, self.num_frames) }
+    }
+
+    /// Returns the number of bytes of the bitmap, the summary, the frames' info and the journal
+    /// for `mem_size` bytes of frames.
+    pub(super) fn metadata_size(mem_size: usize) -> usize {
+        Self::journal_offset(mem_size) + size_of::<Record>()
+    }
+
+    /// Returns the offset of the journal from the bitmap, for `mem_size` bytes of frames.
+    fn journal_offset(mem_size: usize) -> usize {
+        let num_frames = mem_size / FRAME_SIZE;
+        let size = num_frames / BITMAP_ENTRY_BITS + 1;
+        let summary_size = size / BITMAP_ENTRY_BITS + 1;
+        let info_end =
+            (size + summary_size) * size_of::<u64>() + num_frames * size_of::<FrameInfo>();
+        align_up(info_end, align_of::<Record>())
     }
 
-    fn set_used(&mut self, address: usize) {
-        let (index, bit) = self.bitmap_entry_index_bit(address);
-        let entry = &mut self.bitmap_slice()[index];
+    /// Updates the summary bits of the bitmap entries in `entries`.
+    fn update_summary(&mut self, entries: Range<usize>) {
+        let bitmap = self.bitmap_slice();
+        let summary = self.summary_slice();
+        for index in entries {
+            let bit = 1 << (index % BITMAP_ENTRY_BITS);
+            if bitmap[index] == u64::MAX {
+                summary[index / BITMAP_ENTRY_BITS] |= bit;
+            } else {
+                summary[index / BITMAP_ENTRY_BITS] &= !bit;
+            }
+        }
+    }
+
+    /// Updates the summary bits of the entries that hold `num_frames` frames from the `first`th.
+    fn update_summary_frames(&mut self, first: usize, num_frames: usize) {
+        let last = first + num_frames.max(1) - 1;
+        self.update_summary(first / BITMAP_ENTRY_BITS..last / BITMAP_ENTRY_BITS + 1);
+    }
 
-        *entry |= 1 << bit;
+    /// Returns the indices of the bitmap entries that have a free frame, in order.
+    fn non_full_entries(&mut self) -> impl Iterator<Item = usize> {
+        let size = self.size;
+        self.summary_slice()
+            .iter()
+            .enumerate()
+            .filter(|(_, word)| **word != u64::MAX) // Skip 64 full entries at once
+            .flat_map(|(i, &word)| {
+                (0..BITMAP_ENTRY_BITS)
+                    .filter(move |bit| (word >> bit) & 1 == 0)
+                    .map(move |bit| i * BITMAP_ENTRY_BITS + bit)
+            })
+            .take_while(move |&index| index < size)
     }
 
-    fn set_unused(&mut self, address: usize) {
-        let (index, bit) = self.bitmap_entry_index_bit(address);
-        let entry = &mut self.bitmap_slice()[index];
+    fn frame_index(&self, address: usize) -> usize {
+        (address - self.mem_start as usize) / FRAME_SIZE
+    }
 
-        *entry &= !(1 << bit);
+    fn is_frame_used(&mut self, frame: usize) -> bool {
+        (self.bitmap_slice()[frame / BITMAP_ENTRY_BITS] >> (frame % BITMAP_ENTRY_BITS)) & 1 == 1
+    }
+
+    /// Marks `num_frames` frames from the `first`th frame as used or unused.
+    fn set_frames(&mut self, first: usize, num_frames: usize, used: bool) {
+        let bitmap = self.bitmap_slice();
+        for frame in first..first + num_frames {
+            let bit = 1 << (frame % BITMAP_ENTRY_BITS);
+            if used {
+                bitmap[frame / BITMAP_ENTRY_BITS] |= bit;
+            } else {
+                bitmap[frame / BITMAP_ENTRY_BITS] &= !bit;
+            }
+        }
     }
 
     pub fn init(&mut self, start: *mut u8, end: *mut u8) {
-        self.mem_start = start;
-        self.mem_end = end;
+        let region = start as usize..end as usize;
+        self.init_regions(slice::from_ref(&region));
+    }
 
-        // Calculate the size of the bitmap
-        let num_frames = (end as usize - start as usize) / FRAME_SIZE;
-        self.bitmap = start.cast();
+    /// Manages the frames of several disjoint regions, sorted by address. The bitmap covers
+    /// everything from the first region's start to the last region's end, and the holes between
+    /// the regions are marked as used, so allocations never cross them.
+    pub fn init_regions(&mut self, regions: &[Range<usize>]) {
+        assert!(!regions.is_empty(), "There are no memory regions");
+        assert!(
+            regions.windows(2).all(|pair| pair[0].end <= pair[1].start),
+            "The memory regions must be sorted and disjoint"
+        );
+        let start = regions[0].start;
+        let end = regions[regions.len() - 1].end;
+        self.mem_start = start as *mut u8;
+        self.mem_end = end as *mut u8;
+
+        // Calculate the size of the bitmap, and of the summary, the frames' info and the journal
+        // right after it
+        let num_frames = (end - start) / FRAME_SIZE;
         self.size = num_frames / BITMAP_ENTRY_BITS + 1;
+        self.summary_size = self.size / BITMAP_ENTRY_BITS + 1;
+        self.num_frames = num_frames;
+        let bitmaps_size = Self::metadata_size(end - start);
+
+        // Put them at the start of the first region that can hold them
+        let bitmaps_start = regions
+            .iter()
+            .map(|region| (align_up(region.start, align_of::<u64>()), region.end))
+            .find(|&(start, end)| start + bitmaps_size <= end)
+            .expect("No memory region can hold the bitmap")
+            .0;
+        self.bitmap = bitmaps_start as *mut u64;
+        self.summary = unsafe { self.bitmap.add(self.size) };
+        self.info = unsafe { self.summary.add(self.summary_size) }.cast::<FrameInfo>();
+        self.journal = (bitmaps_start + Self::journal_offset(end - start)) as *mut Record;
 
         println!(
             "Bitmap: {{ Start: {:#p}, End: {:#p}, Size: {:#X} }}",
             self.bitmap, self.mem_end, self.size
         );
         self.bitmap_slice().fill(0); // Clear the bitmap
+        self.info_slice().fill(FrameInfo::default());
+        unsafe { self.journal.write(Record::empty()) };
+
+        // The last entry's frames past the end don't exist
+        self.set_frames(num_frames, self.size * BITMAP_ENTRY_BITS - num_frames, true);
+
+        // The frames between the regions don't exist either
+        let mut hole_frames = 0;
+        for pair in regions.windows(2) {
+            let first = self.frame_index(pair[0].end);
+            let holes = (pair[1].start - start).div_ceil(FRAME_SIZE) - first;
+            hole_frames += (first..first + holes)
+                .filter(|&frame| !self.is_frame_used(frame))
+                .count();
+            self.set_frames(first, holes, true);
+        }
 
-        // Go over the frames required to store the bitmap and mark them as used
-        for frame in 0..(self.size / FRAME_SIZE + 1) {
-            unsafe {
-                self.set_used(self.mem_start.add(frame * FRAME_SIZE) as usize);
+        self.summary_slice().fill(0);
+        self.update_summary(0..self.size);
+
+        self.stats = FrameStats {
+            total_frames: num_frames - hole_frames,
+            ..Default::default()
+        };
+
+        // Reserve the frames of the bitmap and the summary, and the ranges reserved before
+        self.mark_reserved(bitmaps_start, bitmaps_start + bitmaps_size);
+        for i in 0..self.num_pending_reservations {
+            let (start, end) = self.pending_reservations[i];
+            self.mark_reserved(start, end);
+        }
+        self.num_pending_reservations = 0;
+    }
+
+    /// Makes sure the frames in `[start, end)` are never allocated. Before `init`, the range is
+    /// kept until `init` reserves it. The parts of the range outside of memory are ignored.
+    pub fn reserve_range(&mut self, start: usize, end: usize) -> Result<(), MemoryError> {
+        if self.bitmap.is_null() {
+            let slot = self
+                .pending_reservations
+                .get_mut(self.num_pending_reservations)
+                .ok_or(MemoryError::TooManyReservations)?;
+            *slot = (start, end);
+            self.num_pending_reservations += 1;
+            return Ok(());
+        }
+
+        self.mark_reserved(start, end);
+        Ok(())
+    }
+
+    /// Marks every frame that `[start, end)` touches as used.
+    fn mark_reserved(&mut self, start: usize, end: usize) {
+        let mem_start = self.mem_start as usize;
+        let mem_end = mem_start + self.frame_index(self.mem_end as usize) * FRAME_SIZE;
+        let (start, end) = (start.max(mem_start), end.min(mem_end));
+        if start >= end {
+            return; // The range is outside of memory
+        }
+
+        let first = self.frame_index(start);
+        let num_frames = (end - mem_start).div_ceil(FRAME_SIZE) - first;
+        let newly_used = (first..first + num_frames)
+            .filter(|&frame| !self.is_frame_used(frame))
+            .count();
+
+        self.update(Update::Reserve {
+            first,
+            num_frames,
+            newly_used,
+        });
+    }
+
+    /// Returns the info of the frame that holds `address`, or None if it's outside of memory.
+    pub fn info(&mut self, address: usize) -> Option<FrameInfo> {
+        if !(self.mem_start as usize..self.mem_end as usize).contains(&address) {
+            return None;
+        }
+        let frame = self.frame_index(address);
+        self.info_slice().get(frame).copied()
+    }
+
+    /// Records that the `num_frames` allocated frames from `address` are used by `owner`.
+    pub fn set_owner(&mut self, address: usize, num_frames: usize, owner: FrameOwner) {
+        let first = self.frame_index(address);
+        self.update(Update::SetOwner {
+            first,
+            num_frames,
+            owner,
+        });
+    }
+
+    /// Adds a user to the allocation that starts at `address`, which is freed only after every
+    /// user releases it.
+    pub fn share(&mut self, address: usize) {
+        let frame = self.frame_index(address);
+        let info = self.info_slice()[frame];
+        debug_assert!(info.flags.contains(FrameFlags::HEAD), "Not an allocation");
+        self.update(Update::Share {
+            frame,
+            refcount: info.refcount,
+        });
+    }
+
+    /// Removes a user from the allocation of `num_frames` pages of `level` that starts at
+    /// `address`, and frees it if that was the last one. Returns true if it was freed.
+    pub fn release(
+        &mut self,
+        address: usize,
+        num_frames: usize,
+        level: PageEntryLevel,
+    ) -> Result<bool, MemoryError> {
+        let frame = self.frame_index(address);
+        let refcount = self.info_slice()[frame].refcount;
+        let num_frames = num_frames * level.size() / FRAME_SIZE;
+        if refcount == 0 || (refcount == 1 && !self.all_used(frame, num_frames)) {
+            return Err(MemoryError::DoubleFree { addr: address });
+        }
+
+        self.update(Update::Release {
+            frame,
+            num_frames,
+            refcount,
+        });
+        Ok(refcount == 1)
+    }
+
+    /// Returns the number of frames that are owned by `owner`.
+    pub fn owned_frames(&mut self, owner: FrameOwner) -> usize {
+        let (num_frames, info) = (self.num_frames, self.info_slice());
+        (0..num_frames)
+            .filter(|&frame| info[frame].owner == owner && self.is_frame_used(frame))
+            .count()
+    }
+
+    /// Splits the frames into `buckets` runs of about the same length, in order of address, and
+    /// returns the part of every run that is used, from 0 to 1.
+    pub fn usage(&mut self, buckets: usize) -> Vec<f64> {
+        let num_frames = self.num_frames;
+        let buckets = buckets.clamp(1, num_frames.max(1));
+        (0..buckets)
+            .map(|bucket| {
+                let frames = bucket * num_frames / buckets..(bucket + 1) * num_frames / buckets;
+                let len = frames.len();
+                let used = frames.filter(|&frame| self.is_frame_used(frame)).count();
+                used as f64 / len.max(1) as f64
+            })
+            .collect()
+    }
+
+    /// Returns how the frames are used.
+    pub fn stats(&mut self) -> FrameStats {
+        FrameStats {
+            free_frames: self.stats.total_frames - self.stats.used_frames,
+            largest_free_run: self.largest_free_run(),
+            ..self.stats
+        }
+    }
+
+    fn largest_free_run(&mut self) -> usize {
+        let (mut largest, mut run) = (0, 0);
+        for entry in self.bitmap_slice().iter() {
+            match *entry {
+                0 => run += BITMAP_ENTRY_BITS, // A whole free entry
+                u64::MAX => run = 0,
+                entry => {
+                    for bit in 0..BITMAP_ENTRY_BITS {
+                        if (entry >> bit) & 1 == 0 {
+                            run += 1;
+                        } else {
+                            largest = largest.max(run);
+                            run = 0;
+                        }
+                    }
+                }
             }
+            largest = largest.max(run);
         }
+        largest
     }
 
-    pub fn alloc(&mut self, num_frames: usize, level: PageEntryLevel) -> *mut u8 {
-        if num_frames == 1 {
+    pub fn alloc(
+        &mut self,
+        num_frames: usize,
+        level: PageEntryLevel,
+    ) -> Result<*mut u8, MemoryError> {
+        let page = if num_frames == 1 {
             self.alloc_single(level)
         } else {
             self.alloc_contigous(num_frames, level)
+        }?;
+
+        self.update(Update::Alloc {
+            first: self.frame_index(page as usize),
+            num_frames: num_frames * level.size() / FRAME_SIZE,
+        });
+        Ok(page)
+    }
+
+    /// Allocates `num_frames` contigous 4KiB frames, starting at an address aligned to `align`
+    /// (a power of two). Alignments smaller than a frame are rounded up to a frame.
+    pub fn alloc_aligned(
+        &mut self,
+        num_frames: usize,
+        align: usize,
+    ) -> Result<*mut u8, MemoryError> {
+        if !align.is_power_of_two() {
+            return Err(MemoryError::InvalidAlignment { align });
         }
+
+        let page = self.alloc_aligned_frames(num_frames, align.max(FRAME_SIZE))?;
+        self.update(Update::Alloc {
+            first: self.frame_index(page as usize),
+            num_frames,
+        });
+        Ok(page)
     }
 
-    pub fn zero_alloc(&mut self, num_frames: usize, level: PageEntryLevel) -> *mut u8 {
-        let page = self.alloc(num_frames, level); // Allocate a page
+    pub fn zero_alloc(
+        &mut self,
+        num_frames: usize,
+        level: PageEntryLevel,
+    ) -> Result<*mut u8, MemoryError> {
+        let page = self.alloc(num_frames, level)?; // Allocate a page
 
         // Cast the pointer to a big int (u64) to force a double-word store instruction and save time.
         // The required stores are 4096 (bytes) / (64 (bits) / 8)
@@ -96,38 +462,36 @@ impl BitmapAllocator {
             }
         }
 
-        page
+        Ok(page)
     }
 
-    fn alloc_single(&mut self, level: PageEntryLevel) -> *mut u8 {
+    fn alloc_single(&mut self, level: PageEntryLevel) -> Result<*mut u8, MemoryError> {
         match level {
             PageEntryLevel::KiB4 => {
                 // Find an entry in the bitmap that is not completly filled
-                if let Some((index, entry)) = self
-                    .bitmap_slice()
-                    .iter_mut()
-                    .enumerate()
-                    .find(|(_, e)| **e != u64::MAX)
-                {
+                if let Some(index) = self.non_full_entries().next() {
+                    let entry = self.bitmap_slice()[index];
                     let bit_index = entry.trailing_ones() as usize; // Calculate the index of the free bit
 
                     // Calculate the frame's address (entry's first frame's address + free entry's index * FRAME_SIZE)
                     let frame_ptr = ((self.mem_start as usize + index * BITMAP_ENTRY_SIZE_BYTES)
                         + (bit_index * FRAME_SIZE)) as *mut u8;
 
-                    if frame_ptr <= self.mem_end {
-                        *entry |= 1 << bit_index;
-                        return frame_ptr;
+                    if frame_ptr < self.mem_end {
+                        return Ok(frame_ptr);
                     }
                 }
-                panic!("Could not find enough frames to allocate.");
+                Err(MemoryError::OutOfMemory { frames: 1 })
             }
-            PageEntryLevel::MiB2 => self.alloc_contigous(1, PageEntryLevel::MiB2),
-            PageEntryLevel::GiB1 => todo!("Frame allocation by GB."),
+            _ => self.alloc_contigous(1, level),
         }
     }
 
-    fn alloc_contigous(&mut self, num_frames: usize, level: PageEntryLevel) -> *mut u8 {
+    fn alloc_contigous(
+        &mut self,
+        num_frames: usize,
+        level: PageEntryLevel,
+    ) -> Result<*mut u8, MemoryError> {
         // Allocate 4KiB frames
         if let PageEntryLevel::KiB4 = level {
             if num_frames <= BITMAP_ENTRY_BITS {
@@ -137,66 +501,72 @@ impl BitmapAllocator {
             }
         }
 
-        // Allocate 2MiB or 2GiB frames
-        // Calculate the number of bitmap entries needed for the alloc (maximum 1)
-        let num_entries = (((level.size() / FRAME_SIZE) * num_frames) / BITMAP_ENTRY_BITS).max(1);
-        let mut start_index = self.mem_start.align_offset(level.size()) / (BITMAP_ENTRY_BITS * KIB);
-        let mut end_index = start_index + num_entries;
-
-        // Find a big enough group of contigous empty entries
-        while self
-            .bitmap_slice()
-            .get(start_index..end_index)
-            .unwrap()
-            .iter()
-            .any(|entry| entry.count_ones() != 0)
-        {
-            start_index += num_entries;
-            end_index = start_index + num_entries;
-        }
+        // Allocate 2MiB or 1GiB frames, aligned to their size
+        let frames_per_page = level.size() / FRAME_SIZE;
+        self.alloc_aligned_frames(num_frames * frames_per_page, level.size())
+    }
 
-        // Mark the entire group of frames as taken
-        for entry in &mut self.bitmap_slice()[start_index..end_index] {
-            *entry = u64::MAX;
+    /// Allocate `num_frames` contigous 4KiB frames, starting at an address aligned to `align`
+    fn alloc_aligned_frames(
+        &mut self,
+        num_frames: usize,
+        align: usize,
+    ) -> Result<*mut u8, MemoryError> {
+        let mut addr = align_up(self.mem_start as usize, align);
+        while addr + num_frames * FRAME_SIZE <= self.mem_end as usize {
+            let first = self.frame_index(addr);
+            match (first..first + num_frames)
+                .rev()
+                .find(|&frame| self.is_frame_used(frame))
+            {
+                // Skip to the first aligned address after the last used frame
+                Some(used) => {
+                    addr = align_up(self.mem_start as usize + (used + 1) * FRAME_SIZE, align)
+                }
+                None => return Ok(addr as *mut u8),
+            }
         }
 
-        let page_ptr = self.mem_start as usize + start_index * BITMAP_ENTRY_SIZE_BYTES;
-        assert!(page_ptr % level.size() == 0, "Allocation is not aligned.");
-        page_ptr as *mut u8
+        Err(MemoryError::OutOfMemory { frames: num_frames })
     }
 
     /// Allocate 64 or less contigous frames
-    fn intra_alloc_contigous_4k_frames(&mut self, num_frames: usize) -> *mut u8 {
+    fn intra_alloc_contigous_4k_frames(
+        &mut self,
+        num_frames: usize,
+    ) -> Result<*mut u8, MemoryError> {
+        let out_of_memory = MemoryError::OutOfMemory { frames: num_frames };
+
         // Check if we need to allocate a whole entry
         if num_frames == BITMAP_ENTRY_BITS {
             // Find an empty entry
-            let (index, entry) = self
+            let mem_start = self.mem_start as usize;
+            let mem_end = self.mem_end;
+            let index = self
                 .bitmap_slice()
-                .iter_mut()
-                .enumerate()
-                .find(|(_, e)| **e == 0)
-                .unwrap();
-            *entry = u64::MAX; // Mark the entry as filled
+                .iter()
+                .position(|e| *e == 0)
+                .ok_or(out_of_memory)?;
 
-            let page_ptr = (self.mem_start as usize + index * BITMAP_ENTRY_SIZE_BYTES) as *mut u8;
+            let page_ptr = (mem_start + index * BITMAP_ENTRY_SIZE_BYTES) as *mut u8;
 
-            if page_ptr < self.mem_end {
-                return page_ptr;
+            if page_ptr < mem_end {
+                return Ok(page_ptr);
             } else {
-                panic!("Could not find enough contigous frames to allocate.");
+                return Err(out_of_memory);
             }
         }
 
         let mask = u64::MAX << num_frames; // The bitmask of the required number of frames
 
         // Find an entry with enough free frames
-        let free_bits_filter = |(_, e): &(usize, &mut u64)| e.count_zeros() as usize >= num_frames;
-        for (index, entry) in self
-            .bitmap_slice()
-            .iter_mut()
-            .enumerate()
-            .filter(free_bits_filter)
-        {
+        let bitmap = self.bitmap_slice();
+        for index in self.non_full_entries() {
+            let entry = &bitmap[index];
+            if (entry.count_zeros() as usize) < num_frames {
+                continue;
+            }
+
             let bit_index = match (0..(BITMAP_ENTRY_BITS - num_frames))
                 .map(|i| (i, *entry >> i))
                 .find(|(_, e)| e | mask == mask)
@@ -205,23 +575,25 @@ impl BitmapAllocator {
                 None => continue,  // Search in another entry
             };
 
-            *entry |= (!mask).rotate_left(bit_index as u32); // Mark the allocated bits as used
-
             let page_ptr = ((self.mem_start as usize + index * BITMAP_ENTRY_SIZE_BYTES)
                 + bit_index * FRAME_SIZE) as *mut u8; // Calculate the pointer to the found page
 
             if page_ptr < self.mem_end {
-                return page_ptr;
+                return Ok(page_ptr);
             } else {
-                panic!("Could not find enough contigous frames to allocate.");
+                return Err(out_of_memory);
             }
         }
 
-        panic!("Could not find enough contigous frames to allocate.");
+        Err(out_of_memory)
     }
 
     /// Allocate more than 64 contigous frames
-    fn inter_alloc_contigous_4k_frames(&mut self, num_frames: usize) -> *mut u8 {
+    fn inter_alloc_contigous_4k_frames(
+        &mut self,
+        num_frames: usize,
+    ) -> Result<*mut u8, MemoryError> {
+        let out_of_memory = MemoryError::OutOfMemory { frames: num_frames };
         let entries_needed = num_frames / BITMAP_ENTRY_BITS;
         let remaining_bits_needed = (num_frames % BITMAP_ENTRY_BITS) as u32;
 
@@ -231,14 +603,20 @@ impl BitmapAllocator {
             let range = start_index..(start_index + entries_needed);
 
             // Check if any of the next entries are not empty
-            if bitmap.get(range.clone()).unwrap().iter().any(|e| *e != 0) {
+            if bitmap
+                .get(range.clone())
+                .ok_or(out_of_memory)?
+                .iter()
+                .any(|e| *e != 0)
+            {
                 start_index += entries_needed; // If yes, skip to the next batch
                 continue;
             }
 
             // Check if there is enough space for the remaining bits in the entry right after the batch
             if remaining_bits_needed != 0
-                && bitmap.get(range.end).unwrap().leading_zeros() < remaining_bits_needed
+                && bitmap.get(range.end).ok_or(out_of_memory)?.trailing_zeros()
+                    < remaining_bits_needed
             {
                 start_index += range.end + 1;
                 continue;
@@ -248,121 +626,91 @@ impl BitmapAllocator {
                 (self.mem_start as usize + start_index * BITMAP_ENTRY_SIZE_BYTES) as *mut u8;
 
             if page_ptr < self.mem_end {
-                // Mark the allocated entries as used
-                bitmap
-                    .get_mut(range.clone())
-                    .unwrap()
-                    .iter_mut()
-                    .for_each(|e| *e = u64::MAX);
-                if remaining_bits_needed > 0 {
-                    *bitmap.get_mut(range.end).unwrap() |= !(u64::MAX << remaining_bits_needed);
-                }
-
-                return page_ptr;
+                return Ok(page_ptr);
             } else {
-                panic!("Could not find enough contigous frames to allocate.");
+                return Err(out_of_memory);
             }
         }
 
-        panic!("Could not find enough contigous frames to allocate.");
+        Err(out_of_memory)
     }
 
-    pub fn dealloc(&mut self, address: usize, size: usize, level: PageEntryLevel) {
-        if size == 1 {
-            self.dealloc_single(address, level);
-        } else {
-            self.dealloc_contigous(address, size, level);
+    pub fn dealloc(
+        &mut self,
+        address: usize,
+        size: usize,
+        level: PageEntryLevel,
+    ) -> Result<(), MemoryError> {
+        let first = self.frame_index(address);
+        let num_frames = size * level.size() / FRAME_SIZE;
+
+        // Check if we're trying to free an already freed frame
+        if !self.all_used(first, num_frames) {
+            return Err(MemoryError::DoubleFree { addr: address });
         }
+
+        self.update(Update::Free { first, num_frames });
+        Ok(())
     }
 
-    fn dealloc_single(&mut self, address: usize, level: PageEntryLevel) {
-        match level {
-            PageEntryLevel::KiB4 => {
-                let (index, bit) = self.bitmap_entry_index_bit(address);
-                let entry = &mut self.bitmap_slice()[index];
+    /// Returns true if all of the `num_frames` frames from the `first`th one are used.
+    fn all_used(&mut self, first: usize, num_frames: usize) -> bool {
+        (first..first + num_frames).all(|frame| self.is_frame_used(frame))
+    }
+}
 
-                // Check if we're trying to free an already freed frame
-                if (*entry >> bit) & 1 != 1 {
-                    panic!("Double free detected at: {:#p}", address as *const u8);
-                }
+unsafe impl Send for BitmapAllocator {}
+unsafe impl Sync for BitmapAllocator {}
 
-                *entry &= !(1 << bit); // Mark the frame as free
-            }
-            PageEntryLevel::MiB2 => {
-                let (index, _) = self.bitmap_entry_index_bit(address);
-                for entry in &mut self.bitmap_slice()[index..][..512] {
-                    // Check if we're trying to free an already freed entry
-                    assert!(
-                        *entry == u64::MAX,
-                        "Doulbe free of 64 frames found at: {:#p}",
-                        address as *const u8,
-                    );
-
-                    *entry = 0; // Mark the current entry as free
-                }
-            }
-            PageEntryLevel::GiB1 => todo!("Frame allocation by GB."),
-        }
-    }
+/// Allocates `num_frames` contigous frames aligned to `align` from the frames allocator,
+/// reclaiming frames if they run low (see `alloc_with_reclaim`).
+pub fn alloc_frames_aligned(num_frames: usize, align: usize) -> Result<PhysAddr, MemoryError> {
+    alloc_with_reclaim(num_frames, || {
+        FRAMES_ALLOCATOR.lock().alloc_aligned(num_frames, align)
+    })
+    .map(|frames| PhysAddr::new(frames as usize))
+}
 
-    fn dealloc_contigous(&mut self, address: usize, size: usize, level: PageEntryLevel) {
-        let (index, bit) = self.bitmap_entry_index_bit(address);
+/// Frees `num_frames` contigous 4KiB frames from `address` back to the frames allocator.
+pub fn dealloc_frames(address: PhysAddr, num_frames: usize) -> Result<(), MemoryError> {
+    FRAMES_ALLOCATOR
+        .lock()
+        .dealloc(address.as_usize(), num_frames, PageEntryLevel::KiB4)
+}
 
-        match level {
-            PageEntryLevel::KiB4 => {
-                match size.cmp(&BITMAP_ENTRY_BITS) {
-                    Ordering::Less => {
-                        // Deallocate less than 64 frames
-                        let mask = (u64::MAX << size).rotate_left(bit as u32);
-                        self.bitmap_slice()[index] &= mask;
-                    }
-                    Ordering::Equal => {
-                        // Deallocate 64 frames
-                        self.bitmap_slice()[index] = 0;
-                    }
-                    Ordering::Greater => {
-                        // Deallocate more than 64 frames
-                        let entries_needed = size / BITMAP_ENTRY_BITS;
-                        let remaining_bits_needed = (size % BITMAP_ENTRY_BITS) as u32;
-                        let range = index..(index + entries_needed);
-
-                        for entry in &mut self.bitmap_slice()[range.clone()] {
-                            // Check if we're trying to free an already freed entry
-                            assert!(
-                                *entry == u64::MAX,
-                                "Doulbe free of 64 frames found at: {:#p}",
-                                address as *const u8,
-                            );
-                            *entry = 0; // Mark the entry as free
-                        }
+/// Makes sure the frames allocator never hands out the frames in `[start, end)`, which can be
+/// called before `init_frames_allocation` too.
+pub fn reserve_range(start: PhysAddr, end: PhysAddr) -> Result<(), MemoryError> {
+    FRAMES_ALLOCATOR
+        .lock()
+        .reserve_range(start.as_usize(), end.as_usize())
+}
 
-                        if remaining_bits_needed > 0 {
-                            self.bitmap_slice()[range.end] &= u64::MAX << remaining_bits_needed;
-                        }
-                    }
-                }
-            }
-            _ => {
-                // Calculate the number of bitmap entries needed for the dealloc (maximum 1)
-                let num_entries = (((level.size() / FRAME_SIZE) * size) / 64).max(1);
-                let end_index = index + num_entries;
-
-                for entry in &mut self.bitmap_slice()[index..][..end_index] {
-                    // Check if we're trying to free an already freed entry
-                    assert!(
-                        *entry == u64::MAX,
-                        "Doulbe free of 64 frames found at: {:#p}",
-                        address as *const u8,
-                    );
-                    *entry = 0; // Mark the entry as free
-                }
-            }
-        }
-    }
+/// Returns the info of the frame that holds `address`.
+pub fn frame_info(address: PhysAddr) -> Option<FrameInfo> {
+    FRAMES_ALLOCATOR.lock().info(address.as_usize())
 }
 
-unsafe impl Send for BitmapAllocator {}
-unsafe impl Sync for BitmapAllocator {}
+/// Adds a user to the allocation that starts at `address`, see `BitmapAllocator::share`.
+pub fn share_frames(address: PhysAddr) {
+    FRAMES_ALLOCATOR.lock().share(address.as_usize())
+}
+
+/// Returns the number of frames of the frames allocator that are owned by `owner`.
+pub fn owned_frames(owner: FrameOwner) -> usize {
+    FRAMES_ALLOCATOR.lock().owned_frames(owner)
+}
+
+/// Returns how the frames of the frames allocator are used.
+pub fn frame_stats() -> FrameStats {
+    FRAMES_ALLOCATOR.lock().stats()
+}
+
+/// Returns the part of each of `buckets` runs of frames that is used, see
+/// `BitmapAllocator::usage`.
+pub fn frame_usage(buckets: usize) -> Vec<f64> {
+    FRAMES_ALLOCATOR.lock().usage(buckets)
+}
 
 pub fn init_frames_allocation(start: *mut u8, size: usize) {
     let start = unsafe { start.add(start.align_offset(u64::BITS as usize)) };
@@ -370,3 +718,241 @@ pub fn init_frames_allocation(start: *mut u8, size: usize) {
         .lock()
         .init(start, (start as usize + size) as *mut u8);
 }
+
+/// Lets the frames allocator manage several disjoint memory regions, sorted by address.
+pub fn init_frames_allocation_regions(regions: &[Range<usize>]) {
+    FRAMES_ALLOCATOR.lock().init_regions(regions);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: usize = 1 << 30;
+
+    #[test]
+    fn single_frames_skip_full_entries() {
+        let (_bitmap, mut allocator) = bench::detached(8 * 1024 * 1024);
+
+        // Fill every frame, the summary has to report the bitmap as full
+        let mut frames = Vec::new();
+        while let Ok(frame) = allocator.alloc(1, PageEntryLevel::KiB4) {
+            frames.push(frame as usize);
+        }
+        assert!(allocator.non_full_entries().next().is_none());
+
+        // A frame freed in the middle is the next one handed out
+        let stats = allocator.stats();
+        assert_eq!(stats.free_frames, 0);
+        assert_eq!(stats.largest_free_run, 0);
+        assert_eq!(stats.allocs, frames.len());
+
+        let freed = frames[frames.len() / 2];
+        allocator.dealloc(freed, 1, PageEntryLevel::KiB4).unwrap();
+        assert_eq!(
+            allocator.alloc(1, PageEntryLevel::KiB4).unwrap() as usize,
+            freed
+        );
+    }
+
+    #[test]
+    fn reserved_ranges_are_never_allocated() {
+        let mem_size = 8 * 1024 * 1024;
+        let mut bitmap = vec![0u64; BitmapAllocator::metadata_size(mem_size).div_ceil(8)];
+        let start = bitmap.as_mut_ptr().cast::<u8>();
+        let frame = |n: usize| start as usize + n * FRAME_SIZE;
+
+        // Reserve one range before init (overlapping the bitmap) and one after
+        let mut allocator = BitmapAllocator::new();
+        allocator.reserve_range(frame(0), frame(10)).unwrap();
+        allocator.init(start, start.wrapping_add(mem_size));
+        allocator.reserve_range(frame(20) + 1, frame(30)).unwrap();
+        assert_eq!(allocator.stats().reserved_frames, 10 + 10);
+
+        let mut frames = Vec::new();
+        while let Ok(frame) = allocator.alloc(1, PageEntryLevel::KiB4) {
+            frames.push(frame as usize);
+        }
+        assert_eq!(frames.len(), mem_size / FRAME_SIZE - 20);
+        assert!(frames
+            .iter()
+            .all(|&f| !(frame(0)..frame(10)).contains(&f) && !(frame(20)..frame(30)).contains(&f)));
+    }
+
+    #[test]
+    fn allocations_skip_the_holes_between_regions() {
+        let mem_size = 8 * 1024 * 1024;
+        let mut bitmap = vec![0u64; BitmapAllocator::metadata_size(mem_size).div_ceil(8)];
+        let start = bitmap.as_mut_ptr() as usize;
+
+        // 3MiB, a 1MiB hole, and 4MiB
+        let hole = start + 3 * 1024 * 1024..start + 4 * 1024 * 1024;
+        let mut allocator = BitmapAllocator::new();
+        allocator.init_regions(&[start..hole.start, hole.end..start + mem_size]);
+        assert_eq!(allocator.stats().total_frames, 7 * 1024 * 1024 / FRAME_SIZE);
+
+        // Runs of 64 frames can't cross the hole
+        let mut runs = Vec::new();
+        while let Ok(run) = allocator.alloc(BITMAP_ENTRY_BITS, PageEntryLevel::KiB4) {
+            runs.push(run as usize);
+        }
+        assert!(runs.iter().all(|&run| {
+            let run = run..run + BITMAP_ENTRY_SIZE_BYTES;
+            run.end <= hole.start || run.start >= hole.end
+        }));
+
+        let mut frames = Vec::new();
+        while let Ok(frame) = allocator.alloc(1, PageEntryLevel::KiB4) {
+            frames.push(frame as usize);
+        }
+        assert!(frames.iter().all(|frame| !hole.contains(frame)));
+        assert_eq!(allocator.stats().free_frames, 0);
+    }
+
+    #[test]
+    fn frames_info_follows_allocations() {
+        let (_bitmap, mut allocator) = bench::detached(8 * 1024 * 1024);
+        let mem_start = allocator.mem_start as usize;
+
+        // The metadata's frames are reserved
+        let info = allocator.info(mem_start).unwrap();
+        assert_eq!(info.owner, FrameOwner::Reserved);
+        assert!(info.flags.contains(FrameFlags::RESERVED));
+
+        let run = allocator.alloc(5, PageEntryLevel::KiB4).unwrap() as usize;
+        let head = allocator.info(run).unwrap();
+        assert!(head.flags.contains(FrameFlags::HEAD));
+        assert_eq!(
+            (head.owner, head.order, head.refcount),
+            (FrameOwner::Kernel, 3, 1)
+        );
+        assert!(!allocator
+            .info(run + FRAME_SIZE)
+            .unwrap()
+            .flags
+            .contains(FrameFlags::HEAD));
+
+        allocator.set_owner(run, 5, FrameOwner::Slab);
+        assert_eq!(allocator.owned_frames(FrameOwner::Slab), 5);
+
+        allocator.dealloc(run, 5, PageEntryLevel::KiB4).unwrap();
+        assert_eq!(allocator.info(run), Some(FrameInfo::default()));
+        assert_eq!(allocator.owned_frames(FrameOwner::Slab), 0);
+        assert_eq!(allocator.info(allocator.mem_end as usize), None);
+    }
+
+    #[test]
+    fn usage_is_split_into_buckets() {
+        let (_bitmap, mut allocator) = bench::detached(8 * 1024 * 1024);
+        let num_frames = allocator.num_frames;
+
+        // The first half of memory is used
+        allocator.set_frames(0, num_frames / 2, true);
+        assert_eq!(allocator.usage(2), [1.0, 0.0]);
+        assert_eq!(allocator.usage(4), [1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(allocator.usage(0).len(), 1);
+        assert_eq!(allocator.usage(num_frames * 2).len(), num_frames);
+    }
+
+    #[test]
+    fn aligned_frames() {
+        let (_bitmap, mut allocator) = bench::detached(8 * 1024 * 1024);
+
+        // Take a frame so the next free one isn't aligned
+        allocator.alloc(1, PageEntryLevel::KiB4).unwrap();
+        let stack = allocator.alloc_aligned(4, 16 * 1024).unwrap() as usize;
+        assert_eq!(stack % (16 * 1024), 0);
+        assert_eq!(
+            allocator.stats().used_frames,
+            allocator.stats().peak_used_frames
+        );
+
+        allocator.dealloc(stack, 4, PageEntryLevel::KiB4).unwrap();
+        assert_eq!(
+            allocator.alloc_aligned(4, 3 * FRAME_SIZE),
+            Err(MemoryError::InvalidAlignment {
+                align: 3 * FRAME_SIZE
+            })
+        );
+    }
+
+    #[test]
+    fn runs_that_cross_an_entry_are_freed() {
+        let (_bitmap, mut allocator) = bench::detached(8 * 1024 * 1024);
+
+        // Leave two free frames at the end of the first entry, after the allocator's metadata
+        loop {
+            let frame = allocator.alloc(1, PageEntryLevel::KiB4).unwrap() as usize;
+            if allocator.frame_index(frame) == BITMAP_ENTRY_BITS - 3 {
+                break;
+            }
+        }
+        let used = allocator.stats().used_frames;
+        let run = allocator.alloc_aligned(4, FRAME_SIZE).unwrap() as usize;
+        let first = allocator.frame_index(run);
+        assert_eq!(first, BITMAP_ENTRY_BITS - 2);
+
+        allocator.dealloc(run, 4, PageEntryLevel::KiB4).unwrap();
+        assert!((first..first + 4).all(|frame| !allocator.is_frame_used(frame)));
+        assert!((0..first).all(|frame| allocator.is_frame_used(frame)));
+        assert_eq!(allocator.stats().used_frames, used);
+        assert_eq!(
+            allocator.alloc_aligned(4, FRAME_SIZE).unwrap() as usize,
+            run
+        );
+    }
+
+    #[test]
+    fn freeing_a_run_twice_is_a_double_free() {
+        let (_bitmap, mut allocator) = bench::detached(8 * 1024 * 1024);
+
+        for num_frames in [2, 17, BITMAP_ENTRY_BITS - 1] {
+            let run = allocator.alloc(num_frames, PageEntryLevel::KiB4).unwrap() as usize;
+            allocator
+                .dealloc(run, num_frames, PageEntryLevel::KiB4)
+                .unwrap();
+            let stats = allocator.stats();
+            assert_eq!(
+                allocator.dealloc(run, num_frames, PageEntryLevel::KiB4),
+                Err(MemoryError::DoubleFree { addr: run })
+            );
+            assert_eq!(allocator.stats().used_frames, stats.used_frames);
+            assert_eq!(allocator.stats().frees, stats.frees);
+        }
+    }
+
+    #[test]
+    fn gib_frames_are_aligned_and_freed() {
+        let (_bitmap, mut allocator) = bench::detached(3 * GIB);
+
+        let frame = allocator.alloc(1, PageEntryLevel::GiB1).unwrap() as usize;
+        assert_eq!(frame % GIB, 0);
+
+        // The 4KiB frames inside the GiB frame are taken
+        let small = allocator.alloc(1, PageEntryLevel::KiB4).unwrap() as usize;
+        assert!(!(frame..frame + GIB).contains(&small));
+
+        allocator.dealloc(frame, 1, PageEntryLevel::GiB1).unwrap();
+        assert_eq!(
+            allocator.dealloc(frame, 1, PageEntryLevel::GiB1),
+            Err(MemoryError::DoubleFree { addr: frame })
+        );
+        assert_eq!(
+            allocator.alloc(1, PageEntryLevel::GiB1).unwrap() as usize,
+            frame
+        );
+    }
+
+    #[test]
+    fn gib_frames_run_out() {
+        let (_bitmap, mut allocator) = bench::detached(3 * GIB);
+
+        // 3GiB that start after the bitmap only have room for 2 aligned GiB frames
+        allocator.alloc(1, PageEntryLevel::GiB1).unwrap();
+        allocator.alloc(1, PageEntryLevel::GiB1).unwrap();
+        assert!(matches!(
+            allocator.alloc(1, PageEntryLevel::GiB1),
+            Err(MemoryError::OutOfMemory { .. })
+        ));
+    }
+}
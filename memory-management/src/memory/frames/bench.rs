@@ -0,0 +1,70 @@
+//! Times searching for a free frame through the summary against scanning the whole bitmap.
+
+use super::{BitmapAllocator, BITMAP_ENTRY_SIZE_BYTES};
+use crate::memory::{consts::FRAME_SIZE, paging::PageEntryLevel};
+use std::time::{Duration, Instant};
+
+/// How long finding the last free frame took.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchResult {
+    pub mem_size: usize,
+    /// Scanning the bitmap from the start, like the allocator did before the summary
+    pub linear: Duration,
+    pub summary: Duration,
+}
+
+/// Returns an allocator for `mem_size` bytes of frames, and the memory of its bitmap. Only the
+/// bitmap and the frames' info are backed by real memory, so the frames must never be touched.
+pub(super) fn detached(mem_size: usize) -> (Vec<u64>, BitmapAllocator) {
+    let mut bitmap = vec![0u64; BitmapAllocator::metadata_size(mem_size).div_ceil(8)];
+    let mut allocator = BitmapAllocator::new();
+    let start = bitmap.as_mut_ptr().cast::<u8>();
+    allocator.init(start, start.wrapping_add(mem_size));
+    (bitmap, allocator)
+}
+
+/// Fills `mem_size` bytes of frames except the last one, then allocates and frees it `rounds`
+/// times with each search.
+pub fn search(mem_size: usize, rounds: usize) -> SearchResult {
+    let (_bitmap, mut allocator) = detached(mem_size);
+    let num_frames = mem_size / FRAME_SIZE;
+    allocator.set_frames(0, num_frames - 1, true);
+    allocator.update_summary(0..allocator.size);
+    allocator.stats.used_frames = num_frames - 1;
+
+    let started = Instant::now();
+    for _ in 0..rounds {
+        let frame = linear_alloc(&mut allocator);
+        allocator.dealloc(frame, 1, PageEntryLevel::KiB4).unwrap();
+    }
+    let linear = started.elapsed();
+
+    let started = Instant::now();
+    for _ in 0..rounds {
+        let frame = allocator.alloc(1, PageEntryLevel::KiB4).unwrap() as usize;
+        allocator.dealloc(frame, 1, PageEntryLevel::KiB4).unwrap();
+    }
+    let summary = started.elapsed();
+
+    SearchResult {
+        mem_size,
+        linear: linear / rounds as u32,
+        summary: summary / rounds as u32,
+    }
+}
+
+/// Allocates a frame by scanning the bitmap for the first entry that isn't full.
+fn linear_alloc(allocator: &mut BitmapAllocator) -> usize {
+    let mem_start = allocator.mem_start as usize;
+    let (index, entry) = allocator
+        .bitmap_slice()
+        .iter_mut()
+        .enumerate()
+        .find(|(_, e)| **e != u64::MAX)
+        .unwrap();
+    let bit_index = entry.trailing_ones() as usize;
+    *entry |= 1 << bit_index;
+    allocator.update_summary(index..index + 1);
+    allocator.stats.used_frames += 1;
+    mem_start + index * BITMAP_ENTRY_SIZE_BYTES + bit_index * FRAME_SIZE
+}
@@ -0,0 +1,100 @@
+//! What the frames allocator knows about every frame, besides whether it's used.
+
+use core::ops;
+
+/// The flags of a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameFlags(u8);
+
+impl FrameFlags {
+    /// The frame was reserved, and is never allocated or freed
+    pub const RESERVED: Self = Self(1 << 0);
+    /// The frame is the first frame of an allocation
+    pub const HEAD: Self = Self(1 << 1);
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl ops::BitOr for FrameFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Who a frame was allocated for.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FrameOwner {
+    #[default]
+    Free,
+    Reserved,
+    /// Allocated without saying what for
+    Kernel,
+    PageTable,
+    Slab,
+    Heap,
+    /// The memory of an address space
+    AddressSpace,
+}
+
+impl FrameOwner {
+    /// Returns the owner that `repr` is the `u8` of, if there is one.
+    pub(super) fn from_repr(repr: u8) -> Option<Self> {
+        [
+            FrameOwner::Free,
+            FrameOwner::Reserved,
+            FrameOwner::Kernel,
+            FrameOwner::PageTable,
+            FrameOwner::Slab,
+            FrameOwner::Heap,
+            FrameOwner::AddressSpace,
+        ]
+        .into_iter()
+        .find(|&owner| owner as u8 == repr)
+    }
+}
+
+/// The metadata of a frame, one for every frame, indexed by frame number.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameInfo {
+    pub flags: FrameFlags,
+    pub owner: FrameOwner,
+    /// log2 of the number of frames in the allocation, rounded up (only in the head frame)
+    pub order: u8,
+    /// The number of users of the frame
+    pub refcount: u16,
+}
+
+impl FrameInfo {
+    /// Returns the info of the first frame of an allocation of `num_frames` frames.
+    pub(super) fn head(num_frames: usize) -> Self {
+        Self {
+            flags: FrameFlags::HEAD,
+            owner: FrameOwner::Kernel,
+            order: num_frames.next_power_of_two().trailing_zeros() as u8,
+            refcount: 1,
+        }
+    }
+
+    /// Returns the info of the other frames of an allocation.
+    pub(super) fn tail() -> Self {
+        Self {
+            owner: FrameOwner::Kernel,
+            refcount: 1,
+            ..Default::default()
+        }
+    }
+
+    pub(super) fn reserved() -> Self {
+        Self {
+            flags: FrameFlags::RESERVED,
+            owner: FrameOwner::Reserved,
+            ..Default::default()
+        }
+    }
+}
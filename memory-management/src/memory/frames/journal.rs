@@ -0,0 +1,616 @@
+//! A journal of the frames allocator's metadata, so memory that was saved in the middle of an
+//! update (by a snapshot, or because the update crashed) can be brought back to a consistent
+//! state. Before an update touches the bitmap, the summary, the frames' info or the stats, it's
+//! written to a record right after the frames' info, and the record is cleared once it's done.
+//! Applying an update again on top of a part of it gives the same metadata as applying it once, so
+//! `recover` finishes an interrupted update by applying the record again.
+//!
+//! The kernel's heap isn't journaled, since it isn't part of a snapshot either.
+
+use super::{BitmapAllocator, FrameFlags, FrameInfo, FrameOwner, FrameStats, BITMAP_ENTRY_BITS};
+use core::fmt;
+
+/// A change to the frames allocator's metadata.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Update {
+    /// The `num_frames` frames from the `first`th one are allocated
+    Alloc {
+        first: usize,
+        num_frames: usize,
+    },
+    Free {
+        first: usize,
+        num_frames: usize,
+    },
+    /// The frames are reserved, and `newly_used` of them were free
+    Reserve {
+        first: usize,
+        num_frames: usize,
+        newly_used: usize,
+    },
+    SetOwner {
+        first: usize,
+        num_frames: usize,
+        owner: FrameOwner,
+    },
+    /// The allocation at the `frame`th frame, which had `refcount` users, gets another one
+    Share {
+        frame: usize,
+        refcount: u16,
+    },
+    /// The allocation of `num_frames` frames at the `frame`th frame, which had `refcount` users,
+    /// loses one, and is freed if that was the last one
+    Release {
+        frame: usize,
+        num_frames: usize,
+        refcount: u16,
+    },
+}
+
+/// How an update is saved in the journal.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Record {
+    /// Which update it is, or `EMPTY`
+    kind: u64,
+    first: usize,
+    num_frames: usize,
+    /// The frames that `Reserve` newly uses, the owner of `SetOwner`, or the users before `Share`
+    /// and `Release`
+    arg: usize,
+    /// The stats before the update
+    stats: FrameStats,
+}
+
+impl Record {
+    const EMPTY: u64 = 0;
+    const ALLOC: u64 = 1;
+    const FREE: u64 = 2;
+    const RESERVE: u64 = 3;
+    const SET_OWNER: u64 = 4;
+    const SHARE: u64 = 5;
+    const RELEASE: u64 = 6;
+
+    pub(super) const fn empty() -> Self {
+        Self {
+            kind: Self::EMPTY,
+            first: 0,
+            num_frames: 0,
+            arg: 0,
+            stats: FrameStats {
+                total_frames: 0,
+                used_frames: 0,
+                free_frames: 0,
+                peak_used_frames: 0,
+                reserved_frames: 0,
+                allocs: 0,
+                frees: 0,
+                largest_free_run: 0,
+            },
+        }
+    }
+
+    fn new(update: Update, stats: FrameStats) -> Self {
+        let (kind, first, num_frames, arg) = match update {
+            Update::Alloc { first, num_frames } => (Self::ALLOC, first, num_frames, 0),
+            Update::Free { first, num_frames } => (Self::FREE, first, num_frames, 0),
+            Update::Reserve {
+                first,
+                num_frames,
+                newly_used,
+            } => (Self::RESERVE, first, num_frames, newly_used),
+            Update::SetOwner {
+                first,
+                num_frames,
+                owner,
+            } => (Self::SET_OWNER, first, num_frames, owner as usize),
+            Update::Share { frame, refcount } => (Self::SHARE, frame, 1, refcount as usize),
+            Update::Release {
+                frame,
+                num_frames,
+                refcount,
+            } => (Self::RELEASE, frame, num_frames, refcount as usize),
+        };
+        Self {
+            kind,
+            first,
+            num_frames,
+            arg,
+            stats,
+        }
+    }
+
+    /// Returns the update in the record, or None if it's empty. Fails if it isn't an update of
+    /// `total` frames, which only happens if the memory it's in was corrupted.
+    fn update(&self, total: usize) -> Result<Option<Update>, AuditError> {
+        let (first, num_frames) = (self.first, self.num_frames);
+        if self.kind == Self::EMPTY {
+            return Ok(None);
+        }
+        if first.checked_add(num_frames).is_none_or(|end| end > total) {
+            return Err(AuditError::InvalidRecord);
+        }
+        let refcount = u16::try_from(self.arg).map_err(|_| AuditError::InvalidRecord);
+        let update = match self.kind {
+            Self::ALLOC => Update::Alloc { first, num_frames },
+            Self::FREE => Update::Free { first, num_frames },
+            Self::RESERVE => Update::Reserve {
+                first,
+                num_frames,
+                newly_used: self.arg,
+            },
+            Self::SET_OWNER => Update::SetOwner {
+                first,
+                num_frames,
+                owner: u8::try_from(self.arg)
+                    .ok()
+                    .and_then(FrameOwner::from_repr)
+                    .ok_or(AuditError::InvalidRecord)?,
+            },
+            Self::SHARE => Update::Share {
+                frame: first,
+                refcount: refcount?,
+            },
+            Self::RELEASE => Update::Release {
+                frame: first,
+                num_frames,
+                refcount: refcount?,
+            },
+            _ => return Err(AuditError::InvalidRecord),
+        };
+        Ok(Some(update))
+    }
+}
+
+/// Why the frames allocator's metadata isn't consistent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditError {
+    /// The journal holds something that isn't an update of these frames
+    InvalidRecord,
+    /// The journal holds an update that wasn't finished
+    Unfinished,
+    /// The summary bit of a bitmap entry doesn't say whether the entry is full
+    Summary { entry: usize },
+    /// A frame past the end of memory is marked as free
+    PastTheEnd { frame: usize },
+    /// The info of a frame doesn't match the bitmap, or the allocation the frame is in
+    Info { frame: usize },
+    /// The bitmap has another number of used frames than the stats (the holes included)
+    UsedFrames { bitmap: usize, stats: usize },
+    /// Another number of frames is reserved in the frames' info than in the stats
+    ReservedFrames { info: usize, stats: usize },
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::InvalidRecord => write!(f, "The journal holds an invalid update"),
+            AuditError::Unfinished => write!(f, "The journal holds an unfinished update"),
+            AuditError::Summary { entry } => {
+                write!(f, "The summary is wrong about bitmap entry {entry}")
+            }
+            AuditError::PastTheEnd { frame } => {
+                write!(f, "Frame {frame} is past the end of memory, but free")
+            }
+            AuditError::Info { frame } => write!(f, "The info of frame {frame} is wrong"),
+            AuditError::UsedFrames { bitmap, stats } => write!(
+                f,
+                "The bitmap has {bitmap} used frames, but the stats say {stats}"
+            ),
+            AuditError::ReservedFrames { info, stats } => write!(
+                f,
+                "The frames' info has {info} reserved frames, but the stats say {stats}"
+            ),
+        }
+    }
+}
+
+impl BitmapAllocator {
+    fn journal(&mut self) -> &'static mut Record {
+        unsafe { &mut *self.journal }
+    }
+
+    /// Journals `update`, applies it, and clears the journal.
+    pub(super) fn update(&mut self, update: Update) {
+        debug_assert_eq!(
+            self.journal().kind,
+            Record::EMPTY,
+            "An update is unfinished"
+        );
+        let stats = self.stats;
+        self.write();
+        *self.journal() = Record::new(update, stats);
+        self.apply(update, stats);
+        self.write();
+        *self.journal() = Record::empty();
+    }
+
+    /// Finishes the update in the journal, if there is one, and returns true if there was.
+    pub(super) fn recover(&mut self) -> Result<bool, AuditError> {
+        let record = *self.journal();
+        let Some(update) = record.update(self.num_frames)? else {
+            return Ok(false);
+        };
+        self.apply(update, record.stats);
+        self.write();
+        *self.journal() = Record::empty();
+        Ok(true)
+    }
+
+    /// Changes the metadata by `update`, with `before` being the stats before it. Every bit, info
+    /// and counter is set to its value after the update, rather than changed by an amount.
+    fn apply(&mut self, update: Update, before: FrameStats) {
+        match update {
+            Update::Alloc { first, num_frames } => {
+                for frame in first..first + num_frames {
+                    self.write();
+                    self.set_frames(frame, 1, true);
+                }
+                self.set_info(first, FrameInfo::head(num_frames));
+                for frame in first + 1..first + num_frames {
+                    self.set_info(frame, FrameInfo::tail());
+                }
+                self.write();
+                self.update_summary_frames(first, num_frames);
+
+                let used_frames = before.used_frames + num_frames;
+                self.write();
+                self.stats = FrameStats {
+                    used_frames,
+                    peak_used_frames: before.peak_used_frames.max(used_frames),
+                    allocs: before.allocs + 1,
+                    ..before
+                };
+            }
+            Update::Free { first, num_frames } => {
+                for frame in first..first + num_frames {
+                    self.write();
+                    self.set_frames(frame, 1, false);
+                }
+                for frame in first..first + num_frames {
+                    self.set_info(frame, FrameInfo::default());
+                }
+                self.write();
+                self.update_summary_frames(first, num_frames);
+
+                self.write();
+                self.stats = FrameStats {
+                    used_frames: before.used_frames - num_frames,
+                    frees: before.frees + 1,
+                    ..before
+                };
+            }
+            Update::Reserve {
+                first,
+                num_frames,
+                newly_used,
+            } => {
+                // The info goes first, so a free frame with a reserved info is one that still has
+                // to be marked
+                for frame in first..first + num_frames {
+                    if !self.is_frame_used(frame) {
+                        self.set_info(frame, FrameInfo::reserved());
+                        self.write();
+                        self.set_frames(frame, 1, true);
+                    }
+                }
+                self.write();
+                self.update_summary_frames(first, num_frames);
+
+                let used_frames = before.used_frames + newly_used;
+                self.write();
+                self.stats = FrameStats {
+                    used_frames,
+                    peak_used_frames: before.peak_used_frames.max(used_frames),
+                    reserved_frames: before.reserved_frames + newly_used,
+                    ..before
+                };
+            }
+            Update::SetOwner {
+                first,
+                num_frames,
+                owner,
+            } => {
+                for frame in first..first + num_frames {
+                    let info = self.info_slice()[frame];
+                    self.set_info(frame, FrameInfo { owner, ..info });
+                }
+            }
+            Update::Share { frame, refcount } => {
+                let info = self.info_slice()[frame];
+                self.set_info(
+                    frame,
+                    FrameInfo {
+                        refcount: refcount + 1,
+                        ..info
+                    },
+                );
+            }
+            Update::Release {
+                frame,
+                num_frames,
+                refcount,
+            } => {
+                if refcount > 1 {
+                    let info = self.info_slice()[frame];
+                    self.set_info(
+                        frame,
+                        FrameInfo {
+                            refcount: refcount - 1,
+                            ..info
+                        },
+                    );
+                } else {
+                    self.apply(
+                        Update::Free {
+                            first: frame,
+                            num_frames,
+                        },
+                        before,
+                    );
+                }
+            }
+        }
+    }
+
+    fn set_info(&mut self, frame: usize, info: FrameInfo) {
+        self.write();
+        self.info_slice()[frame] = info;
+    }
+
+    /// Is called before every write of the metadata, and crashes if a crash was injected there.
+    fn write(&mut self) {
+        #[cfg(test)]
+        if let Some(writes) = self.crash_after.as_mut() {
+            if *writes == 0 {
+                self.crash_after = None;
+                std::panic::resume_unwind(Box::new(Crash));
+            }
+            *writes -= 1;
+        }
+    }
+
+    /// Checks that the summary matches the bitmap, that the frames' info matches the bitmap and
+    /// the allocations, that the stats match both, and that no update is unfinished.
+    pub fn audit(&mut self) -> Result<(), AuditError> {
+        if self.journal().update(self.num_frames)?.is_some() {
+            return Err(AuditError::Unfinished);
+        }
+
+        let (size, num_frames) = (self.size, self.num_frames);
+        let (bitmap, summary) = (self.bitmap_slice(), self.summary_slice());
+        if let Some(entry) = (0..size).find(|&entry| {
+            let full = (summary[entry / BITMAP_ENTRY_BITS] >> (entry % BITMAP_ENTRY_BITS)) & 1;
+            full != (bitmap[entry] == u64::MAX) as u64
+        }) {
+            return Err(AuditError::Summary { entry });
+        }
+        if let Some(frame) =
+            (num_frames..size * BITMAP_ENTRY_BITS).find(|&frame| !self.is_frame_used(frame))
+        {
+            return Err(AuditError::PastTheEnd { frame });
+        }
+
+        let used = (0..num_frames)
+            .filter(|&frame| self.is_frame_used(frame))
+            .count();
+        let info = self.info_slice();
+        let mut reserved = 0;
+        // The head of the allocation the last frames were in, and how many frames it has so far
+        let mut allocation: Option<(usize, usize)> = None;
+        for frame in 0..=num_frames {
+            let is_used = frame < num_frames && self.is_frame_used(frame);
+            let frame_info = info.get(frame).copied().unwrap_or_default();
+            let is_tail = is_used
+                && frame_info.flags == FrameFlags::default()
+                && frame_info.owner != FrameOwner::Free;
+
+            if let Some((head, len)) = allocation {
+                if is_tail {
+                    allocation = Some((head, len + 1));
+                    if frame_info.refcount != 1 {
+                        return Err(AuditError::Info { frame });
+                    }
+                    continue;
+                }
+                if u32::from(info[head].order) != len.next_power_of_two().trailing_zeros() {
+                    return Err(AuditError::Info { frame: head });
+                }
+                allocation = None;
+            }
+            if frame == num_frames {
+                break;
+            }
+
+            let consistent = if !is_used {
+                frame_info == FrameInfo::default()
+            } else if frame_info.flags.contains(FrameFlags::HEAD) {
+                allocation = Some((frame, 1));
+                frame_info.refcount > 0 && !frame_info.flags.contains(FrameFlags::RESERVED)
+            } else if frame_info.flags.contains(FrameFlags::RESERVED) {
+                reserved += 1;
+                true
+            } else {
+                // A hole between regions, which has no info
+                frame_info == FrameInfo::default()
+            };
+            if !consistent {
+                return Err(AuditError::Info { frame });
+            }
+        }
+
+        let stats = self.stats;
+        let holes = num_frames - stats.total_frames;
+        if used != stats.used_frames + holes {
+            return Err(AuditError::UsedFrames {
+                bitmap: used,
+                stats: stats.used_frames + holes,
+            });
+        }
+        if reserved != stats.reserved_frames {
+            return Err(AuditError::ReservedFrames {
+                info: reserved,
+                stats: stats.reserved_frames,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// What an injected crash unwinds with.
+#[cfg(test)]
+pub(super) struct Crash;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{consts::FRAME_SIZE, paging::PageEntryLevel};
+    use std::panic::{self, AssertUnwindSafe};
+
+    type Op = fn(&mut BitmapAllocator, usize, usize);
+
+    const MEM_SIZE: usize = 8 * 1024 * 1024;
+
+    /// Returns the memory for the metadata of `MEM_SIZE` bytes of frames.
+    fn memory() -> Vec<u64> {
+        vec![0; BitmapAllocator::metadata_size(MEM_SIZE).div_ceil(8)]
+    }
+
+    /// Returns an allocator in `memory` with a run of 9 frames and a run of 3 frames with 2
+    /// users, and the addresses of both runs. It's the same allocator every time, as long as the
+    /// memory is the same (alignments depend on where it is).
+    fn allocator(memory: &mut [u64]) -> (BitmapAllocator, usize, usize) {
+        let mut allocator = BitmapAllocator::new();
+        let start = memory.as_mut_ptr().cast::<u8>();
+        allocator.init(start, start.wrapping_add(MEM_SIZE));
+        let run = allocator.alloc(9, PageEntryLevel::KiB4).unwrap() as usize;
+        let shared = allocator.alloc(3, PageEntryLevel::KiB4).unwrap() as usize;
+        allocator.share(shared);
+        (allocator, run, shared)
+    }
+
+    /// Returns the bitmap, the frames' info and the stats, which don't depend on where the
+    /// memory is.
+    fn state(allocator: &mut BitmapAllocator) -> (Vec<u64>, Vec<FrameInfo>, FrameStats) {
+        (
+            allocator.bitmap_slice().to_vec(),
+            allocator.info_slice().to_vec(),
+            allocator.stats(),
+        )
+    }
+
+    /// Runs `f`, crashing after `writes` writes of the metadata, and returns how many writes
+    /// were left if it didn't crash.
+    fn crash_after(
+        allocator: &mut BitmapAllocator,
+        writes: usize,
+        f: impl FnOnce(&mut BitmapAllocator),
+    ) -> Option<usize> {
+        allocator.crash_after = Some(writes);
+        let crashed = panic::catch_unwind(AssertUnwindSafe(|| f(allocator))).is_err();
+        allocator.crash_after.take().filter(|_| !crashed)
+    }
+
+    #[test]
+    fn crashed_updates_are_finished_by_recovery() {
+        let ops: [Op; 8] = [
+            |allocator, _, _| {
+                allocator.alloc(70, PageEntryLevel::KiB4).unwrap();
+            },
+            |allocator, _, _| {
+                allocator.alloc_aligned(5, 8 * FRAME_SIZE).unwrap();
+            },
+            |allocator, run, _| allocator.dealloc(run, 9, PageEntryLevel::KiB4).unwrap(),
+            |allocator, run, _| {
+                // Half of the range is already used
+                let start = run + 5 * FRAME_SIZE;
+                allocator
+                    .reserve_range(start, start + 8 * FRAME_SIZE)
+                    .unwrap();
+            },
+            |allocator, run, _| allocator.set_owner(run, 9, FrameOwner::Slab),
+            |allocator, _, shared| allocator.share(shared),
+            |allocator, _, shared| {
+                allocator.release(shared, 3, PageEntryLevel::KiB4).unwrap();
+            },
+            |allocator, run, _| {
+                allocator.release(run, 9, PageEntryLevel::KiB4).unwrap();
+            },
+        ];
+
+        // xorshift64, so the crash points are random but the same in every run
+        let mut seed = 0x5EED_u64;
+        let mut random = |below: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize % below
+        };
+        let mut memory = memory();
+        for op in ops {
+            let (mut allocator, run, shared) = self::allocator(&mut memory);
+            let before = state(&mut allocator);
+            let left = crash_after(&mut allocator, usize::MAX, |allocator| {
+                op(allocator, run, shared)
+            });
+            let writes = usize::MAX - left.unwrap();
+            let after = state(&mut allocator);
+
+            for _ in 0..20 {
+                let (mut allocator, run, shared) = self::allocator(&mut memory);
+                let crashed = crash_after(&mut allocator, random(writes), |allocator| {
+                    op(allocator, run, shared)
+                });
+                assert!(crashed.is_none());
+
+                // Recovery itself can crash too, and is finished by the next one
+                let unfinished = allocator.audit() == Err(AuditError::Unfinished);
+                crash_after(&mut allocator, random(writes), |allocator| {
+                    allocator.recover().unwrap();
+                });
+                allocator.recover().unwrap();
+                allocator.audit().unwrap();
+
+                // The update is done once it's in the journal, and not at all before
+                let expected = if unfinished { &after } else { &before };
+                assert_eq!(&state(&mut allocator), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn audits_find_inconsistent_metadata() {
+        let mut memory = memory();
+        let (mut allocator, run, _) = allocator(&mut memory);
+        allocator.audit().unwrap();
+        let first = allocator.frame_index(run);
+        let free = first + 9 + 3;
+
+        allocator.set_frames(free, 1, true);
+        assert!(matches!(
+            allocator.audit(),
+            Err(AuditError::UsedFrames { .. })
+        ));
+        allocator.set_frames(free, 1, false);
+        allocator.info_slice()[free] = FrameInfo::tail();
+        assert_eq!(allocator.audit(), Err(AuditError::Info { frame: free }));
+        allocator.info_slice()[free] = FrameInfo::default();
+        allocator.info_slice()[first].order = 0;
+        assert_eq!(allocator.audit(), Err(AuditError::Info { frame: first }));
+        allocator.info_slice()[first] = FrameInfo::head(9);
+        allocator.summary_slice()[0] |= 1;
+        assert_eq!(allocator.audit(), Err(AuditError::Summary { entry: 0 }));
+        allocator.summary_slice()[0] &= !1;
+        allocator.audit().unwrap();
+
+        *allocator.journal() = Record::new(
+            Update::Free {
+                first: allocator.num_frames,
+                num_frames: 1,
+            },
+            allocator.stats,
+        );
+        assert_eq!(allocator.audit(), Err(AuditError::InvalidRecord));
+        assert_eq!(allocator.recover(), Err(AuditError::InvalidRecord));
+    }
+}
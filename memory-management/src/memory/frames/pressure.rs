@@ -0,0 +1,241 @@
+//! Low memory watermarks, and the hooks that let higher layers react to them. When an allocation
+//! leaves fewer free frames than the low watermark, the reclaim hooks (like swap, or a cache) are
+//! asked to free enough frames to get back above it. When an allocation fails, they are asked for
+//! the missing frames and it's retried, and if they can't free any, the OOM hooks are told and the
+//! caller gets `MemoryError::OutOfMemory` to handle.
+//!
+//! The hooks run without the frames allocator's lock held, so they can free frames.
+
+use super::FRAMES_ALLOCATOR;
+use crate::memory::error::MemoryError;
+use spin::Mutex;
+
+static WATERMARKS: Mutex<Watermarks> = Mutex::new(Watermarks { low: 0, min: 0 });
+static RECLAIM_HOOKS: Mutex<Vec<(&'static str, ReclaimHook)>> = Mutex::new(Vec::new());
+static OOM_HOOKS: Mutex<Vec<(&'static str, OomHook)>> = Mutex::new(Vec::new());
+static STATS: Mutex<PressureStats> = Mutex::new(PressureStats {
+    reclaims: 0,
+    reclaimed_frames: 0,
+    ooms: 0,
+});
+
+/// The numbers of free frames below which frames are reclaimed. Both are 0 by default, so frames
+/// are only reclaimed when an allocation fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Watermarks {
+    pub low: usize,
+    /// Below it, the hooks are asked with `Pressure::Min`, and should try harder
+    pub min: usize,
+}
+
+/// How short of frames the allocator is when the reclaim hooks are called.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pressure {
+    /// The free frames dropped below the low watermark
+    Low,
+    /// The free frames dropped below the min watermark, or an allocation failed
+    Min,
+}
+
+/// Frees up to `wanted` frames, and returns how many it freed.
+pub type ReclaimHook = fn(pressure: Pressure, wanted: usize) -> usize;
+
+/// Is told that `frames` frames couldn't be allocated, even after reclaiming.
+pub type OomHook = fn(frames: usize);
+
+/// How often frames were short.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PressureStats {
+    /// The number of times the reclaim hooks were called
+    pub reclaims: usize,
+    pub reclaimed_frames: usize,
+    /// The number of allocations that failed after reclaiming
+    pub ooms: usize,
+}
+
+/// Replaces the watermarks, unless the min watermark is above the low one.
+pub fn set_watermarks(watermarks: Watermarks) -> Result<(), MemoryError> {
+    if watermarks.min > watermarks.low {
+        return Err(MemoryError::InvalidWatermarks {
+            low: watermarks.low,
+            min: watermarks.min,
+        });
+    }
+    *WATERMARKS.lock() = watermarks;
+    Ok(())
+}
+
+pub fn watermarks() -> Watermarks {
+    *WATERMARKS.lock()
+}
+
+/// Registers `hook` to free frames when they run low. The hooks are called in the order they were
+/// registered, and registering another hook with the same `name` replaces it.
+pub fn register_reclaim_hook(name: &'static str, hook: ReclaimHook) {
+    register(&mut RECLAIM_HOOKS.lock(), name, hook);
+}
+
+/// Registers `hook` to be told about allocations that failed. Registering another hook with the
+/// same `name` replaces it.
+pub fn register_oom_hook(name: &'static str, hook: OomHook) {
+    register(&mut OOM_HOOKS.lock(), name, hook);
+}
+
+fn register<T>(hooks: &mut Vec<(&'static str, T)>, name: &'static str, hook: T) {
+    match hooks.iter_mut().find(|(registered, _)| *registered == name) {
+        Some(registered) => registered.1 = hook,
+        None => hooks.push((name, hook)),
+    }
+}
+
+/// Returns how often frames were short.
+pub fn pressure_stats() -> PressureStats {
+    *STATS.lock()
+}
+
+/// Allocates `num_frames` frames with `alloc`, which locks the frames allocator itself. If there
+/// aren't enough free frames, the reclaim hooks are asked for the missing ones and it's retried
+/// for as long as they free some; after that the OOM hooks are told, and the error is returned.
+/// After it succeeds, frames are reclaimed if the free frames dropped below the low watermark.
+pub fn alloc_with_reclaim<T>(
+    num_frames: usize,
+    mut alloc: impl FnMut() -> Result<T, MemoryError>,
+) -> Result<T, MemoryError> {
+    loop {
+        match alloc() {
+            Err(error @ MemoryError::OutOfMemory { .. }) => {
+                // With enough free frames, the allocation failed because they are fragmented,
+                // which freeing more of them is unlikely to fix
+                let free = free_frames();
+                if free >= num_frames || reclaim(Pressure::Min, num_frames - free) == 0 {
+                    out_of_memory(num_frames);
+                    return Err(error);
+                }
+            }
+            Err(error) => return Err(error),
+            Ok(frames) => {
+                balance();
+                return Ok(frames);
+            }
+        }
+    }
+}
+
+/// Reclaims frames if the free frames dropped below the low watermark, until they are above it.
+fn balance() {
+    let watermarks = watermarks();
+    let free = free_frames();
+    if free < watermarks.low {
+        let pressure = if free < watermarks.min {
+            Pressure::Min
+        } else {
+            Pressure::Low
+        };
+        reclaim(pressure, watermarks.low - free);
+    }
+}
+
+/// Asks the reclaim hooks for `wanted` frames, and returns how many they freed.
+fn reclaim(pressure: Pressure, wanted: usize) -> usize {
+    // The hooks may register others, or allocate
+    let hooks: Vec<ReclaimHook> = RECLAIM_HOOKS.lock().iter().map(|&(_, hook)| hook).collect();
+    let mut freed = 0;
+    for hook in hooks {
+        if freed >= wanted {
+            break;
+        }
+        freed += hook(pressure, wanted - freed);
+    }
+
+    let mut stats = STATS.lock();
+    stats.reclaims += 1;
+    stats.reclaimed_frames += freed;
+    freed
+}
+
+fn out_of_memory(num_frames: usize) {
+    STATS.lock().ooms += 1;
+    let hooks: Vec<OomHook> = OOM_HOOKS.lock().iter().map(|&(_, hook)| hook).collect();
+    for hook in hooks {
+        hook(num_frames);
+    }
+}
+
+fn free_frames() -> usize {
+    let frames = FRAMES_ALLOCATOR.lock();
+    frames.stats.total_frames - frames.stats.used_frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{
+        addr::PhysAddr, alloc_frames_aligned, consts::FRAME_SIZE, dealloc_frames, tests::frames,
+    };
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static CACHE: Mutex<Vec<PhysAddr>> = Mutex::new(Vec::new());
+    static OOMS: AtomicUsize = AtomicUsize::new(0);
+
+    fn shrink_cache(_pressure: Pressure, wanted: usize) -> usize {
+        let mut cache = CACHE.lock();
+        let freed = wanted.min(cache.len());
+        let start = cache.len() - freed;
+        for frame in cache.drain(start..) {
+            dealloc_frames(frame, 1).unwrap();
+        }
+        freed
+    }
+
+    fn count_oom(_frames: usize) {
+        OOMS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn low_memory_reclaims_frames_before_running_out() {
+        let _frames = frames();
+        assert_eq!(
+            set_watermarks(Watermarks { low: 1, min: 2 }),
+            Err(MemoryError::InvalidWatermarks { low: 1, min: 2 })
+        );
+        register_reclaim_hook("test cache", shrink_cache);
+        register_oom_hook("test", count_oom);
+
+        let free = free_frames();
+        set_watermarks(Watermarks {
+            low: free / 2,
+            min: free / 4,
+        })
+        .unwrap();
+        for _ in 0..free / 4 {
+            let frame = alloc_frames_aligned(1, FRAME_SIZE).unwrap();
+            CACHE.lock().push(frame);
+        }
+
+        // The cache shrinks as soon as the free frames drop below the low watermark
+        let mut frames = Vec::new();
+        while CACHE.lock().len() == free / 4 {
+            frames.push(alloc_frames_aligned(1, FRAME_SIZE).unwrap());
+        }
+        assert!(free_frames() >= free / 2);
+        assert!(pressure_stats().reclaims > 0);
+
+        // Once the cache is empty, running out is reported instead of reclaimed
+        let ooms = OOMS.load(Ordering::Relaxed);
+        let error = loop {
+            match alloc_frames_aligned(1, FRAME_SIZE) {
+                Ok(frame) => frames.push(frame),
+                Err(error) => break error,
+            }
+        };
+        assert_eq!(error, MemoryError::OutOfMemory { frames: 1 });
+        assert!(CACHE.lock().is_empty());
+        assert_eq!(OOMS.load(Ordering::Relaxed), ooms + 1);
+        assert!(frames.len() >= free);
+
+        set_watermarks(Watermarks::default()).unwrap();
+        for frame in frames {
+            dealloc_frames(frame, 1).unwrap();
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! Snapshots of the whole simulated memory and the frames allocator's state, so an experiment can
+//! be saved and resumed later, in another run. Everything the frames allocator tracks lives in the
+//! memory itself (the bitmap, the summary, the frames' info and the journal), so a snapshot is the
+//! memory's bytes, the allocator's fields as offsets into it, the paging mode and a root table. A
+//! snapshot taken in the middle of an update of the allocator is restored with the update finished.
+//!
+//! The kernel's heap and the address spaces aren't part of a snapshot: their frames stay allocated
+//! when it's restored, but nothing refers to them.
+
+use super::{
+    journal::{AuditError, Record},
+    BitmapAllocator, FrameInfo, FrameOwner, FrameStats, BITMAP_ENTRY_BITS, FRAMES_ALLOCATOR,
+};
+use crate::memory::{
+    consts::FRAME_SIZE,
+    fault,
+    paging::{self, PageTable, PagingMode},
+    rmap::REVERSE_MAP,
+    swap, tlb,
+};
+use core::{
+    fmt,
+    mem::{align_of, size_of},
+    slice,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 8] = b"RISCYSNP";
+const VERSION: u64 = 2;
+/// Saved in place of the root table's offset when there is no root table
+const NO_ROOT: u64 = u64::MAX;
+
+/// Why a snapshot couldn't be saved or restored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnapshotError {
+    Io(io::ErrorKind),
+    /// The file isn't a snapshot, or one of another version
+    NotASnapshot,
+    /// The frames allocator wasn't initialized, so there is nothing to save
+    Uninitialized,
+    /// The snapshot is of memory of another size than the memory it's restored into
+    SizeMismatch {
+        snapshot: usize,
+        memory: usize,
+    },
+    /// Pages are mapped or swapped out in the memory that the snapshot would replace
+    InUse,
+    /// The frames allocator's metadata in the snapshot is inconsistent, even after finishing the
+    /// update it was taken in
+    Inconsistent(AuditError),
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Io(error.kind())
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(kind) => write!(f, "Failed to access the snapshot: {kind}"),
+            SnapshotError::NotASnapshot => write!(f, "The file is not a snapshot of this version"),
+            SnapshotError::Uninitialized => {
+                write!(f, "The frames allocator has no memory to snapshot")
+            }
+            SnapshotError::SizeMismatch { snapshot, memory } => write!(
+                f,
+                "The snapshot is of {snapshot:#X} bytes of memory, not {memory:#X}"
+            ),
+            SnapshotError::InUse => {
+                write!(
+                    f,
+                    "Pages are still mapped in the memory the snapshot would replace"
+                )
+            }
+            SnapshotError::Inconsistent(error) => {
+                write!(
+                    f,
+                    "The snapshot's frames allocator is inconsistent: {error}"
+                )
+            }
+        }
+    }
+}
+
+/// Writes the memory, the frames allocator's state and the paging mode to `path`, along with
+/// `root`, the table that `restore_snapshot` returns.
+pub fn save_snapshot(
+    path: impl AsRef<Path>,
+    root: Option<&PageTable>,
+) -> Result<(), SnapshotError> {
+    let allocator = FRAMES_ALLOCATOR.lock();
+    if allocator.bitmap.is_null() {
+        return Err(SnapshotError::Uninitialized);
+    }
+    let start = allocator.mem_start as usize;
+    let offset = |ptr: usize| (ptr - start) as u64;
+    let stats = &allocator.stats;
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+    let root = root.map_or(NO_ROOT, |root| offset(root as *const PageTable as usize));
+    for value in [
+        VERSION,
+        (allocator.mem_end as usize - start) as u64,
+        start as u64,
+        paging::mode().levels() as u64,
+        root,
+        offset(allocator.bitmap as usize),
+        allocator.size as u64,
+        offset(allocator.summary as usize),
+        allocator.summary_size as u64,
+        offset(allocator.info as usize),
+        allocator.num_frames as u64,
+        offset(allocator.journal as usize),
+        stats.total_frames as u64,
+        stats.used_frames as u64,
+        stats.free_frames as u64,
+        stats.peak_used_frames as u64,
+        stats.reserved_frames as u64,
+        stats.allocs as u64,
+        stats.frees as u64,
+        stats.largest_free_run as u64,
+    ] {
+        file.write_all(&value.to_le_bytes())?;
+    }
+    file.write_all(allocator.memory())?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Replaces the `size` bytes of memory at `start` and the frames allocator's state with the
+/// snapshot at `path`, switches to the snapshot's paging mode, and returns the root table that
+/// was saved with it.
+///
+/// If the memory is mapped at another address than the snapshot's, the page tables are moved
+/// along with it. Superpages that map the memory itself are only correct if it moved by a
+/// multiple of their size.
+///
+/// A snapshot taken in the middle of an update of the frames allocator is restored with the update
+/// finished, and one whose allocator isn't consistent after that is refused (`Inconsistent`).
+///
+/// The reverse map and the swap point into the current memory, so a snapshot can't be restored
+/// while pages are mapped in that memory or swapped out (`InUse`). The pages of the restored
+/// tables aren't in the reverse map, so they are never swapped out, and the zero frame is
+/// allocated again on its next use.
+pub fn restore_snapshot(
+    path: impl AsRef<Path>,
+    start: *mut u8,
+    size: usize,
+) -> Result<Option<*mut PageTable>, SnapshotError> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)?;
+    let mut next = || -> Result<usize, SnapshotError> {
+        let mut bytes = [0; 8];
+        file.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes) as usize)
+    };
+    if &magic != MAGIC || next()? != VERSION as usize {
+        return Err(SnapshotError::NotASnapshot);
+    }
+    let snapshot_size = next()?;
+    if snapshot_size != size {
+        return Err(SnapshotError::SizeMismatch {
+            snapshot: snapshot_size,
+            memory: size,
+        });
+    }
+    let snapshot_base = next()?;
+    let mode = match next()? {
+        3 => PagingMode::Sv39,
+        4 => PagingMode::Sv48,
+        5 => PagingMode::Sv57,
+        _ => return Err(SnapshotError::NotASnapshot),
+    };
+    let root = next()?;
+
+    let (bitmap, bitmap_size) = (next()?, next()?);
+    let (summary, summary_size) = (next()?, next()?);
+    let (info, num_frames) = (next()?, next()?);
+    let journal = next()?;
+    let stats = FrameStats {
+        total_frames: next()?,
+        used_frames: next()?,
+        free_frames: next()?,
+        peak_used_frames: next()?,
+        reserved_frames: next()?,
+        allocs: next()?,
+        frees: next()?,
+        largest_free_run: next()?,
+    };
+
+    // Everything the allocator points to has to be inside the memory, with the sizes `init` gives
+    let inside = |offset: usize, len: usize, count: usize, align: usize| {
+        offset.is_multiple_of(align)
+            && len
+                .checked_mul(count)
+                .and_then(|bytes| bytes.checked_add(offset))
+                .is_some_and(|end| end <= size)
+    };
+    if num_frames != size / FRAME_SIZE
+        || bitmap_size != num_frames / BITMAP_ENTRY_BITS + 1
+        || summary_size != bitmap_size / BITMAP_ENTRY_BITS + 1
+        || !inside(bitmap, bitmap_size, size_of::<u64>(), align_of::<u64>())
+        || !inside(summary, summary_size, size_of::<u64>(), align_of::<u64>())
+        || !inside(
+            info,
+            num_frames,
+            size_of::<FrameInfo>(),
+            align_of::<FrameInfo>(),
+        )
+        || !inside(journal, 1, size_of::<Record>(), align_of::<Record>())
+        || stats.used_frames > stats.total_frames
+        || stats.total_frames > num_frames
+    {
+        return Err(SnapshotError::NotASnapshot);
+    }
+    if in_use(start as usize, size) {
+        return Err(SnapshotError::InUse);
+    }
+
+    let at = |base: usize| {
+        let mut allocator = BitmapAllocator::new();
+        allocator.mem_start = base as *mut u8;
+        allocator.mem_end = (base + size) as *mut u8;
+        allocator.bitmap = (base + bitmap) as *mut u64;
+        allocator.size = bitmap_size;
+        allocator.summary = (base + summary) as *mut u64;
+        allocator.summary_size = summary_size;
+        allocator.info = (base + info) as *mut FrameInfo;
+        allocator.num_frames = num_frames;
+        allocator.journal = (base + journal) as *mut Record;
+        allocator.stats = stats;
+        allocator
+    };
+
+    // The old state is only replaced once the whole snapshot was read, and its allocator finished
+    // the update it was taken in and was audited
+    let mut words = vec![0u64; size.div_ceil(size_of::<u64>())];
+    let memory = unsafe { slice::from_raw_parts_mut(words.as_mut_ptr().cast::<u8>(), size) };
+    file.read_exact(memory)?;
+    let mut read = at(memory.as_mut_ptr() as usize);
+    read.recover()
+        .and_then(|_| read.audit())
+        .map_err(SnapshotError::Inconsistent)?;
+
+    let base = start as usize;
+    let mut allocator = at(base);
+    allocator.stats = read.stats;
+    {
+        let mut frames = FRAMES_ALLOCATOR.lock();
+        unsafe { slice::from_raw_parts_mut(start, size) }.copy_from_slice(memory);
+        *frames = allocator;
+    }
+    // The zero frame of the old memory may be free in the restored one
+    fault::forget_zero_frame();
+    paging::set_mode(mode);
+    relocate_tables(snapshot_base, base, size);
+    tlb::sfence_vma(None, None);
+
+    Ok((root as u64 != NO_ROOT).then(|| (base + root) as *mut PageTable))
+}
+
+/// Returns true if pages are swapped out, or mapped by entries or to frames in the `size` bytes of
+/// memory at `start`, which the restored memory would replace.
+fn in_use(start: usize, size: usize) -> bool {
+    let memory = start..start + size;
+    REVERSE_MAP
+        .lock()
+        .iter()
+        .any(|mapping| memory.contains(&mapping.entry) || memory.contains(&mapping.phys))
+        || swap::swap_stats().used_slots > 0
+}
+
+/// Moves the entries of every page table that point into the memory from `old_base` to `base`.
+fn relocate_tables(old_base: usize, base: usize, size: usize) {
+    if old_base == base {
+        return;
+    }
+    let mut frames = FRAMES_ALLOCATOR.lock();
+    let tables: Vec<usize> = (base..base + size)
+        .step_by(FRAME_SIZE)
+        .filter(|&frame| {
+            frames
+                .info(frame)
+                .is_some_and(|info| info.owner == FrameOwner::PageTable)
+        })
+        .collect();
+    drop(frames);
+
+    for table in tables {
+        let table = unsafe { &mut *(table as *mut PageTable) };
+        for entry in table.entries.iter_mut().filter(|entry| entry.is_valid()) {
+            let ppn = entry.get_ppn();
+            if (old_base..old_base + size).contains(&ppn) {
+                entry.set_ppn(ppn - old_base + base);
+            }
+        }
+    }
+}
+
+impl BitmapAllocator {
+    /// Returns the bytes of the memory the allocator manages.
+    fn memory(&self) -> &[u8] {
+        let size = self.mem_end as usize - self.mem_start as usize;
+        unsafe { slice::from_raw_parts(self.mem_start, size) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{
+        addr::{PhysAddr, VirtAddr},
+        alloc_frames_aligned, dealloc_frames, frame_info, frame_stats,
+        paging::{PageEntryFlags, PageEntryLevel},
+        tests::frames,
+    };
+    use std::{env, panic};
+
+    #[test]
+    fn snapshots_restore_the_memory_and_the_frames() {
+        let _frames = frames();
+        let path = env::temp_dir().join(format!("riscy-snapshot-{}.bin", std::process::id()));
+        let root = unsafe { &mut *paging::create_root_table().unwrap() };
+        let frame = alloc_frames_aligned(1, FRAME_SIZE).unwrap();
+        let virt = VirtAddr::new(0x4000_0000);
+        paging::map(
+            root,
+            frame,
+            virt,
+            &PageEntryFlags::READ_WRITE,
+            PageEntryLevel::KiB4,
+        )
+        .unwrap();
+        unsafe { *(frame.as_usize() as *mut u64) = 0x5EED };
+        save_snapshot(&path, Some(root)).unwrap();
+        let used = frame_stats().used_frames;
+        let (start, size) = {
+            let frames = FRAMES_ALLOCATOR.lock();
+            let start = frames.mem_start;
+            (start, frames.mem_end as usize - start as usize)
+        };
+        assert_eq!(
+            restore_snapshot(&path, start, size),
+            Err(SnapshotError::InUse)
+        );
+
+        // Change everything the snapshot holds
+        unsafe { *(frame.as_usize() as *mut u64) = 0 };
+        paging::unmap_range(root, virt.as_usize(), FRAME_SIZE).unwrap();
+        dealloc_frames(frame, 1).unwrap();
+        alloc_frames_aligned(4, FRAME_SIZE).unwrap();
+        assert_ne!(frame_stats().used_frames, used);
+
+        let restored = restore_snapshot(&path, start, size).unwrap().unwrap();
+        assert_eq!(restored, root as *mut PageTable);
+        assert_eq!(frame_stats().used_frames, used);
+        let translation = paging::translate(root, virt.as_usize()).unwrap();
+        assert_eq!(translation.phys, frame.as_usize());
+        assert_eq!(unsafe { *(frame.as_usize() as *const u64) }, 0x5EED);
+        let zero = fault::zero_frame().unwrap();
+        assert!(frame_info(PhysAddr::new(zero)).is_some_and(|info| info.refcount > 0));
+        assert_eq!(
+            restore_snapshot(&path, start, size / 2),
+            Err(SnapshotError::SizeMismatch {
+                snapshot: size,
+                memory: size / 2
+            })
+        );
+
+        // A bitmap that isn't inside the memory is refused
+        let mut corrupt = std::fs::read(&path).unwrap();
+        corrupt[48..56].copy_from_slice(&(size as u64).to_le_bytes());
+        std::fs::write(&path, corrupt).unwrap();
+        assert_eq!(
+            restore_snapshot(&path, start, size),
+            Err(SnapshotError::NotASnapshot)
+        );
+
+        paging::unmap_range(root, virt.as_usize(), FRAME_SIZE).unwrap();
+        dealloc_frames(frame, 1).unwrap();
+        dealloc_frames(PhysAddr::from_ptr(root), 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn snapshots_taken_mid_update_finish_it() {
+        let _frames = frames();
+        let path = env::temp_dir().join(format!("riscy-mid-update-{}.bin", std::process::id()));
+        let stats = frame_stats();
+        let (start, size) = {
+            let frames = FRAMES_ALLOCATOR.lock();
+            let start = frames.mem_start;
+            (start, frames.mem_end as usize - start as usize)
+        };
+
+        // Crash in the middle of freeing a run of frames, and save the memory like that
+        let run = alloc_frames_aligned(6, FRAME_SIZE).unwrap();
+        FRAMES_ALLOCATOR.lock().crash_after = Some(4);
+        let crashed = panic::catch_unwind(|| dealloc_frames(run, 6));
+        assert!(crashed.is_err());
+        assert_eq!(FRAMES_ALLOCATOR.lock().audit(), Err(AuditError::Unfinished));
+        save_snapshot(&path, None).unwrap();
+
+        assert_eq!(restore_snapshot(&path, start, size), Ok(None));
+        FRAMES_ALLOCATOR.lock().audit().unwrap();
+        assert_eq!(frame_info(run), Some(FrameInfo::default()));
+        assert_eq!(frame_stats().used_frames, stats.used_frames);
+        assert_eq!(frame_stats().frees, stats.frees + 1);
+
+        // A snapshot whose allocator is inconsistent is refused
+        save_snapshot(&path, None).unwrap();
+        let used_frames = MAGIC.len() + 13 * size_of::<u64>(); // After the version and 12 fields
+        let mut corrupt = std::fs::read(&path).unwrap();
+        corrupt[used_frames..used_frames + 8].copy_from_slice(&0u64.to_le_bytes());
+        std::fs::write(&path, corrupt).unwrap();
+        assert!(matches!(
+            restore_snapshot(&path, start, size),
+            Err(SnapshotError::Inconsistent(AuditError::UsedFrames { .. }))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
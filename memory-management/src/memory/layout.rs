@@ -0,0 +1,220 @@
+//! The physical layout of the kernel's image: where each of its sections starts and ends.
+//! `map_kernel` maps it at the same offsets into the higher half of the address space (see
+//! `kernel_address`), and leaves the lower half to user mappings. It's `DEFAULT_LAYOUT` until
+//! `set_layout` replaces it, usually with one `load_layout` read from a TOML file like
+//! `layout.toml`:
+//!
+//! ```toml
+//! [text]
+//! start = 0x0
+//! end = 0x2000
+//! ```
+//!
+//! with a table for each of `text`, `rodata`, `data`, `bss`, `stack` and `heap`.
+
+use super::consts::FRAME_SIZE;
+use core::fmt;
+use serde::Deserialize;
+use spin::Mutex;
+use std::{fs, path::Path};
+
+pub const DEFAULT_LAYOUT: KernelLayout = KernelLayout {
+    text: Section::new(0x0, 0x2000),
+    rodata: Section::new(0x2000, 0x4000),
+    data: Section::new(0x4000, 0x8000),
+    bss: Section::new(0x8000, 0x10000),
+    stack: Section::new(0x10000, 0x20000),
+    heap: Section::new(0x20000, 0x40000),
+};
+
+static LAYOUT: Mutex<KernelLayout> = Mutex::new(DEFAULT_LAYOUT);
+
+/// The physical range `[start, end)` of a section.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Section {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Section {
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KernelLayout {
+    /// Code
+    pub text: Section,
+    /// Constants
+    pub rodata: Section,
+    /// Initialized variables
+    pub data: Section,
+    /// Zero-initialized variables
+    pub bss: Section,
+    pub stack: Section,
+    pub heap: Section,
+}
+
+impl KernelLayout {
+    /// Returns the sections by name, in the order `map_kernel` maps them.
+    pub fn sections(&self) -> [(&'static str, Section); 6] {
+        [
+            ("text", self.text),
+            ("rodata", self.rodata),
+            ("data", self.data),
+            ("bss", self.bss),
+            ("stack", self.stack),
+            ("heap", self.heap),
+        ]
+    }
+
+    /// Returns the range from the start of the first section to the end of the last one.
+    pub fn image(&self) -> Section {
+        let sections = self.sections().map(|(_, section)| section);
+        Section::new(
+            sections.iter().map(|section| section.start).min().unwrap(),
+            sections.iter().map(|section| section.end).max().unwrap(),
+        )
+    }
+
+    /// Checks that every section is a non-empty range of whole frames, and that no two sections
+    /// overlap.
+    pub fn validate(&self) -> Result<(), LayoutError> {
+        let mut sections = self.sections();
+        for (name, section) in sections {
+            if section.start >= section.end {
+                return Err(LayoutError::Empty { section: name });
+            }
+            if let Some(addr) = [section.start, section.end]
+                .into_iter()
+                .find(|addr| !addr.is_multiple_of(FRAME_SIZE))
+            {
+                return Err(LayoutError::Misaligned {
+                    section: name,
+                    addr,
+                });
+            }
+        }
+
+        sections.sort_by_key(|(_, section)| section.start);
+        for pair in sections.windows(2) {
+            let [(first, lower), (second, upper)] = pair else {
+                unreachable!()
+            };
+            if lower.end > upper.start {
+                return Err(LayoutError::Overlapping { first, second });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why a layout was refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutError {
+    /// The file couldn't be read
+    Read(String),
+    /// The file isn't a layout in TOML
+    Parse(String),
+    /// The section doesn't end after it starts
+    Empty { section: &'static str },
+    /// The section starts or ends at an address that isn't aligned to `FRAME_SIZE`
+    Misaligned { section: &'static str, addr: usize },
+    /// The sections share some memory
+    Overlapping {
+        first: &'static str,
+        second: &'static str,
+    },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Read(error) => write!(f, "Failed to read the layout: {error}"),
+            LayoutError::Parse(error) => write!(f, "Failed to parse the layout: {error}"),
+            LayoutError::Empty { section } => write!(f, "The {section} section is empty"),
+            LayoutError::Misaligned { section, addr } => write!(
+                f,
+                "The {section} section's bound {addr:#X} isn't aligned to a frame"
+            ),
+            LayoutError::Overlapping { first, second } => {
+                write!(f, "The {first} and {second} sections overlap")
+            }
+        }
+    }
+}
+
+/// Returns the layout of the kernel's image.
+pub fn layout() -> KernelLayout {
+    *LAYOUT.lock()
+}
+
+/// Replaces the layout of the kernel's image, if it's valid. It must be set before the kernel is
+/// mapped and its heap is created.
+pub fn set_layout(layout: KernelLayout) -> Result<(), LayoutError> {
+    layout.validate()?;
+    *LAYOUT.lock() = layout;
+    Ok(())
+}
+
+/// Parses a layout from TOML, and validates it.
+pub fn parse_layout(text: &str) -> Result<KernelLayout, LayoutError> {
+    let layout: KernelLayout =
+        toml::from_str(text).map_err(|error| LayoutError::Parse(error.to_string()))?;
+    layout.validate()?;
+    Ok(layout)
+}
+
+/// Reads a layout from a TOML file, and validates it.
+pub fn load_layout(path: impl AsRef<Path>) -> Result<KernelLayout, LayoutError> {
+    let text = fs::read_to_string(path).map_err(|error| LayoutError::Read(error.to_string()))?;
+    parse_layout(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_are_parsed_and_validated() {
+        assert_eq!(DEFAULT_LAYOUT.validate(), Ok(()));
+        assert_eq!(
+            parse_layout(include_str!("../../layout.toml")),
+            Ok(DEFAULT_LAYOUT)
+        );
+
+        let heap_in_stack = "
+            text = { start = 0x0, end = 0x1000 }
+            rodata = { start = 0x1000, end = 0x2000 }
+            data = { start = 0x2000, end = 0x3000 }
+            bss = { start = 0x3000, end = 0x4000 }
+            stack = { start = 0x4000, end = 0x8000 }
+            heap = { start = 0x7000, end = 0x10000 }
+        ";
+        assert_eq!(
+            parse_layout(heap_in_stack),
+            Err(LayoutError::Overlapping {
+                first: "stack",
+                second: "heap"
+            })
+        );
+        assert_eq!(
+            parse_layout(&heap_in_stack.replace("0x7000", "0x8800")),
+            Err(LayoutError::Misaligned {
+                section: "heap",
+                addr: 0x8800
+            })
+        );
+        assert!(matches!(
+            parse_layout(&heap_in_stack.replace("heap", "hepa")),
+            Err(LayoutError::Parse(_))
+        ));
+    }
+}
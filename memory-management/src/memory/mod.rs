@@ -1,11 +1,30 @@
+pub mod addr;
 pub mod alloc;
+pub mod bus;
 pub mod consts;
+pub mod cpu;
+pub mod disk;
+mod error;
+pub mod fault;
+pub mod fdt;
 mod frames;
+pub mod layout;
 pub mod paging;
+pub mod recursive;
+pub mod rmap;
+pub mod space;
+pub mod swap;
+pub mod tlb;
 pub mod virt;
 
-use consts::*;
-pub use frames::init_frames_allocation;
+use addr::{PhysAddr, VirtAddr};
+pub use error::MemoryError;
+pub use frames::{
+    alloc_frames_aligned, bench as frames_bench, dealloc_frames, frame_info, frame_stats,
+    frame_usage, init_frames_allocation, init_frames_allocation_regions, owned_frames,
+    pressure_stats, register_oom_hook, register_reclaim_hook, reserve_range, restore_snapshot,
+    save_snapshot, set_watermarks, share_frames, FrameFlags, FrameOwner, Pressure, Watermarks,
+};
 use paging::{PageEntryFlags, PageEntryLevel};
 
 /// Aligns `value` to 2 to the power of `order`. Always rounds up.
@@ -25,57 +44,327 @@ pub const fn align_up(value: usize, align: usize) -> usize {
 }
 
 /// Identity map means that the virtual address is equal to the physical address.
+/// The range is widened to whole pages, so a page that it shares with a region that was mapped
+/// before gets the flags of both regions (see `map_range`).
 pub fn identity_map_range(
     root: &mut paging::PageTable,
     start: usize,
     end: usize,
     flags: PageEntryFlags,
     level: PageEntryLevel,
-) {
+) -> Result<(), MemoryError> {
     let page_size = level.size();
 
-    let start = align_order(start, page_size.ilog2() as usize);
+    let start = start - start % page_size;
     let end = align_order(end, page_size.ilog2() as usize);
 
-    for addr in (start..end).step_by(page_size) {
-        paging::map(root, addr, addr, &flags, level);
+    map_range(
+        root,
+        VirtAddr::new(start),
+        PhysAddr::new(start),
+        end.saturating_sub(start),
+        flags,
+        level,
+    )
+}
+
+/// Maps `len` bytes (rounded up to whole pages of `level`) from `phys_start` at `virt_start`.
+/// Both addresses must be aligned to the page size. Pages that already map the same frames at the
+/// same level are kept, with both their flags and `flags`, but any other mapped page fails with
+/// `AlreadyMapped`. Nothing is mapped if the W^X policy denies any of the merged flags.
+pub fn map_range(
+    root: &mut paging::PageTable,
+    virt_start: VirtAddr,
+    phys_start: PhysAddr,
+    len: usize,
+    flags: PageEntryFlags,
+    level: PageEntryLevel,
+) -> Result<(), MemoryError> {
+    let page_size = level.size();
+    level.check_aligned(virt_start.as_usize())?;
+    level.check_aligned(phys_start.as_usize())?;
+
+    // Check the flags of the pages that are already mapped before mapping anything
+    for offset in (0..len).step_by(page_size) {
+        let (phys, virt) = (phys_start + offset, virt_start + offset);
+        match paging::translate(root, virt.as_usize()) {
+            Ok(translation)
+                if translation.phys == phys.as_usize() && translation.level == level =>
+            {
+                if !translation.flags.contains(flags) {
+                    paging::check_wx(virt.as_usize(), &(translation.flags | flags))?;
+                }
+            }
+            Ok(_) => {
+                return Err(MemoryError::AlreadyMapped {
+                    virt: virt.as_usize(),
+                })
+            }
+            Err(MemoryError::NotMapped { .. }) => {}
+            Err(error) => return Err(error),
+        }
     }
+
+    for offset in (0..len).step_by(page_size) {
+        let (phys, virt) = (phys_start + offset, virt_start + offset);
+        match paging::try_map(root, phys, virt, &flags, level) {
+            // Adjacent regions that share a page map it once, with what both of them allow
+            Err(error @ MemoryError::AlreadyMapped { .. }) => {
+                let translation = paging::translate(root, virt.as_usize())?;
+                if translation.phys != phys.as_usize() || translation.level != level {
+                    return Err(error);
+                }
+                if !translation.flags.contains(flags) {
+                    let merged = translation.flags | flags;
+                    paging::set_leaf_flags(root, virt.as_usize(), &merged)?;
+                }
+            }
+            result => result?,
+        }
+    }
+    Ok(())
 }
 
-macro_rules! map_region {
-    ($root:ident, $start:ident, $end:ident, $flags:expr) => {
-        identity_map_range(
-            $root,
-            $start,
-            $end,
-            $flags | PageEntryFlags::ACCESSED_DIRTY,
-            PageEntryLevel::from_size($end - $start),
-        );
-    };
+/// Maps exactly `len` bytes from `phys_start` at `virt_start` with the fewest pages: each page is
+/// the biggest one that fits in the rest of the range and that both addresses are aligned to, so
+/// superpages are used in the middle and 4 KiB pages at the unaligned ends. Both addresses and
+/// `len` must be aligned to `FRAME_SIZE`.
+pub fn map_range_exact(
+    root: &mut paging::PageTable,
+    virt_start: VirtAddr,
+    phys_start: PhysAddr,
+    len: usize,
+    flags: PageEntryFlags,
+) -> Result<(), MemoryError> {
+    PageEntryLevel::KiB4.check_aligned(len)?;
+
+    let mut offset = 0;
+    while offset < len {
+        let (virt, phys) = (virt_start + offset, phys_start + offset);
+        let level = PageEntryLevel::fitting(virt.as_usize(), phys.as_usize(), len - offset);
+        map_range(root, virt, phys, level.size(), flags, level)?;
+        offset += level.size();
+    }
+    Ok(())
+}
+
+/// Maps the physical range `[start, end)` at `start + offset`, like a kernel that is linked at a
+/// fixed distance from where it's loaded (see `map_range_exact`).
+pub fn offset_map_range(
+    root: &mut paging::PageTable,
+    start: usize,
+    end: usize,
+    offset: usize,
+    flags: PageEntryFlags,
+) -> Result<(), MemoryError> {
+    let virt_start = start.wrapping_add(offset);
+    map_range_exact(
+        root,
+        VirtAddr::new(virt_start),
+        PhysAddr::new(start),
+        end.saturating_sub(start),
+        flags,
+    )
+}
+
+/// Maps the physical range `[start, end)` at the same offset into the higher half of the address
+/// space (the half that starts at `PagingMode::higher_half`).
+pub fn higher_half_map_range(
+    root: &mut paging::PageTable,
+    start: usize,
+    end: usize,
+    flags: PageEntryFlags,
+) -> Result<(), MemoryError> {
+    let offset = paging::mode().higher_half();
+    offset_map_range(root, start, end, offset, flags)
+}
+
+/// Returns the virtual address the kernel's physical address `phys` is mapped at.
+pub fn kernel_address(phys: usize) -> usize {
+    phys + paging::mode().higher_half()
+}
+
+/// Maps the kernel's image into the higher half, with global pages. No section is both writable
+/// and executable, so it maps under any W^X policy.
+pub fn map_kernel(root: &mut paging::PageTable) -> Result<(), MemoryError> {
+    let layout = layout::layout();
+    for (name, section) in layout.sections() {
+        let flags = match name {
+            "text" => PageEntryFlags::READ_EXECUTE,
+            "rodata" => PageEntryFlags::READ,
+            _ => PageEntryFlags::READ_WRITE,
+        } | PageEntryFlags::ACCESSED_DIRTY
+            | PageEntryFlags::GLOBAL;
+        let (start, end) = (section.start, section.end);
+        paging::assert_kernel_mapping(kernel_address(start), kernel_address(end), &flags);
+        higher_half_map_range(root, start, end, flags)?;
+        println!("Mapped {name}.");
+    }
+    Ok(())
 }
 
-pub fn map_kernel(root: &mut paging::PageTable) {
-    // Map text (code)
-    map_region!(root, TEXT_START, TEXT_END, PageEntryFlags::READ_EXECUTE);
-    println!("Mapped text.");
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use consts::FRAME_SIZE;
+    use std::{
+        alloc::{self, Layout},
+        sync::{Mutex, MutexGuard, Once},
+    };
+
+    const MEM_SIZE: usize = 4 * 1024 * 1024;
 
-    // Map read-only-data (constants)
-    map_region!(root, RODATA_START, RODATA_END, PageEntryFlags::READ);
-    println!("Mapped rodata.");
+    /// Gives the frames allocator real memory once, and keeps the tests that use it from running
+    /// at the same time.
+    pub(crate) fn frames() -> MutexGuard<'static, ()> {
+        static INIT: Once = Once::new();
+        static LOCK: Mutex<()> = Mutex::new(());
 
-    // Map data (initialized variables)
-    map_region!(root, DATA_START, DATA_END, PageEntryFlags::READ_WRITE);
-    println!("Mapped data.");
+        INIT.call_once(|| {
+            let layout = Layout::from_size_align(MEM_SIZE, FRAME_SIZE).unwrap();
+            init_frames_allocation(unsafe { alloc::alloc_zeroed(layout) }, MEM_SIZE);
+        });
+        LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
 
-    // Map block-starting-symbol (zero-initialized variables)
-    map_region!(root, BSS_START, BSS_END, PageEntryFlags::READ_WRITE);
-    println!("Mapped bss.");
+    #[test]
+    fn the_kernel_is_global_in_the_higher_half() {
+        let _frames = frames();
+        let root = unsafe { &mut *paging::create_root_table().unwrap() };
+        map_kernel(root).unwrap();
 
-    // Map stack
-    map_region!(root, STACK_START, STACK_END, PageEntryFlags::READ_WRITE);
-    println!("Mapped stack.");
+        let layout = layout::layout();
+        let heap = paging::translate(root, kernel_address(layout.heap.start) + 0x123).unwrap();
+        assert_eq!(heap.phys, layout.heap.start + 0x123);
+        assert!(heap.flags.contains(PageEntryFlags::GLOBAL));
+        assert!(paging::translate(root, layout.heap.start).is_err());
+        let image = layout.image();
+        paging::unmap_range(root, kernel_address(image.start), image.size()).unwrap();
+        dealloc_frames(PhysAddr::from_ptr(root), 1).unwrap();
+    }
 
-    // Map heap
-    map_region!(root, HEAP_START, HEAP_END, PageEntryFlags::READ_WRITE);
-    println!("Mapped heap.");
+    #[test]
+    fn exact_ranges_use_the_biggest_pages_that_fit() {
+        let _frames = frames();
+        let root = unsafe { &mut *paging::create_root_table().unwrap() };
+        let huge = PageEntryLevel::MiB2.size();
+        let (virt, phys) = (0x4000_0000 - FRAME_SIZE, huge - FRAME_SIZE);
+        let len = FRAME_SIZE + huge + 2 * FRAME_SIZE;
+
+        map_range_exact(
+            root,
+            VirtAddr::new(virt),
+            PhysAddr::new(phys),
+            len,
+            PageEntryFlags::READ,
+        )
+        .unwrap();
+        let levels = [0, FRAME_SIZE, FRAME_SIZE + huge, len - FRAME_SIZE]
+            .map(|offset| paging::translate(root, virt + offset).unwrap().level);
+        assert_eq!(
+            levels,
+            [
+                PageEntryLevel::KiB4,
+                PageEntryLevel::MiB2,
+                PageEntryLevel::KiB4,
+                PageEntryLevel::KiB4
+            ]
+        );
+        let last = paging::translate(root, virt + len - 1).unwrap();
+        assert_eq!(last.phys, phys + len - 1);
+        assert!(paging::translate(root, virt - 1).is_err());
+        assert!(paging::translate(root, virt + len).is_err());
+
+        assert!(map_range_exact(
+            root,
+            VirtAddr::new(0),
+            PhysAddr::new(0),
+            0x800,
+            PageEntryFlags::READ
+        )
+        .is_err());
+        paging::unmap_range(root, virt, len).unwrap();
+        dealloc_frames(PhysAddr::from_ptr(root), 1).unwrap();
+    }
+
+    #[test]
+    fn overlapping_regions_share_their_boundary_page() {
+        let _frames = frames();
+        let root = unsafe { &mut *paging::create_root_table().unwrap() };
+        let base = alloc_frames_aligned(3, FRAME_SIZE).unwrap().as_usize();
+        let level = PageEntryLevel::KiB4;
+
+        identity_map_range(root, base, base + 0x1800, PageEntryFlags::READ, level).unwrap();
+        identity_map_range(
+            root,
+            base + 0x1800,
+            base + 0x3000,
+            PageEntryFlags::READ_WRITE,
+            level,
+        )
+        .unwrap();
+        let boundary = paging::translate(root, base + 0x1800).unwrap();
+        assert_eq!(boundary.phys, base + 0x1800);
+        assert_eq!(
+            boundary.flags & PageEntryFlags::READ_WRITE,
+            PageEntryFlags::READ_WRITE
+        );
+
+        // A boundary page can't become both writable and executable, and a range that would
+        // make one maps none of its pages
+        paging::unmap_range(root, base, FRAME_SIZE).unwrap();
+        assert_eq!(
+            identity_map_range(
+                root,
+                base,
+                base + 0x1400,
+                PageEntryFlags::READ_EXECUTE,
+                level,
+            ),
+            Err(MemoryError::WritableAndExecutable {
+                virt: base + FRAME_SIZE
+            })
+        );
+        assert_eq!(
+            paging::translate(root, base),
+            Err(MemoryError::NotMapped { virt: base })
+        );
+        assert_eq!(
+            paging::translate(root, base + FRAME_SIZE).unwrap().flags & PageEntryFlags::READ_WRITE,
+            PageEntryFlags::READ_WRITE
+        );
+        identity_map_range(root, base, base + 0x800, PageEntryFlags::READ, level).unwrap();
+
+        // Pages that map other frames are still refused
+        let (virt, other) = (VirtAddr::new(base), PhysAddr::new(base + FRAME_SIZE));
+        assert_eq!(
+            map_range(root, virt, other, FRAME_SIZE, PageEntryFlags::READ, level),
+            Err(MemoryError::AlreadyMapped { virt: base })
+        );
+        assert_eq!(
+            paging::try_map(root, other, virt, &PageEntryFlags::WRITE, level),
+            Err(MemoryError::InvalidFlags {
+                flags: PageEntryFlags::WRITE
+            })
+        );
+        assert_eq!(
+            paging::map_overwrite(root, other, virt, &PageEntryFlags::READ, level),
+            Ok(Some(PhysAddr::new(base)))
+        );
+        assert_eq!(
+            paging::translate(root, base).unwrap().phys,
+            base + FRAME_SIZE
+        );
+
+        paging::unmap_range(root, base, 3 * FRAME_SIZE).unwrap();
+        dealloc_frames(PhysAddr::new(base), 3).unwrap();
+        dealloc_frames(PhysAddr::from_ptr(root), 1).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "User mappings must not be global")]
+    fn user_mappings_are_not_global() {
+        let flags = PageEntryFlags::READ | PageEntryFlags::USER | PageEntryFlags::GLOBAL;
+        paging::assert_user_mapping(0x1000_0000, 0x1000_1000, &flags);
+    }
 }
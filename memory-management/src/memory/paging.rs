@@ -1,15 +1,42 @@
 #![allow(dead_code)] // REMOVE THIS LINE
+#![allow(unused_parens)] // The accessors generated by `#[bitfield]` trigger it
 
-use core::ops;
+use core::{fmt, ops};
 use modular_bitfield::prelude::*;
+use spin::Mutex;
 
-use crate::memory::frames::FRAMES_ALLOCATOR;
+use crate::memory::{
+    addr::{PhysAddr, VirtAddr},
+    error::MemoryError,
+    frames::{FrameOwner, FRAMES_ALLOCATOR},
+    rmap::{Mapping, REVERSE_MAP},
+    swap, tlb,
+};
 
-use super::consts::NUM_VPNS;
+use super::consts::{FRAME_SIZE, MAX_VPNS};
 
-pub struct PageEntryFlags(u8);
+mod dot;
+mod dump;
+mod verify;
+
+pub use dot::write_dot;
+pub use dump::{dump, mapped_ranges};
+pub(crate) use verify::verify_mutation;
+pub use verify::{set_verify_mutations, verify};
+
+/// The translation scheme of every page table, like `satp.MODE`.
+static MODE: Mutex<PagingMode> = Mutex::new(PagingMode::Sv39);
+
+/// What `map` and `protect_range` do with pages that are both writable and executable.
+static WX_POLICY: Mutex<WxPolicy> = Mutex::new(WxPolicy::Deny);
+
+/// The low 10 bits of a page entry: the hardware flags, and the two bits that are reserved for
+/// software.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct PageEntryFlags(u16);
 
 impl PageEntryFlags {
+    pub const EMPTY: Self = Self(0);
     pub const VALID: Self = Self(1 << 0);
     pub const READ: Self = Self(1 << 1);
     pub const WRITE: Self = Self(1 << 2);
@@ -18,6 +45,11 @@ impl PageEntryFlags {
     pub const GLOBAL: Self = Self(1 << 5);
     pub const ACCESSED: Self = Self(1 << 6);
     pub const DIRTY: Self = Self(1 << 7);
+    /// The page is shared, and is copied on the first write to it (software)
+    pub const COW: Self = Self(1 << 8);
+    /// The page was written out to swap, the entry isn't valid and its PPN is the swap slot
+    /// (software)
+    pub const SWAPPED: Self = Self(1 << 9);
 
     // Convenience combinations
     pub const READ_WRITE: Self = Self((1 << 1) | (1 << 2));
@@ -30,17 +62,55 @@ impl PageEntryFlags {
     pub const USER_READ_EXECUTE: Self = Self((1 << 1) | (1 << 3) | (1 << 4));
     pub const USER_READ_WRITE_EXECUTE: Self = Self((1 << 1) | (1 << 2) | (1 << 3) | (1 << 4));
 
-    pub fn val(self) -> u8 {
+    pub fn val(self) -> u16 {
         self.0
     }
 
     pub fn is_leaf(&self) -> bool {
-        self.contains(Self::READ) || self.contains(Self::WRITE) || self.contains(Self::EXECUTE)
+        self.intersects(Self::READ_WRITE_EXECUTE)
     }
 
+    /// Returns true if every flag of `other` is set.
     pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if any flag of `other` is set.
+    pub fn intersects(&self, other: Self) -> bool {
         self.0 & other.0 != 0
     }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Debug for PageEntryFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NAMES: [&str; 10] = [
+            "VALID", "READ", "WRITE", "EXECUTE", "USER", "GLOBAL", "ACCESSED", "DIRTY", "COW",
+            "SWAPPED",
+        ];
+        if self.is_empty() {
+            return write!(f, "EMPTY");
+        }
+
+        let mut names = NAMES
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| (self.0 >> bit) & 1 == 1)
+            .map(|(_, name)| name);
+        write!(f, "{}", names.next().unwrap())?;
+        names.try_for_each(|name| write!(f, " | {name}"))
+    }
 }
 
 impl ops::BitOr for PageEntryFlags {
@@ -76,8 +146,9 @@ pub struct PageEntry {
     global: bool,
     accessed: bool,
     dirty: bool,
-    #[skip(getters, setters)]
-    reserved_for_software: B2,
+    // The two bits that are reserved for software
+    cow: bool,
+    swapped: bool,
     ppn0: B9,
     ppn1: B9,
     ppn2: B26,
@@ -102,6 +173,22 @@ impl PageEntry {
         !self.valid()
     }
 
+    /// Returns true if the page is shared, and must be copied before it's written to.
+    pub fn is_cow(&self) -> bool {
+        self.cow()
+    }
+
+    /// Returns true if the page was written out to swap, so the entry isn't valid but isn't free
+    /// either.
+    pub fn is_swapped(&self) -> bool {
+        self.swapped()
+    }
+
+    /// Returns true if the entry neither maps anything nor holds a swapped out page.
+    pub fn is_free(&self) -> bool {
+        self.is_invalid() && !self.is_swapped()
+    }
+
     pub fn get_type(&self) -> PageEntryType {
         if self.is_invalid() {
             return PageEntryType::Invalid;
@@ -120,6 +207,11 @@ impl PageEntry {
         self.set_ppn2(((ppn >> 30) & 0x3ff_ffff) as u32); // PPN[2] = physical_addr[30:55]
     }
 
+    /// Returns the entry as the 64 bits the hardware reads.
+    pub fn bits(&self) -> u64 {
+        u64::from_le_bytes(self.bytes)
+    }
+
     pub fn get_ppn(&self) -> usize {
         ((self.ppn0() as usize) << 12)
             | ((self.ppn1() as usize) << 21)
@@ -135,6 +227,33 @@ impl PageEntry {
         self.set_global(flags.contains(PageEntryFlags::GLOBAL));
         self.set_accessed(flags.contains(PageEntryFlags::ACCESSED));
         self.set_dirty(flags.contains(PageEntryFlags::DIRTY));
+        self.set_cow(flags.contains(PageEntryFlags::COW));
+        self.set_swapped(flags.contains(PageEntryFlags::SWAPPED));
+    }
+
+    /// Makes the entry a valid branch which points at the page table at `table_addr`.
+    pub fn set_branch(&mut self, table_addr: usize) {
+        self.set_flags(&PageEntryFlags::VALID);
+        self.set_ppn(table_addr);
+    }
+
+    /// Returns the flags that are set in the entry.
+    pub fn flags(&self) -> PageEntryFlags {
+        [
+            (self.valid(), PageEntryFlags::VALID),
+            (self.read(), PageEntryFlags::READ),
+            (self.write(), PageEntryFlags::WRITE),
+            (self.execute(), PageEntryFlags::EXECUTE),
+            (self.user(), PageEntryFlags::USER),
+            (self.global(), PageEntryFlags::GLOBAL),
+            (self.accessed(), PageEntryFlags::ACCESSED),
+            (self.dirty(), PageEntryFlags::DIRTY),
+            (self.cow(), PageEntryFlags::COW),
+            (self.swapped(), PageEntryFlags::SWAPPED),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .fold(PageEntryFlags::EMPTY, |flags, (_, flag)| flags | flag)
     }
 
     pub fn copy_flags(&mut self, entry: PageEntry) {
@@ -146,30 +265,150 @@ impl PageEntry {
         self.set_global(entry.global());
         self.set_accessed(entry.accessed());
         self.set_dirty(entry.dirty());
+        self.set_cow(entry.cow());
+        self.set_swapped(entry.swapped());
+    }
+
+    /// Returns the VPNs of `vpn` that the current paging mode uses, VPN[0] first.
+    pub fn extract_vpns(vpn: usize) -> impl DoubleEndedIterator<Item = usize> {
+        Self::extract_all_vpns(vpn)
+            .into_iter()
+            .take(mode().levels())
     }
 
-    pub fn extract_vpns(vpn: usize) -> [usize; NUM_VPNS] {
+    pub fn extract_all_vpns(vpn: usize) -> [usize; MAX_VPNS] {
         // Extract the parts of the VPN. Each part is 9 bits (0x1FF = 0b1_1111_1111).
         // We ignore the first 12 bits because they are the frame offset (there are 2^12 = 4096 addresses in a frame).
         [
             (vpn >> 12) & 0x1FF, // VPN[0] = virtual_addr[12:20]
             (vpn >> 21) & 0x1FF, // VPN[1] = virtual_addr[21:29]
             (vpn >> 30) & 0x1FF, // VPN[2] = virtual_addr[30:38]
+            (vpn >> 39) & 0x1FF, // VPN[3] = virtual_addr[39:47] (Sv48 and up)
+            (vpn >> 48) & 0x1FF, // VPN[4] = virtual_addr[48:56] (Sv57)
         ]
     }
 }
 
+/// The translation schemes, which differ in the number of levels of the page table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PagingMode {
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl PagingMode {
+    /// Returns the number of levels of the page table.
+    pub fn levels(self) -> usize {
+        match self {
+            Self::Sv39 => 3,
+            Self::Sv48 => 4,
+            Self::Sv57 => 5,
+        }
+    }
+
+    /// Returns the level that the entries of the root table map.
+    pub fn top_level(self) -> PageEntryLevel {
+        match self {
+            Self::Sv39 => PageEntryLevel::GiB1,
+            Self::Sv48 => PageEntryLevel::GiB512,
+            Self::Sv57 => PageEntryLevel::TiB256,
+        }
+    }
+
+    /// Returns the number of bits of a virtual address, the ones above it must copy the top one.
+    pub fn virtual_address_bits(self) -> u32 {
+        12 + 9 * self.levels() as u32
+    }
+
+    /// Virtual addresses must have the bits above the mode's top bit equal to it (bits 63-39 equal
+    /// to bit 38 in Sv39).
+    pub fn canonical(self, addr: usize) -> usize {
+        let shift = usize::BITS - self.virtual_address_bits();
+        (((addr << shift) as isize) >> shift) as usize
+    }
+
+    /// Returns the first address of the higher half of the address space (0xFFFF_FFC0_0000_0000
+    /// in Sv39), which belongs to the kernel.
+    pub fn higher_half(self) -> usize {
+        usize::MAX << (self.virtual_address_bits() - 1)
+    }
+
+    /// Returns the end of the lower half of the address space (0x40_0000_0000 in Sv39), which
+    /// belongs to user mappings.
+    pub fn lower_half_end(self) -> usize {
+        1 << (self.virtual_address_bits() - 1)
+    }
+}
+
+/// Returns the current paging mode.
+pub fn mode() -> PagingMode {
+    *MODE.lock()
+}
+
+/// What to do with pages that are both writable (or copy-on-write) and executable, which let
+/// anything that can write to memory run code (W^X).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WxPolicy {
+    /// Fail with `WritableAndExecutable`
+    Deny,
+    /// Map them, but print a warning
+    Warn,
+    /// Map them, for the rare code that really needs it (like a JIT)
+    Allow,
+}
+
+/// Selects what `map`, `update_flags` and `protect_range` do with writable and executable pages.
+pub fn set_wx_policy(policy: WxPolicy) {
+    *WX_POLICY.lock() = policy;
+}
+
+/// Checks `flags`, the flags of the page at `virt`, against the W^X policy.
+pub(super) fn check_wx(virt: usize, flags: &PageEntryFlags) -> Result<(), MemoryError> {
+    let writable = flags.intersects(PageEntryFlags::WRITE | PageEntryFlags::COW);
+    if !writable || !flags.contains(PageEntryFlags::EXECUTE) {
+        return Ok(());
+    }
+    match *WX_POLICY.lock() {
+        WxPolicy::Deny => Err(MemoryError::WritableAndExecutable { virt }),
+        WxPolicy::Warn => {
+            println!("- Warning: {virt:#X} is both writable and executable");
+            Ok(())
+        }
+        WxPolicy::Allow => Ok(()),
+    }
+}
+
+/// Selects the paging mode of every page table. Must be called before the first root table is
+/// created, since the tables of one mode can't be walked in another.
+pub fn set_mode(mode: PagingMode) {
+    *MODE.lock() = mode;
+}
+
 #[repr(usize)]
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PageEntryLevel {
     KiB4 = 0,
     MiB2 = 1,
     GiB1 = 2,
+    /// Sv48 and up
+    GiB512 = 3,
+    /// Sv57
+    TiB256 = 4,
 }
 
 impl PageEntryLevel {
+    const ALL: [Self; 5] = [
+        Self::KiB4,
+        Self::MiB2,
+        Self::GiB1,
+        Self::GiB512,
+        Self::TiB256,
+    ];
+
+    /// Returns the level of the root table's entries in the current paging mode.
     pub fn top() -> Self {
-        Self::GiB1
+        mode().top_level()
     }
 
     pub fn val(self) -> usize {
@@ -180,28 +419,34 @@ impl PageEntryLevel {
         4096 * 512usize.pow(self as u32)
     }
 
-    pub fn from_size(size: usize) -> Self {
-        if size > Self::GiB1.size() {
-            return Self::GiB1;
-        } else if size > Self::MiB2.size() {
-            return Self::MiB2;
-        }
-        PageEntryLevel::KiB4
+    /// Returns the biggest level (up to the top one) of a page that fits in `len` bytes, and that
+    /// both `virt` and `phys` are aligned to.
+    pub fn fitting(virt: usize, phys: usize, len: usize) -> Self {
+        Self::ALL[..=Self::top().val()]
+            .iter()
+            .rev()
+            .copied()
+            .find(|level| {
+                level.size() <= len
+                    && virt.is_multiple_of(level.size())
+                    && phys.is_multiple_of(level.size())
+            })
+            .unwrap_or(PageEntryLevel::KiB4)
     }
 
     pub fn next_level(self) -> Option<Self> {
-        match self {
-            Self::KiB4 => None,
-            Self::MiB2 => Some(Self::KiB4),
-            Self::GiB1 => Some(Self::MiB2),
-        }
+        self.val().checked_sub(1).map(|level| Self::ALL[level])
     }
 
-    pub fn assert_aligned(self, addr: usize) {
-        assert!(
-            addr % self.size() == 0,
-            "Address is not aligned with page size"
-        )
+    pub fn check_aligned(self, addr: usize) -> Result<(), MemoryError> {
+        if addr.is_multiple_of(self.size()) {
+            Ok(())
+        } else {
+            Err(MemoryError::Misaligned {
+                addr,
+                align: self.size(),
+            })
+        }
     }
 }
 
@@ -212,18 +457,81 @@ pub struct PageTable {
     pub entries: [PageEntry; PAGE_TABLE_LEN],
 }
 
-/// root - A mutable reference to the root of the page table (level 2).
-/// from_addr - The physical address.
-/// to_addr - The virtual address.
+/// Fails with `InvalidFlags` if `flags` can't be the flags of a leaf.
+fn check_leaf_flags(flags: &PageEntryFlags) -> Result<(), MemoryError> {
+    // Writable pages that aren't readable are reserved
+    let writable_only =
+        flags.contains(PageEntryFlags::WRITE) && !flags.contains(PageEntryFlags::READ);
+    if !flags.is_leaf() || writable_only {
+        return Err(MemoryError::InvalidFlags { flags: *flags });
+    }
+    Ok(())
+}
+
+/// What `map_leaf` does when the page is already mapped.
+#[derive(Clone, Copy, PartialEq)]
+enum Existing {
+    Fail,
+    Overwrite,
+}
+
+/// root - A mutable reference to the root of the page table (the top level of the paging mode).
+/// phys - The physical address of the frame.
+/// virt - The virtual address of the page.
 /// entry_flags - Any additional flags of the entry (Read, Write, Execute, etc.)
 /// level - The level in which the page will be mapped
+///
+/// Fails with `AlreadyMapped` if the page is already mapped, and panics if `entry_flags` can't be
+/// the flags of a page (see `try_map`).
 pub fn map(
     root: &mut PageTable,
-    from_addr: usize,
-    to_addr: usize,
+    phys: PhysAddr,
+    virt: VirtAddr,
     entry_flags: &PageEntryFlags,
     level: PageEntryLevel,
-) {
+) -> Result<(), MemoryError> {
+    match try_map(root, phys, virt, entry_flags, level) {
+        Err(MemoryError::InvalidFlags { flags }) => panic!("Cannot map a page with {flags:?}"),
+        result => result,
+    }
+}
+
+/// Maps `virt` to `phys` like `map`, but never panics: flags that can't be the flags of a page fail
+/// with `InvalidFlags`, and a page that is already mapped fails with `AlreadyMapped`.
+pub fn try_map(
+    root: &mut PageTable,
+    phys: PhysAddr,
+    virt: VirtAddr,
+    entry_flags: &PageEntryFlags,
+    level: PageEntryLevel,
+) -> Result<(), MemoryError> {
+    map_leaf(root, phys, virt, entry_flags, level, Existing::Fail).map(|_| ())
+}
+
+/// Maps `virt` to `phys` like `try_map`, in place of the page of `level` that maps `virt` if there
+/// is one, and returns the frame that page mapped, which the caller may have to free. Still fails
+/// with `AlreadyMapped` if `virt` is in a bigger page, in a table of smaller pages, or swapped out.
+pub fn map_overwrite(
+    root: &mut PageTable,
+    phys: PhysAddr,
+    virt: VirtAddr,
+    entry_flags: &PageEntryFlags,
+    level: PageEntryLevel,
+) -> Result<Option<PhysAddr>, MemoryError> {
+    map_leaf(root, phys, virt, entry_flags, level, Existing::Overwrite)
+}
+
+/// Maps a leaf, doing `existing` with the leaf that is already there. Returns the frame of the leaf
+/// it replaced.
+fn map_leaf(
+    root: &mut PageTable,
+    phys: PhysAddr,
+    virt: VirtAddr,
+    entry_flags: &PageEntryFlags,
+    level: PageEntryLevel,
+    existing: Existing,
+) -> Result<Option<PhysAddr>, MemoryError> {
+    let (from_addr, to_addr) = (phys.as_usize(), virt.as_usize());
     println!(
         "- Mapping: {:#X} -> {:#X} | FLAGS={:#b} | LEVEL={}",
         from_addr,
@@ -231,47 +539,59 @@ pub fn map(
         entry_flags.0,
         level.val()
     );
-    level.assert_aligned(to_addr);
-    level.assert_aligned(from_addr);
-    assert!(entry_flags.is_leaf(), "Cannot map branch");
+    level.check_aligned(to_addr)?;
+    level.check_aligned(from_addr)?;
+    check_leaf_flags(entry_flags)?;
+    check_wx(to_addr, entry_flags)?;
 
     // Extract the parts of the VPN.
     let vpns = PageEntry::extract_vpns(to_addr);
 
-    let mut table = root;
+    let mut table = &mut *root;
     let mut current_level = PageEntryLevel::top(); // Start from the top level
 
     // Traverse the page table (the root is expected to be valid, but the rest can be created)
-    for vpn in vpns.into_iter().rev() {
-        // A reference to the current entry that we're on (can be level 2, 1 or 0)
+    for vpn in vpns.rev() {
+        // A reference to the current entry that we're on (from the top level down to level 0)
         let entry = &mut table.entries[vpn];
 
         if current_level == level {
-            if entry.is_valid() {
-                panic!(
-                    "Attempt to map an already mapped address: {:#X} -> {:#X}",
-                    from_addr, to_addr
-                );
+            let replaceable = existing == Existing::Overwrite && entry.is_leaf();
+            if entry.is_swapped() || (entry.is_valid() && !replaceable) {
+                return Err(MemoryError::AlreadyMapped { virt: to_addr });
             }
 
+            let entry_addr = entry as *const PageEntry as usize;
+            let previous = entry.is_valid().then(|| PhysAddr::new(entry.get_ppn()));
+            if previous.is_some() {
+                REVERSE_MAP
+                    .lock()
+                    .remove_entries(entry_addr, entry_addr + size_of::<PageEntry>());
+                tlb::sfence_vma(None, Some(to_addr));
+            }
+            REVERSE_MAP.lock().insert(Mapping {
+                phys: from_addr,
+                virt: to_addr,
+                level,
+                entry: entry_addr,
+            })?;
             entry.set_flags(entry_flags);
+            entry.set_valid(true); // A leaf that isn't valid doesn't map anything
             entry.set_ppn(from_addr);
 
-            return;
+            verify_mutation(root, "map");
+            return Ok(previous);
         }
 
         match entry.get_type() {
-            PageEntryType::Leaf => panic!("Tried to go through leaf instead of branch"),
+            // A bigger page already maps the address
+            PageEntryType::Leaf => return Err(MemoryError::AlreadyMapped { virt: to_addr }),
             PageEntryType::Branch(next_addr) => {
                 table = unsafe { (next_addr as *mut PageTable).as_mut().unwrap() }
             }
             PageEntryType::Invalid => {
-                let subtable = FRAMES_ALLOCATOR
-                    .lock()
-                    .zero_alloc(1, PageEntryLevel::KiB4)
-                    .cast::<PageTable>();
-                entry.set_valid(true);
-                entry.set_ppn(subtable as usize);
+                let subtable = alloc_table()?;
+                entry.set_branch(subtable as usize);
 
                 table = unsafe { subtable.as_mut().unwrap() };
             }
@@ -282,36 +602,151 @@ pub fn map(
             None => panic!("There is no page size smaller than 4KiB"),
         }
     }
+    unreachable!("The walk reaches every level");
 }
 
-/// Unmap and free all of the memory of this table (doesn't have to be root)
-pub fn unmap(table: &mut PageTable) {
-    for level2 in 0..PAGE_TABLE_LEN {
-        let entry_level2 = &table.entries[level2];
-        if entry_level2.is_valid() && entry_level2.is_branch() {
-            // This is a branch, free all of the other entries
-            let ptr_level1 = entry_level2.get_ppn();
-            let table_level1 = unsafe { (ptr_level1 as *mut PageTable).as_mut().unwrap() };
-            for level1 in 0..PAGE_TABLE_LEN {
-                let entry_level1 = &table_level1.entries[level1];
-                if entry_level1.is_valid() && entry_level1.is_branch() {
-                    // This is a branch, free all of the other entries
-                    let ptr_level0 = entry_level1.get_ppn();
+/// Unmap and free all of the memory of this table (a root table)
+pub fn unmap(table: &mut PageTable) -> Result<(), MemoryError> {
+    free_subtables(table, PageEntryLevel::top())
+}
+
+/// Frees every table under `table`, whose entries map pages of `level`.
+fn free_subtables(table: &mut PageTable, level: PageEntryLevel) -> Result<(), MemoryError> {
+    if level == PageEntryLevel::KiB4 {
+        return Ok(()); // The entries of the last level are all leaves
+    }
+
+    for entry in &table.entries {
+        if entry.is_valid() && entry.is_branch() {
+            // This is a branch, free all of the tables under it first
+            let ptr = entry.get_ppn();
+            if ptr == table as *const PageTable as usize {
+                continue; // The recursive slot points back at the root
+            }
+            let subtable = unsafe { (ptr as *mut PageTable).as_mut().unwrap() };
+            free_subtables(subtable, level.next_level().unwrap())?;
+
+            REVERSE_MAP.lock().remove_entries(ptr, ptr + FRAME_SIZE);
+            FRAMES_ALLOCATOR
+                .lock()
+                .dealloc(ptr, 1, PageEntryLevel::KiB4)?;
+        }
+    }
+    Ok(())
+}
+
+/// Unmaps the pages in `[virt_start, virt_start + len)` and frees the tables that are left empty,
+/// leaving the rest of the address space intact. The pages' frames aren't freed, since only the
+/// caller knows whether they own them, so the unmapped pages are returned instead.
+///
+/// Fails with `Misaligned` if the range covers only part of a superpage, in which case the pages
+/// before it are already unmapped.
+pub fn unmap_range(
+    root: &mut PageTable,
+    virt_start: usize,
+    len: usize,
+) -> Result<Vec<Mapping>, MemoryError> {
+    let mode = mode();
+    let mask = usize::MAX >> (usize::BITS - mode.virtual_address_bits());
+    let start = virt_start & mask;
+    let end = start.saturating_add(len).min(mask + 1);
+
+    let mut unmapped = Vec::new();
+    let root_addr = root as *const PageTable as usize;
+    unmap_in(
+        root,
+        root_addr,
+        mode.top_level(),
+        0,
+        start..end,
+        &mut unmapped,
+    )?;
+    verify_mutation(root, "unmap_range");
+    Ok(unmapped)
+}
+
+/// Unmaps the pages of `range` under `table`, whose entries map pages of `level` from `base`.
+fn unmap_in(
+    table: &mut PageTable,
+    root_addr: usize,
+    level: PageEntryLevel,
+    base: usize,
+    range: ops::Range<usize>,
+    unmapped: &mut Vec<Mapping>,
+) -> Result<(), MemoryError> {
+    let size = level.size();
+    let first = (range.start - base) / size;
+    let last = ((range.end - base).div_ceil(size)).min(PAGE_TABLE_LEN);
+
+    for index in first..last {
+        let entry = &mut table.entries[index];
+        let page = base + index * size;
+        let virt = mode().canonical(page);
+
+        match entry.get_type() {
+            PageEntryType::Invalid => {}
+            PageEntryType::Leaf => {
+                if page < range.start || page + size > range.end {
+                    return Err(MemoryError::Misaligned {
+                        addr: virt,
+                        align: size,
+                    });
+                }
+
+                let entry_addr = entry as *const PageEntry as usize;
+                unmapped.push(Mapping {
+                    phys: entry.get_ppn(),
+                    virt,
+                    level,
+                    entry: entry_addr,
+                });
+                REVERSE_MAP
+                    .lock()
+                    .remove_entries(entry_addr, entry_addr + size_of::<PageEntry>());
+                *entry = PageEntry::new();
+                tlb::sfence_vma(None, Some(virt));
+            }
+            PageEntryType::Branch(ptr) => {
+                if ptr == root_addr {
+                    continue; // The recursive slot points back at the root
+                }
+                let subtable = unsafe { (ptr as *mut PageTable).as_mut().unwrap() };
+                let next = level.next_level().unwrap();
+                let sub_range = range.start.max(page)..range.end.min(page + size);
+                unmap_in(subtable, root_addr, next, page, sub_range, unmapped)?;
+
+                // Free the table if nothing is mapped through it anymore
+                if subtable.entries.iter().all(PageEntry::is_free) {
+                    *entry = PageEntry::new();
                     FRAMES_ALLOCATOR
                         .lock()
-                        .dealloc(ptr_level0, 1, PageEntryLevel::KiB4);
+                        .dealloc(ptr, 1, PageEntryLevel::KiB4)?;
                 }
             }
-            FRAMES_ALLOCATOR
-                .lock()
-                .dealloc(ptr_level1, 1, PageEntryLevel::MiB2);
         }
     }
+    Ok(())
+}
+
+/// Where a virtual address is mapped, and how.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Translation {
+    /// The physical address, including the offset inside the page
+    pub phys: usize,
+    /// The level of the leaf that maps the address
+    pub level: PageEntryLevel,
+    pub flags: PageEntryFlags,
 }
 
 /// Convert a virtual address to a physical address by walking the page table.
-/// If a page fault occurs, return None. Otherwise return Some(physical_address).
-pub fn virtual_to_physical(root: &PageTable, virtual_addr: usize) -> Option<usize> {
+/// If a page fault occurs, return an error. Otherwise return Ok(physical_address).
+pub fn virtual_to_physical(root: &PageTable, virt: VirtAddr) -> Result<PhysAddr, MemoryError> {
+    translate(root, virt.as_usize()).map(|translation| PhysAddr::new(translation.phys))
+}
+
+/// Walks the page table for `virtual_addr`, and returns the physical address it is mapped to
+/// along with the level and the flags of the leaf that maps it.
+pub fn translate(root: &PageTable, virtual_addr: usize) -> Result<Translation, MemoryError> {
     let mut table = root;
     let mut current_level = PageEntryLevel::top();
 
@@ -319,16 +754,24 @@ pub fn virtual_to_physical(root: &PageTable, virtual_addr: usize) -> Option<usiz
     let vpns = PageEntry::extract_vpns(virtual_addr);
 
     // Traverse the page table
-    for vpn in vpns.into_iter().rev() {
-        // A reference to the current entry that we're on (can be level 2, 1 or 0)
+    for vpn in vpns.rev() {
+        // A reference to the current entry that we're on (from the top level down to level 0)
         let entry = &table.entries[vpn];
 
         match entry.get_type() {
-            PageEntryType::Leaf => return Some(entry.get_ppn()),
+            PageEntryType::Leaf => {
+                // A superpage's offset covers the VPNs of the levels under it too
+                let offset = virtual_addr & (current_level.size() - 1);
+                return Ok(Translation {
+                    phys: entry.get_ppn() | offset,
+                    level: current_level,
+                    flags: entry.flags(),
+                });
+            }
             PageEntryType::Branch(next_addr) => {
                 table = unsafe { (next_addr as *mut PageTable).as_mut().unwrap() };
             }
-            PageEntryType::Invalid => todo!(),
+            PageEntryType::Invalid => return Err(not_mapped(entry, virtual_addr)),
         }
 
         current_level = match current_level.next_level() {
@@ -337,12 +780,374 @@ pub fn virtual_to_physical(root: &PageTable, virtual_addr: usize) -> Option<usiz
         }
     }
     // If we got to here, it means we havn't found a leaf.
-    None
+    Err(MemoryError::NotMapped { virt: virtual_addr })
+}
+
+/// Returns why an invalid entry doesn't map `virtual_addr`.
+fn not_mapped(entry: &PageEntry, virtual_addr: usize) -> MemoryError {
+    if entry.is_swapped() {
+        MemoryError::Swapped { virt: virtual_addr }
+    } else {
+        MemoryError::NotMapped { virt: virtual_addr }
+    }
+}
+
+/// Returns the leaf entry that maps `virtual_addr`, and its level.
+pub(super) fn leaf_entry(
+    root: &mut PageTable,
+    virtual_addr: usize,
+) -> Result<(&mut PageEntry, PageEntryLevel), MemoryError> {
+    let (entry, level) = last_entry(root, virtual_addr);
+    match entry.get_type() {
+        PageEntryType::Leaf => Ok((entry, level)),
+        _ => Err(not_mapped(entry, virtual_addr)),
+    }
+}
+
+/// Returns the entry that mapped `virtual_addr` before it was swapped out, and its level.
+pub(super) fn swapped_entry(
+    root: &mut PageTable,
+    virtual_addr: usize,
+) -> Result<(&mut PageEntry, PageEntryLevel), MemoryError> {
+    let (entry, level) = last_entry(root, virtual_addr);
+    if entry.is_invalid() && entry.is_swapped() {
+        Ok((entry, level))
+    } else {
+        Err(MemoryError::NotMapped { virt: virtual_addr })
+    }
+}
+
+/// Walks the page table for `virtual_addr` until an entry that isn't a branch, and returns it
+/// along with its level.
+fn last_entry(root: &mut PageTable, virtual_addr: usize) -> (&mut PageEntry, PageEntryLevel) {
+    let vpns = PageEntry::extract_all_vpns(virtual_addr);
+    let mut table = root;
+    let mut current_level = PageEntryLevel::top();
+
+    loop {
+        let entry = &mut table.entries[vpns[current_level.val()]];
+        match (entry.get_type(), current_level.next_level()) {
+            (PageEntryType::Branch(next_addr), Some(next_level)) => {
+                table = unsafe { (next_addr as *mut PageTable).as_mut().unwrap() };
+                current_level = next_level;
+            }
+            _ => return (entry, current_level),
+        }
+    }
+}
+
+/// Asserts that a kernel mapping of `[virt_start, virt_end)` with `flags` is in the higher half,
+/// and is global and not accessible to user mode, since every address space shares it.
+pub fn assert_kernel_mapping(virt_start: usize, virt_end: usize, flags: &PageEntryFlags) {
+    let higher_half = mode().higher_half();
+    assert!(
+        virt_start >= higher_half && virt_end.wrapping_sub(1) >= higher_half,
+        "Kernel mappings must be in the higher half, not at {virt_start:#X}"
+    );
+    assert!(
+        flags.contains(PageEntryFlags::GLOBAL),
+        "Kernel mappings must be global"
+    );
+    assert!(
+        !flags.contains(PageEntryFlags::USER),
+        "Kernel mappings must not be user pages"
+    );
 }
 
-pub fn create_root_table() -> *mut PageTable {
+/// Asserts that a user mapping of `[virt_start, virt_end)` with `flags` is in the lower half, and
+/// isn't global, since no other address space may see it.
+pub fn assert_user_mapping(virt_start: usize, virt_end: usize, flags: &PageEntryFlags) {
+    assert!(
+        virt_end <= mode().lower_half_end(),
+        "User mappings must be in the lower half, not at {virt_start:#X}"
+    );
+    assert!(
+        !flags.contains(PageEntryFlags::GLOBAL),
+        "User mappings must not be global"
+    );
+}
+
+/// Replaces the flags of the page that maps `virtual_addr` with `new_flags`, without remapping it,
+/// unless the W^X policy denies them. Returns the level of the page.
+pub fn update_flags(
+    root: &mut PageTable,
+    virtual_addr: usize,
+    new_flags: &PageEntryFlags,
+) -> Result<PageEntryLevel, MemoryError> {
+    check_leaf_flags(new_flags)?;
+    check_wx(virtual_addr, new_flags)?;
+    set_leaf_flags(root, virtual_addr, new_flags)
+}
+
+/// Replaces the flags of the page that maps `virtual_addr`, which the caller already checked.
+pub(super) fn set_leaf_flags(
+    root: &mut PageTable,
+    virtual_addr: usize,
+    new_flags: &PageEntryFlags,
+) -> Result<PageEntryLevel, MemoryError> {
+    let (entry, level) = leaf_entry(root, virtual_addr)?;
+    entry.set_flags(new_flags);
+    entry.set_valid(true);
+    tlb::sfence_vma(None, Some(virtual_addr));
+    verify_mutation(root, "update_flags");
+    Ok(level)
+}
+
+/// Replaces the flags of every page in `[virt_start, virt_start + len)` with `new_flags`, like
+/// `mprotect`. Nothing is changed unless the whole range is mapped by pages that are inside it,
+/// and the W^X policy allows `new_flags`.
+pub fn protect_range(
+    root: &mut PageTable,
+    virt_start: usize,
+    len: usize,
+    new_flags: &PageEntryFlags,
+) -> Result<(), MemoryError> {
+    let end = virt_start
+        .checked_add(len)
+        .ok_or(MemoryError::RangeOverflow {
+            virt: virt_start,
+            len,
+        })?;
+    check_leaf_flags(new_flags)?;
+    check_wx(virt_start, new_flags)?;
+
+    // Check the whole range before changing anything
+    let mut addr = virt_start;
+    while addr < end {
+        let level = translate(root, addr)?.level;
+        let page = addr - addr % level.size();
+        if page < virt_start || page + level.size() > end {
+            // Only part of the page is in the range
+            return Err(MemoryError::Misaligned {
+                addr: page,
+                align: level.size(),
+            });
+        }
+        addr = page + level.size();
+    }
+
+    let mut addr = virt_start;
+    while addr < end {
+        addr += set_leaf_flags(root, addr, new_flags)?.size();
+    }
+    Ok(())
+}
+
+pub fn create_root_table() -> Result<*mut PageTable, MemoryError> {
+    alloc_table()
+}
+
+/// Allocates a zeroed frame for a page table, swapping pages out to make room if needed.
+fn alloc_table() -> Result<*mut PageTable, MemoryError> {
+    let table = swap::alloc_page(PageEntryLevel::KiB4)?;
+    unsafe { core::ptr::write_bytes(table, 0, FRAME_SIZE) };
     FRAMES_ALLOCATOR
         .lock()
-        .zero_alloc(1, PageEntryLevel::KiB4)
-        .cast::<PageTable>()
+        .set_owner(table as usize, 1, FrameOwner::PageTable);
+    Ok(table.cast::<PageTable>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: usize = 1 << 30;
+
+    #[test]
+    fn flags_are_sets_of_bits() {
+        let mut flags = PageEntryFlags::READ_WRITE;
+        assert!(flags.contains(PageEntryFlags::READ));
+        assert!(!flags.contains(PageEntryFlags::READ_EXECUTE));
+        assert!(flags.intersects(PageEntryFlags::READ_EXECUTE));
+        assert!(flags.is_leaf());
+
+        flags.remove(PageEntryFlags::WRITE);
+        flags.insert(PageEntryFlags::ACCESSED);
+        assert_eq!(flags, PageEntryFlags::READ | PageEntryFlags::ACCESSED);
+        assert_eq!(format!("{flags:?}"), "READ | ACCESSED");
+
+        flags.remove(PageEntryFlags::READ);
+        assert!(!flags.is_leaf());
+        assert_eq!(format!("{:?}", PageEntryFlags::EMPTY), "EMPTY");
+    }
+
+    #[test]
+    fn writable_and_executable_pages_follow_the_wx_policy() {
+        let mut root: Box<PageTable> = unsafe { Box::new(core::mem::zeroed()) };
+        let flags = PageEntryFlags::READ_WRITE_EXECUTE;
+        let (phys, virt) = (PhysAddr::new(0), VirtAddr::new(0));
+        assert_eq!(
+            map(&mut root, phys, virt, &flags, PageEntryLevel::GiB1),
+            Err(MemoryError::WritableAndExecutable { virt: 0 })
+        );
+        assert_eq!(
+            check_wx(0, &(PageEntryFlags::READ_EXECUTE | PageEntryFlags::COW)),
+            Err(MemoryError::WritableAndExecutable { virt: 0 })
+        );
+        assert_eq!(check_wx(0, &PageEntryFlags::READ_EXECUTE), Ok(()));
+
+        let phys = PhysAddr::new(2 * GIB); // Never touched
+        let flags = PageEntryFlags::READ_EXECUTE;
+        map(&mut root, phys, virt, &flags, PageEntryLevel::GiB1).unwrap();
+        assert_eq!(
+            update_flags(&mut root, 0, &PageEntryFlags::READ_WRITE_EXECUTE),
+            Err(MemoryError::WritableAndExecutable { virt: 0 })
+        );
+        assert_eq!(
+            translate(&root, 0).unwrap().flags,
+            PageEntryFlags::VALID | flags
+        );
+    }
+
+    #[test]
+    fn swapped_entries_are_not_free() {
+        let mut root: Box<PageTable> = unsafe { Box::new(core::mem::zeroed()) };
+        root.entries[1].set_flags(&PageEntryFlags::SWAPPED);
+
+        assert_eq!(
+            translate(&root, GIB),
+            Err(MemoryError::Swapped { virt: GIB })
+        );
+        assert_eq!(
+            map(
+                &mut root,
+                PhysAddr::new(0),
+                VirtAddr::new(GIB),
+                &PageEntryFlags::READ,
+                PageEntryLevel::GiB1
+            ),
+            Err(MemoryError::AlreadyMapped { virt: GIB })
+        );
+        assert_eq!(
+            crate::memory::fault::handle_fault(&mut root, GIB, crate::memory::fault::Access::Read),
+            Err(MemoryError::Swapped { virt: GIB })
+        );
+    }
+
+    #[test]
+    fn gib_pages_map_and_unmap() {
+        let mut root: Box<PageTable> = unsafe { Box::new(core::mem::zeroed()) };
+
+        let frame = PhysAddr::new(2 * GIB); // Never touched
+        let flags =
+            PageEntryFlags::VALID | PageEntryFlags::READ_WRITE | PageEntryFlags::ACCESSED_DIRTY;
+        map(
+            &mut root,
+            frame,
+            VirtAddr::new(GIB),
+            &flags,
+            PageEntryLevel::GiB1,
+        )
+        .unwrap();
+        assert_eq!(virtual_to_physical(&root, VirtAddr::new(GIB)), Ok(frame));
+        assert_eq!(
+            map(
+                &mut root,
+                frame,
+                VirtAddr::new(GIB),
+                &flags,
+                PageEntryLevel::GiB1
+            ),
+            Err(MemoryError::AlreadyMapped { virt: GIB })
+        );
+
+        // The offset inside the page covers the VPNs under the GiB level too
+        let inside = GIB + 0x12_3456;
+        assert_eq!(
+            virtual_to_physical(&root, VirtAddr::new(inside)),
+            Ok(frame + 0x12_3456)
+        );
+        let translation = translate(&root, inside).unwrap();
+        assert_eq!(translation.level, PageEntryLevel::GiB1);
+        assert_eq!(translation.flags, flags);
+
+        // A GiB page has no tables under it, so there's nothing for unmap to free
+        unmap(&mut root).unwrap();
+    }
+
+    #[test]
+    fn unmap_range_leaves_the_rest_mapped() {
+        let mut root: Box<PageTable> = unsafe { Box::new(core::mem::zeroed()) };
+
+        let frame = PhysAddr::new(2 * GIB); // Never touched
+        for virt in [GIB, 2 * GIB] {
+            map(
+                &mut root,
+                frame,
+                VirtAddr::new(virt),
+                &PageEntryFlags::READ_WRITE,
+                PageEntryLevel::GiB1,
+            )
+            .unwrap();
+        }
+
+        // Part of a GiB page can't be unmapped
+        assert_eq!(
+            unmap_range(&mut root, GIB, FRAME_SIZE).unwrap_err(),
+            MemoryError::Misaligned {
+                addr: GIB,
+                align: GIB
+            }
+        );
+
+        let unmapped = unmap_range(&mut root, 0, 2 * GIB).unwrap();
+        assert_eq!(unmapped.len(), 1);
+        assert_eq!(
+            (unmapped[0].phys, unmapped[0].virt),
+            (frame.as_usize(), GIB)
+        );
+        assert_eq!(
+            virtual_to_physical(&root, VirtAddr::new(GIB)),
+            Err(MemoryError::NotMapped { virt: GIB })
+        );
+        assert_eq!(
+            virtual_to_physical(&root, VirtAddr::new(2 * GIB)),
+            Ok(frame)
+        );
+    }
+
+    #[test]
+    fn protect_range_changes_whole_pages_only() {
+        let mut root: Box<PageTable> = unsafe { Box::new(core::mem::zeroed()) };
+
+        let frame = PhysAddr::new(2 * GIB); // Never touched
+        let flags = PageEntryFlags::READ_WRITE;
+        map(
+            &mut root,
+            frame,
+            VirtAddr::new(GIB),
+            &flags,
+            PageEntryLevel::GiB1,
+        )
+        .unwrap();
+
+        assert_eq!(
+            protect_range(&mut root, GIB, FRAME_SIZE, &PageEntryFlags::READ),
+            Err(MemoryError::Misaligned {
+                addr: GIB,
+                align: GIB
+            })
+        );
+        assert_eq!(
+            protect_range(&mut root, GIB, 2 * GIB, &PageEntryFlags::READ),
+            Err(MemoryError::NotMapped { virt: 2 * GIB })
+        );
+        assert_eq!(
+            protect_range(&mut root, GIB, usize::MAX, &PageEntryFlags::READ),
+            Err(MemoryError::RangeOverflow {
+                virt: GIB,
+                len: usize::MAX
+            })
+        );
+        assert_eq!(
+            translate(&root, GIB).unwrap().flags,
+            PageEntryFlags::VALID | PageEntryFlags::READ_WRITE
+        );
+
+        protect_range(&mut root, GIB, GIB, &PageEntryFlags::READ).unwrap();
+        assert_eq!(
+            translate(&root, GIB).unwrap().flags,
+            PageEntryFlags::VALID | PageEntryFlags::READ
+        );
+    }
 }
@@ -0,0 +1,115 @@
+//! Exports the tree of a page table to Graphviz's DOT language: every table is a node, every
+//! entry that's in use is an edge to the table or the page it points at, and pages are labeled
+//! with where they map to and their flags. Render it with `dot -Tsvg page_tables.dot`.
+
+use super::{mode, PageEntryLevel, PageEntryType, PageTable, PAGE_TABLE_LEN};
+use core::fmt::Write as _;
+use std::{fs, io};
+
+/// Returns the DOT graph of the tables under `root`.
+pub fn to_dot(root: &PageTable) -> String {
+    let mut dot =
+        String::from("digraph page_tables {\n    node [shape=box, fontname=monospace];\n");
+    add_table(&mut dot, root, root, PageEntryLevel::top(), 0);
+    dot.push_str("}\n");
+    dot
+}
+
+/// Writes the DOT graph of the tables under `root` to `path`.
+pub fn write_dot(root: &PageTable, path: &str) -> io::Result<()> {
+    fs::write(path, to_dot(root))
+}
+
+/// Adds the node of `table`, whose entries map pages of `level` from `base`, and everything under
+/// it.
+fn add_table(
+    dot: &mut String,
+    root: &PageTable,
+    table: &PageTable,
+    level: PageEntryLevel,
+    base: usize,
+) {
+    let table_addr = table as *const PageTable as usize;
+    let node = format!("table_{table_addr:X}");
+    writeln!(
+        dot,
+        "    {node} [label=\"Level {} table\\n{table_addr:#X}\"];",
+        level.val()
+    )
+    .unwrap();
+
+    for index in 0..PAGE_TABLE_LEN {
+        let entry = &table.entries[index];
+        let page = base + index * level.size();
+        let virt = mode().canonical(page);
+        match entry.get_type() {
+            PageEntryType::Invalid if entry.is_swapped() => {
+                writeln!(
+                    dot,
+                    "    {node}_{index} [label=\"{virt:#X}\\nswapped to {:#X}\", style=dashed];",
+                    entry.get_ppn()
+                )
+                .unwrap();
+                writeln!(dot, "    {node} -> {node}_{index} [label=\"{index}\"];").unwrap();
+            }
+            PageEntryType::Invalid => {}
+            PageEntryType::Leaf => {
+                writeln!(
+                    dot,
+                    "    {node}_{index} [shape=ellipse, label=\"{virt:#X} -> {:#X}\\n{:?}\"];",
+                    entry.get_ppn(),
+                    entry.flags()
+                )
+                .unwrap();
+                writeln!(dot, "    {node} -> {node}_{index} [label=\"{index}\"];").unwrap();
+            }
+            PageEntryType::Branch(ptr) => {
+                let child = format!("table_{ptr:X}");
+                if ptr == root as *const PageTable as usize {
+                    // The recursive slot points back at the root
+                    writeln!(
+                        dot,
+                        "    {node} -> {child} [label=\"{index}\", style=dashed];"
+                    )
+                    .unwrap();
+                    continue;
+                }
+                writeln!(dot, "    {node} -> {child} [label=\"{index}\"];").unwrap();
+                let subtable = unsafe { &*(ptr as *const PageTable) };
+                add_table(dot, root, subtable, level.next_level().unwrap(), page);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::paging::PageEntryFlags;
+
+    #[test]
+    fn tables_are_nodes_and_entries_are_edges() {
+        let mut root: Box<PageTable> = unsafe { Box::new(core::mem::zeroed()) };
+        let mut table: Box<PageTable> = unsafe { Box::new(core::mem::zeroed()) };
+        let (root_addr, table_addr) = (&*root as *const _ as usize, &*table as *const _ as usize);
+        table.entries[3].set_flags(&(PageEntryFlags::VALID | PageEntryFlags::READ));
+        table.entries[3].set_ppn(0x20_0000);
+        table.entries[4].set_flags(&PageEntryFlags::SWAPPED);
+        root.entries[1].set_branch(table_addr);
+        root.entries[511].set_branch(root_addr);
+
+        let dot = to_dot(&root);
+        // Leaves have an arrow in their label too
+        let edges: Vec<_> = dot
+            .lines()
+            .filter(|line| line.split('[').next().unwrap().contains("->"))
+            .collect();
+        assert_eq!(edges.len(), 4);
+        assert!(dot.contains(&format!(
+            "table_{root_addr:X} -> table_{table_addr:X} [label=\"1\"]"
+        )));
+        assert!(dot.contains("0x40600000 -> 0x200000\\nVALID | READ"));
+        assert!(dot.contains("0x40800000\\nswapped to 0x0"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
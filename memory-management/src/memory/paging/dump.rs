@@ -0,0 +1,134 @@
+//! A compact view of what a page table maps: the pages that map contiguous frames with the same
+//! flags are merged into one range, so a kernel mapped with thousands of 4KiB pages still fits on
+//! a screen.
+
+use super::{mode, PageEntryFlags, PageEntryLevel, PageEntryType, PageTable, PAGE_TABLE_LEN};
+use core::fmt;
+
+/// A range of virtual memory that maps a contiguous range of physical memory with the same flags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MappedRange {
+    pub virt: usize,
+    pub phys: usize,
+    pub len: usize,
+    pub flags: PageEntryFlags,
+}
+
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#018X}-{:#018X} -> {:#X} ({}) {:?}",
+            self.virt,
+            self.virt.wrapping_add(self.len),
+            self.phys,
+            Size(self.len),
+            self.flags
+        )
+    }
+}
+
+/// A size in bytes, in the biggest unit it's a whole number of.
+struct Size(usize);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+        let power = (0..UNITS.len())
+            .rev()
+            .find(|power| self.0.is_multiple_of(1 << (10 * power)))
+            .unwrap();
+        let (size, unit) = (self.0 >> (10 * power), UNITS[power]);
+        write!(f, "{size}{unit}")
+    }
+}
+
+/// Returns the ranges that `root` maps, in order of address, with the ranges that continue each
+/// other merged.
+pub fn mapped_ranges(root: &PageTable) -> Vec<MappedRange> {
+    let mut ranges = Vec::new();
+    collect(root, root, PageEntryLevel::top(), 0, &mut ranges);
+    ranges
+}
+
+/// Adds the pages mapped under `table`, whose entries map pages of `level` from `base`.
+fn collect(
+    root: &PageTable,
+    table: &PageTable,
+    level: PageEntryLevel,
+    base: usize,
+    ranges: &mut Vec<MappedRange>,
+) {
+    for index in 0..PAGE_TABLE_LEN {
+        let entry = &table.entries[index];
+        let page = base + index * level.size();
+        match entry.get_type() {
+            PageEntryType::Invalid => {}
+            PageEntryType::Leaf => {
+                let range = MappedRange {
+                    virt: mode().canonical(page),
+                    phys: entry.get_ppn(),
+                    len: level.size(),
+                    flags: entry.flags(),
+                };
+                match ranges.last_mut() {
+                    Some(last)
+                        if last.virt.wrapping_add(last.len) == range.virt
+                            && last.phys + last.len == range.phys
+                            && last.flags == range.flags =>
+                    {
+                        last.len += range.len
+                    }
+                    _ => ranges.push(range),
+                }
+            }
+            PageEntryType::Branch(ptr) => {
+                if ptr == root as *const PageTable as usize {
+                    continue; // The recursive slot points back at the root
+                }
+                let subtable = unsafe { &*(ptr as *const PageTable) };
+                collect(root, subtable, level.next_level().unwrap(), page, ranges);
+            }
+        }
+    }
+}
+
+/// Prints the ranges that `root` maps, merged like `mapped_ranges`.
+pub fn dump(root: &PageTable) {
+    let ranges = mapped_ranges(root);
+    println!("- {} mapped ranges:", ranges.len());
+    for range in ranges {
+        println!("- {range}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contiguous_pages_with_the_same_flags_merge() {
+        const GIB: usize = 1 << 30;
+        let mut root: Box<PageTable> = unsafe { Box::new(core::mem::zeroed()) };
+        let flags = PageEntryFlags::VALID | PageEntryFlags::READ;
+        for (index, phys, flags) in [
+            (1, 4 * GIB, flags),
+            (2, 5 * GIB, flags),
+            (3, 6 * GIB, flags | PageEntryFlags::WRITE),
+            (5, 7 * GIB, flags),
+        ] {
+            root.entries[index].set_flags(&flags);
+            root.entries[index].set_ppn(phys);
+        }
+
+        let ranges = mapped_ranges(&root);
+        assert_eq!(
+            ranges.iter().map(|r| (r.virt, r.len)).collect::<Vec<_>>(),
+            [(GIB, 2 * GIB), (3 * GIB, GIB), (5 * GIB, GIB)]
+        );
+        assert_eq!(
+            ranges[0].to_string(),
+            "0x0000000040000000-0x00000000C0000000 -> 0x100000000 (2GiB) VALID | READ"
+        );
+    }
+}
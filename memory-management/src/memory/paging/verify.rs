@@ -0,0 +1,264 @@
+//! Checks the structure of a page table: that every branch points at a page table of its own, and
+//! every entry is one the hardware would accept. The mutators of this module run it after every
+//! change in debug builds once `set_verify_mutations` turns it on, so a broken table is caught
+//! where it's broken instead of on a later walk.
+
+use super::{mode, PageEntry, PageEntryFlags, PageEntryLevel, PageEntryType, PageTable};
+use crate::memory::{
+    frames::{FrameOwner, FRAMES_ALLOCATOR},
+    layout,
+};
+use core::fmt;
+use spin::Mutex;
+use std::collections::BTreeSet;
+
+/// Whether the mutators verify the tables after changing them.
+static VERIFY_MUTATIONS: Mutex<bool> = Mutex::new(false);
+
+/// The bits of an entry above the PPN, which must be zero (Svpbmt and Svnapot aren't supported).
+const RESERVED_BITS: u64 = !0 << 54;
+
+/// The flags a branch may have. The rest are only meaningful in leaves, and are reserved in
+/// branches.
+const BRANCH_FLAGS: PageEntryFlags =
+    PageEntryFlags(PageEntryFlags::VALID.0 | PageEntryFlags::GLOBAL.0);
+
+/// An entry that breaks the structure of the page table. `virt` is the first address the entry
+/// covers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Violation {
+    /// A branch at the last level, where there is no smaller page for a table to hold
+    BranchAtLastLevel { virt: usize },
+    /// A branch with flags that are reserved in branches (like `USER` or `ACCESSED`)
+    BranchWithLeafFlags { virt: usize, flags: PageEntryFlags },
+    /// A branch that points at a frame which isn't an allocated page table
+    NotATable { virt: usize, table: usize },
+    /// A table that two branches point at, so changing one part of the tree changes the other
+    SharedTable { virt: usize, table: usize },
+    /// A leaf that is writable but not readable, which the hardware reserves
+    WritableNotReadable { virt: usize },
+    /// A superpage whose frames aren't aligned to its size
+    MisalignedSuperpage {
+        virt: usize,
+        phys: usize,
+        level: PageEntryLevel,
+    },
+    /// A leaf that maps memory outside of the frames allocator's and the kernel's image
+    OutsideMemory { virt: usize, phys: usize },
+    /// An entry with some of the reserved bits above the PPN set
+    ReservedBits { virt: usize, bits: u64 },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::BranchAtLastLevel { virt } => {
+                write!(f, "The entry of {virt:#X} is a branch at the last level")
+            }
+            Violation::BranchWithLeafFlags { virt, flags } => {
+                write!(f, "The branch of {virt:#X} has leaf flags: {flags:?}")
+            }
+            Violation::NotATable { virt, table } => write!(
+                f,
+                "The branch of {virt:#X} points at {table:#X}, which isn't a page table"
+            ),
+            Violation::SharedTable { virt, table } => write!(
+                f,
+                "The branch of {virt:#X} points at {table:#X}, which another branch points at"
+            ),
+            Violation::WritableNotReadable { virt } => {
+                write!(f, "The page at {virt:#X} is writable but not readable")
+            }
+            Violation::MisalignedSuperpage { virt, phys, level } => write!(
+                f,
+                "The {level:?} page at {virt:#X} maps {phys:#X}, which isn't aligned to it"
+            ),
+            Violation::OutsideMemory { virt, phys } => write!(
+                f,
+                "The page at {virt:#X} maps {phys:#X}, which is outside of memory"
+            ),
+            Violation::ReservedBits { virt, bits } => {
+                write!(f, "The entry of {virt:#X} has reserved bits set: {bits:#X}")
+            }
+        }
+    }
+}
+
+/// Walks every table under `root`, and returns the first entry that breaks the structure of the
+/// page table, if there is one.
+pub fn verify(root: &PageTable) -> Result<(), Violation> {
+    let mut tables = BTreeSet::from([root as *const PageTable as usize]);
+    verify_table(root, root, PageEntryLevel::top(), 0, &mut tables)
+}
+
+/// Selects whether `map`, `unmap_range`, `update_flags` and `swap_in_page` verify the tables they
+/// change. Only debug builds verify, since it walks the whole tree every time.
+pub fn set_verify_mutations(enabled: bool) {
+    *VERIFY_MUTATIONS.lock() = enabled;
+}
+
+/// Panics if `root` breaks the structure of the page table after `operation` changed it, when
+/// verifying mutations is on.
+pub(crate) fn verify_mutation(root: &PageTable, operation: &str) {
+    if !cfg!(debug_assertions) || !*VERIFY_MUTATIONS.lock() {
+        return;
+    }
+    if let Err(violation) = verify(root) {
+        panic!("{operation} broke the page table at {root:p}: {violation}");
+    }
+}
+
+/// Verifies the entries of `table`, whose entries map pages of `level` from `base`, and the
+/// tables under it. `tables` are the tables that were reached so far.
+fn verify_table(
+    root: &PageTable,
+    table: &PageTable,
+    level: PageEntryLevel,
+    base: usize,
+    tables: &mut BTreeSet<usize>,
+) -> Result<(), Violation> {
+    for (index, entry) in table.entries.iter().enumerate() {
+        let page = base + index * level.size();
+        let virt = mode().canonical(page);
+
+        let bits = entry.bits() & RESERVED_BITS;
+        if bits != 0 && (entry.is_valid() || entry.is_swapped()) {
+            return Err(Violation::ReservedBits { virt, bits });
+        }
+
+        match entry.get_type() {
+            PageEntryType::Invalid => {}
+            PageEntryType::Leaf => verify_leaf(entry, level, virt)?,
+            PageEntryType::Branch(ptr) => {
+                if ptr == root as *const PageTable as usize {
+                    continue; // The recursive slot points back at the root
+                }
+                let Some(next_level) = level.next_level() else {
+                    return Err(Violation::BranchAtLastLevel { virt });
+                };
+                let flags = entry.flags();
+                if !BRANCH_FLAGS.contains(flags) {
+                    return Err(Violation::BranchWithLeafFlags { virt, flags });
+                }
+
+                let info = FRAMES_ALLOCATOR.lock().info(ptr);
+                if !info
+                    .is_some_and(|info| info.owner == FrameOwner::PageTable && info.refcount > 0)
+                {
+                    return Err(Violation::NotATable { virt, table: ptr });
+                }
+                if !tables.insert(ptr) {
+                    return Err(Violation::SharedTable { virt, table: ptr });
+                }
+
+                let subtable = unsafe { &*(ptr as *const PageTable) };
+                verify_table(root, subtable, next_level, page, tables)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn verify_leaf(entry: &PageEntry, level: PageEntryLevel, virt: usize) -> Result<(), Violation> {
+    let flags = entry.flags();
+    if flags.contains(PageEntryFlags::WRITE) && !flags.contains(PageEntryFlags::READ) {
+        return Err(Violation::WritableNotReadable { virt });
+    }
+
+    let phys = entry.get_ppn();
+    if !phys.is_multiple_of(level.size()) {
+        return Err(Violation::MisalignedSuperpage { virt, phys, level });
+    }
+
+    // The kernel's image was loaded before the frames allocator took over the rest of memory
+    let last = phys + level.size() - 1;
+    let image = layout::layout().image();
+    let kernel = image.start..image.end;
+    let mut frames = FRAMES_ALLOCATOR.lock();
+    let managed = frames.info(phys).is_some() && frames.info(last).is_some();
+    let in_kernel = kernel.contains(&phys) && kernel.contains(&last);
+    if !managed && !in_kernel {
+        return Err(Violation::OutsideMemory { virt, phys });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{
+        addr::{PhysAddr, VirtAddr},
+        alloc_frames_aligned,
+        consts::FRAME_SIZE,
+        dealloc_frames, paging,
+        tests::frames,
+    };
+
+    #[test]
+    fn broken_tables_are_caught() {
+        let _frames = frames();
+        let root = unsafe { &mut *paging::create_root_table().unwrap() };
+        let frame = alloc_frames_aligned(1, FRAME_SIZE).unwrap();
+        let virt = 0x4000_0000;
+        let flags = PageEntryFlags::READ_WRITE;
+        paging::map(
+            root,
+            frame,
+            VirtAddr::new(virt),
+            &flags,
+            PageEntryLevel::KiB4,
+        )
+        .unwrap();
+        paging::map(
+            root,
+            PhysAddr::new(layout::layout().text.start),
+            VirtAddr::new(virt + FRAME_SIZE),
+            &PageEntryFlags::READ_EXECUTE,
+            PageEntryLevel::KiB4,
+        )
+        .unwrap();
+        assert_eq!(verify(root), Ok(()));
+
+        // A leaf past the end of memory
+        let leaf: *mut PageEntry = paging::leaf_entry(root, virt).unwrap().0;
+        unsafe { (*leaf).set_ppn(usize::MAX >> 20 << 12) };
+        assert!(matches!(
+            verify(root),
+            Err(Violation::OutsideMemory {
+                virt: 0x4000_0000,
+                ..
+            })
+        ));
+        unsafe { (*leaf).set_ppn(frame.as_usize()) };
+
+        // A branch that points at the mapped frame instead of a table
+        let vpn2 = (virt >> 30) & 0x1FF;
+        let table = root.entries[vpn2].get_ppn();
+        root.entries[vpn2].set_ppn(frame.as_usize());
+        assert_eq!(
+            verify(root),
+            Err(Violation::NotATable {
+                virt,
+                table: frame.as_usize()
+            })
+        );
+
+        // Two branches that share a table
+        root.entries[vpn2].set_ppn(table);
+        root.entries[vpn2 + 1].set_branch(table);
+        assert!(matches!(verify(root), Err(Violation::SharedTable { .. })));
+        root.entries[vpn2 + 1].set_flags(&PageEntryFlags::EMPTY);
+
+        root.entries[vpn2].set_flags(&(PageEntryFlags::VALID | PageEntryFlags::USER));
+        assert!(matches!(
+            verify(root),
+            Err(Violation::BranchWithLeafFlags { .. })
+        ));
+        root.entries[vpn2].set_flags(&PageEntryFlags::VALID);
+        assert_eq!(verify(root), Ok(()));
+
+        paging::unmap_range(root, virt, 2 * FRAME_SIZE).unwrap();
+        dealloc_frames(frame, 1).unwrap();
+        dealloc_frames(PhysAddr::from_ptr(root), 1).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+//! Recursive page table mapping: one slot of the root table points back at the root itself, which
+//! makes every page table reachable through a window of virtual addresses at the top of the
+//! address space, instead of dereferencing the tables' physical addresses directly.
+
+use super::{
+    error::MemoryError,
+    paging::{self, PageEntry, PageEntryLevel, PageEntryType, PageTable, PagingMode, Translation},
+};
+
+/// The root slot which points back at the root (the top root entry's worth of the address space).
+pub const RECURSIVE_INDEX: usize = 511;
+
+const ENTRY_SIZE: usize = 8;
+
+/// Makes the root table map itself through `RECURSIVE_INDEX`.
+pub fn enable(root: &mut PageTable) {
+    let root_addr = root as *mut PageTable as usize;
+    let entry = &mut root.entries[RECURSIVE_INDEX];
+    assert!(entry.is_invalid(), "The recursive slot is already in use");
+
+    entry.set_branch(root_addr);
+}
+
+pub fn is_enabled(root: &PageTable) -> bool {
+    let entry = &root.entries[RECURSIVE_INDEX];
+    entry.is_valid() && entry.is_branch() && entry.get_ppn() == root as *const PageTable as usize
+}
+
+/// Returns the virtual address through which the entry that maps `virt` at `level` can be accessed.
+///
+/// Every pass through the recursive slot "removes" one level of the walk, so the table that holds
+/// the entry ends up being mapped as if it was a regular page.
+pub fn entry_address(virt: usize, level: PageEntryLevel) -> usize {
+    window_address(virt, level, paging::mode())
+}
+
+/// Returns the address in the recursive window of `mode` of the entry that maps `virt` at `level`.
+///
+/// The top `level + 1` VPNs go through the recursive slot, and the VPNs of `virt` above `level`
+/// are shifted down to make up the rest of the walk.
+fn window_address(virt: usize, level: PageEntryLevel, mode: PagingMode) -> usize {
+    let vpns = PageEntry::extract_all_vpns(virt);
+    let levels = mode.levels();
+    let recursive_levels = level.val() + 1;
+
+    let addr = (0..levels).fold(vpns[level.val()] * ENTRY_SIZE, |addr, i| {
+        let vpn = if i >= levels - recursive_levels {
+            RECURSIVE_INDEX
+        } else {
+            vpns[i + recursive_levels]
+        };
+        addr | (vpn << (12 + 9 * i))
+    });
+    mode.canonical(addr)
+}
+
+/// Accesses the entry that maps `virt` at `level` through the recursive window.
+///
+/// Returns None if the table holding that entry doesn't exist (e.g. it is covered by a superpage).
+pub fn entry(
+    root: &PageTable,
+    virt: usize,
+    level: PageEntryLevel,
+) -> Option<&'static mut PageEntry> {
+    assert!(is_enabled(root), "Recursive mapping is not enabled");
+
+    let entry_addr = translate_window(root, entry_address(virt, level))?;
+    unsafe { (entry_addr as *mut PageEntry).as_mut() }
+}
+
+/// Walks the page table for `virt` like `paging::translate`, but reads the entry of every level
+/// through the recursive window instead of following the tables' physical addresses, the way a
+/// kernel does once paging is on.
+pub fn translate(root: &PageTable, virt: usize) -> Result<Translation, MemoryError> {
+    let mut level = PageEntryLevel::top();
+    loop {
+        // The entries above are branches, so the table that holds this one exists
+        let entry = entry(root, virt, level).unwrap();
+        match (entry.get_type(), level.next_level()) {
+            (PageEntryType::Leaf, _) => {
+                return Ok(Translation {
+                    phys: entry.get_ppn() | (virt & (level.size() - 1)),
+                    level,
+                    flags: entry.flags(),
+                })
+            }
+            (PageEntryType::Branch(_), Some(next_level)) => level = next_level,
+            (PageEntryType::Invalid, _) if entry.is_swapped() => {
+                return Err(MemoryError::Swapped { virt })
+            }
+            _ => return Err(MemoryError::NotMapped { virt }),
+        }
+    }
+}
+
+/// Walks the page table for an address inside the recursive window.
+///
+/// Sv39 (like Sv48 and Sv57) only allows leaves at the last level, so a real MMU would fault on
+/// the final branch entry. Like x86 does, the simulated walk treats it as a 4KiB page that maps the next table.
+fn translate_window(root: &PageTable, virt: usize) -> Option<usize> {
+    let mut table_addr = root as *const PageTable as usize;
+
+    for vpn in PageEntry::extract_vpns(virt).rev() {
+        let table = unsafe { (table_addr as *const PageTable).as_ref().unwrap() };
+        let entry = &table.entries[vpn];
+
+        // Only branches lead to page tables, anything else means the table doesn't exist
+        if entry.is_invalid() || entry.is_leaf() {
+            return None;
+        }
+        table_addr = entry.get_ppn();
+    }
+
+    Some(table_addr + (virt & 0xFFF))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{
+        addr::{PhysAddr, VirtAddr},
+        alloc_frames_aligned,
+        consts::FRAME_SIZE,
+        dealloc_frames,
+        paging::PageEntryFlags,
+        tests::frames,
+    };
+
+    #[test]
+    fn translations_through_the_window_match_the_walk() {
+        let _frames = frames();
+        let root = unsafe { &mut *paging::create_root_table().unwrap() };
+        let frame = alloc_frames_aligned(1, FRAME_SIZE).unwrap();
+        let virt = 0x4000_0000;
+        let flags = PageEntryFlags::READ_WRITE;
+        paging::map(
+            root,
+            frame,
+            VirtAddr::new(virt),
+            &flags,
+            PageEntryLevel::KiB4,
+        )
+        .unwrap();
+        enable(root);
+
+        assert_eq!(
+            translate(root, virt + 0x123),
+            paging::translate(root, virt + 0x123)
+        );
+        assert_eq!(
+            translate(root, virt + 0x123).unwrap().phys,
+            frame.as_usize() + 0x123
+        );
+        assert_eq!(
+            translate(root, virt + FRAME_SIZE),
+            Err(MemoryError::NotMapped {
+                virt: virt + FRAME_SIZE
+            })
+        );
+        assert!(translate(root, 0x8000_0000).is_err());
+
+        root.entries[RECURSIVE_INDEX].set_flags(&PageEntryFlags::EMPTY);
+        paging::unmap_range(root, virt, FRAME_SIZE).unwrap();
+        dealloc_frames(frame, 1).unwrap();
+        dealloc_frames(PhysAddr::from_ptr(root), 1).unwrap();
+    }
+
+    #[test]
+    fn window_addresses_of_every_mode() {
+        let virt = 0x20000;
+
+        // Sv39: [511, vpn2, vpn1] and vpn0 as the index
+        assert_eq!(
+            window_address(virt, PageEntryLevel::KiB4, PagingMode::Sv39),
+            0xFFFF_FFFF_C000_0000 | (0x20 * ENTRY_SIZE)
+        );
+        assert_eq!(
+            window_address(virt, PageEntryLevel::GiB1, PagingMode::Sv39),
+            0xFFFF_FFFF_FFFF_F000
+        );
+
+        // Sv48 adds a level on top of every walk
+        assert_eq!(
+            window_address(virt, PageEntryLevel::KiB4, PagingMode::Sv48),
+            0xFFFF_FF80_0000_0000 | (0x20 * ENTRY_SIZE)
+        );
+        assert_eq!(
+            window_address(virt, PageEntryLevel::GiB512, PagingMode::Sv48),
+            0xFFFF_FFFF_FFFF_F000
+        );
+        assert_eq!(
+            window_address(virt, PageEntryLevel::TiB256, PagingMode::Sv57),
+            0xFFFF_FFFF_FFFF_F000
+        );
+    }
+}
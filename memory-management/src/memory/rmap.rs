@@ -0,0 +1,119 @@
+//! The reverse map: which page table entries map every physical frame, so a frame can be evicted
+//! (by clearing every entry that maps it) and "who maps this frame?" can be answered.
+
+use super::{consts::FRAME_SIZE, error::MemoryError, paging::PageEntryLevel};
+use spin::Mutex;
+
+/// The number of leaf mappings the reverse map can hold.
+const MAX_MAPPINGS: usize = 4096;
+
+pub static REVERSE_MAP: Mutex<ReverseMap> = Mutex::new(ReverseMap::new());
+
+/// A leaf entry that maps a page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mapping {
+    /// The physical address of the page
+    pub phys: usize,
+    pub virt: usize,
+    pub level: PageEntryLevel,
+    /// The address of the page table entry
+    pub entry: usize,
+}
+
+impl Mapping {
+    /// Returns true if the page holds the frame at `frame`.
+    pub fn maps(&self, frame: usize) -> bool {
+        (self.phys..self.phys + self.level.size()).contains(&frame)
+    }
+}
+
+pub struct ReverseMap {
+    mappings: [Option<Mapping>; MAX_MAPPINGS],
+    len: usize,
+}
+
+impl ReverseMap {
+    pub const fn new() -> Self {
+        Self {
+            mappings: [None; MAX_MAPPINGS],
+            len: 0,
+        }
+    }
+
+    /// Records that the entry at `mapping.entry` maps `mapping.phys`.
+    pub fn insert(&mut self, mapping: Mapping) -> Result<(), MemoryError> {
+        let slot = self
+            .mappings
+            .get_mut(self.len)
+            .ok_or(MemoryError::ReverseMapFull)?;
+        *slot = Some(mapping);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Forgets the mappings whose entries are in `[start, end)`, e.g. the entries of a freed page
+    /// table.
+    pub fn remove_entries(&mut self, start: usize, end: usize) {
+        let mut i = 0;
+        while i < self.len {
+            let entry = self.mappings[i].unwrap().entry;
+            if (start..end).contains(&entry) {
+                // Move the last mapping into the hole
+                self.len -= 1;
+                self.mappings[i] = self.mappings[self.len].take();
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Returns every mapping, in the order they are kept in.
+    pub fn iter(&self) -> impl Iterator<Item = Mapping> + '_ {
+        self.mappings[..self.len].iter().flatten().copied()
+    }
+
+    /// Returns the mappings of the page that holds the frame at `frame`.
+    pub fn mappers(&self, frame: usize) -> impl Iterator<Item = Mapping> + '_ {
+        let frame = frame - frame % FRAME_SIZE;
+        self.iter().filter(move |mapping| mapping.maps(frame))
+    }
+}
+
+/// Returns the mappings of the page that holds the frame at `frame`.
+pub fn who_maps(frame: usize) -> Vec<Mapping> {
+    REVERSE_MAP.lock().mappers(frame).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(phys: usize, virt: usize, entry: usize, level: PageEntryLevel) -> Mapping {
+        Mapping {
+            phys,
+            virt,
+            level,
+            entry,
+        }
+    }
+
+    #[test]
+    fn finds_every_mapper_of_a_frame() {
+        let mut rmap = ReverseMap::new();
+        let shared = mapping(0x10000, 0x1000, 0xA000, PageEntryLevel::KiB4);
+        let alias = mapping(0x10000, 0x5000, 0xA028, PageEntryLevel::KiB4);
+        let huge = mapping(0x200000, 0x200000, 0xB008, PageEntryLevel::MiB2);
+        for m in [shared, alias, huge] {
+            rmap.insert(m).unwrap();
+        }
+
+        assert_eq!(rmap.mappers(0x10123).collect::<Vec<_>>(), [shared, alias]);
+        assert_eq!(rmap.mappers(0x3FF000).collect::<Vec<_>>(), [huge]);
+        assert_eq!(rmap.mappers(0x20000).count(), 0);
+
+        // Freeing the table that holds the first two entries forgets them
+        rmap.remove_entries(0xA000, 0xB000);
+        assert_eq!(rmap.mappers(0x10000).count(), 0);
+        assert_eq!(rmap.mappers(0x200000).count(), 1);
+    }
+}
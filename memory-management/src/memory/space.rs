@@ -0,0 +1,1005 @@
+//! Address spaces: a root page table with the areas that may be mapped in it, so every process can
+//! have its own view of memory.
+
+mod asid;
+mod vma;
+
+pub use asid::{asid_generation, asids_disabled, set_asid_bits};
+pub use vma::{Backing, Vma, VmaList};
+
+use super::{
+    addr::{PhysAddr, VirtAddr},
+    consts::FRAME_SIZE,
+    disk,
+    error::MemoryError,
+    fault::{self, Access},
+    frames::{FrameOwner, FRAMES_ALLOCATOR},
+    paging::{self, PageEntryFlags, PageEntryLevel, PageTable, Translation},
+    swap, tlb,
+};
+use asid::Asid;
+use core::ptr;
+
+/// How large stacks may grow by default, like the default `ulimit -s`.
+pub const DEFAULT_STACK_LIMIT: usize = 8 * 1024 * 1024;
+
+/// Where `mmap` starts looking for room for mappings.
+const MMAP_BASE: usize = 0x10_0000_0000;
+
+/// Where `mmap` puts a mapping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    /// Wherever there is room
+    Any,
+    /// At the address if there is room there, and wherever there is room if there isn't
+    Hint(usize),
+    /// At the address, in place of whatever was mapped there (`MAP_FIXED`)
+    Fixed(usize),
+}
+
+pub struct AddressSpace {
+    root: *mut PageTable,
+    asid: Asid,
+    areas: VmaList,
+    /// How large the stack areas may grow down to on faults below them
+    stack_limit: usize,
+    /// Where the heap starts, once it was started
+    heap_start: Option<usize>,
+    /// The end of the heap (the program break), which may be in the middle of its last page
+    brk: usize,
+}
+
+impl AddressSpace {
+    /// Creates an address space with nothing mapped in it.
+    pub fn new() -> Result<Self, MemoryError> {
+        Ok(Self {
+            root: paging::create_root_table()?,
+            asid: asid::alloc_asid(),
+            areas: VmaList::default(),
+            stack_limit: DEFAULT_STACK_LIMIT,
+            heap_start: None,
+            brk: 0,
+        })
+    }
+
+    /// Returns the ASID the address space got last, which may be of an old generation.
+    pub fn asid(&self) -> u16 {
+        self.asid.value
+    }
+
+    /// Returns the ASID of the address space, and gives it a new one first if its ASID is of an
+    /// old generation (and may be another address space's by now).
+    pub fn current_asid(&mut self) -> u16 {
+        self.asid = asid::refresh(self.asid);
+        self.asid.value
+    }
+
+    pub fn root(&mut self) -> &mut PageTable {
+        unsafe { self.root.as_mut() }.unwrap()
+    }
+
+    pub fn areas(&self) -> &[Vma] {
+        self.areas.as_slice()
+    }
+
+    /// Sets how large (in bytes) the stack areas may grow.
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stack_limit = limit;
+    }
+
+    /// Returns a line for every area, like `/proc/<pid>/maps`.
+    pub fn maps(&self) -> String {
+        self.areas.iter().map(|vma| format!("{vma}\n")).collect()
+    }
+
+    /// Adds an area of `len` bytes (rounded up to whole pages of `level`) at `virt`, without
+    /// mapping it. Its pages are mapped when they are first accessed.
+    pub fn reserve(
+        &mut self,
+        virt: usize,
+        len: usize,
+        flags: PageEntryFlags,
+        level: PageEntryLevel,
+        backing: Backing,
+    ) -> Result<(), MemoryError> {
+        level.check_aligned(virt)?;
+        let vma = Vma {
+            start: virt,
+            end: virt + len.div_ceil(level.size()) * level.size(),
+            flags,
+            level,
+            backing,
+        };
+        paging::assert_user_mapping(vma.start, vma.end, &flags);
+        self.areas
+            .insert(vma)
+            .map_err(|other| MemoryError::AlreadyMapped {
+                virt: other.start.max(virt),
+            })
+    }
+
+    /// Adds an area like `reserve`, and maps all of its pages to fresh zeroed frames right away.
+    pub fn map(
+        &mut self,
+        virt: usize,
+        len: usize,
+        flags: PageEntryFlags,
+        level: PageEntryLevel,
+        backing: Backing,
+    ) -> Result<(), MemoryError> {
+        self.reserve(virt, len, flags, level, backing)?;
+        let vma = *self.areas.find(virt).unwrap();
+
+        for page in vma.pages() {
+            if let Err(error) = self.map_fresh_page(page, &flags, level) {
+                // Take back the pages that were mapped before, and the area
+                self.release_range(vma.start, page)?;
+                self.areas.remove_range(vma.start, vma.end);
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn map_fresh_page(
+        &mut self,
+        page: usize,
+        flags: &PageEntryFlags,
+        level: PageEntryLevel,
+    ) -> Result<(), MemoryError> {
+        let frame = swap::alloc_page(level)?;
+        FRAMES_ALLOCATOR.lock().set_owner(
+            frame as usize,
+            level.size() / FRAME_SIZE,
+            FrameOwner::AddressSpace,
+        );
+        unsafe { ptr::write_bytes(frame, 0, level.size()) };
+
+        let (phys, virt) = (PhysAddr::new(frame as usize), VirtAddr::new(page));
+        paging::map(self.root(), phys, virt, flags, level).inspect_err(|_| {
+            FRAMES_ALLOCATOR
+                .lock()
+                .dealloc(frame as usize, 1, level)
+                .expect("Failed to free the frame that was just allocated.");
+        })
+    }
+
+    /// Maps `page` to the zero frame, copy-on-write if it's writable.
+    fn map_zero_page(&mut self, page: usize, mut flags: PageEntryFlags) -> Result<(), MemoryError> {
+        if flags.contains(PageEntryFlags::WRITE) {
+            flags.remove(PageEntryFlags::WRITE);
+            flags.insert(PageEntryFlags::COW);
+        }
+
+        let zero = fault::zero_frame()?;
+        let (phys, virt) = (PhysAddr::new(zero), VirtAddr::new(page));
+        paging::map(self.root(), phys, virt, &flags, PageEntryLevel::KiB4)?;
+        FRAMES_ALLOCATOR.lock().share(zero);
+        Ok(())
+    }
+
+    /// Unmaps `[virt, virt + len)` and releases the frames that were mapped there. The areas that
+    /// are only partly unmapped shrink or split.
+    pub fn unmap(&mut self, virt: usize, len: usize) -> Result<(), MemoryError> {
+        self.release_range(virt, virt + len)?;
+        self.areas.remove_range(virt, virt + len);
+        Ok(())
+    }
+
+    /// Maps `len` bytes (rounded up to whole pages) of `backing` with `flags` like `mmap`, and
+    /// returns where. Nothing is mapped until it's accessed, so the pages of files are read in
+    /// by the fault handler.
+    ///
+    /// A fixed mapping replaces what was mapped there, once it's known to be valid: in the lower
+    /// half, and not global.
+    pub fn mmap(
+        &mut self,
+        len: usize,
+        flags: PageEntryFlags,
+        backing: Backing,
+        placement: Placement,
+    ) -> Result<usize, MemoryError> {
+        let len = len.next_multiple_of(FRAME_SIZE);
+        let limit = paging::mode().lower_half_end();
+        if flags.contains(PageEntryFlags::GLOBAL) {
+            return Err(MemoryError::InvalidFlags { flags });
+        }
+        let virt = match placement {
+            Placement::Fixed(virt) => {
+                PageEntryLevel::KiB4.check_aligned(virt)?;
+                if virt.checked_add(len).is_none_or(|end| end > limit) {
+                    return Err(MemoryError::NotInLowerHalf { virt, len });
+                }
+                self.unmap(virt, len)?;
+                virt
+            }
+            Placement::Hint(hint)
+                if hint % FRAME_SIZE == 0
+                    && self.areas.find_gap(hint, len, limit) == Some(hint) =>
+            {
+                hint
+            }
+            _ => self
+                .areas
+                .find_gap(MMAP_BASE, len, limit)
+                .ok_or(MemoryError::AddressSpaceFull { len })?,
+        };
+        self.reserve(virt, len, flags, PageEntryLevel::KiB4, backing)?;
+        Ok(virt)
+    }
+
+    /// Unmaps `[virt, virt + len)` like `munmap`: `virt` has to be page aligned, and `len` is
+    /// rounded up to whole pages.
+    pub fn munmap(&mut self, virt: usize, len: usize) -> Result<(), MemoryError> {
+        PageEntryLevel::KiB4.check_aligned(virt)?;
+        self.unmap(virt, len.next_multiple_of(FRAME_SIZE))
+    }
+
+    /// Starts an empty heap at `start`, that `brk` and `sbrk` grow up from, like `exec` does right
+    /// after the data.
+    pub fn start_heap(&mut self, start: usize) -> Result<(), MemoryError> {
+        PageEntryLevel::KiB4.check_aligned(start)?;
+        if self.areas.find(start).is_some() {
+            return Err(MemoryError::AlreadyMapped { virt: start });
+        }
+        self.heap_start = Some(start);
+        self.brk = start;
+        Ok(())
+    }
+
+    /// Moves the end of the heap to `new_end`, and returns it. The pages the heap grows by are
+    /// mapped to fresh zeroed frames, and the pages it shrinks by are unmapped.
+    ///
+    /// Fails with `NotMapped` if the heap wasn't started or `new_end` is below its start, and with
+    /// `AlreadyMapped` if the heap would run into another area.
+    pub fn brk(&mut self, new_end: usize) -> Result<usize, MemoryError> {
+        let start = self
+            .heap_start
+            .filter(|&start| new_end >= start)
+            .ok_or(MemoryError::NotMapped { virt: new_end })?;
+        let old_top = self.brk.next_multiple_of(FRAME_SIZE);
+        let new_top = new_end.next_multiple_of(FRAME_SIZE);
+        let flags = PageEntryFlags::READ_WRITE | PageEntryFlags::USER;
+
+        if new_top > old_top {
+            if old_top == start {
+                self.reserve(
+                    start,
+                    new_top - start,
+                    flags,
+                    PageEntryLevel::KiB4,
+                    Backing::Heap,
+                )?;
+            } else {
+                paging::assert_user_mapping(start, new_top, &flags);
+                self.areas
+                    .grow_up(start, new_top)
+                    .map_err(|other| MemoryError::AlreadyMapped { virt: other.start })?;
+            }
+
+            for page in (old_top..new_top).step_by(FRAME_SIZE) {
+                if let Err(error) = self.map_fresh_page(page, &flags, PageEntryLevel::KiB4) {
+                    // Shrink the heap back to where it was
+                    self.release_range(old_top, page)?;
+                    self.areas.remove_range(old_top, new_top);
+                    return Err(error);
+                }
+            }
+        } else if new_top < old_top {
+            self.unmap(new_top, old_top - new_top)?;
+        }
+        self.brk = new_end;
+        Ok(new_end)
+    }
+
+    /// Moves the end of the heap by `delta` bytes like `brk`, and returns where it was.
+    pub fn sbrk(&mut self, delta: isize) -> Result<usize, MemoryError> {
+        let old_end = self.brk;
+        let new_end = old_end
+            .checked_add_signed(delta)
+            .ok_or(MemoryError::NotMapped { virt: old_end })?;
+        self.brk(new_end)?;
+        Ok(old_end)
+    }
+
+    /// Unmaps `[start, end)` and releases the frames of the pages, and the swap slots of the pages
+    /// that are swapped out.
+    fn release_range(&mut self, start: usize, end: usize) -> Result<(), MemoryError> {
+        if swap::is_enabled() {
+            let pages: Vec<usize> = self
+                .areas
+                .iter()
+                .filter(|vma| vma.level == PageEntryLevel::KiB4)
+                .flat_map(|vma| (vma.start.max(start)..vma.end.min(end)).step_by(FRAME_SIZE))
+                .collect();
+            for page in pages {
+                swap::discard_page(self.root(), page);
+            }
+        }
+        for page in paging::unmap_range(self.root(), start, end - start)? {
+            FRAMES_ALLOCATOR.lock().release(page.phys, 1, page.level)?;
+        }
+        Ok(())
+    }
+
+    pub fn translate(&mut self, virt: usize) -> Result<Translation, MemoryError> {
+        paging::translate(self.root(), virt)
+    }
+
+    /// Translates `virt` for `access` like the MMU would, handling the fault and retrying once if
+    /// it faults. Like the MMU, it marks the page as accessed, and as dirty if it's written to.
+    pub fn access(&mut self, virt: usize, access: Access) -> Result<usize, MemoryError> {
+        let phys = match fault::check_access(self.root(), virt, access) {
+            Err(MemoryError::PageFault { .. }) => {
+                self.handle_fault(virt, access)?;
+                fault::check_access(self.root(), virt, access)?
+            }
+            result => result?,
+        };
+
+        // The MMU sets the bits without a fence, so the TLB isn't flushed
+        let (entry, _) = paging::leaf_entry(self.root(), virt)?;
+        let mut flags = entry.flags();
+        flags.insert(PageEntryFlags::ACCESSED);
+        if access == Access::Write {
+            flags.insert(PageEntryFlags::DIRTY);
+        }
+        entry.set_flags(&flags);
+        Ok(phys)
+    }
+
+    /// Writes the page at `virt` out to swap and frees its frame, until it's accessed again.
+    pub fn swap_out(&mut self, virt: usize) -> Result<(), MemoryError> {
+        swap::swap_out_page(self.root(), virt)
+    }
+
+    /// Handles a fault on `access` to `virt`: accesses outside of the areas, or that their flags
+    /// don't allow, fail, unless they are just below a stack, which grows down to them. Pages that
+    /// were never accessed are mapped to fresh zeroed frames, or to the shared zero frame if they
+    /// are only read, until they are written to. The pages of files are read from the disk (and
+    /// fail if the file isn't there). Pages that were swapped out are read back in.
+    pub fn handle_fault(&mut self, virt: usize, access: Access) -> Result<(), MemoryError> {
+        let fault = MemoryError::PageFault { virt, access };
+        let (vma, grows_stack) = match self.areas.find(virt) {
+            Some(vma) => (*vma, false),
+            None => (self.stack_growth(virt).ok_or(fault)?, true),
+        };
+        let allowed = match access {
+            Access::Read => PageEntryFlags::READ,
+            Access::Write => PageEntryFlags::WRITE | PageEntryFlags::COW,
+            Access::Execute => PageEntryFlags::EXECUTE,
+        };
+        if !vma.flags.intersects(allowed) {
+            return Err(fault);
+        }
+        if grows_stack {
+            let stack = self.areas.next(virt).unwrap().start;
+            self.areas.grow_down(stack, vma.start).map_err(|_| fault)?;
+        }
+
+        let page = virt - (virt - vma.start) % vma.level.size();
+        match self.translate(virt) {
+            Err(MemoryError::NotMapped { .. }) => match vma.backing {
+                Backing::File { name, offset } => {
+                    let data = disk::read_file(name, offset + page - vma.start, vma.level.size())
+                        .ok_or(fault)?;
+                    self.map_fresh_page(page, &vma.flags, vma.level)?;
+                    let frame = self.translate(page)?.phys;
+                    unsafe {
+                        ptr::copy_nonoverlapping(data.as_ptr(), frame as *mut u8, data.len())
+                    };
+                    Ok(())
+                }
+                _ => {
+                    if access == Access::Write || vma.level != PageEntryLevel::KiB4 {
+                        self.map_fresh_page(page, &vma.flags, vma.level)
+                    } else {
+                        self.map_zero_page(page, vma.flags)
+                    }
+                }
+            },
+            Err(MemoryError::Swapped { .. }) => {
+                swap::swap_in_page(self.root(), virt)?;
+                match fault::check_access(self.root(), virt, access) {
+                    Ok(_) => Ok(()),
+                    // Like a write to a copy-on-write page
+                    Err(_) => fault::handle_fault(self.root(), virt, access),
+                }
+            }
+            _ => fault::handle_fault(self.root(), virt, access),
+        }
+    }
+
+    /// Returns the stack area above `virt`, grown down to the page of `virt`, if `virt` is in the
+    /// gap below it and the stack may grow that far. A guard page is kept between the stack and
+    /// the area below it, so a stack that overflows faults instead of running into it.
+    fn stack_growth(&self, virt: usize) -> Option<Vma> {
+        let stack = self
+            .areas
+            .next(virt)
+            .filter(|vma| vma.backing == Backing::Stack)?;
+        let start = virt - virt % stack.level.size();
+        if stack.end - start > self.stack_limit {
+            return None;
+        }
+        let guard = start.checked_sub(stack.level.size())?;
+        if self.areas.find(guard).is_some() {
+            return None;
+        }
+        Some(Vma { start, ..*stack })
+    }
+
+    /// Returns a new address space with the same areas, that shares their mapped frames with this
+    /// one. The writable pages of both become copy-on-write, so the first write to a page copies
+    /// it instead of changing the other address space's memory. Every address space that maps a
+    /// frame holds a reference to it, and the last one to let go of it frees it.
+    pub fn fork(&mut self) -> Result<AddressSpace, MemoryError> {
+        let mut child = AddressSpace::new()?;
+        child.stack_limit = self.stack_limit;
+        child.heap_start = self.heap_start;
+        child.brk = self.brk;
+        for vma in self.areas.as_slice().to_vec() {
+            for page in vma.pages() {
+                let translation = match self.translate(page) {
+                    Ok(translation) => translation,
+                    // The frame is shared, so it has to be in memory
+                    Err(MemoryError::Swapped { .. }) => {
+                        swap::swap_in_page(self.root(), page)?;
+                        self.translate(page)?
+                    }
+                    Err(_) => continue, // Never accessed, so there is nothing to share
+                };
+                let mut flags = translation.flags;
+                if flags.contains(PageEntryFlags::WRITE) {
+                    flags.remove(PageEntryFlags::WRITE);
+                    flags.insert(PageEntryFlags::COW);
+                    paging::update_flags(self.root(), page, &flags)?;
+                }
+                let (phys, virt) = (PhysAddr::new(translation.phys), VirtAddr::new(page));
+                paging::map(child.root(), phys, virt, &flags, vma.level)?;
+                FRAMES_ALLOCATOR.lock().share(translation.phys);
+            }
+            child.areas.insert(vma).unwrap();
+        }
+        Ok(child)
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Releases the frames of every area, the page tables, and the root table.
+    fn drop(&mut self) {
+        for vma in self.areas.as_slice().to_vec() {
+            self.release_range(vma.start, vma.end)
+                .expect("Failed to release the frames of an area.");
+        }
+        paging::unmap(self.root()).expect("Failed to free the page tables.");
+        if !asids_disabled() {
+            tlb::sfence_vma(Some(self.asid.value), None);
+        }
+        FRAMES_ALLOCATOR
+            .lock()
+            .dealloc(self.root as usize, 1, PageEntryLevel::KiB4)
+            .expect("Failed to free the root table.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{frame_stats, swap::PolicyKind, tests::frames};
+
+    #[test]
+    fn children_share_frames_until_dropped() {
+        let _frames = frames();
+        let used = frame_stats().used_frames;
+
+        let mut space = AddressSpace::new().unwrap();
+        space
+            .map(
+                0x1000_0000,
+                4 * FRAME_SIZE,
+                PageEntryFlags::READ_WRITE,
+                PageEntryLevel::KiB4,
+                Backing::Anonymous,
+            )
+            .unwrap();
+        let frame = space.translate(0x1000_2000).unwrap().phys;
+
+        let mut child = space.fork().unwrap();
+        assert_ne!(child.asid(), space.asid());
+        assert_eq!(child.areas(), space.areas());
+        for translation in [space.translate(0x1000_2000), child.translate(0x1000_2000)] {
+            let flags = translation.unwrap().flags;
+            assert!(flags.contains(PageEntryFlags::COW));
+            assert!(!flags.contains(PageEntryFlags::WRITE));
+        }
+        assert_eq!(child.translate(0x1000_2000).unwrap().phys, frame);
+
+        // The frames outlive the address space they were mapped in first
+        drop(space);
+        assert_eq!(child.translate(0x1000_2000).unwrap().phys, frame);
+        drop(child);
+        assert_eq!(frame_stats().used_frames, used);
+    }
+
+    #[test]
+    fn unmap_splits_areas() {
+        let _frames = frames();
+        let used = frame_stats().used_frames;
+
+        let mut space = AddressSpace::new().unwrap();
+        space
+            .map(
+                0x1000_0000,
+                4 * FRAME_SIZE,
+                PageEntryFlags::READ,
+                PageEntryLevel::KiB4,
+                Backing::Anonymous,
+            )
+            .unwrap();
+        assert_eq!(
+            space.map(
+                0x1000_3000,
+                FRAME_SIZE,
+                PageEntryFlags::READ,
+                PageEntryLevel::KiB4,
+                Backing::Heap,
+            ),
+            Err(MemoryError::AlreadyMapped { virt: 0x1000_3000 })
+        );
+
+        space.unmap(0x1000_1000, 2 * FRAME_SIZE).unwrap();
+        let ranges: Vec<_> = space.areas().iter().map(|r| (r.start, r.end)).collect();
+        assert_eq!(
+            ranges,
+            [(0x1000_0000, 0x1000_1000), (0x1000_3000, 0x1000_4000)]
+        );
+        assert_eq!(
+            space.translate(0x1000_1000),
+            Err(MemoryError::NotMapped { virt: 0x1000_1000 })
+        );
+
+        drop(space);
+        assert_eq!(frame_stats().used_frames, used);
+    }
+
+    #[test]
+    fn faults_map_the_pages_of_areas_on_demand() {
+        let _frames = frames();
+        let used = frame_stats().used_frames;
+
+        let mut space = AddressSpace::new().unwrap();
+        let stack = 0x2000_0000;
+        space
+            .reserve(
+                stack,
+                4 * FRAME_SIZE,
+                PageEntryFlags::READ_WRITE,
+                PageEntryLevel::KiB4,
+                Backing::Stack,
+            )
+            .unwrap();
+        assert_eq!(
+            space.translate(stack),
+            Err(MemoryError::NotMapped { virt: stack })
+        );
+
+        // The first access maps the page, outside of the area it faults
+        let frame = space.access(stack + 0x1234, Access::Write).unwrap();
+        assert_eq!(space.translate(stack + 0x1234).unwrap().phys, frame);
+        assert_eq!(
+            space.translate(stack),
+            Err(MemoryError::NotMapped { virt: stack })
+        );
+        assert_eq!(
+            space.access(stack + 4 * FRAME_SIZE, Access::Read),
+            Err(MemoryError::PageFault {
+                virt: stack + 4 * FRAME_SIZE,
+                access: Access::Read
+            })
+        );
+        assert_eq!(
+            space.access(stack, Access::Execute),
+            Err(MemoryError::PageFault {
+                virt: stack,
+                access: Access::Execute
+            })
+        );
+        assert_eq!(space.maps(), "20000000-20004000 rw-p 00000000 [stack]\n");
+
+        drop(space);
+        assert_eq!(frame_stats().used_frames, used);
+    }
+
+    #[test]
+    fn stacks_grow_down_on_faults_below_them() {
+        let _frames = frames();
+        let used = frame_stats().used_frames;
+
+        let mut space = AddressSpace::new().unwrap();
+        let stack = 0x2000_0000;
+        for (start, backing) in [(stack, Backing::Stack), (stack - 0x8000, Backing::Heap)] {
+            space
+                .reserve(
+                    start,
+                    FRAME_SIZE,
+                    PageEntryFlags::READ_WRITE,
+                    PageEntryLevel::KiB4,
+                    backing,
+                )
+                .unwrap();
+        }
+        space.set_stack_limit(4 * FRAME_SIZE);
+
+        // Pushing below the stack grows it, even past pages that were never touched
+        space.access(stack - 8, Access::Write).unwrap();
+        space.access(stack - 0x2FF8, Access::Write).unwrap();
+        assert_eq!(space.areas()[1].start, stack - 0x3000);
+        assert_eq!(
+            space.translate(stack - 0x2000),
+            Err(MemoryError::NotMapped {
+                virt: stack - 0x2000
+            })
+        );
+
+        // It stops at the limit, and a page above the area below it
+        let overflow = stack - 0x3008;
+        assert_eq!(
+            space.access(overflow, Access::Write),
+            Err(MemoryError::PageFault {
+                virt: overflow,
+                access: Access::Write
+            })
+        );
+        space.set_stack_limit(DEFAULT_STACK_LIMIT);
+        space.access(stack - 0x6000, Access::Read).unwrap();
+        assert!(space.access(stack - 0x6008, Access::Read).is_err());
+        assert_eq!(space.areas()[1].start, stack - 0x6000);
+
+        drop(space);
+        assert_eq!(frame_stats().used_frames, used);
+    }
+
+    #[test]
+    fn brk_maps_and_unmaps_the_heap() {
+        let _frames = frames();
+        let used = frame_stats().used_frames;
+
+        let mut space = AddressSpace::new().unwrap();
+        let heap = 0x1000_0000;
+        assert_eq!(space.sbrk(8), Err(MemoryError::NotMapped { virt: 8 }));
+        space
+            .reserve(
+                heap + 0x4000,
+                FRAME_SIZE,
+                PageEntryFlags::READ,
+                PageEntryLevel::KiB4,
+                Backing::Anonymous,
+            )
+            .unwrap();
+        space.start_heap(heap).unwrap();
+
+        // The heap grows a page at a time, and its pages are mapped right away
+        assert_eq!(space.sbrk(0x10), Ok(heap));
+        assert_eq!(space.sbrk(0x1000), Ok(heap + 0x10));
+        assert_eq!(space.areas()[0].end, heap + 0x2000);
+        let phys = space.translate(heap + 0x1008).unwrap().phys;
+        assert_eq!(unsafe { (phys as *const u64).read() }, 0);
+        assert_eq!(
+            space.brk(heap + 0x4001),
+            Err(MemoryError::AlreadyMapped {
+                virt: heap + 0x4000
+            })
+        );
+        assert_eq!(
+            space.brk(heap - 1),
+            Err(MemoryError::NotMapped { virt: heap - 1 })
+        );
+
+        // Shrinking it unmaps the pages it doesn't cover anymore
+        assert_eq!(space.brk(heap + 0x800), Ok(heap + 0x800));
+        assert_eq!(
+            space.translate(heap + 0x1000),
+            Err(MemoryError::NotMapped {
+                virt: heap + 0x1000
+            })
+        );
+        space.brk(heap).unwrap();
+        assert_eq!(space.areas().len(), 1);
+        assert_eq!(space.sbrk(0x4000), Ok(heap));
+
+        drop(space);
+        assert_eq!(frame_stats().used_frames, used);
+    }
+
+    #[test]
+    fn mmap_reads_files_in_on_demand() {
+        let _frames = frames();
+        let path = std::env::temp_dir().join(format!("riscy-disk-{}.img", std::process::id()));
+        disk::init_disk(path.to_str().unwrap());
+        let contents: Vec<u8> = (0..FRAME_SIZE + 16).map(|i| i as u8).collect();
+        disk::create_file("test.bin", &contents);
+        let used = frame_stats().used_frames;
+
+        let mut space = AddressSpace::new().unwrap();
+        let file = Backing::File {
+            name: "test.bin",
+            offset: 0x10,
+        };
+        let virt = space
+            .mmap(2 * FRAME_SIZE, PageEntryFlags::READ, file, Placement::Any)
+            .unwrap();
+        assert_eq!(virt, MMAP_BASE);
+        assert_eq!(space.translate(virt), Err(MemoryError::NotMapped { virt }));
+
+        // The file is read a page at a time, and the rest of its last page is zeroed
+        let phys = space.access(virt + FRAME_SIZE - 1, Access::Read).unwrap();
+        assert_eq!(unsafe { *(phys as *const u8) }, (FRAME_SIZE + 15) as u8);
+        let phys = space.access(virt + FRAME_SIZE, Access::Read).unwrap();
+        assert_eq!(unsafe { *(phys as *const u8) }, 0);
+
+        // A taken hint is ignored, and a fixed mapping replaces what was there
+        let anonymous = space
+            .mmap(
+                FRAME_SIZE,
+                PageEntryFlags::READ_WRITE,
+                Backing::Anonymous,
+                Placement::Hint(virt),
+            )
+            .unwrap();
+        assert_eq!(anonymous, virt + 2 * FRAME_SIZE);
+        space
+            .mmap(
+                FRAME_SIZE,
+                PageEntryFlags::READ_WRITE,
+                Backing::Anonymous,
+                Placement::Fixed(virt),
+            )
+            .unwrap();
+        assert_eq!(space.areas()[0].backing, Backing::Anonymous);
+        assert_eq!(
+            space.areas()[1],
+            Vma {
+                start: virt + FRAME_SIZE,
+                end: virt + 2 * FRAME_SIZE,
+                flags: PageEntryFlags::READ,
+                level: PageEntryLevel::KiB4,
+                backing: Backing::File {
+                    name: "test.bin",
+                    offset: 0x10 + FRAME_SIZE
+                },
+            }
+        );
+
+        space.munmap(virt, 3 * FRAME_SIZE).unwrap();
+        assert!(space.areas().is_empty());
+        drop(space);
+        assert_eq!(frame_stats().used_frames, used);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_fixed_mmaps_keep_what_was_mapped() {
+        let _frames = frames();
+        let used = frame_stats().used_frames;
+
+        let mut space = AddressSpace::new().unwrap();
+        let virt = space
+            .mmap(
+                FRAME_SIZE,
+                PageEntryFlags::READ_WRITE,
+                Backing::Anonymous,
+                Placement::Any,
+            )
+            .unwrap();
+        let limit = paging::mode().lower_half_end();
+        let fixed = |space: &mut AddressSpace, virt, len, flags| {
+            space.mmap(len, flags, Backing::Anonymous, Placement::Fixed(virt))
+        };
+
+        assert_eq!(
+            fixed(&mut space, limit, FRAME_SIZE, PageEntryFlags::READ_WRITE),
+            Err(MemoryError::NotInLowerHalf {
+                virt: limit,
+                len: FRAME_SIZE
+            })
+        );
+        assert_eq!(
+            fixed(
+                &mut space,
+                virt,
+                usize::MAX - FRAME_SIZE,
+                PageEntryFlags::READ
+            ),
+            Err(MemoryError::NotInLowerHalf {
+                virt,
+                len: usize::MAX - FRAME_SIZE + 1
+            })
+        );
+        let global = PageEntryFlags::READ_WRITE | PageEntryFlags::GLOBAL;
+        assert_eq!(
+            fixed(&mut space, virt, FRAME_SIZE, global),
+            Err(MemoryError::InvalidFlags { flags: global })
+        );
+        assert_eq!(space.areas().len(), 1);
+        assert_eq!(space.areas()[0].start, virt);
+        assert_eq!(space.areas()[0].flags, PageEntryFlags::READ_WRITE);
+
+        drop(space);
+        assert_eq!(frame_stats().used_frames, used);
+    }
+
+    #[test]
+    fn writes_after_fork_stay_private() {
+        let _frames = frames();
+        let used = frame_stats().used_frames;
+        let write = |space: &mut AddressSpace, virt, value| {
+            let phys = space.access(virt, Access::Write).unwrap();
+            unsafe { (phys as *mut u64).write(value) };
+            phys
+        };
+        let read = |space: &mut AddressSpace, virt| {
+            let phys = space.access(virt, Access::Read).unwrap();
+            unsafe { (phys as *const u64).read() }
+        };
+
+        let mut parent = AddressSpace::new().unwrap();
+        parent
+            .map(
+                0x1000_0000,
+                2 * FRAME_SIZE,
+                PageEntryFlags::READ_WRITE,
+                PageEntryLevel::KiB4,
+                Backing::Heap,
+            )
+            .unwrap();
+        let (first, second) = (0x1000_0000, 0x1000_1000);
+        let shared = write(&mut parent, first, 1);
+        write(&mut parent, second, 2);
+
+        let mut child = parent.fork().unwrap();
+        let used_after_fork = frame_stats().used_frames;
+
+        // The child's write copies the page, the parent keeps its value and its frame
+        let copy = write(&mut child, first, 10);
+        assert_ne!(copy, shared);
+        assert_eq!(read(&mut parent, first), 1);
+        assert_eq!(frame_stats().used_frames, used_after_fork + 1);
+
+        // The parent is now the only user of the frame, so writing to it doesn't copy
+        assert_eq!(write(&mut parent, first, 100), shared);
+        assert_eq!(read(&mut child, first), 10);
+        assert_eq!(frame_stats().used_frames, used_after_fork + 1);
+
+        // Writing to the other page copies it for the parent
+        write(&mut parent, second, 20);
+        assert_eq!(read(&mut child, second), 2);
+        assert_eq!(read(&mut parent, second), 20);
+
+        drop(parent);
+        assert_eq!(read(&mut child, first), 10);
+        drop(child);
+        assert_eq!(frame_stats().used_frames, used);
+    }
+
+    #[test]
+    fn reads_share_the_zero_frame() {
+        let _frames = frames();
+
+        let mut space = AddressSpace::new().unwrap();
+        let heap = 0x3000_0000;
+        space
+            .reserve(
+                heap,
+                8 * FRAME_SIZE,
+                PageEntryFlags::READ_WRITE,
+                PageEntryLevel::KiB4,
+                Backing::Heap,
+            )
+            .unwrap();
+        let zero = fault::zero_frame().unwrap();
+        let users = fault::zero_frame_users();
+
+        // Reading every page maps all of them to the zero frame, without taking a frame for them
+        let used = frame_stats().used_frames;
+        for page in (heap..heap + 8 * FRAME_SIZE).step_by(FRAME_SIZE) {
+            let phys = space.access(page + 8, Access::Read).unwrap();
+            assert_eq!(phys, zero + 8);
+            assert_eq!(unsafe { (phys as *const u64).read() }, 0);
+        }
+        assert_eq!(fault::zero_frame_users(), users + 8);
+        let tables = frame_stats().used_frames - used;
+
+        // The first write gives the page its own frame
+        let phys = space.access(heap, Access::Write).unwrap();
+        assert_ne!(phys, zero);
+        unsafe { (phys as *mut u64).write(7) };
+        assert_eq!(fault::zero_frame_users(), users + 7);
+        assert_eq!(frame_stats().used_frames, used + tables + 1);
+        assert_eq!(unsafe { (zero as *const u64).read() }, 0);
+
+        drop(space);
+        assert_eq!(fault::zero_frame_users(), users);
+    }
+
+    #[test]
+    fn pages_written_after_a_read_can_be_swapped_out() {
+        let _frames = frames();
+        let path = std::env::temp_dir().join(format!("riscy-cow-{}.img", std::process::id()));
+        swap::init_swap(path.to_str().unwrap(), 16, PolicyKind::Clock);
+
+        let mut space = AddressSpace::new().unwrap();
+        let heap = 0x3000_0000;
+        space
+            .reserve(
+                heap,
+                FRAME_SIZE,
+                PageEntryFlags::READ_WRITE,
+                PageEntryLevel::KiB4,
+                Backing::Heap,
+            )
+            .unwrap();
+
+        // The read maps the zero frame, and the write copies it into the page's own frame
+        space.access(heap, Access::Read).unwrap();
+        let phys = space.access(heap, Access::Write).unwrap();
+        unsafe { (phys as *mut u64).write(0x5EED) };
+        let info = FRAMES_ALLOCATOR.lock().info(phys).unwrap();
+        assert_eq!(info.owner, FrameOwner::AddressSpace);
+
+        space.swap_out(heap).unwrap();
+        let phys = space.access(heap, Access::Read).unwrap();
+        assert_eq!(unsafe { (phys as *const u64).read() }, 0x5EED);
+
+        drop(space);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn swapped_out_pages_are_read_back_on_access() {
+        let _frames = frames();
+        let path = std::env::temp_dir().join(format!("riscy-swap-{}.img", std::process::id()));
+        swap::init_swap(path.to_str().unwrap(), 16, PolicyKind::Clock);
+        let used = frame_stats().used_frames;
+
+        let mut space = AddressSpace::new().unwrap();
+        let heap = 0x5000_0000;
+        space
+            .map(
+                heap,
+                2 * FRAME_SIZE,
+                PageEntryFlags::READ_WRITE,
+                PageEntryLevel::KiB4,
+                Backing::Heap,
+            )
+            .unwrap();
+        let phys = space.access(heap + 8, Access::Write).unwrap();
+        unsafe { (phys as *mut u64).write(0xC0FFEE) };
+
+        let used_before = frame_stats().used_frames;
+        space.swap_out(heap).unwrap();
+        assert_eq!(
+            space.translate(heap + 8),
+            Err(MemoryError::Swapped { virt: heap + 8 })
+        );
+        assert_eq!(frame_stats().used_frames, used_before - 1);
+        assert_eq!(swap::swap_stats().used_slots, 1);
+
+        // Reading the page faults it back in, the flags it had are kept
+        let phys = space.access(heap + 8, Access::Read).unwrap();
+        assert_eq!(unsafe { (phys as *const u64).read() }, 0xC0FFEE);
+        assert!(space
+            .translate(heap)
+            .unwrap()
+            .flags
+            .contains(PageEntryFlags::READ_WRITE));
+        assert_eq!(swap::swap_stats().used_slots, 0);
+
+        // Dropping the address space frees the slots of the pages that are still swapped out
+        space.swap_out(heap + FRAME_SIZE).unwrap();
+        drop(space);
+        assert_eq!(swap::swap_stats().used_slots, 0);
+        assert_eq!(frame_stats().used_frames, used);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+//! ASIDs: the tags that tell the TLB's translations of different address spaces apart, so
+//! switching between them doesn't have to flush it.
+//!
+//! There are only as many ASIDs as `satp.ASID` has bits, so they are handed out in generations:
+//! when a generation runs out, the TLB is flushed, and every address space gets an ASID of the
+//! new generation the next time it's switched to.
+
+use crate::memory::tlb;
+use spin::Mutex;
+
+static ASIDS: Mutex<AsidAllocator> = Mutex::new(AsidAllocator {
+    generation: 0,
+    next: 1,
+    bits: 16,
+});
+
+/// An ASID, and the generation it was handed out in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Asid {
+    pub value: u16,
+    pub generation: u64,
+}
+
+struct AsidAllocator {
+    generation: u64,
+    /// The next ASID of the generation, 0 is the kernel's
+    next: u32,
+    /// The number of bits of `satp.ASID` the hart implements
+    bits: u32,
+}
+
+impl AsidAllocator {
+    fn alloc(&mut self) -> Asid {
+        if self.bits == 0 {
+            // Every address space shares ASID 0, so the TLB is flushed on every switch instead
+            return Asid {
+                value: 0,
+                generation: self.generation,
+            };
+        }
+
+        if self.next >= 1 << self.bits {
+            self.rollover();
+        }
+        let value = self.next as u16;
+        self.next += 1;
+        Asid {
+            value,
+            generation: self.generation,
+        }
+    }
+
+    /// Starts a new generation. The ASIDs of the old one may be cached in the TLB, so it's flushed.
+    fn rollover(&mut self) {
+        self.generation += 1;
+        self.next = 1;
+        tlb::sfence_vma(None, None);
+    }
+}
+
+/// Returns an ASID of the current generation.
+pub(super) fn alloc_asid() -> Asid {
+    ASIDS.lock().alloc()
+}
+
+/// Returns `asid` if it's of the current generation, and a new ASID if it isn't.
+pub(super) fn refresh(asid: Asid) -> Asid {
+    let mut asids = ASIDS.lock();
+    if asid.generation == asids.generation {
+        asid
+    } else {
+        asids.alloc()
+    }
+}
+
+/// Returns true if the address spaces share ASID 0, because the hart doesn't implement ASIDs.
+pub fn asids_disabled() -> bool {
+    ASIDS.lock().bits == 0
+}
+
+/// Emulates a hart whose `satp.ASID` has `bits` bits (at most 16, and 0 for no ASIDs at all),
+/// and starts a new generation.
+pub fn set_asid_bits(bits: u32) {
+    assert!(bits <= u16::BITS, "satp.ASID has at most 16 bits");
+    let mut asids = ASIDS.lock();
+    asids.bits = bits;
+    asids.rollover();
+}
+
+/// Returns the generation of the ASIDs that are handed out.
+pub fn asid_generation() -> u64 {
+    ASIDS.lock().generation
+}
@@ -0,0 +1,232 @@
+//! The virtual memory areas of an address space: the ranges the address space may access, what
+//! backs them, and how they may be accessed, like `/proc/<pid>/maps`.
+
+use crate::memory::paging::{PageEntryFlags, PageEntryLevel};
+use core::fmt;
+
+/// What the memory of an area comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backing {
+    /// Zeroed memory
+    Anonymous,
+    /// The contents of a file from `offset`
+    File {
+        name: &'static str,
+        offset: usize,
+    },
+    Stack,
+    Heap,
+}
+
+/// A range of virtual memory that the address space may access.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vma {
+    pub start: usize,
+    pub end: usize,
+    pub flags: PageEntryFlags,
+    /// The level of the pages that map the area
+    pub level: PageEntryLevel,
+    pub backing: Backing,
+}
+
+impl Vma {
+    /// Returns the virtual addresses of the area's pages.
+    pub fn pages(&self) -> impl Iterator<Item = usize> {
+        (self.start..self.end).step_by(self.level.size())
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+}
+
+impl fmt::Display for Vma {
+    /// Formats the area like a line of `/proc/<pid>/maps`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |flags, c| if self.flags.intersects(flags) { c } else { '-' };
+        write!(
+            f,
+            "{:08x}-{:08x} {}{}{}p ", // Every area is private
+            self.start,
+            self.end,
+            flag(PageEntryFlags::READ, 'r'),
+            flag(PageEntryFlags::WRITE | PageEntryFlags::COW, 'w'),
+            flag(PageEntryFlags::EXECUTE, 'x'),
+        )?;
+        match self.backing {
+            Backing::Anonymous => write!(f, "{:08x}", 0),
+            Backing::File { name, offset } => write!(f, "{offset:08x} {name}"),
+            Backing::Stack => write!(f, "{:08x} [stack]", 0),
+            Backing::Heap => write!(f, "{:08x} [heap]", 0),
+        }
+    }
+}
+
+/// The areas of an address space, sorted by address and never overlapping.
+#[derive(Default)]
+pub struct VmaList {
+    areas: Vec<Vma>,
+}
+
+impl VmaList {
+    /// Adds `vma`, unless it overlaps another area.
+    pub fn insert(&mut self, vma: Vma) -> Result<(), Vma> {
+        let index = self.areas.partition_point(|other| other.start < vma.start);
+        let overlaps = |other: Option<&Vma>| {
+            other.is_some_and(|other| other.start < vma.end && vma.start < other.end)
+        };
+        if let Some(other) = [index.checked_sub(1), Some(index)]
+            .into_iter()
+            .flatten()
+            .map(|i| self.areas.get(i))
+            .find(|&other| overlaps(other))
+        {
+            return Err(*other.unwrap());
+        }
+
+        self.areas.insert(index, vma);
+        Ok(())
+    }
+
+    /// Returns the area that holds `addr`.
+    pub fn find(&self, addr: usize) -> Option<&Vma> {
+        let index = self.areas.partition_point(|vma| vma.end <= addr);
+        self.areas.get(index).filter(|vma| vma.contains(addr))
+    }
+
+    /// Returns the first area that starts above `addr`.
+    pub fn next(&self, addr: usize) -> Option<&Vma> {
+        let index = self.areas.partition_point(|vma| vma.start <= addr);
+        self.areas.get(index)
+    }
+
+    /// Returns the first address from `from` where `len` bytes fit between the areas, if it's
+    /// below `limit`.
+    pub fn find_gap(&self, from: usize, len: usize, limit: usize) -> Option<usize> {
+        let mut start = from;
+        for vma in &self.areas {
+            if vma.end <= start {
+                continue;
+            }
+            if vma.start >= start + len {
+                break;
+            }
+            start = vma.end;
+        }
+        (start + len <= limit).then_some(start)
+    }
+
+    /// Moves the start of the area that starts at `start` down to `new_start`, unless it would
+    /// overlap the area below it.
+    pub fn grow_down(&mut self, start: usize, new_start: usize) -> Result<(), Vma> {
+        let index = self.areas.partition_point(|vma| vma.start < start);
+        assert_eq!(
+            self.areas[index].start, start,
+            "No area starts at {start:#X}"
+        );
+        if let Some(below) = index.checked_sub(1).map(|i| self.areas[i]) {
+            if below.end > new_start {
+                return Err(below);
+            }
+        }
+        self.areas[index].start = new_start;
+        Ok(())
+    }
+
+    /// Moves the end of the area that starts at `start` up to `new_end`, unless it would overlap
+    /// the area above it.
+    pub fn grow_up(&mut self, start: usize, new_end: usize) -> Result<(), Vma> {
+        let index = self.areas.partition_point(|vma| vma.start < start);
+        assert_eq!(
+            self.areas[index].start, start,
+            "No area starts at {start:#X}"
+        );
+        if let Some(&above) = self.areas.get(index + 1) {
+            if above.start < new_end {
+                return Err(above);
+            }
+        }
+        self.areas[index].end = new_end;
+        Ok(())
+    }
+
+    /// Removes `[start, end)` from the areas. The areas that are only partly in the range shrink
+    /// or split.
+    pub fn remove_range(&mut self, start: usize, end: usize) {
+        let mut areas = Vec::with_capacity(self.areas.len() + 1);
+        for vma in self.areas.drain(..) {
+            if vma.end <= start || vma.start >= end {
+                areas.push(vma); // Not in the range
+                continue;
+            }
+            if vma.start < start {
+                areas.push(Vma { end: start, ..vma });
+            }
+            if vma.end > end {
+                // The rest of a file starts further into it
+                let backing = match vma.backing {
+                    Backing::File { name, offset } => Backing::File {
+                        name,
+                        offset: offset + end - vma.start,
+                    },
+                    backing => backing,
+                };
+                areas.push(Vma {
+                    start: end,
+                    backing,
+                    ..vma
+                });
+            }
+        }
+        self.areas = areas;
+    }
+
+    pub fn as_slice(&self) -> &[Vma] {
+        &self.areas
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vma(start: usize, end: usize) -> Vma {
+        Vma {
+            start,
+            end,
+            flags: PageEntryFlags::READ_WRITE,
+            level: PageEntryLevel::KiB4,
+            backing: Backing::Anonymous,
+        }
+    }
+
+    #[test]
+    fn areas_stay_sorted_and_disjoint() {
+        let mut areas = VmaList::default();
+        areas.insert(vma(0x5000, 0x8000)).unwrap();
+        areas.insert(vma(0x1000, 0x3000)).unwrap();
+        assert_eq!(areas.insert(vma(0x2000, 0x6000)), Err(vma(0x1000, 0x3000)));
+        assert_eq!(areas.insert(vma(0x7000, 0x9000)), Err(vma(0x5000, 0x8000)));
+        areas.insert(vma(0x3000, 0x5000)).unwrap();
+
+        assert_eq!(areas.find(0x4FFF), Some(&vma(0x3000, 0x5000)));
+        assert_eq!(areas.find(0x8000), None);
+        assert_eq!(areas.next(0x3000), Some(&vma(0x5000, 0x8000)));
+        assert_eq!(areas.grow_down(0x5000, 0x4000), Err(vma(0x3000, 0x5000)));
+        assert_eq!(areas.grow_up(0x1000, 0x4000), Err(vma(0x3000, 0x5000)));
+        assert_eq!(areas.find_gap(0x2000, 0x2000, 0x10000), Some(0x8000));
+        assert_eq!(areas.find_gap(0, 0x1000, 0x10000), Some(0));
+        assert_eq!(areas.find_gap(0x8000, 0x9000, 0x10000), None);
+
+        areas.remove_range(0x2000, 0x6000);
+        assert_eq!(areas.as_slice(), [vma(0x1000, 0x2000), vma(0x6000, 0x8000)]);
+        assert_eq!(
+            vma(0x1000, 0x2000).to_string(),
+            "00001000-00002000 rw-p 00000000"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Swap: a file next to `mem.img` that pages are written out to when there are no free frames left,
+//! so address spaces can use more memory than there is.
+//!
+//! A swapped out page's entry isn't valid, has the `SWAPPED` flag, and holds the address of its
+//! slot in the swap file where the frame's address used to be.
+
+pub mod bench;
+mod policy;
+
+pub use policy::{PageState, PolicyKind, ReplacementPolicy};
+
+use super::{
+    consts::FRAME_SIZE,
+    error::MemoryError,
+    frames::{alloc_with_reclaim, register_reclaim_hook, FrameOwner, Pressure, FRAMES_ALLOCATOR},
+    paging::{self, PageEntry, PageEntryFlags, PageEntryLevel, PageTable},
+    rmap::{Mapping, REVERSE_MAP},
+    tlb,
+};
+use spin::Mutex;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+static SWAP: Mutex<Option<Swap>> = Mutex::new(None);
+
+/// How the swap is used.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SwapStats {
+    pub slots: usize,
+    pub used_slots: usize,
+    /// The number of pages that were written out
+    pub swapped_out: usize,
+    /// The number of pages that were read back in
+    pub swapped_in: usize,
+}
+
+struct Swap {
+    file: File,
+    /// Whether every slot holds a page
+    used: Vec<bool>,
+    policy: Box<dyn ReplacementPolicy>,
+    /// The entries of the pages the policy knows about
+    tracked: BTreeSet<usize>,
+    stats: SwapStats,
+}
+
+impl Swap {
+    /// Tells the policy about the pages that were mapped and unmapped since the last eviction,
+    /// which are the mappings that appeared in or disappeared from the reverse map.
+    fn sync(&mut self, mappings: &[Mapping]) {
+        let current: BTreeSet<usize> = mappings.iter().map(|mapping| mapping.entry).collect();
+        for &gone in self.tracked.difference(&current) {
+            self.policy.unloaded(gone);
+        }
+        for mapping in mappings {
+            if !self.tracked.contains(&mapping.entry) {
+                self.policy.loaded(mapping.entry);
+            }
+        }
+        self.tracked = current;
+    }
+}
+
+/// The pages of the address spaces, as the policy sees them.
+struct ResidentPages(BTreeMap<usize, Mapping>);
+
+impl PageState for ResidentPages {
+    fn evictable(&mut self, page: usize) -> bool {
+        self.0.get(&page).is_some_and(is_evictable)
+    }
+
+    fn take_accessed(&mut self, page: usize) -> bool {
+        let entry = unsafe { &mut *(page as *mut PageEntry) };
+        let mut flags = entry.flags();
+        if !flags.contains(PageEntryFlags::ACCESSED) {
+            return false;
+        }
+        flags.remove(PageEntryFlags::ACCESSED);
+        entry.set_flags(&flags);
+        true
+    }
+}
+
+/// Creates a swap file at `path` with room for `num_slots` pages, which are picked to be swapped
+/// out by the policy of `policy`.
+pub fn init_swap(path: &str, num_slots: usize, policy: PolicyKind) {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .expect("Failed to create swap file");
+    file.set_len((num_slots * FRAME_SIZE) as u64)
+        .expect("Failed to set the size of the swap file");
+
+    *SWAP.lock() = Some(Swap {
+        file,
+        used: vec![false; num_slots],
+        policy: policy.build(),
+        tracked: BTreeSet::new(),
+        stats: SwapStats {
+            slots: num_slots,
+            ..Default::default()
+        },
+    });
+    register_reclaim_hook("swap", reclaim);
+}
+
+pub fn is_enabled() -> bool {
+    SWAP.lock().is_some()
+}
+
+/// Returns how the swap is used.
+pub fn swap_stats() -> SwapStats {
+    SWAP.lock()
+        .as_ref()
+        .map(|swap| swap.stats)
+        .unwrap_or_default()
+}
+
+/// Allocates a frame for a page of `level`, and if frames run low, reclaims some (which swaps
+/// pages out once `init_swap` was called). Only 4KiB pages are swapped, so they rarely make room
+/// for bigger pages.
+pub fn alloc_page(level: PageEntryLevel) -> Result<*mut u8, MemoryError> {
+    alloc_with_reclaim(level.size() / FRAME_SIZE, || {
+        FRAMES_ALLOCATOR.lock().alloc(1, level)
+    })
+}
+
+/// Swaps pages out until `wanted` frames were freed or no page can be swapped out, as the reclaim
+/// hook of the frames allocator.
+fn reclaim(_pressure: Pressure, wanted: usize) -> usize {
+    if !is_enabled() {
+        return 0;
+    }
+    (0..wanted).take_while(|_| evict().is_ok()).count()
+}
+
+/// Swaps out a page of an address space, picked by the replacement policy, to free its frame.
+/// Only 4KiB pages whose frames have no other users are swapped out.
+pub fn evict() -> Result<(), MemoryError> {
+    let mappings: Vec<Mapping> = REVERSE_MAP.lock().iter().collect();
+    let mappings: Vec<Mapping> = mappings
+        .into_iter()
+        .filter(|mapping| {
+            let info = FRAMES_ALLOCATOR.lock().info(mapping.phys);
+            mapping.level == PageEntryLevel::KiB4
+                && info.is_some_and(|info| info.owner == FrameOwner::AddressSpace)
+        })
+        .collect();
+
+    let mut pages = ResidentPages(
+        mappings
+            .iter()
+            .map(|mapping| (mapping.entry, *mapping))
+            .collect(),
+    );
+    let victim = {
+        let mut swap = SWAP.lock();
+        let swap = swap.as_mut().expect("Swap is not enabled");
+        swap.sync(&mappings);
+        let victim = swap.policy.victim(&mut pages);
+        if let Some(victim) = victim {
+            swap.tracked.remove(&victim);
+        }
+        victim.ok_or(MemoryError::OutOfMemory { frames: 1 })?
+    };
+
+    swap_out_entry(pages.0[&victim])
+}
+
+/// Returns true if the page of `mapping` is an address space's 4KiB page, that nobody else uses.
+fn is_evictable(mapping: &Mapping) -> bool {
+    let info = FRAMES_ALLOCATOR.lock().info(mapping.phys);
+    mapping.level == PageEntryLevel::KiB4
+        && info.is_some_and(|info| info.owner == FrameOwner::AddressSpace && info.refcount == 1)
+}
+
+/// Swaps out the page that maps `virt` in `root`, and frees its frame.
+pub fn swap_out_page(root: &mut PageTable, virt: usize) -> Result<(), MemoryError> {
+    let (entry, level) = paging::leaf_entry(root, virt)?;
+    let mapping = Mapping {
+        phys: entry.get_ppn(),
+        virt,
+        level,
+        entry: entry as *const PageEntry as usize,
+    };
+    if !is_evictable(&mapping) {
+        return Err(MemoryError::NotSwappable { virt });
+    }
+    swap_out_entry(mapping)
+}
+
+fn swap_out_entry(mapping: Mapping) -> Result<(), MemoryError> {
+    let entry = unsafe { &mut *(mapping.entry as *mut PageEntry) };
+    let slot = write_slot(mapping.phys)?;
+
+    let mut flags = entry.flags();
+    flags.remove(PageEntryFlags::VALID | PageEntryFlags::ACCESSED);
+    flags.insert(PageEntryFlags::SWAPPED);
+    entry.set_flags(&flags);
+    entry.set_ppn(slot);
+    tlb::sfence_vma(None, Some(mapping.virt));
+
+    REVERSE_MAP
+        .lock()
+        .remove_entries(mapping.entry, mapping.entry + size_of::<PageEntry>());
+    FRAMES_ALLOCATOR
+        .lock()
+        .release(mapping.phys, 1, PageEntryLevel::KiB4)?;
+    Ok(())
+}
+
+/// Reads the page that maps `virt` in `root` back from swap into a new frame, and maps it again.
+pub fn swap_in_page(root: &mut PageTable, virt: usize) -> Result<(), MemoryError> {
+    let (entry, level) = paging::swapped_entry(root, virt)?;
+    let slot = entry.get_ppn();
+
+    // The entry isn't valid, so making room can't swap it out again
+    let frame = alloc_page(level)? as usize;
+    FRAMES_ALLOCATOR
+        .lock()
+        .set_owner(frame, 1, FrameOwner::AddressSpace);
+    read_slot(slot, frame);
+
+    let (entry, level) = paging::swapped_entry(root, virt)?;
+    let mut flags = entry.flags();
+    flags.remove(PageEntryFlags::SWAPPED);
+    flags.insert(PageEntryFlags::VALID);
+    entry.set_flags(&flags);
+    entry.set_ppn(frame);
+
+    REVERSE_MAP.lock().insert(Mapping {
+        phys: frame,
+        virt: virt - virt % level.size(),
+        level,
+        entry: entry as *const PageEntry as usize,
+    })?;
+    paging::verify_mutation(root, "swap_in_page");
+    Ok(())
+}
+
+/// Frees the swap slot of the page that maps `virt` in `root` if it's swapped out, and clears its
+/// entry. Returns true if the page was swapped out.
+pub fn discard_page(root: &mut PageTable, virt: usize) -> bool {
+    let Ok((entry, _)) = paging::swapped_entry(root, virt) else {
+        return false;
+    };
+    let mut swap = SWAP.lock();
+    free_slot(swap.as_mut().expect("Swap is not enabled"), entry.get_ppn());
+    *entry = PageEntry::new();
+    true
+}
+
+/// Writes the frame at `frame` to a free slot, and returns the slot's address in the swap file.
+fn write_slot(frame: usize) -> Result<usize, MemoryError> {
+    let mut swap = SWAP.lock();
+    let swap = swap.as_mut().expect("Swap is not enabled");
+    let slot = swap
+        .used
+        .iter()
+        .position(|used| !used)
+        .ok_or(MemoryError::SwapFull)?;
+
+    let page = unsafe { core::slice::from_raw_parts(frame as *const u8, FRAME_SIZE) };
+    swap.file
+        .seek(SeekFrom::Start((slot * FRAME_SIZE) as u64))
+        .and_then(|_| swap.file.write_all(page))
+        .expect("Failed to write to the swap file");
+
+    swap.used[slot] = true;
+    swap.stats.used_slots += 1;
+    swap.stats.swapped_out += 1;
+    Ok(slot * FRAME_SIZE)
+}
+
+/// Reads the page at `slot` (an address in the swap file) into the frame at `frame`, and frees the
+/// slot.
+fn read_slot(slot: usize, frame: usize) {
+    let mut swap = SWAP.lock();
+    let swap = swap.as_mut().expect("Swap is not enabled");
+
+    let page = unsafe { core::slice::from_raw_parts_mut(frame as *mut u8, FRAME_SIZE) };
+    swap.file
+        .seek(SeekFrom::Start(slot as u64))
+        .and_then(|_| swap.file.read_exact(page))
+        .expect("Failed to read from the swap file");
+
+    swap.stats.swapped_in += 1;
+    free_slot(swap, slot);
+}
+
+fn free_slot(swap: &mut Swap, slot: usize) {
+    let used = &mut swap.used[slot / FRAME_SIZE];
+    assert!(*used, "The swap slot at {slot:#X} is already free");
+    *used = false;
+    swap.stats.used_slots -= 1;
+}
@@ -0,0 +1,197 @@
+//! Page replacement policies: which resident page to swap out when a frame is needed.
+
+use std::collections::VecDeque;
+
+/// What a policy can find out about the pages it tracks when it picks a victim.
+pub trait PageState {
+    /// Returns true if the page can be swapped out right now.
+    fn evictable(&mut self, page: usize) -> bool;
+
+    /// Returns true if the page was accessed since the last time this was asked, and clears its
+    /// accessed bit.
+    fn take_accessed(&mut self, page: usize) -> bool;
+}
+
+/// Picks the pages to swap out. Pages are identified by the address of the entry that maps them.
+pub trait ReplacementPolicy: Send {
+    /// The page was brought into memory.
+    fn loaded(&mut self, page: usize);
+
+    /// The page left memory without being picked, e.g. it was unmapped.
+    fn unloaded(&mut self, page: usize);
+
+    /// Samples the accessed bits of the pages, for the policies that keep a history of them. Called
+    /// periodically, and before picking a victim.
+    fn tick(&mut self, _pages: &mut dyn PageState) {}
+
+    /// Picks an evictable page to swap out, and stops tracking it. Returns None if none of the
+    /// pages can be evicted.
+    fn victim(&mut self, pages: &mut dyn PageState) -> Option<usize>;
+}
+
+/// The policies `init_swap` can use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PolicyKind {
+    /// Evicts the page that was loaded first
+    Fifo,
+    /// Second chance: goes around the pages, and evicts the first one that wasn't accessed since
+    /// the last time around
+    Clock,
+    /// Evicts the page that was least recently accessed, by the history of its accessed bits
+    Lru,
+    /// Evicts a random page
+    Random,
+}
+
+impl PolicyKind {
+    pub const ALL: [Self; 4] = [Self::Fifo, Self::Clock, Self::Lru, Self::Random];
+
+    pub fn build(self) -> Box<dyn ReplacementPolicy> {
+        match self {
+            PolicyKind::Fifo => Box::new(Fifo::default()),
+            PolicyKind::Clock => Box::new(Clock::default()),
+            PolicyKind::Lru => Box::new(Lru::default()),
+            PolicyKind::Random => Box::new(Random::new(0x5EED)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Fifo {
+    /// The pages, in the order they were loaded
+    queue: VecDeque<usize>,
+}
+
+impl ReplacementPolicy for Fifo {
+    fn loaded(&mut self, page: usize) {
+        self.queue.push_back(page);
+    }
+
+    fn unloaded(&mut self, page: usize) {
+        self.queue.retain(|&other| other != page);
+    }
+
+    fn victim(&mut self, pages: &mut dyn PageState) -> Option<usize> {
+        let index = self.queue.iter().position(|&page| pages.evictable(page))?;
+        self.queue.remove(index)
+    }
+}
+
+#[derive(Default)]
+pub struct Clock {
+    pages: Vec<usize>,
+    /// The page the clock points at, the next one to look at
+    hand: usize,
+}
+
+impl ReplacementPolicy for Clock {
+    fn loaded(&mut self, page: usize) {
+        // Right behind the hand, so it's looked at last
+        self.pages.insert(self.hand, page);
+        self.hand += 1;
+    }
+
+    fn unloaded(&mut self, page: usize) {
+        if let Some(index) = self.pages.iter().position(|&other| other == page) {
+            self.pages.remove(index);
+            if index < self.hand {
+                self.hand -= 1;
+            }
+        }
+    }
+
+    fn victim(&mut self, pages: &mut dyn PageState) -> Option<usize> {
+        // Two rounds, so the pages that were given a second chance are looked at again
+        for _ in 0..2 * self.pages.len() {
+            if self.hand >= self.pages.len() {
+                self.hand = 0;
+            }
+            let page = self.pages[self.hand];
+            if pages.evictable(page) && !pages.take_accessed(page) {
+                return Some(self.pages.remove(self.hand));
+            }
+            self.hand += 1;
+        }
+        None
+    }
+}
+
+/// Approximates LRU by aging: every tick, a page's age shifts right, and its accessed bit is
+/// shifted in from the left. The page with the lowest age was accessed the least recently.
+#[derive(Default)]
+pub struct Lru {
+    /// The pages and their ages, in the order they were loaded
+    ages: Vec<(usize, u8)>,
+}
+
+impl ReplacementPolicy for Lru {
+    fn loaded(&mut self, page: usize) {
+        self.ages.push((page, 0));
+    }
+
+    fn unloaded(&mut self, page: usize) {
+        self.ages.retain(|&(other, _)| other != page);
+    }
+
+    fn tick(&mut self, pages: &mut dyn PageState) {
+        for (page, age) in &mut self.ages {
+            *age = (*age >> 1) | ((pages.take_accessed(*page) as u8) << 7);
+        }
+    }
+
+    fn victim(&mut self, pages: &mut dyn PageState) -> Option<usize> {
+        self.tick(pages);
+        // The first of the oldest, so ties go to the page that was loaded first
+        let (index, _) = self
+            .ages
+            .iter()
+            .enumerate()
+            .filter(|(_, (page, _))| pages.evictable(*page))
+            .min_by_key(|(_, (_, age))| *age)?;
+        Some(self.ages.remove(index).0)
+    }
+}
+
+pub struct Random {
+    pages: Vec<usize>,
+    state: u64,
+}
+
+impl Random {
+    /// The same `seed` picks the same victims.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            pages: Vec::new(),
+            state: seed | 1,
+        }
+    }
+
+    fn next(&mut self) -> usize {
+        // xorshift64
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state as usize
+    }
+}
+
+impl ReplacementPolicy for Random {
+    fn loaded(&mut self, page: usize) {
+        self.pages.push(page);
+    }
+
+    fn unloaded(&mut self, page: usize) {
+        self.pages.retain(|&other| other != page);
+    }
+
+    fn victim(&mut self, pages: &mut dyn PageState) -> Option<usize> {
+        if self.pages.is_empty() {
+            return None;
+        }
+        // Start at a random page, and take the first evictable one from there
+        let start = self.next() % self.pages.len();
+        let offset = (0..self.pages.len())
+            .find(|offset| pages.evictable(self.pages[(start + offset) % self.pages.len()]))?;
+        Some(self.pages.swap_remove((start + offset) % self.pages.len()))
+    }
+}
@@ -0,0 +1,279 @@
+//! A software TLB: a small set-associative cache of translations in front of the page tables, so
+//! how often the tables are walked (and what context switches and flushes cost) can be measured.
+//!
+//! Like the hardware one, it isn't kept coherent with the page tables by itself: whoever changes or
+//! removes an entry that may be cached must call `sfence_vma`.
+
+use super::{
+    addr::VirtAddr,
+    consts::FRAME_SIZE,
+    error::MemoryError,
+    paging::{self, PageEntryFlags, PageEntryLevel, PageTable, Translation},
+};
+use spin::Mutex;
+
+/// The TLB of the (only) hart.
+pub static TLB: Mutex<Tlb> = Mutex::new(Tlb::new(32, 4));
+
+/// How the TLB was used.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TlbStats {
+    pub hits: usize,
+    pub misses: usize,
+    /// The number of `sfence_vma`s
+    pub flushes: usize,
+    /// The translations that were flushed
+    pub flushed: usize,
+    /// The translations that were replaced to make room for others
+    pub evictions: usize,
+}
+
+impl TlbStats {
+    pub fn hit_rate(&self) -> f64 {
+        self.hits as f64 / (self.hits + self.misses).max(1) as f64
+    }
+}
+
+/// A cached translation of a 4KiB page. Superpages are cached a 4KiB page at a time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TlbEntry {
+    asid: u16,
+    /// The virtual address of the 4KiB page
+    page: usize,
+    /// The physical address of the 4KiB page
+    frame: usize,
+    /// The level of the leaf the translation came from
+    level: PageEntryLevel,
+    flags: PageEntryFlags,
+    /// When the entry was last used, to replace the least recently used entry of a full set
+    last_used: u64,
+}
+
+impl TlbEntry {
+    fn matches(&self, asid: u16, page: usize) -> bool {
+        self.page == page && (self.asid == asid || self.flags.contains(PageEntryFlags::GLOBAL))
+    }
+
+    /// Returns true if the leaf the entry came from maps `addr`.
+    fn covers(&self, addr: usize) -> bool {
+        let size = self.level.size();
+        self.page - self.page % size == addr - addr % size
+    }
+}
+
+pub struct Tlb {
+    /// The entries of every set, one after the other
+    entries: Vec<Option<TlbEntry>>,
+    num_sets: usize,
+    ways: usize,
+    now: u64,
+    stats: TlbStats,
+}
+
+impl Tlb {
+    /// Returns a TLB of `num_entries` entries, in sets of `ways` entries (so `ways ==
+    /// num_entries` is fully associative, and 1 is direct mapped).
+    pub const fn new(num_entries: usize, ways: usize) -> Self {
+        assert!(ways > 0 && num_entries.is_multiple_of(ways));
+        Self {
+            entries: Vec::new(), // Allocated on the first insert, so the TLB can be a static
+            num_sets: num_entries / ways,
+            ways,
+            now: 0,
+            stats: TlbStats {
+                hits: 0,
+                misses: 0,
+                flushes: 0,
+                flushed: 0,
+                evictions: 0,
+            },
+        }
+    }
+
+    pub fn stats(&self) -> TlbStats {
+        self.stats
+    }
+
+    /// Returns the entries of the set that caches `page`.
+    fn set(&mut self, page: usize) -> &mut [Option<TlbEntry>] {
+        if self.entries.is_empty() {
+            self.entries = vec![None; self.num_sets * self.ways];
+        }
+        let set = VirtAddr::new(page).page_number() % self.num_sets;
+        &mut self.entries[set * self.ways..(set + 1) * self.ways]
+    }
+
+    /// Returns the cached translation of `virt` in the address space of `asid`.
+    pub fn lookup(&mut self, asid: u16, virt: usize) -> Option<Translation> {
+        let page = virt - virt % FRAME_SIZE;
+        self.now += 1;
+        let now = self.now;
+
+        let found = self
+            .set(page)
+            .iter_mut()
+            .flatten()
+            .find(|entry| entry.matches(asid, page))
+            .map(|entry| {
+                entry.last_used = now;
+                Translation {
+                    phys: entry.frame + virt % FRAME_SIZE,
+                    level: entry.level,
+                    flags: entry.flags,
+                }
+            });
+        match found {
+            Some(_) => self.stats.hits += 1,
+            None => self.stats.misses += 1,
+        }
+        found
+    }
+
+    /// Caches `translation`, the translation of `virt` in the address space of `asid`, in place of
+    /// the translation of the same page if there is one, or of the least recently used entry of
+    /// the set if it's full.
+    pub fn insert(&mut self, asid: u16, virt: usize, translation: Translation) {
+        let page = virt - virt % FRAME_SIZE;
+        self.now += 1;
+        let entry = TlbEntry {
+            asid,
+            page,
+            frame: translation.phys - translation.phys % FRAME_SIZE,
+            level: translation.level,
+            flags: translation.flags,
+            last_used: self.now,
+        };
+
+        let set = self.set(page);
+        let slot = match set
+            .iter()
+            .position(|slot| slot.is_some_and(|other| other.matches(asid, page)))
+            .or_else(|| set.iter().position(Option::is_none))
+        {
+            Some(slot) => slot,
+            None => {
+                let (lru, _) = set
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, slot)| slot.map(|entry| entry.last_used))
+                    .unwrap();
+                self.stats.evictions += 1;
+                lru
+            }
+        };
+        self.set(page)[slot] = Some(entry);
+    }
+
+    /// Flushes translations like `sfence.vma`: `addr` limits the flush to the translations of the
+    /// page that maps it, and `asid` to the translations of one address space, except for global
+    /// ones. Without either, everything is flushed.
+    pub fn flush(&mut self, asid: Option<u16>, addr: Option<usize>) {
+        self.stats.flushes += 1;
+        for slot in &mut self.entries {
+            let Some(entry) = slot else {
+                continue;
+            };
+            let in_space = asid.is_none_or(|asid| {
+                entry.asid == asid && !entry.flags.contains(PageEntryFlags::GLOBAL)
+            });
+            if in_space && addr.is_none_or(|addr| entry.covers(addr)) {
+                *slot = None;
+                self.stats.flushed += 1;
+            }
+        }
+    }
+}
+
+/// Translates `virt` in the address space of `asid`, whose root table is `root`, through the TLB,
+/// and walks the tables (caching the translation) on a miss.
+pub fn translate(root: &PageTable, asid: u16, virt: usize) -> Result<Translation, MemoryError> {
+    if let Some(translation) = TLB.lock().lookup(asid, virt) {
+        return Ok(translation);
+    }
+    let translation = paging::translate(root, virt)?;
+    TLB.lock().insert(asid, virt, translation);
+    Ok(translation)
+}
+
+/// `sfence.vma`: flushes the cached translations of `addr` in the address space of `asid` (see
+/// `Tlb::flush`).
+pub fn sfence_vma(asid: Option<u16>, addr: Option<usize>) {
+    TLB.lock().flush(asid, addr);
+}
+
+/// Replaces the TLB with an empty one of `num_entries` entries in sets of `ways`.
+pub fn configure_tlb(num_entries: usize, ways: usize) {
+    *TLB.lock() = Tlb::new(num_entries, ways);
+}
+
+/// Returns how the TLB was used.
+pub fn tlb_stats() -> TlbStats {
+    TLB.lock().stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(phys: usize, flags: PageEntryFlags) -> Translation {
+        Translation {
+            phys,
+            level: PageEntryLevel::KiB4,
+            flags,
+        }
+    }
+
+    #[test]
+    fn sets_replace_their_least_recently_used_entry() {
+        // Two sets of two, so every other page goes to the same set
+        let mut tlb = Tlb::new(4, 2);
+        let flags = PageEntryFlags::READ;
+        tlb.insert(1, 0x0000, translation(0xA000, flags));
+        tlb.insert(1, 0x2000, translation(0xB000, flags));
+        assert_eq!(tlb.lookup(1, 0x0123).unwrap().phys, 0xA123);
+        assert_eq!(tlb.lookup(2, 0x0123), None); // Another address space
+
+        tlb.insert(1, 0x4000, translation(0xC000, flags));
+        assert_eq!(tlb.lookup(1, 0x2000), None);
+        assert!(tlb.lookup(1, 0x0000).is_some());
+        assert_eq!(
+            tlb.stats(),
+            TlbStats {
+                hits: 2,
+                misses: 2,
+                evictions: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn flushes_follow_sfence_vma() {
+        let mut tlb = Tlb::new(8, 8);
+        let global = PageEntryFlags::READ | PageEntryFlags::GLOBAL;
+        tlb.insert(1, 0x1000, translation(0xA000, PageEntryFlags::READ));
+        tlb.insert(1, 0x2000, translation(0xB000, PageEntryFlags::READ));
+        tlb.insert(2, 0x1000, translation(0xC000, PageEntryFlags::READ));
+        tlb.insert(1, 0x8000, translation(0xD000, global));
+        let superpage = Translation {
+            phys: 0x20_0000,
+            level: PageEntryLevel::MiB2,
+            flags: PageEntryFlags::READ,
+        };
+        tlb.insert(2, 0x40_3000, superpage);
+
+        // Global translations are shared by every address space, and survive ASID flushes
+        assert!(tlb.lookup(2, 0x8000).is_some());
+        tlb.flush(Some(1), None);
+        assert_eq!(tlb.lookup(1, 0x1000), None);
+        assert!(tlb.lookup(2, 0x1000).is_some());
+        assert!(tlb.lookup(1, 0x8000).is_some());
+
+        // An address flushes the pieces of the superpage that maps it
+        tlb.flush(None, Some(0x40_0000));
+        assert_eq!(tlb.lookup(2, 0x40_3000), None);
+        tlb.flush(None, None);
+        assert_eq!(tlb.lookup(2, 0x1000), None);
+        assert_eq!(tlb.stats().flushes, 3);
+    }
+}
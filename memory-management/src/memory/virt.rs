@@ -1,44 +1,130 @@
-use std::{
-    fs::{File, OpenOptions},
-    io::{Result, Seek, Write},
-};
+//! The simulated physical memory: a file mapped into the address space, so what the memory
+//! manager writes can be inspected after it runs.
+
+use std::{env, fs::OpenOptions, io::Result, path::PathBuf};
 
 use memmap::MmapMut;
 
-fn create_memory_file(path: &str, size: usize) -> Result<()> {
-    let mut file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(path)?;
+use super::consts::FRAME_SIZE;
+
+/// Where the memory is kept, and how big it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryImage {
+    pub path: PathBuf,
+    pub size: usize,
+    /// Whether the contents an earlier run left in the file are kept, instead of starting from
+    /// zeros
+    pub reuse: bool,
+}
 
-    // Set the size of the file to the desired memory size
-    file.set_len(size as u64)?;
+impl MemoryImage {
+    pub const DEFAULT_PATH: &'static str = "mem.img";
+    pub const DEFAULT_SIZE: usize = 0x800000;
 
-    // Initialize the memory to all zeros
-    file.rewind()?;
-    let zeros = vec![0u8; size];
-    file.write_all(&zeros)?;
+    /// Reads the image's configuration from the command line (`--mem-image PATH`,
+    /// `--mem-size SIZE` and `--reuse-mem`), or else from the environment (`RISCY_MEM_IMAGE`,
+    /// `RISCY_MEM_SIZE` and `RISCY_MEM_REUSE`). A size is a number of bytes, with an optional
+    /// `K`, `M` or `G` suffix, like `8M` or `0x800000`.
+    pub fn from_args() -> core::result::Result<Self, String> {
+        let option = |flag: &str, var: &str| {
+            env::args()
+                .skip_while(|arg| arg != flag)
+                .nth(1)
+                .or_else(|| env::var(var).ok())
+        };
 
-    Ok(())
+        let size = match option("--mem-size", "RISCY_MEM_SIZE") {
+            Some(size) => parse_size(&size)?,
+            None => Self::DEFAULT_SIZE,
+        };
+        if size == 0 || !size.is_multiple_of(FRAME_SIZE) {
+            return Err(format!(
+                "The memory's size {size:#X} is not a whole number of frames"
+            ));
+        }
+        Ok(Self {
+            path: option("--mem-image", "RISCY_MEM_IMAGE")
+                .unwrap_or_else(|| Self::DEFAULT_PATH.to_owned())
+                .into(),
+            size,
+            reuse: env::args().any(|arg| arg == "--reuse-mem")
+                || env::var("RISCY_MEM_REUSE").is_ok_and(|reuse| reuse != "0"),
+        })
+    }
 }
 
-pub fn init_virtual_memory(mem_size: usize) -> MmapMut {
-    // Create the memory file with the desired size
-    create_memory_file("mem.img", mem_size).expect("Failed to create memory file");
-    println!("Created memory file.");
+/// Parses a size like `4096`, `0x1000`, `64K`, `8M` or `1G`.
+fn parse_size(word: &str) -> core::result::Result<usize, String> {
+    let digits = word.replace('_', "");
+    let (digits, scale) = match digits.chars().last().map(|last| last.to_ascii_uppercase()) {
+        Some('K') => (&digits[..digits.len() - 1], 1 << 10),
+        Some('M') => (&digits[..digits.len() - 1], 1 << 20),
+        Some('G') => (&digits[..digits.len() - 1], 1 << 30),
+        _ => (digits.as_str(), 1),
+    };
+    match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .ok()
+    .and_then(|value| value.checked_mul(scale))
+    .ok_or_else(|| format!("`{word}` is not a size"))
+}
 
-    // Open the memory file and map it into memory
+/// Maps the image's file into memory. The file is created sparse, so a big image takes no disk
+/// space until its frames are written, and it's only cleared when it isn't reused.
+pub fn init_virtual_memory(image: &MemoryImage) -> Result<MmapMut> {
     let file = OpenOptions::new()
         .read(true)
         .write(true)
-        .open("mem.img")
-        .expect("Failed to open memory file");
-
-    unsafe {
-        memmap::MmapOptions::new()
-            .len(mem_size)
-            .map_mut(&file)
-            .expect("Failed to map memory file into memory")
+        .create(true)
+        .truncate(!image.reuse)
+        .open(&image.path)?;
+
+    // Extending a file fills it with zeros without writing them
+    let len = file.metadata()?.len();
+    if len < image.size as u64 {
+        file.set_len(image.size as u64)?;
+    }
+    if image.reuse && len > 0 {
+        println!("Reused memory file {}.", image.path.display());
+    } else {
+        println!("Created memory file {}.", image.path.display());
+    }
+
+    unsafe { memmap::MmapOptions::new().len(image.size).map_mut(&file) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, os::unix::fs::MetadataExt};
+
+    #[test]
+    fn images_are_sparse_and_can_be_reused() {
+        assert_eq!(parse_size("8M"), Ok(0x800000));
+        assert_eq!(parse_size("0x1_000"), Ok(0x1000));
+        assert_eq!(parse_size("64k"), Ok(0x10000));
+        assert!(parse_size("8T").is_err());
+
+        let mut image = MemoryImage {
+            path: env::temp_dir().join(format!("riscy-mem-{}.img", std::process::id())),
+            size: 1 << 30,
+            reuse: false,
+        };
+        let mut mem = init_virtual_memory(&image).unwrap();
+        mem[0x1234] = 0x42;
+        mem.flush().unwrap();
+        drop(mem);
+        // A gigabyte of zeros, without a gigabyte of blocks
+        let metadata = fs::metadata(&image.path).unwrap();
+        assert_eq!(metadata.len(), 1 << 30);
+        assert!(metadata.blocks() * 512 < 1 << 20);
+
+        image.reuse = true;
+        assert_eq!(init_virtual_memory(&image).unwrap()[0x1234], 0x42);
+        image.reuse = false;
+        assert_eq!(init_virtual_memory(&image).unwrap()[0x1234], 0);
+        fs::remove_file(&image.path).unwrap();
     }
 }
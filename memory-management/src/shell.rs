@@ -0,0 +1,307 @@
+//! A shell for trying the memory manager by hand: every line is a command that allocates, frees,
+//! maps or inspects memory (see `HELP`), so there's no need to edit `main.rs` to experiment.
+
+use crate::memory::{
+    self,
+    addr::{PhysAddr, VirtAddr},
+    alloc,
+    paging::{self, PageEntryFlags, PageEntryLevel, PageTable},
+};
+use std::{
+    alloc::Layout,
+    collections::BTreeMap,
+    io::{self, BufRead, Write},
+};
+
+const HELP: &str = "\
+alloc N                     Allocates N bytes from the kernel's heap
+free ADDR                   Frees the object `alloc` returned at ADDR
+map VIRT PHYS FLAGS LEVEL   Maps VIRT to PHYS with FLAGS (any of rwxugad) in a page of LEVEL
+                            (4K, 2M, 1G, 512G or 256T)
+remap VIRT PHYS FLAGS LEVEL Like `map`, in place of the page of LEVEL that maps VIRT
+translate VIRT              Walks the page tables to the address VIRT maps
+dump                        Prints the ranges the page tables map
+verify                      Checks the structure of the page tables
+stats                       Prints how the frames and the heap are used
+save PATH                   Saves the memory and the frames to PATH, to resume from with
+                            `--restore-snapshot PATH`
+help                        Prints this
+quit                        Leaves the shell";
+
+/// The alignment of the objects `alloc` allocates.
+const ALLOC_ALIGN: usize = 8;
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Alloc(usize),
+    Free(usize),
+    Map {
+        virt: usize,
+        phys: usize,
+        flags: PageEntryFlags,
+        level: PageEntryLevel,
+        overwrite: bool,
+    },
+    Translate(usize),
+    Dump,
+    Verify,
+    Stats,
+    Save(String),
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Parses a line, like `map 0x4000_0000 0x80200000 rw 4K`.
+    fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Err("Empty command".to_owned());
+        };
+
+        let expected = match name {
+            "alloc" | "free" | "translate" | "save" => 1,
+            "map" | "remap" => 4,
+            "dump" | "verify" | "stats" | "help" | "quit" | "exit" => 0,
+            _ => return Err(format!("Unknown command `{name}`, try `help`")),
+        };
+        if args.len() != expected {
+            return Err(format!(
+                "`{name}` takes {expected} arguments, not {}",
+                args.len()
+            ));
+        }
+
+        Ok(match name {
+            "alloc" => Command::Alloc(parse_number(args[0])?),
+            "free" => Command::Free(parse_number(args[0])?),
+            "map" | "remap" => Command::Map {
+                virt: parse_number(args[0])?,
+                phys: parse_number(args[1])?,
+                flags: parse_flags(args[2])?,
+                level: parse_level(args[3])?,
+                overwrite: name == "remap",
+            },
+            "translate" => Command::Translate(parse_number(args[0])?),
+            "dump" => Command::Dump,
+            "verify" => Command::Verify,
+            "stats" => Command::Stats,
+            "save" => Command::Save(args[0].to_owned()),
+            "help" => Command::Help,
+            _ => Command::Quit,
+        })
+    }
+}
+
+/// Parses a decimal number, or a hexadecimal one that starts with `0x`. Both may have `_`s.
+fn parse_number(word: &str) -> Result<usize, String> {
+    let digits = word.replace('_', "");
+    match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| format!("`{word}` is not a number"))
+}
+
+/// Parses flags like `rw` or `rxug`, a letter per flag.
+fn parse_flags(word: &str) -> Result<PageEntryFlags, String> {
+    let mut flags = PageEntryFlags::EMPTY;
+    for letter in word.chars() {
+        flags = flags
+            | match letter.to_ascii_lowercase() {
+                'r' => PageEntryFlags::READ,
+                'w' => PageEntryFlags::WRITE,
+                'x' => PageEntryFlags::EXECUTE,
+                'u' => PageEntryFlags::USER,
+                'g' => PageEntryFlags::GLOBAL,
+                'a' => PageEntryFlags::ACCESSED,
+                'd' => PageEntryFlags::DIRTY,
+                _ => return Err(format!("Unknown flag `{letter}`, the flags are rwxugad")),
+            };
+    }
+    Ok(flags)
+}
+
+fn parse_level(word: &str) -> Result<PageEntryLevel, String> {
+    Ok(match word.to_ascii_uppercase().as_str() {
+        "4K" | "4KIB" => PageEntryLevel::KiB4,
+        "2M" | "2MIB" => PageEntryLevel::MiB2,
+        "1G" | "1GIB" => PageEntryLevel::GiB1,
+        "512G" | "512GIB" => PageEntryLevel::GiB512,
+        "256T" | "256TIB" => PageEntryLevel::TiB256,
+        _ => return Err(format!("Unknown page size `{word}`, try 4K, 2M or 1G")),
+    })
+}
+
+pub struct Shell<'a> {
+    root: &'a mut PageTable,
+    /// The objects `alloc` allocated and `free` didn't free yet, by address
+    objects: BTreeMap<usize, Layout>,
+}
+
+impl<'a> Shell<'a> {
+    /// Creates a shell that maps and translates in `root`.
+    pub fn new(root: &'a mut PageTable) -> Self {
+        Self {
+            root,
+            objects: BTreeMap::new(),
+        }
+    }
+
+    /// Runs the commands in `input`, a line each, until it ends or one of them is `quit`.
+    pub fn run(&mut self, input: impl BufRead) -> Result<(), io::Error> {
+        prompt()?;
+        for line in input.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                match Command::parse(&line) {
+                    Ok(Command::Quit) => return Ok(()),
+                    Ok(command) => self.execute(command),
+                    Err(error) => println!("! {error}"),
+                }
+            }
+            prompt()?;
+        }
+        println!();
+        Ok(())
+    }
+
+    fn execute(&mut self, command: Command) {
+        match command {
+            Command::Alloc(size) => {
+                let Ok(layout) = Layout::from_size_align(size.max(1), ALLOC_ALIGN) else {
+                    println!("! {size} bytes is too big");
+                    return;
+                };
+                let object = alloc::alloc(layout);
+                if object.is_null() {
+                    println!("! Out of memory");
+                    return;
+                }
+                self.objects.insert(object as usize, layout);
+                println!("{object:?}");
+            }
+            Command::Free(addr) => match self.objects.remove(&addr) {
+                Some(layout) => {
+                    unsafe { alloc::dealloc(addr as *mut u8, layout) };
+                    println!("Freed {} bytes", layout.size());
+                }
+                None => println!("! {addr:#X} is not an object `alloc` returned"),
+            },
+            Command::Map {
+                virt,
+                phys,
+                flags,
+                level,
+                overwrite,
+            } => {
+                if level.val() > PageEntryLevel::top().val() {
+                    println!("! {:?} paging has no {level:?} pages", paging::mode());
+                    return;
+                }
+                let (frame, page) = (PhysAddr::new(phys), VirtAddr::new(virt));
+                let result = if overwrite {
+                    paging::map_overwrite(self.root, frame, page, &flags, level)
+                } else {
+                    paging::try_map(self.root, frame, page, &flags, level).map(|()| None)
+                };
+                match result {
+                    Ok(Some(previous)) => println!(
+                        "Mapped {virt:#X} -> {phys:#X} in place of {:#X}",
+                        previous.as_usize()
+                    ),
+                    Ok(None) => println!("Mapped {virt:#X} -> {phys:#X}"),
+                    Err(error) => println!("! {error:?}"),
+                }
+            }
+            Command::Translate(virt) => match paging::translate(self.root, virt) {
+                Ok(translation) => println!(
+                    "{virt:#X} -> {:#X} ({:?}, {:?})",
+                    translation.phys, translation.level, translation.flags
+                ),
+                Err(error) => println!("! {error:?}"),
+            },
+            Command::Dump => paging::dump(self.root),
+            Command::Verify => match paging::verify(self.root) {
+                Ok(()) => println!("The page tables are intact"),
+                Err(violation) => println!("! {violation}"),
+            },
+            Command::Stats => {
+                let frames = memory::frame_stats();
+                println!(
+                    "Frames: {}/{} used, {} at peak, largest free run of {}",
+                    frames.used_frames,
+                    frames.total_frames,
+                    frames.peak_used_frames,
+                    frames.largest_free_run
+                );
+                let heap = alloc::heap_stats();
+                println!(
+                    "Heap: {} frames, {} bytes free in {} regions ({:.0}% fragmented)",
+                    alloc::heap_frames(),
+                    heap.free_bytes,
+                    heap.free_regions,
+                    heap.fragmentation() * 100.0
+                );
+                for cache in alloc::slab_stats() {
+                    if cache.slabs > 0 {
+                        println!(
+                            "Slab cache {}: {} objects in {} slabs",
+                            cache.object_size, cache.allocated, cache.slabs
+                        );
+                    }
+                }
+                println!("Shell objects: {}", self.objects.len());
+            }
+            Command::Save(path) => match memory::save_snapshot(&path, Some(self.root)) {
+                Ok(()) => println!("Saved the memory to {path}"),
+                Err(error) => println!("! {error}"),
+            },
+            Command::Help => println!("{HELP}"),
+            Command::Quit => {}
+        }
+    }
+}
+
+fn prompt() -> Result<(), io::Error> {
+    print!("> ");
+    io::stdout().flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_parse() {
+        assert_eq!(Command::parse("alloc 4096"), Ok(Command::Alloc(4096)));
+        assert_eq!(
+            Command::parse("  free 0x8020_1000 "),
+            Ok(Command::Free(0x8020_1000))
+        );
+        assert_eq!(
+            Command::parse("map 0x40000000 0x80200000 rwa 2M"),
+            Ok(Command::Map {
+                virt: 0x4000_0000,
+                phys: 0x8020_0000,
+                flags: PageEntryFlags::READ_WRITE | PageEntryFlags::ACCESSED,
+                level: PageEntryLevel::MiB2,
+                overwrite: false,
+            })
+        );
+        assert!(matches!(
+            Command::parse("remap 0 0 w 4K"),
+            Ok(Command::Map {
+                overwrite: true,
+                ..
+            })
+        ));
+        assert_eq!(Command::parse("exit"), Ok(Command::Quit));
+
+        assert!(Command::parse("alloc").is_err());
+        assert!(Command::parse("free 12z").is_err());
+        assert!(Command::parse("map 0 0 rq 4K").is_err());
+        assert!(Command::parse("map 0 0 r 3K").is_err());
+        assert!(Command::parse("mmap 0").is_err());
+    }
+}
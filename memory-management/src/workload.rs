@@ -0,0 +1,140 @@
+//! A scripted workload for the display to watch: it replays a random allocation trace on the
+//! kernel's heap, and maps and unmaps memory in an address space along with every object, so the
+//! frames, the heap's free list and the page tables all change as it runs.
+
+use crate::memory::{
+    alloc::{self, bench::TraceOp},
+    paging::{PageEntryFlags, PageEntryLevel, PageTable},
+    space::{AddressSpace, Backing},
+    MemoryError,
+};
+use std::alloc::Layout;
+
+/// The steps of a trace, after which the objects are freed and a new trace starts.
+const TRACE_LEN: usize = 500;
+const MAX_ALIVE: usize = 14;
+/// Every object comes with a mapping this many times its size
+const MAPPING_SCALE: usize = 16;
+/// Where the mapping of the `n`th object goes, each in a slot big enough for the biggest object
+const MAPPINGS_BASE: usize = 0x1000_0000;
+const MAPPING_SLOT: usize = 0x40000;
+
+/// An object the trace allocated, and where its mapping is.
+struct Object {
+    ptr: *mut u8,
+    layout: Layout,
+    mapping: Option<(usize, usize)>,
+}
+
+pub struct Workload {
+    trace: Vec<TraceOp>,
+    step: usize,
+    seed: u64,
+    /// The objects of the trace's allocations, None once freed (or if the allocation failed)
+    objects: Vec<Option<Object>>,
+    space: AddressSpace,
+}
+
+impl Workload {
+    pub fn new(seed: u64) -> Result<Self, MemoryError> {
+        Ok(Self {
+            trace: alloc::bench::random_trace(TRACE_LEN, MAX_ALIVE, seed),
+            step: 0,
+            seed,
+            objects: Vec::new(),
+            space: AddressSpace::new()?,
+        })
+    }
+
+    /// Returns the number of steps taken in the current trace.
+    pub fn step_count(&self) -> usize {
+        self.step
+    }
+
+    /// Returns the root table of the address space the workload maps in.
+    pub fn root(&mut self) -> &mut PageTable {
+        self.space.root()
+    }
+
+    /// Takes the next step of the trace, and returns what it did.
+    pub fn step(&mut self) -> String {
+        if self.step == self.trace.len() {
+            self.restart();
+            return format!("Freed everything, starting trace {:#X}", self.seed);
+        }
+
+        let op = self.trace[self.step];
+        self.step += 1;
+        match op {
+            TraceOp::Alloc(layout) => self.alloc(layout),
+            TraceOp::Free(n) => self.free(n),
+        }
+    }
+
+    fn alloc(&mut self, layout: Layout) -> String {
+        let ptr = alloc::alloc(layout);
+        if ptr.is_null() {
+            self.objects.push(None);
+            return format!("Failed to allocate {} bytes", layout.size());
+        }
+
+        let virt = MAPPINGS_BASE + self.objects.len() * MAPPING_SLOT;
+        let len = layout.size() * MAPPING_SCALE;
+        let mapped = self.space.map(
+            virt,
+            len,
+            PageEntryFlags::READ_WRITE | PageEntryFlags::USER,
+            PageEntryLevel::KiB4,
+            Backing::Anonymous,
+        );
+        let (mapping, message) = match mapped {
+            Ok(()) => (
+                Some((virt, len)),
+                format!(
+                    "Allocated {} bytes at {ptr:?}, mapped {len:#X} at {virt:#X}",
+                    layout.size()
+                ),
+            ),
+            Err(error) => (
+                None,
+                format!(
+                    "Allocated {} bytes at {ptr:?}, mapping failed: {error:?}",
+                    layout.size()
+                ),
+            ),
+        };
+        self.objects.push(Some(Object {
+            ptr,
+            layout,
+            mapping,
+        }));
+        message
+    }
+
+    fn free(&mut self, n: usize) -> String {
+        let Some(object) = self.objects[n].take() else {
+            return format!("Skipped freeing allocation #{n}, it failed");
+        };
+        self.release(&object);
+        format!("Freed {} bytes at {:?}", object.layout.size(), object.ptr)
+    }
+
+    fn release(&mut self, object: &Object) {
+        unsafe { alloc::dealloc(object.ptr, object.layout) };
+        if let Some((virt, len)) = object.mapping {
+            self.space
+                .unmap(virt, len)
+                .expect("Failed to unmap an object's mapping.");
+        }
+    }
+
+    /// Frees the objects that are still alive, and starts the trace of the next seed.
+    fn restart(&mut self) {
+        for object in core::mem::take(&mut self.objects).into_iter().flatten() {
+            self.release(&object);
+        }
+        self.seed += 1;
+        self.trace = alloc::bench::random_trace(TRACE_LEN, MAX_ALIVE, self.seed);
+        self.step = 0;
+    }
+}
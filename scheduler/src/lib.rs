@@ -0,0 +1,6 @@
+//! A completely fair scheduler simulation: schedulers, the processes and tasks they run, and a
+//! terminal runner to watch them.
+
+mod scheduler;
+
+pub use scheduler::*;
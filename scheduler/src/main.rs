@@ -1,26 +1,436 @@
-mod scheduler;
-
+use completely_fair_scheduler::{
+    cores, exits, fuzz, locks, sweep,
+    testing::{FixedTask, MockClock},
+    workload, BatchExperiment, BigLittle, BurstyTask, ControlServer, Core, CounterTask, Cpus,
+    InteractiveTask, IoBoundTask, Keymap, LockProtocol, MemoryHogTask, Niceness, NicenessScheduler,
+    Placement, Process, ProcessRunner, QuantumSweep, RotatingFileLogger, RoundRobinScheduler,
+    Scheduler, TaskRegistry, Theme, Watchdog,
+};
 use crossterm::{
     execute,
     terminal::{Clear, ClearType},
 };
-use scheduler::{CounterTask, NicenessScheduler, Process, ProcessRunner};
-use std::{io, time::Duration};
+use log::LevelFilter;
+use std::{env, fs, io, path::Path, time::Duration};
+
+fn demo_processes() -> Vec<Process> {
+    // The editor should respond within 10ms of a key press
+    let mut editor = Process::named(13, "Editor", Box::new(InteractiveTask::new()));
+    editor.set_deadline(Some(Duration::from_millis(10)));
+
+    // Processes 3 and 4 are children of process 0, and the memory hog was spawned by the compiler
+    let nice = Niceness::clamped;
+    let mut processes = vec![
+        Process::with_niceness(0, "Process 0", Box::new(CounterTask::new()), nice(10)),
+        Process::with_niceness(1, "Process 1", Box::new(CounterTask::new()), nice(19)),
+        Process::with_niceness(3, "Process 2", Box::new(CounterTask::new()), nice(-19)),
+        Process::named(10, "Process 3", Box::new(CounterTask::new())),
+        Process::with_niceness(12, "Process 4", Box::new(CounterTask::new()), nice(-2)),
+        editor,
+        Process::named(14, "Disk Reader", Box::new(IoBoundTask::new())),
+        Process::named(15, "Compiler", Box::new(BurstyTask::new())),
+        Process::with_niceness(16, "Memory Hog", Box::new(MemoryHogTask::new()), nice(5)),
+    ];
+    processes[3].set_parent(Some(0));
+    processes[4].set_parent(Some(0));
+    processes[8].set_parent(Some(15));
+    processes
+}
+
+/// CPU-bound processes and interactive ones which sleep most of the time, in mock time.
+fn sweep_processes() -> Vec<Process> {
+    let cpu_bound = |pid| {
+        Process::named(
+            pid,
+            "CPU Bound",
+            Box::new(FixedTask::new(Duration::from_millis(1))),
+        )
+    };
+    let interactive = |pid| {
+        Process::named(
+            pid,
+            "Interactive",
+            Box::new(FixedTask::sleeping(
+                Duration::from_millis(1),
+                Duration::from_millis(30),
+            )),
+        )
+    };
+    vec![
+        cpu_bound(0),
+        cpu_bound(1),
+        cpu_bound(2),
+        interactive(3),
+        interactive(4),
+    ]
+}
+
+/// Prints how the sweep workload does with every quantum, with `--sweep`.
+fn run_sweep() {
+    let sweep = QuantumSweep::default();
+
+    println!("{}", RoundRobinScheduler::NAME);
+    let points =
+        sweep.run(|quantum| RoundRobinScheduler::with_processes(sweep_processes(), quantum));
+    println!("{}", sweep::report(&points));
+
+    println!("{}", NicenessScheduler::NAME);
+    let points = sweep.run(|quantum| NicenessScheduler::with_processes(sweep_processes(), quantum));
+    println!("{}", sweep::report(&points));
+}
+
+/// Interactive and foreground processes, and CPU-bound background ones, in mock time.
+fn big_little_processes() -> Vec<Process> {
+    let interactive = |pid| {
+        Process::named(
+            pid,
+            "Interactive",
+            Box::new(FixedTask::sleeping(
+                Duration::from_millis(2),
+                Duration::from_millis(20),
+            )),
+        )
+    };
+    let cpu_bound = |pid, niceness| {
+        Process::with_niceness(
+            pid,
+            "CPU Bound",
+            Box::new(FixedTask::new(Duration::from_millis(1))),
+            Niceness::clamped(niceness),
+        )
+    };
+    vec![
+        interactive(0),
+        interactive(1),
+        cpu_bound(2, 0),
+        cpu_bound(3, 10),
+        cpu_bound(4, 10),
+        cpu_bound(5, 15),
+    ]
+}
+
+/// Prints the energy the big.LITTLE workload takes with every placement, with `--big-little`.
+fn run_big_little() {
+    for placement in [Placement::Spread, Placement::EnergyAware] {
+        let system = BigLittle::with_placement(placement);
+        let usages = system.run(big_little_processes(), |processes| {
+            NicenessScheduler::with_processes(processes, Duration::from_millis(10))
+        });
+        println!("{placement:?}");
+        println!("{}", cores::report(&usages, BigLittle::DEFAULT_DURATION));
+    }
+}
+
+/// Runs the workload file with both schedulers `BatchExperiment::DEFAULT_RUNS` times, varying the
+/// seed, with `--batch`. Prints a summary of the metrics, and writes every run's to a CSV file.
+fn run_batch(path: &str) -> Result<(), io::Error> {
+    fn batch<S: Scheduler>(
+        workload: &str,
+        results_path: &str,
+        scheduler: impl Fn(Vec<Process>) -> S,
+    ) -> Result<(), io::Error> {
+        let results = BatchExperiment::default().run(workload, scheduler)?;
+        println!("{}", S::NAME);
+        println!("{}", results.report());
+        fs::write(results_path, results.to_csv())?;
+        println!("Wrote every run to {results_path}\n");
+        Ok(())
+    }
+
+    let workload = fs::read_to_string(path)?;
+    batch(&workload, "batch-round-robin.csv", |processes| {
+        RoundRobinScheduler::with_processes(processes, BATCH_TICK_RATE)
+    })?;
+    batch(&workload, "batch-niceness.csv", |processes| {
+        NicenessScheduler::with_processes(processes, BATCH_TICK_RATE)
+    })
+}
+
+/// Runs random operations against both schedulers, and prints the first invariant each one
+/// broke, with `--fuzz`. Every run is seeded by its index, so a failing run can be replayed.
+fn run_fuzz() {
+    fn fuzz_all<S: Scheduler>(scheduler: impl Fn() -> S) {
+        let failure = (1..=FUZZ_RUNS).find_map(|seed| {
+            fuzz::check(&scheduler, &fuzz::random_ops(seed, FUZZ_OPS))
+                .err()
+                .map(|violation| (seed, violation))
+        });
+        match failure {
+            Some((seed, violation)) => println!("{}: seed {seed} failed: {violation}", S::NAME),
+            None => println!("{}: {FUZZ_RUNS} runs passed", S::NAME),
+        }
+    }
+
+    fuzz_all(|| RoundRobinScheduler::with_processes(Vec::new(), Duration::from_millis(10)));
+    fuzz_all(|| NicenessScheduler::with_processes(Vec::new(), Duration::from_millis(10)));
+}
+
+/// Prints the processes of every core as cores go offline and come back, with `--hotplug`.
+fn run_hotplug() {
+    let _clock = MockClock::install();
+    let cores = (0..4).map(|index| Core::little(&format!("CPU {index}")));
+    let processes = (0..8)
+        .map(|pid| {
+            Process::with_niceness(
+                pid,
+                "CPU Bound",
+                Box::new(FixedTask::new(Duration::from_millis(1))),
+                Niceness::clamped((pid % 4) as i8 * 3),
+            )
+        })
+        .collect();
+    let mut cpus = Cpus::new(cores.collect(), Placement::Spread, processes, |processes| {
+        NicenessScheduler::with_processes(processes, Duration::from_millis(10))
+    });
+
+    let print = |event: &str, cpus: &Cpus<NicenessScheduler>| {
+        println!("{:>5}ms {event}", cpus.elapsed().as_millis());
+        for ((core, pids), index) in cpus.cores().zip(cpus.pids()).zip(0..) {
+            let state = if cpus.is_online(index) {
+                ""
+            } else {
+                " (offline)"
+            };
+            println!("        {}{state}: {pids:?}", core.name);
+        }
+    };
+
+    print("Started", &cpus);
+    let events = [
+        (2, false),
+        (3, false),
+        (2, true),
+        (0, false),
+        (3, true),
+        (0, true),
+    ];
+    for (core, online) in events {
+        cpus.run_for(Duration::from_secs(1));
+        cpus.set_online(core, online);
+        let event = if online { "online" } else { "offline" };
+        print(&format!("CPU {core} went {event}"), &cpus);
+    }
+    cpus.run_for(Duration::from_secs(1));
+
+    println!("\n{} migrations", cpus.migrations());
+    println!("{}", cores::report(&cpus.usage(), cpus.elapsed()));
+}
+
+/// The priority inversion demo, which `--inversion` runs with every lock protocol, and
+/// `--donation-demo` runs in the terminal-user-interface with notes on what its mutex does.
+const INVERSION_WORKLOAD: &str = include_str!("../workloads/priority-inversion.txt");
+
+/// Prints how long the processes of the priority inversion demo waited for its mutex with every
+/// protocol, with `--inversion`.
+fn run_inversion() -> Result<(), io::Error> {
+    let protocols = [
+        LockProtocol::None,
+        LockProtocol::Inheritance,
+        LockProtocol::Ceiling(Niceness::MIN),
+    ];
+    for protocol in protocols {
+        let _clock = MockClock::install();
+        let registry = TaskRegistry::with_builtin_tasks();
+        let processes = workload::parse(INVERSION_WORKLOAD, &registry)?;
+        registry.locks().declare("shared", protocol);
+
+        let mut scheduler = NicenessScheduler::with_processes(processes, Duration::from_millis(10));
+        let waits = locks::simulate(&mut scheduler, &registry.locks(), Duration::from_secs(10));
+        println!("{protocol:?}");
+        println!("{}", locks::report(&waits));
+    }
+    Ok(())
+}
+
+/// Processes whose tasks are futures, waiting on timers and channels instead of blocking.
+#[cfg(feature = "async")]
+fn async_demo_processes() -> Vec<Process> {
+    use completely_fair_scheduler::FutureTask;
+    use tokio::{sync::mpsc, task, time};
+
+    let (sender, mut receiver) = mpsc::channel(1);
+    vec![
+        Process::named(
+            0,
+            "Ticker",
+            Box::new(FutureTask::new(|output| async move {
+                for tick in 1u64.. {
+                    output.set(format!("Tick {tick}"));
+                    time::sleep(Duration::from_millis(300)).await;
+                }
+            })),
+        ),
+        Process::named(
+            1,
+            "Producer",
+            Box::new(FutureTask::new(|output| async move {
+                for message in 1u64.. {
+                    time::sleep(Duration::from_secs(1)).await;
+                    if sender.send(message).await.is_err() {
+                        break;
+                    }
+                    output.set(format!("Sent {message}"));
+                }
+            })),
+        ),
+        Process::named(
+            2,
+            "Consumer",
+            Box::new(FutureTask::new(|output| async move {
+                while let Some(message) = receiver.recv().await {
+                    output.set(format!("Received {message}"));
+                }
+            })),
+        ),
+        // Never waits for anything, but yields after every step
+        Process::named(
+            3,
+            "Cruncher",
+            Box::new(FutureTask::new(|output| async move {
+                for step in 1u64.. {
+                    output.set(format!("Step {step}"));
+                    task::yield_now().await;
+                }
+            })),
+        ),
+    ]
+}
+
+/// Runs the async demo processes on a tokio runtime, with `--async`.
+#[cfg(feature = "async")]
+fn run_async() -> Result<(), io::Error> {
+    use completely_fair_scheduler::AsyncProcessRunner;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()?;
+
+    execute!(io::stdout(), Clear(ClearType::All))?;
+    let scheduler =
+        NicenessScheduler::with_processes(async_demo_processes(), Duration::from_millis(500));
+    runtime.block_on(AsyncProcessRunner::new(scheduler).run());
+    execute!(io::stdout(), Clear(ClearType::All))?;
+    Ok(())
+}
+
+const LOG_PATH: &str = "logs/scheduler.log";
+const KEYMAP_PATH: &str = "keys.conf";
+const THEME_PATH: &str = "theme.conf";
+/// How long `--headless` runs for if some processes never exit
+const HEADLESS_LIMIT: Duration = Duration::from_secs(10);
+/// How many random runs `--fuzz` makes per scheduler, and how many operations each one has
+const FUZZ_RUNS: u64 = 500;
+const FUZZ_OPS: usize = 200;
+/// The tick rate of the schedulers of `--batch`
+const BATCH_TICK_RATE: Duration = Duration::from_millis(10);
+/// How long a task gets to run on its thread with `--threads`
+const THREAD_SLICE: Duration = Duration::from_millis(20);
 
 fn main() -> Result<(), io::Error> {
+    // The TUI owns the terminal, so diagnostics go to a log file instead
+    RotatingFileLogger::new(
+        LOG_PATH,
+        RotatingFileLogger::DEFAULT_MAX_SIZE,
+        RotatingFileLogger::DEFAULT_BACKUPS,
+    )?
+    .init(LevelFilter::Info)
+    .expect("Failed to set the logger.");
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let threaded = args.iter().any(|arg| arg == "--threads");
+    let headless = args.iter().any(|arg| arg == "--headless");
+    let donation_demo = args.iter().any(|arg| arg == "--donation-demo");
+    if args.iter().any(|arg| arg == "--sweep") {
+        run_sweep();
+        return Ok(());
+    }
+    if args.iter().any(|arg| arg == "--inversion") {
+        return run_inversion();
+    }
+    if args.iter().any(|arg| arg == "--fuzz") {
+        run_fuzz();
+        return Ok(());
+    }
+    if args.iter().any(|arg| arg == "--hotplug") {
+        run_hotplug();
+        return Ok(());
+    }
+    if args.iter().any(|arg| arg == "--big-little") {
+        run_big_little();
+        return Ok(());
+    }
+    #[cfg(feature = "async")]
+    if args.iter().any(|arg| arg == "--async") {
+        return run_async();
+    }
+    if args.iter().any(|arg| arg == "--batch") {
+        return match args.iter().find(|arg| !arg.starts_with("--")) {
+            Some(path) => run_batch(path),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--batch needs a workload file",
+            )),
+        };
+    }
+
+    // Run the processes of the given workload file, or the demo processes if there isn't one
+    let registry = TaskRegistry::with_builtin_tasks();
+    let processes = match args.into_iter().find(|arg| !arg.starts_with("--")) {
+        _ if donation_demo => workload::parse(INVERSION_WORKLOAD, &registry)?,
+        Some(path) => workload::load(path, &registry)?,
+        None => demo_processes(),
+    };
+
+    // Run without the terminal-user-interface, and print how the processes that exited did
+    if headless {
+        let mut scheduler =
+            NicenessScheduler::with_processes(processes, Duration::from_millis(500));
+        println!(
+            "{}",
+            exits::run_headless(&mut scheduler, &registry.zombies(), HEADLESS_LIMIT)
+        );
+        return Ok(());
+    }
+
+    // Rebind keys if there's a keymap config
+    let keymap = if Path::new(KEYMAP_PATH).exists() {
+        Keymap::load(KEYMAP_PATH)?
+    } else {
+        Keymap::default()
+    };
+    let theme = if Path::new(THEME_PATH).exists() {
+        Theme::load(THEME_PATH)?
+    } else {
+        Theme::default()
+    };
+
     execute!(io::stdout(), Clear(ClearType::All))?;
 
-    let scheduler = NicenessScheduler::with_processes(
-        vec![
-            Process::with_niceness(0, "Process 0", Box::new(CounterTask::new()), 10),
-            Process::with_niceness(1, "Process 1", Box::new(CounterTask::new()), 20),
-            Process::with_niceness(3, "Process 2", Box::new(CounterTask::new()), -19),
-            Process::named(10, "Process 3", Box::new(CounterTask::new())),
-            Process::with_niceness(12, "Process 4", Box::new(CounterTask::new()), -2),
-        ],
-        Duration::from_millis(500),
-    );
+    let scheduler = NicenessScheduler::with_processes(Vec::new(), Duration::from_millis(500));
     let mut runner = ProcessRunner::new(scheduler);
+    // Processes with a later arrival time wait until the scheduler gets to it
+    for process in processes {
+        runner.add_process(process);
+    }
+    runner.set_keymap(keymap);
+    runner.set_theme(theme);
+    runner.set_locks(registry.locks());
+    if donation_demo {
+        runner.narrate_locks();
+    }
+    runner.set_zombies(registry.zombies());
+    // Keep the display responsive even if a task never returns
+    if threaded {
+        runner.use_threads(THREAD_SLICE);
+    } else {
+        runner.set_watchdog(Watchdog::isolated(Watchdog::DEFAULT_BUDGET));
+    }
+
+    // Let external scripts drive the simulation, unless another instance already does
+    match ControlServer::bind(ControlServer::DEFAULT_ADDRESS) {
+        Ok(server) => runner.listen(server, registry),
+        Err(error) => log::warn!("Failed to bind the control socket: {error}"),
+    }
 
     while runner.run() {}
 
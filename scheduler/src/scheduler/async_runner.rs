@@ -0,0 +1,258 @@
+use super::{
+    clock,
+    display::{DisplayTerminal, View},
+    runner::RunnerEvent,
+    syscall, ExitReport, LatencyHistogram, LoadAverage, ProcessState, Scheduler, Syscall, Task,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+use tokio::{sync::Notify, time};
+
+/// Woken whenever a future task is, so the runner can stop waiting.
+static WAKEUPS: Notify = Notify::const_new();
+
+/// How often the display is redrawn and the input is handled.
+const DRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Lets a future task report its output, which is returned from its next run.
+#[derive(Clone, Default)]
+pub struct TaskOutput(Arc<Mutex<String>>);
+
+impl TaskOutput {
+    pub fn set(&self, output: impl Into<String>) {
+        *self.0.lock().expect("Failed to lock the task output.") = output.into();
+    }
+
+    fn take(&self) -> String {
+        std::mem::take(&mut self.0.lock().expect("Failed to lock the task output."))
+    }
+}
+
+/// Set by the future's waker, when whatever it was waiting for happened.
+struct Woken(AtomicBool);
+
+impl Wake for Woken {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+        WAKEUPS.notify_one();
+    }
+}
+
+/// A task written as a future. Every run polls it once, and a pending future waits (like a
+/// blocked task) until its waker is called, e.g. by a timer or a channel.
+///
+/// The future must be polled by an `AsyncProcessRunner`, which provides the tokio timers.
+pub struct FutureTask {
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    output: TaskOutput,
+    woken: Arc<Woken>,
+    finished: bool,
+}
+
+impl FutureTask {
+    /// Creates the task's future, handing it the output it reports to.
+    pub fn new<F: Future<Output = ()> + Send + 'static>(
+        future: impl FnOnce(TaskOutput) -> F,
+    ) -> Self {
+        let output = TaskOutput::default();
+        Self {
+            future: Box::pin(future(output.clone())),
+            output,
+            woken: Arc::new(Woken(AtomicBool::new(true))),
+            finished: false,
+        }
+    }
+}
+
+impl Task for FutureTask {
+    fn run(&mut self) -> String {
+        if self.finished {
+            return "Finished".to_owned();
+        }
+
+        self.woken.0.store(false, Ordering::Release);
+        let waker = Waker::from(Arc::clone(&self.woken));
+        if let Poll::Ready(()) = self.future.as_mut().poll(&mut Context::from_waker(&waker)) {
+            self.finished = true;
+        }
+        self.output.take()
+    }
+
+    fn is_waiting(&self) -> bool {
+        !self.woken.0.load(Ordering::Acquire)
+    }
+
+    /// A finished future exits with code 0.
+    fn syscalls(&mut self) -> Vec<Syscall> {
+        self.finished
+            .then_some(Syscall::Exit(0))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Runs the scheduler on a tokio runtime, where the processes' tasks are `FutureTask`s.
+///
+/// Instead of running a process every tick, the runner keeps running processes while there are
+/// runnable ones, and awaits a waker, a sleeping process' wake up time or the next redraw once
+/// there aren't, so an idle system doesn't spin.
+pub struct AsyncProcessRunner<S> {
+    terminal: DisplayTerminal,
+    scheduler: S,
+    paused: bool,
+    view: View,
+    /// The output of the last process that ran
+    output: String,
+    /// Wake-to-run latencies with the sleeper boost disabled (0) and enabled (1)
+    latencies: [LatencyHistogram; 2],
+    load_average: LoadAverage,
+    exits: ExitReport,
+}
+
+impl<S: Scheduler> AsyncProcessRunner<S> {
+    pub fn new(scheduler: S) -> Self {
+        Self {
+            terminal: DisplayTerminal::new().expect("Failed to create a terminal."),
+            scheduler,
+            paused: false,
+            view: View::default(),
+            output: String::new(),
+            latencies: Default::default(),
+            load_average: LoadAverage::new(),
+            exits: ExitReport::default(),
+        }
+    }
+
+    /// Runs until the user quits.
+    pub async fn run(mut self) {
+        let mut last_draw = Instant::now() - DRAW_INTERVAL;
+        loop {
+            if last_draw.elapsed() >= DRAW_INTERVAL {
+                last_draw = Instant::now();
+                if !self.handle_input() {
+                    return;
+                }
+                self.draw();
+            }
+
+            if !self.paused && self.run_process() {
+                // Let the runtime fire the timers and wakers before running the next process
+                tokio::task::yield_now().await;
+                continue;
+            }
+
+            // Nothing can run, so wait for something to wake up
+            let next_draw = last_draw + DRAW_INTERVAL;
+            let next_wake = self
+                .scheduler
+                .processes()
+                .filter_map(|process| match process.state() {
+                    ProcessState::Sleeping { until } => Some(until),
+                    _ => None,
+                })
+                .min()
+                .map_or(next_draw, |until| until.min(next_draw));
+            tokio::select! {
+                _ = WAKEUPS.notified() => {}
+                _ = time::sleep_until(next_wake.into()) => {}
+            }
+        }
+    }
+
+    /// Runs the scheduled process. Returns false if no process could run.
+    fn run_process(&mut self) -> bool {
+        let sleeper_boost = self.scheduler.sleeper_boost();
+        let Some(process) = self.scheduler.schedule() else {
+            return false;
+        };
+
+        if let Some(latency) = process.wake_latency() {
+            self.latencies[usize::from(sleeper_boost)].record(latency);
+        }
+
+        let pid = process.pid();
+        let start_time = clock::now();
+        self.output = process.run();
+        let elapsed = process.dilate(clock::elapsed(start_time));
+        self.scheduler.add_cpu_elapsed(elapsed);
+        syscall::service(&mut self.scheduler, pid);
+        let exit_code = self
+            .scheduler
+            .process(pid)
+            .and_then(|process| process.exit_code());
+
+        if let Some(exit_code) = exit_code {
+            let process = self
+                .scheduler
+                .remove_pid(pid)
+                .expect("Failed to remove the exited process.");
+            self.exits
+                .record(&process, exit_code, self.scheduler.cpu_elapsed());
+        }
+        true
+    }
+
+    /// Handles the keys pressed since the last time. Returns false if the program should quit.
+    fn handle_input(&mut self) -> bool {
+        while let Some(event) = self.terminal.try_input() {
+            match event {
+                RunnerEvent::Quit => return false,
+                RunnerEvent::Pause => self.paused = true,
+                RunnerEvent::Resume => self.paused = false,
+                RunnerEvent::Step if self.paused => {
+                    self.run_process();
+                }
+                RunnerEvent::ToggleSleeperBoost => {
+                    let enabled = self.scheduler.sleeper_boost();
+                    self.scheduler.set_sleeper_boost(!enabled);
+                }
+                RunnerEvent::ToggleAutogroup => {
+                    let enabled = self.scheduler.autogroup();
+                    self.scheduler.set_autogroup(!enabled);
+                }
+                RunnerEvent::SelectPrevious => {
+                    self.view.selected = self.view.selected.saturating_sub(1)
+                }
+                RunnerEvent::SelectNext => self.view.selected += 1,
+                RunnerEvent::ToggleExits => self.view.show_exits = !self.view.show_exits,
+                RunnerEvent::ToggleHelp => self.view.show_help = !self.view.show_help,
+                _ => {}
+            }
+        }
+        true
+    }
+
+    fn draw(&mut self) {
+        let runnable = self
+            .scheduler
+            .processes()
+            .filter(|process| process.is_runnable())
+            .count();
+        self.load_average.sample(runnable);
+
+        self.view.selected = self
+            .view
+            .selected
+            .min(self.scheduler.processes().len().saturating_sub(1));
+        self.terminal.draw(
+            &self.scheduler,
+            self.output.clone(),
+            &self.view,
+            &self.latencies,
+            &self.load_average,
+            &self.exits,
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! Runs a workload many times with different seeds, and sums up how much its metrics vary, for
+//! reports that need more than a single run.
+
+use super::{
+    clock, syscall,
+    testing::{MockClock, IDLE_STEP},
+    workload, Process, Scheduler, TaskRegistry,
+};
+use std::{fmt::Write, io, time::Duration};
+
+/// The two-sided 95% critical values of Student's t distribution, by degrees of freedom from 1.
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+/// The critical value for more degrees of freedom than `T_95` has, from the normal distribution
+const Z_95: f64 = 1.960;
+
+/// How a workload did in one run.
+#[derive(Clone, Debug)]
+pub struct RunMetrics {
+    pub seed: u64,
+    /// The fraction of the run spent running tasks, rather than idling
+    pub throughput: f64,
+    pub context_switches: u64,
+    pub mean_latency: Duration,
+    pub max_latency: Duration,
+    /// How many processes exited during the run
+    pub exited: usize,
+}
+
+/// The mean of a metric over the runs, its sample standard deviation, and the 95% confidence
+/// interval of the mean.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    pub mean: f64,
+    pub stddev: f64,
+    pub ci_low: f64,
+    pub ci_high: f64,
+}
+
+impl Summary {
+    pub fn of(values: &[f64]) -> Self {
+        let count = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / count;
+        if values.len() < 2 {
+            return Self {
+                mean,
+                stddev: 0.0,
+                ci_low: mean,
+                ci_high: mean,
+            };
+        }
+
+        let variance = values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / (count - 1.0);
+        let stddev = variance.sqrt();
+        let critical = T_95.get(values.len() - 2).copied().unwrap_or(Z_95);
+        let margin = critical * stddev / count.sqrt();
+        Self {
+            mean,
+            stddev,
+            ci_low: mean - margin,
+            ci_high: mean + margin,
+        }
+    }
+}
+
+/// The metrics of every run of a batch.
+#[derive(Clone, Debug, Default)]
+pub struct BatchResults {
+    pub runs: Vec<RunMetrics>,
+}
+
+impl BatchResults {
+    /// The names of the metrics, in the order of the report's rows and the CSV's columns.
+    pub const METRICS: [&'static str; 5] = [
+        "throughput_percent",
+        "context_switches",
+        "mean_latency_ms",
+        "max_latency_ms",
+        "exited",
+    ];
+
+    fn values(run: &RunMetrics) -> [f64; 5] {
+        [
+            run.throughput * 100.0,
+            run.context_switches as f64,
+            run.mean_latency.as_secs_f64() * 1000.0,
+            run.max_latency.as_secs_f64() * 1000.0,
+            run.exited as f64,
+        ]
+    }
+
+    /// The summary of every metric, in the order of `METRICS`.
+    pub fn summaries(&self) -> Vec<Summary> {
+        let values: Vec<[f64; 5]> = self.runs.iter().map(BatchResults::values).collect();
+        (0..BatchResults::METRICS.len())
+            .map(|metric| {
+                let column: Vec<f64> = values.iter().map(|run| run[metric]).collect();
+                Summary::of(&column)
+            })
+            .collect()
+    }
+
+    /// A table of the summary of every metric.
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{} runs\n{:<20} {:>10} {:>10} {:>23}\n",
+            self.runs.len(),
+            "Metric",
+            "Mean",
+            "Stddev",
+            "95% CI"
+        );
+        for (metric, summary) in BatchResults::METRICS.iter().zip(self.summaries()) {
+            writeln!(
+                report,
+                "{metric:<20} {:>10.2} {:>10.2} {:>10.2} .. {:>9.2}",
+                summary.mean, summary.stddev, summary.ci_low, summary.ci_high
+            )
+            .expect("Failed to write the report.");
+        }
+        report
+    }
+
+    /// The results as CSV: a row for every run by its seed, followed by rows for the mean, the
+    /// standard deviation, and the bounds of the confidence interval.
+    pub fn to_csv(&self) -> String {
+        let mut csv = format!("seed,{}\n", BatchResults::METRICS.join(","));
+        let mut row = |label: &str, values: &[f64]| {
+            let values: Vec<String> = values.iter().map(|value| format!("{value:.6}")).collect();
+            writeln!(csv, "{label},{}", values.join(",")).expect("Failed to write the CSV.");
+        };
+
+        for run in &self.runs {
+            row(&run.seed.to_string(), &BatchResults::values(run));
+        }
+        let summaries = self.summaries();
+        let summary_row =
+            |field: fn(&Summary) -> f64| -> Vec<f64> { summaries.iter().map(field).collect() };
+        row("mean", &summary_row(|summary| summary.mean));
+        row("stddev", &summary_row(|summary| summary.stddev));
+        row("ci95_low", &summary_row(|summary| summary.ci_low));
+        row("ci95_high", &summary_row(|summary| summary.ci_high));
+        csv
+    }
+}
+
+/// Runs a workload once for every seed from 1 to `runs`, on a mock clock.
+///
+/// Every run parses the workload again with a registry seeded by the run's seed, so generators
+/// spawn different processes at different times in every run, while the rest of the workload
+/// stays the same. Tasks which don't take mock time when they run (like `counter`) are charged
+/// an idle step per run, so the runs still end.
+pub struct BatchExperiment {
+    runs: u64,
+    duration: Duration,
+}
+
+impl BatchExperiment {
+    pub const DEFAULT_RUNS: u64 = 10;
+    pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+
+    /// A batch of `runs` runs, every one taking `duration` of mock time.
+    pub fn new(runs: u64, duration: Duration) -> Self {
+        Self { runs, duration }
+    }
+
+    /// Runs the workload on the scheduler that `scheduler` creates with its processes.
+    pub fn run<S: Scheduler>(
+        &self,
+        workload: &str,
+        mut scheduler: impl FnMut(Vec<Process>) -> S,
+    ) -> Result<BatchResults, io::Error> {
+        let runs = (1..=self.runs)
+            .map(|seed| self.run_once(seed, workload, &mut scheduler))
+            .collect::<Result<_, _>>()?;
+        Ok(BatchResults { runs })
+    }
+
+    fn run_once<S: Scheduler>(
+        &self,
+        seed: u64,
+        workload: &str,
+        scheduler: &mut impl FnMut(Vec<Process>) -> S,
+    ) -> Result<RunMetrics, io::Error> {
+        let registry = TaskRegistry::with_seed(seed);
+        let (locks, zombies) = (registry.locks(), registry.zombies());
+        let (mut arrivals, processes): (Vec<Process>, Vec<Process>) =
+            workload::parse(workload, &registry)?
+                .into_iter()
+                .partition(|process| process.arrival().is_some());
+        arrivals.sort_by_key(Process::arrival);
+
+        // The scheduler is created after the clock, so it starts at the mock time
+        let clock = MockClock::install();
+        let mut scheduler = scheduler(processes);
+
+        let mut busy = Duration::ZERO;
+        let mut context_switches = 0;
+        let mut exited = 0;
+        let mut previous = None;
+        let (mut latencies, mut total_latency, mut max_latency) =
+            (0, Duration::ZERO, Duration::ZERO);
+        while clock.elapsed() < self.duration {
+            let arrived = arrivals
+                .partition_point(|process| process.arrival() <= Some(scheduler.cpu_elapsed()));
+            for process in arrivals.drain(..arrived) {
+                scheduler.add_process(process);
+            }
+            zombies.track(scheduler.processes());
+
+            let Some(process) = scheduler.schedule() else {
+                clock.advance(IDLE_STEP);
+                continue;
+            };
+            let pid = process.pid();
+            if previous != Some(pid) {
+                previous = Some(pid);
+                context_switches += 1;
+            }
+            if let Some(latency) = process.wake_latency() {
+                latencies += 1;
+                total_latency += latency;
+                max_latency = max_latency.max(latency);
+            }
+
+            let start_time = clock::now();
+            process.run();
+            let mut elapsed = clock::elapsed(start_time);
+            if elapsed.is_zero() {
+                clock.advance(IDLE_STEP);
+                elapsed = IDLE_STEP;
+            }
+            busy += elapsed;
+            let elapsed = process.dilate(elapsed);
+            scheduler.add_cpu_elapsed(elapsed);
+
+            for mut child in syscall::service(&mut scheduler, pid) {
+                let child_pid = scheduler
+                    .processes()
+                    .chain(&arrivals)
+                    .map(|process| process.pid() + 1)
+                    .max()
+                    .unwrap_or(0);
+                child.set_pid(child_pid);
+                child.set_parent(Some(pid));
+                scheduler.add_process(child);
+            }
+            let exit_code = scheduler.process_mut(pid).and_then(|process| {
+                process.enforce_cpu_limit();
+                process.exit_code()
+            });
+            if let Some(exit_code) = exit_code {
+                let process = scheduler
+                    .remove_pid(pid)
+                    .expect("Failed to remove the exited process.");
+                zombies.exit(&process, exit_code);
+                exited += 1;
+            }
+            locks.apply(scheduler.processes_mut());
+        }
+
+        Ok(RunMetrics {
+            seed,
+            throughput: busy.as_secs_f64() / clock.elapsed().as_secs_f64(),
+            context_switches,
+            mean_latency: total_latency.checked_div(latencies).unwrap_or_default(),
+            max_latency,
+            exited,
+        })
+    }
+}
+
+impl Default for BatchExperiment {
+    fn default() -> Self {
+        BatchExperiment::new(
+            BatchExperiment::DEFAULT_RUNS,
+            BatchExperiment::DEFAULT_DURATION,
+        )
+    }
+}
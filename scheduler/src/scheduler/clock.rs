@@ -0,0 +1,46 @@
+//! The time source of the schedulers, which tests can replace with a mock clock.
+
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+thread_local! {
+    /// The current time of the mock clock, if one is installed on this thread
+    static MOCK_NOW: Cell<Option<Instant>> = const { Cell::new(None) };
+    /// The speed of the core the tasks on this thread run on, relative to a speed of 1
+    static CORE_SPEED: Cell<f64> = const { Cell::new(1.0) };
+}
+
+pub fn now() -> Instant {
+    MOCK_NOW.with(Cell::get).unwrap_or_else(Instant::now)
+}
+
+pub fn elapsed(since: Instant) -> Duration {
+    now().saturating_duration_since(since)
+}
+
+pub(super) fn set_mock(now: Option<Instant>) {
+    MOCK_NOW.with(|mock_now| mock_now.set(now));
+}
+
+pub(super) fn set_core_speed(speed: f64) {
+    CORE_SPEED.with(|core_speed| core_speed.set(speed));
+}
+
+/// How long `work` takes on the current core.
+pub(super) fn at_core_speed(work: Duration) -> Duration {
+    work.div_f64(CORE_SPEED.with(Cell::get))
+}
+
+pub(super) fn is_mock() -> bool {
+    MOCK_NOW.with(Cell::get).is_some()
+}
+
+/// Moves the mock clock forward.
+pub(super) fn advance_mock(duration: Duration) {
+    MOCK_NOW.with(|mock_now| {
+        let now = mock_now.get().expect("No mock clock is installed.");
+        mock_now.set(Some(now + duration));
+    });
+}
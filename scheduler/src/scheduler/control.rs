@@ -0,0 +1,156 @@
+use super::{Niceness, Process};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+/// A command sent over the control socket.
+pub enum Command {
+    /// Adds a process, described like a line of a workload file
+    Add(String),
+    /// Removes the process with the given name
+    Kill(String),
+    Renice {
+        pid: u32,
+        niceness: Niceness,
+    },
+    /// Makes the process' bursts take `dilation` times longer in virtual time
+    Dilate {
+        pid: u32,
+        dilation: u32,
+    },
+    Pause,
+    Resume,
+    Stats,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+
+        match command {
+            "add" => Ok(Command::Add(argument.to_owned())),
+            "kill" if !argument.is_empty() => Ok(Command::Kill(argument.to_owned())),
+            "renice" => {
+                let (pid, niceness) = argument
+                    .split_once(char::is_whitespace)
+                    .ok_or("expected `renice <pid> <niceness>`")?;
+                Ok(Command::Renice {
+                    pid: pid.parse().map_err(|_| format!("invalid pid \"{pid}\""))?,
+                    niceness: niceness.trim().parse()?,
+                })
+            }
+            "dilate" => {
+                let (pid, dilation) = argument
+                    .split_once(char::is_whitespace)
+                    .ok_or("expected `dilate <pid> <dilation>`")?;
+                let dilation = dilation.trim();
+                Ok(Command::Dilate {
+                    pid: pid.parse().map_err(|_| format!("invalid pid \"{pid}\""))?,
+                    dilation: dilation
+                        .parse()
+                        .ok()
+                        .filter(|dilation| (1..=Process::MAX_TIME_DILATION).contains(dilation))
+                        .ok_or_else(|| {
+                            format!(
+                                "invalid dilation \"{dilation}\", expected 1 to {}",
+                                Process::MAX_TIME_DILATION
+                            )
+                        })?,
+                })
+            }
+            "pause" => Ok(Command::Pause),
+            "resume" => Ok(Command::Resume),
+            "stats" => Ok(Command::Stats),
+            _ => Err(format!("unknown command \"{line}\"")),
+        }
+    }
+}
+
+/// A command waiting to be executed, and where to send its result.
+pub struct Request {
+    pub command: Command,
+    reply: Sender<Result<String, String>>,
+}
+
+impl Request {
+    pub fn reply(self, result: Result<String, String>) {
+        // The client may have disconnected in the meantime
+        let _ = self.reply.send(result);
+    }
+}
+
+/// Accepts commands from a local TCP socket, one per line.
+///
+/// Every reply starts with `ok` or `error: <reason>`, may be followed by data lines, and ends
+/// with an empty line, e.g. `echo stats | nc 127.0.0.1 7420`.
+pub struct ControlServer {
+    requests: Receiver<Request>,
+}
+
+impl ControlServer {
+    pub const DEFAULT_ADDRESS: &'static str = "127.0.0.1:7420";
+
+    pub fn bind(address: impl ToSocketAddrs) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(address)?;
+        let (request_tx, request_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let request_tx = request_tx.clone();
+                thread::spawn(move || handle_client(stream, request_tx));
+            }
+        });
+
+        Ok(Self {
+            requests: request_rx,
+        })
+    }
+
+    /// Returns the requests that arrived since the last call, without blocking.
+    pub fn requests(&self) -> impl Iterator<Item = Request> + '_ {
+        self.requests.try_iter()
+    }
+}
+
+fn handle_client(stream: TcpStream, requests: Sender<Request>) -> Result<(), io::Error> {
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let result = match Command::parse(line) {
+            Ok(command) => {
+                let (reply_tx, reply_rx) = mpsc::channel();
+                let request = Request {
+                    command,
+                    reply: reply_tx,
+                };
+                if requests.send(request).is_err() {
+                    // The runner is gone
+                    return Ok(());
+                }
+                reply_rx
+                    .recv()
+                    .unwrap_or_else(|_| Err("the runner stopped".to_owned()))
+            }
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok(output) if output.is_empty() => write!(writer, "ok\n\n")?,
+            Ok(output) => write!(writer, "ok\n{output}\n\n")?,
+            Err(error) => write!(writer, "error: {error}\n\n")?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,429 @@
+//! Simulated cores of different speeds and power costs, each with its own scheduler: a big.LITTLE
+//! system and the energy it takes to run a workload on it, and cores that can be hotplugged.
+
+use super::{
+    clock, niceness_to_weight, syscall,
+    testing::{MockClock, IDLE_STEP},
+    Niceness, Process, Scheduler,
+};
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+/// A core's speed, relative to a speed of 1, and its power draw in watts.
+#[derive(Clone, Debug)]
+pub struct Core {
+    pub name: String,
+    pub speed: f64,
+    pub active_power: f64,
+    pub idle_power: f64,
+}
+
+impl Core {
+    /// A fast core, which draws a lot of power while running.
+    pub fn big(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            speed: 2.0,
+            active_power: 2.0,
+            idle_power: 0.1,
+        }
+    }
+
+    /// A slow core, which is much cheaper to run.
+    pub fn little(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            speed: 1.0,
+            active_power: 0.4,
+            idle_power: 0.02,
+        }
+    }
+}
+
+/// How processes are placed on the cores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    /// Deal the processes to the cores in turn, ignoring what they are
+    Spread,
+    /// Put background (positive niceness) processes on the slowest cores and the rest on the
+    /// fastest ones, balancing the weights of every core's processes
+    EnergyAware,
+}
+
+/// How one core did.
+#[derive(Clone, Debug)]
+pub struct CoreUsage {
+    pub core: Core,
+    pub pids: Vec<u32>,
+    pub busy: Duration,
+    /// The work done, in time at a speed of 1
+    pub work: Duration,
+    /// In joules
+    pub energy: f64,
+}
+
+/// Runs a workload on a set of cores for some mock time, and measures the energy it took.
+///
+/// Work done on a core takes `1 / speed` of the time it would take at a speed of 1, so the
+/// workload's tasks must take mock time when they run, like `FixedTask`.
+pub struct BigLittle {
+    cores: Vec<Core>,
+    placement: Placement,
+    duration: Duration,
+}
+
+impl BigLittle {
+    pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+
+    pub fn new(cores: Vec<Core>, placement: Placement, duration: Duration) -> Self {
+        Self {
+            cores,
+            placement,
+            duration,
+        }
+    }
+
+    /// Two big cores and two little ones.
+    pub fn with_placement(placement: Placement) -> Self {
+        let cores = vec![
+            Core::big("Big 0"),
+            Core::big("Big 1"),
+            Core::little("Little 0"),
+            Core::little("Little 1"),
+        ];
+        BigLittle::new(cores, placement, BigLittle::DEFAULT_DURATION)
+    }
+
+    /// Places the processes on the cores, runs every core's scheduler that `scheduler` creates
+    /// from its processes, and returns how every core did.
+    pub fn run<S: Scheduler>(
+        &self,
+        processes: Vec<Process>,
+        scheduler: impl FnMut(Vec<Process>) -> S + 'static,
+    ) -> Vec<CoreUsage> {
+        let _clock = MockClock::install();
+        let mut cpus = Cpus::new(self.cores.clone(), self.placement, processes, scheduler);
+        cpus.run_for(self.duration);
+        cpus.usage()
+    }
+}
+
+/// The index of the core every process is placed on.
+fn place(cores: &[Core], placement: Placement, processes: &[Process]) -> Vec<usize> {
+    match placement {
+        Placement::Spread => (0..processes.len())
+            .map(|index| index % cores.len())
+            .collect(),
+        Placement::EnergyAware => {
+            let speeds = cores.iter().map(|core| core.speed);
+            let slowest = speeds.clone().fold(f64::INFINITY, f64::min);
+            let fastest = speeds.fold(0.0, f64::max);
+
+            let mut weights = vec![0; cores.len()];
+            processes
+                .iter()
+                .map(|process| {
+                    let speed = if process.niceness() > Niceness::default() {
+                        slowest
+                    } else {
+                        fastest
+                    };
+                    let (index, _) = cores
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, core)| core.speed == speed)
+                        .min_by_key(|&(index, _)| weights[index])
+                        .expect("Failed to find a core.");
+                    weights[index] += niceness_to_weight(process.niceness());
+                    index
+                })
+                .collect()
+        }
+    }
+}
+
+/// A core while it runs.
+struct CoreRun<S> {
+    core: Core,
+    online: bool,
+    scheduler: S,
+    /// The core's own mock time
+    now: Instant,
+    busy: Duration,
+    idle: Duration,
+}
+
+impl<S: Scheduler> CoreRun<S> {
+    /// The combined weight of the core's processes.
+    fn load(&self) -> u32 {
+        self.scheduler.processes().map(Process::weight).sum()
+    }
+}
+
+/// Cores that each run their own processes with their own scheduler, and which can be taken
+/// offline and brought back (hotplugged) while they run.
+///
+/// Processes are migrated off a core that goes offline, and the load is rebalanced whenever the
+/// cores change. The cores share the mock clock, which must be installed before they're created,
+/// so every core keeps its own time, and the core that's furthest behind runs next.
+pub struct Cpus<S> {
+    cores: Vec<CoreRun<S>>,
+    start: Instant,
+    /// Creates the scheduler of a core from its processes
+    scheduler: Box<dyn FnMut(Vec<Process>) -> S>,
+    migrations: u64,
+}
+
+impl<S: Scheduler> Cpus<S> {
+    pub fn new(
+        cores: Vec<Core>,
+        placement: Placement,
+        processes: Vec<Process>,
+        scheduler: impl FnMut(Vec<Process>) -> S + 'static,
+    ) -> Self {
+        let placement = place(&cores, placement, &processes);
+        let mut core_processes: Vec<Vec<Process>> = cores.iter().map(|_| Vec::new()).collect();
+        for (process, index) in processes.into_iter().zip(placement) {
+            core_processes[index].push(process);
+        }
+
+        let mut scheduler: Box<dyn FnMut(Vec<Process>) -> S> = Box::new(scheduler);
+        let start = clock::now();
+        let cores = cores
+            .into_iter()
+            .zip(core_processes)
+            .map(|(core, processes)| CoreRun {
+                core,
+                online: true,
+                scheduler: scheduler(processes),
+                now: start,
+                busy: Duration::ZERO,
+                idle: Duration::ZERO,
+            })
+            .collect();
+
+        Self {
+            cores,
+            start,
+            scheduler,
+            migrations: 0,
+        }
+    }
+
+    /// The time of the core that's furthest behind, which is where the system is at.
+    fn now(&self) -> Option<Instant> {
+        self.cores
+            .iter()
+            .filter(|core| core.online)
+            .map(|core| core.now)
+            .min()
+    }
+
+    /// The mock time since the cores were created.
+    pub fn elapsed(&self) -> Duration {
+        self.now()
+            .map_or(Duration::ZERO, |now| now.duration_since(self.start))
+    }
+
+    /// Makes a scheduling decision on the core that's furthest behind, and runs its process.
+    /// Returns false if every core is offline.
+    pub fn step(&mut self) -> bool {
+        let Some(core) = self
+            .cores
+            .iter_mut()
+            .filter(|core| core.online)
+            .min_by_key(|core| core.now)
+        else {
+            return false;
+        };
+
+        clock::set_mock(Some(core.now));
+        clock::set_core_speed(core.core.speed);
+        match core.scheduler.schedule() {
+            Some(process) => {
+                let pid = process.pid();
+                let start_time = clock::now();
+                process.run();
+                let elapsed = clock::elapsed(start_time);
+                core.busy += elapsed;
+                let elapsed = process.dilate(elapsed);
+                core.scheduler.add_cpu_elapsed(elapsed);
+                syscall::service(&mut core.scheduler, pid);
+            }
+            None => {
+                clock::advance_mock(IDLE_STEP);
+                core.idle += IDLE_STEP;
+            }
+        }
+        core.now = clock::now();
+        clock::set_core_speed(1.0);
+        true
+    }
+
+    /// Runs the cores until the system gets `duration` further.
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.elapsed() + duration;
+        while self.elapsed() < end && self.step() {}
+    }
+
+    /// Adds a core with no processes, and rebalances the load. Returns its index.
+    pub fn add_core(&mut self, core: Core) -> usize {
+        let now = self.now().unwrap_or(self.start);
+        self.cores.push(CoreRun {
+            core,
+            online: true,
+            scheduler: (self.scheduler)(Vec::new()),
+            now,
+            busy: Duration::ZERO,
+            idle: Duration::ZERO,
+        });
+        self.rebalance();
+        self.cores.len() - 1
+    }
+
+    /// Brings a core online or takes it offline, migrating its processes to the other cores, and
+    /// rebalances the load. Returns false if there's no such core, or if it's the last one online.
+    pub fn set_online(&mut self, index: usize, online: bool) -> bool {
+        let online_cores = self.cores.iter().filter(|core| core.online).count();
+        if index >= self.cores.len() || (!online && self.cores[index].online && online_cores == 1) {
+            return false;
+        }
+
+        if online && !self.cores[index].online {
+            // The core picks up from where the system is, not from when it went offline
+            let now = self.now().unwrap_or(self.start);
+            self.cores[index].now = self.cores[index].now.max(now);
+        }
+        self.cores[index].online = online;
+
+        if !online {
+            let pids: Vec<u32> = self.cores[index]
+                .scheduler
+                .processes()
+                .map(Process::pid)
+                .collect();
+            for pid in pids {
+                let target = self.least_loaded().expect("Failed to find an online core.");
+                self.migrate(index, target, pid);
+            }
+        }
+        self.rebalance();
+        true
+    }
+
+    pub fn is_online(&self, index: usize) -> bool {
+        self.cores.get(index).is_some_and(|core| core.online)
+    }
+
+    /// The PIDs of the processes on every core.
+    pub fn pids(&self) -> Vec<Vec<u32>> {
+        self.cores
+            .iter()
+            .map(|core| core.scheduler.processes().map(Process::pid).collect())
+            .collect()
+    }
+
+    pub fn cores(&self) -> impl Iterator<Item = &Core> {
+        self.cores.iter().map(|core| &core.core)
+    }
+
+    /// How many processes moved between cores so far.
+    pub fn migrations(&self) -> u64 {
+        self.migrations
+    }
+
+    fn least_loaded(&self) -> Option<usize> {
+        (0..self.cores.len())
+            .filter(|&index| self.cores[index].online)
+            .min_by_key(|&index| self.cores[index].load())
+    }
+
+    fn migrate(&mut self, from: usize, to: usize, pid: u32) {
+        if let Some(process) = self.cores[from].scheduler.remove_pid(pid) {
+            self.cores[to].scheduler.add_process(process);
+            self.migrations += 1;
+        }
+    }
+
+    /// Moves processes from the most loaded online core to the least loaded one, for as long as
+    /// that makes the loads closer.
+    pub fn rebalance(&mut self) {
+        loop {
+            let online = (0..self.cores.len()).filter(|&index| self.cores[index].online);
+            let (Some(busiest), Some(idlest)) = (
+                online.clone().max_by_key(|&index| self.cores[index].load()),
+                online.min_by_key(|&index| self.cores[index].load()),
+            ) else {
+                return;
+            };
+
+            // Moving a process lighter than the gap makes it smaller, and the best one to move
+            // weighs half of it
+            let gap = self.cores[busiest].load() - self.cores[idlest].load();
+            let Some(pid) = self.cores[busiest]
+                .scheduler
+                .processes()
+                .filter(|process| process.weight() < gap)
+                .min_by_key(|process| process.weight().abs_diff(gap / 2))
+                .map(Process::pid)
+            else {
+                return;
+            };
+            self.migrate(busiest, idlest, pid);
+        }
+    }
+
+    /// How every core did so far.
+    pub fn usage(&self) -> Vec<CoreUsage> {
+        self.cores
+            .iter()
+            .zip(self.pids())
+            .map(|(run, pids)| CoreUsage {
+                energy: run.busy.as_secs_f64() * run.core.active_power
+                    + run.idle.as_secs_f64() * run.core.idle_power,
+                work: run.busy.mul_f64(run.core.speed),
+                core: run.core.clone(),
+                pids,
+                busy: run.busy,
+            })
+            .collect()
+    }
+}
+
+/// A table of every core's usage, and the total energy and work.
+pub fn report(usages: &[CoreUsage], duration: Duration) -> String {
+    let mut report = format!(
+        "{:<10} {:<16} {:>6} {:>10} {:>10}\n",
+        "Core", "PIDs", "Busy", "Work", "Energy"
+    );
+    for usage in usages {
+        let pids: Vec<String> = usage.pids.iter().map(u32::to_string).collect();
+        writeln!(
+            report,
+            "{:<10} {:<16} {:>5.1}% {:>9.2}s {:>9.2}J",
+            usage.core.name,
+            pids.join(", "),
+            usage.busy.as_secs_f64() / duration.as_secs_f64() * 100.0,
+            usage.work.as_secs_f64(),
+            usage.energy,
+        )
+        .expect("Failed to write the report.");
+    }
+
+    let work: Duration = usages.iter().map(|usage| usage.work).sum();
+    let energy: f64 = usages.iter().map(|usage| usage.energy).sum();
+    writeln!(
+        report,
+        "{:<10} {:<16} {:>6} {:>9.2}s {:>9.2}J ({:.2}J per second of work)",
+        "Total",
+        "",
+        "",
+        work.as_secs_f64(),
+        energy,
+        energy / work.as_secs_f64()
+    )
+    .expect("Failed to write the report.");
+    report
+}
@@ -1,5 +1,8 @@
-use super::{runner::RunnerEvent, Scheduler};
-use crossterm::event::{self, Event, KeyCode, KeyEvent};
+use super::{
+    keymap::Keymap, runner::RunnerEvent, tree, ExitReport, LatencyHistogram, LoadAverage, Process,
+    ProcessState, RunqueueKind, Scheduler, Theme,
+};
+use crossterm::event::{self, Event, KeyEvent};
 use std::{
     io::{self, Stdout},
     sync::mpsc::{self, Receiver},
@@ -8,22 +11,158 @@ use std::{
 };
 use tui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
-    widgets::{Block, BorderType, Borders, Cell, Paragraph, Row, Table},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, BorderType, Borders, Cell, Clear, Paragraph, Row, Table, TableState},
     Terminal,
 };
 
+/// What the user chose to look at.
+#[derive(Default)]
+pub struct View {
+    /// The index of the selected row in the process table
+    pub selected: usize,
+    /// Whether the process table shows the process tree
+    pub tree_view: bool,
+    /// How many decisions the history pane is scrolled back from the newest one
+    pub history_scroll: usize,
+    /// How many ticks were stepped back from the present
+    pub rewound: usize,
+    /// Whether the selected process' output is shown in a popup
+    pub show_output: bool,
+    /// How many outputs the popup is scrolled back from the newest one
+    pub output_scroll: usize,
+    /// Whether the processes that exited are shown in a popup
+    pub show_exits: bool,
+    pub show_help: bool,
+}
+
 pub enum DisplayEvent {
     Input(KeyEvent),
+    Resize,
     Tick,
 }
 
 const TICK_RATE: Duration = Duration::from_millis(200);
 
+const HEADER_HEIGHT: u16 = 3;
+const MIN_TABLE_HEIGHT: u16 = 5;
+const DECISIONS_HEIGHT: u16 = 8;
+/// Narrower terminals drop the optional columns of the process table
+const NARROW_WIDTH: u16 = 120;
+/// Wider terminals show the decisions and the latencies side by side
+const WIDE_WIDTH: u16 = 160;
+/// Terminals with room for the whole process table and this show the runqueue beside the table
+const RUNQUEUE_WIDTH: u16 = 36;
+
+/// The process table's columns: the header, the width, and whether narrow terminals drop it.
+const COLUMNS: [(&str, u16, bool); 11] = [
+    ("PID", 3, false),
+    ("Name", 20, false),
+    ("Niceness", 8, false),
+    ("Weight", 6, true),
+    ("State", 14, false),
+    ("CPU", 4, false),
+    ("%CPU", 5, false),
+    ("Recent", 6, true),
+    ("Time", 9, true),
+    ("Age", 9, true),
+    ("Dilation", 8, true),
+];
+const CPU_COLUMN: usize = 5;
+
+/// Where each panel is drawn. The runqueue, decisions and latencies are left out when they don't
+/// fit.
+struct Panels {
+    header: Rect,
+    table: Rect,
+    runqueue: Option<Rect>,
+    decisions: Option<Rect>,
+    latency: Option<Rect>,
+}
+
+impl Panels {
+    fn new(area: Rect, process_count: usize) -> Self {
+        let mut panels = Panels::stacked(area, process_count);
+        if area.width >= NARROW_WIDTH + RUNQUEUE_WIDTH {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(0), Constraint::Length(RUNQUEUE_WIDTH)])
+                .split(panels.table);
+            panels.table = columns[0];
+            panels.runqueue = Some(columns[1]);
+        }
+        panels
+    }
+
+    fn stacked(area: Rect, process_count: usize) -> Self {
+        // Borders, header, the two totals and a row per process
+        let latency_height = 5 + process_count as u16;
+        // Leave out the margin
+        let height = area.height.saturating_sub(2);
+        let top = HEADER_HEIGHT + MIN_TABLE_HEIGHT;
+
+        let bottom = latency_height.max(DECISIONS_HEIGHT);
+        if area.width >= WIDE_WIDTH && height >= top + bottom {
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(1)
+                .constraints([
+                    Constraint::Length(HEADER_HEIGHT),
+                    Constraint::Min(MIN_TABLE_HEIGHT),
+                    Constraint::Length(bottom),
+                ])
+                .split(area);
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(45), Constraint::Percentage(55)])
+                .split(rows[2]);
+
+            return Self {
+                header: rows[0],
+                table: rows[1],
+                runqueue: None,
+                decisions: Some(columns[0]),
+                latency: Some(columns[1]),
+            };
+        }
+
+        // Stack the panels, giving up the latencies and then the decisions to keep the table
+        let show_decisions = height >= top + DECISIONS_HEIGHT;
+        let show_latency = show_decisions && height >= top + DECISIONS_HEIGHT + latency_height;
+
+        let mut constraints = vec![
+            Constraint::Length(HEADER_HEIGHT),
+            Constraint::Min(MIN_TABLE_HEIGHT),
+        ];
+        if show_decisions {
+            constraints.push(Constraint::Length(DECISIONS_HEIGHT));
+        }
+        if show_latency {
+            constraints.push(Constraint::Length(latency_height));
+        }
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(1)
+            .constraints(constraints)
+            .split(area);
+
+        Self {
+            header: chunks[0],
+            table: chunks[1],
+            runqueue: None,
+            decisions: show_decisions.then(|| chunks[2]),
+            latency: show_latency.then(|| chunks[3]),
+        }
+    }
+}
+
 pub struct DisplayTerminal {
     terminal: Terminal<CrosstermBackend<Stdout>>,
     input_rx: Receiver<DisplayEvent>,
+    keymap: Keymap,
+    theme: Theme,
 }
 
 impl DisplayTerminal {
@@ -40,10 +179,13 @@ impl DisplayTerminal {
                     .unwrap_or(Duration::ZERO);
 
                 if event::poll(timeout).expect("Failed to poll events.") {
-                    if let Event::Key(key) = event::read().expect("Failed to read events.") {
-                        input_tx
-                            .send(DisplayEvent::Input(key))
-                            .expect("Failed to send input events.");
+                    let event = match event::read().expect("Failed to read events.") {
+                        Event::Key(key) => Some(DisplayEvent::Input(key)),
+                        Event::Resize(_, _) => Some(DisplayEvent::Resize),
+                        _ => None,
+                    };
+                    if let Some(event) = event {
+                        input_tx.send(event).expect("Failed to send input events.");
                     }
                 }
 
@@ -57,23 +199,44 @@ impl DisplayTerminal {
         let backend = CrosstermBackend::new(io::stdout());
         let terminal = Terminal::new(backend)?;
 
-        Ok(Self { terminal, input_rx })
+        Ok(Self {
+            terminal,
+            input_rx,
+            keymap: Keymap::default(),
+            theme: Theme::default(),
+        })
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
     }
 
-    pub fn draw<S>(&mut self, scheduler: &S, process_output: String)
-    where
+    pub fn draw<S>(
+        &mut self,
+        scheduler: &S,
+        process_output: String,
+        view: &View,
+        latencies: &[LatencyHistogram; 2],
+        load_average: &LoadAverage,
+        exits: &ExitReport,
+    ) where
         S: Scheduler,
     {
         let current_process = scheduler.current_process();
+        let keymap = &self.keymap;
+        let theme = &self.theme;
 
         // Draw the tui to the terminal
         self.terminal
             .draw(|f| {
-                let chunks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .margin(1)
-                    .constraints([Constraint::Length(3), Constraint::Min(5)])
-                    .split(f.size());
+                let panels = Panels::new(f.size(), scheduler.processes().len());
+                let columns: Vec<usize> = (0..COLUMNS.len())
+                    .filter(|&column| f.size().width >= NARROW_WIDTH || !COLUMNS[column].2)
+                    .collect();
 
                 let process = Paragraph::new(match current_process {
                     Some(process) => format!(
@@ -84,53 +247,310 @@ impl DisplayTerminal {
                     ),
                     None => "No task is currently running.".to_owned(),
                 })
-                .style(
-                    Style::default()
-                        .add_modifier(Modifier::BOLD)
-                        .fg(Color::LightBlue),
-                )
+                .style(theme.header.add_modifier(Modifier::BOLD))
                 .block(
                     Block::default()
                         .borders(Borders::ALL)
-                        .title("Current Task")
+                        .title(match view.rewound {
+                            0 => format!("Current Task | Load Average: {load_average}"),
+                            rewound => format!(
+                                "Current Task | Load Average: {load_average} | {rewound} tick(s) ago"
+                            ),
+                        })
                         .border_type(BorderType::Rounded),
                 );
 
-                f.render_widget(process, chunks[0]);
+                f.render_widget(process, panels.header);
 
                 let cpu_elapsed = scheduler.cpu_elapsed();
-                let items = scheduler.processes().iter().map(|process| {
-                    Row::new(vec![
+                let recent_cpu_elapsed = scheduler.recent_cpu_elapsed();
+                let processes: Vec<&Process> = scheduler.processes().collect();
+
+                // In the tree view the names show the branches, and the CPU covers whole subtrees
+                let rows: Vec<(usize, String)> = if view.tree_view {
+                    tree::tree_rows(&processes)
+                        .into_iter()
+                        .map(|row| (row.index, row.prefix))
+                        .collect()
+                } else {
+                    (0..processes.len())
+                        .map(|index| (index, String::new()))
+                        .collect()
+                };
+                let selected = rows.get(view.selected).map(|&(index, _)| index);
+                let items = rows.into_iter().map(|(index, prefix)| {
+                    let process = processes[index];
+                    let cpu_usage = if view.tree_view {
+                        Process::percentage(tree::subtree_cpu_usage(&processes, index), cpu_elapsed)
+                    } else {
+                        process.cpu_usage_percentage(cpu_elapsed)
+                    };
+
+                    let cells = [
                         Cell::from(process.pid().to_string())
                             .style(Style::default().add_modifier(Modifier::BOLD)),
-                        Cell::from("|"),
-                        Cell::from(process.name()),
-                        Cell::from("|"),
-                        Cell::from(process.niceness().to_string()),
-                        Cell::from("|"),
-                        Cell::from(process.cpu_usage_percentage(cpu_elapsed)),
-                    ])
+                        Cell::from(format!("{prefix}{}", process.name())),
+                        Cell::from(if process.effective_niceness() == process.niceness() {
+                            process.niceness().to_string()
+                        } else {
+                            format!("{} ({})", process.niceness(), process.effective_niceness())
+                        }),
+                        Cell::from(process.weight().to_string()),
+                        Cell::from(if process.is_hung() {
+                            "Hung".to_owned()
+                        } else if process.is_stopped() {
+                            "Stopped".to_owned()
+                        } else if let Some(target) = process
+                            .waits_on()
+                            .filter(|_| process.state() == ProcessState::Waiting)
+                        {
+                            format!("Waiting on {target}")
+                        } else {
+                            process.state().to_string()
+                        }),
+                        Cell::from(cpu_usage),
+                        Cell::from(process.interval_cpu_percentage()),
+                        Cell::from(process.recent_cpu_usage_percentage(recent_cpu_elapsed)),
+                        Cell::from(format_time(process.cpu_usage())),
+                        Cell::from(format_time(process.age())),
+                        Cell::from(format!("{}x", process.time_dilation())),
+                    ];
+                    Row::new(separated(
+                        cells
+                            .into_iter()
+                            .enumerate()
+                            .filter(|(column, _)| columns.contains(column))
+                            .map(|(_, cell)| cell),
+                        || Cell::from("|"),
+                    ))
                 });
 
+                let headers = columns.iter().map(|&column| match column {
+                    CPU_COLUMN if view.tree_view => "Tree",
+                    column => COLUMNS[column].0,
+                });
+                let widths = separated(
+                    columns
+                        .iter()
+                        .map(|&column| Constraint::Length(COLUMNS[column].1)),
+                    || Constraint::Length(1),
+                );
                 let table = Table::new(items)
                     .header(
-                        Row::new(vec!["PID", "|", "Name", "|", "Niceness", "|", "CPU"])
+                        Row::new(separated(headers, || "|"))
                             .style(Style::default().add_modifier(Modifier::BOLD)),
                     )
-                    .widths(&[
-                        Constraint::Length(3),
-                        Constraint::Length(1),
-                        Constraint::Length(20),
-                        Constraint::Length(1),
-                        Constraint::Length(8),
-                        Constraint::Length(1),
-                        Constraint::Length(3),
-                    ])
-                    .block(Block::default().title(S::NAME).borders(Borders::ALL))
-                    .style(Style::default().fg(Color::LightGreen))
+                    .widths(&widths)
+                    .block(
+                        Block::default()
+                            .title(if scheduler.autogroup() {
+                                format!("{} | Autogroup: On", S::NAME)
+                            } else {
+                                S::NAME.to_owned()
+                            })
+                            .borders(Borders::ALL),
+                    )
+                    .style(theme.table)
+                    .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
                     .column_spacing(1);
 
-                f.render_widget(table, chunks[1]);
+                let mut table_state = TableState::default();
+                table_state.select(Some(view.selected));
+                f.render_stateful_widget(table, panels.table, &mut table_state);
+
+                if let Some(area) = panels.runqueue {
+                    let runqueue = scheduler.runqueue();
+                    let title = match runqueue.kind {
+                        RunqueueKind::Fifo => "Runqueue (FIFO)",
+                        RunqueueKind::Sorted => "Runqueue (by Badness)",
+                    };
+                    let runqueue = Paragraph::new(runqueue.lines().join("\n"))
+                        .block(Block::default().title(title).borders(Borders::ALL))
+                        .style(theme.runqueue);
+                    f.render_widget(runqueue, area);
+                }
+
+                // A line per decision, followed by its notes, which get the whole width
+                let decisions: Vec<Spans> = scheduler
+                    .history()
+                    .iter()
+                    .skip(view.history_scroll)
+                    .flat_map(|decision| {
+                        let process = match &decision.process {
+                            Some((pid, name)) => format!("{pid} {name}"),
+                            None => "nothing".to_owned(),
+                        };
+                        let line = format!(
+                            "{:<8} {:<24.24} {:<16} {} runnable",
+                            format!("#{}", decision.tick),
+                            process,
+                            decision.reason.to_string(),
+                            decision.runqueue
+                        );
+                        let notes = decision.notes.iter().map(|note| {
+                            Spans::from(Span::styled(
+                                format!("{:<8} -> {note}", ""),
+                                Style::default().add_modifier(Modifier::ITALIC),
+                            ))
+                        });
+                        std::iter::once(Spans::from(line)).chain(notes)
+                    })
+                    .collect();
+                let history = Paragraph::new(decisions)
+                    .block(
+                        Block::default()
+                            .title("Decisions (PgUp/PgDn to scroll)")
+                            .borders(Borders::ALL),
+                    )
+                    .style(theme.decisions);
+
+                if let Some(area) = panels.decisions {
+                    f.render_widget(history, area);
+                }
+
+                let labels = LatencyHistogram::labels();
+                let totals = ["Boost off", "Boost on"]
+                    .into_iter()
+                    .zip(latencies)
+                    .map(|(name, histogram)| (name.to_owned(), histogram, String::new(), true));
+                // Only count missed deadlines for processes that have one
+                let per_process = scheduler.processes().map(|process| {
+                    let missed = match process.deadline() {
+                        Some(_) => process.missed_deadlines().to_string(),
+                        None => "-".to_owned(),
+                    };
+                    (process.name(), process.latencies(), missed, false)
+                });
+                let rows = totals
+                    .chain(per_process)
+                    .map(|(name, histogram, missed, is_total)| {
+                        let style = if is_total {
+                            Style::default().add_modifier(Modifier::BOLD)
+                        } else {
+                            Style::default()
+                        };
+                        let mut cells = vec![Cell::from(name).style(style)];
+                        cells.extend(
+                            histogram
+                                .buckets()
+                                .iter()
+                                .map(|count| Cell::from(count.to_string())),
+                        );
+                        cells.push(Cell::from(missed));
+                        Row::new(cells)
+                    });
+                let widths = [Constraint::Length(12)]
+                    .into_iter()
+                    .chain(labels.iter().map(|_| Constraint::Length(7)))
+                    .chain([Constraint::Length(6)])
+                    .collect::<Vec<_>>();
+
+                let latency_table = Table::new(rows)
+                    .header(
+                        Row::new(
+                            [String::new()]
+                                .into_iter()
+                                .chain(labels)
+                                .chain(["Missed".to_owned()]),
+                        )
+                        .style(Style::default().add_modifier(Modifier::BOLD)),
+                    )
+                    .widths(&widths)
+                    .block(
+                        Block::default()
+                            .title(format!(
+                                "Wake Latency (Sleeper Boost: {})",
+                                if scheduler.sleeper_boost() {
+                                    "On"
+                                } else {
+                                    "Off"
+                                }
+                            ))
+                            .borders(Borders::ALL),
+                    )
+                    .style(theme.latency)
+                    .column_spacing(1);
+
+                if let Some(area) = panels.latency {
+                    f.render_widget(latency_table, area);
+                }
+
+                if let Some(process) = selected
+                    .filter(|_| view.show_output)
+                    .map(|index| processes[index])
+                {
+                    let size = f.size();
+                    let area = centered_rect(size.width * 3 / 4, size.height * 3 / 4, size);
+                    // Show the newest outputs that fit, the newest at the bottom
+                    let visible = area.height.saturating_sub(2) as usize;
+                    let outputs = process.outputs();
+                    let end = outputs.len().saturating_sub(view.output_scroll);
+                    let lines: Vec<&str> = outputs
+                        .range(end.saturating_sub(visible)..end)
+                        .map(String::as_str)
+                        .collect();
+
+                    let output = Paragraph::new(lines.join("\n"))
+                        .style(theme.popup)
+                        .block(
+                            Block::default()
+                                .title(format!(
+                                    "Output of {} | {}/{} (PgUp/PgDn to scroll)",
+                                    process.name(),
+                                    end,
+                                    outputs.len()
+                                ))
+                                .borders(Borders::ALL)
+                                .border_type(BorderType::Rounded),
+                        );
+
+                    f.render_widget(Clear, area);
+                    f.render_widget(output, area);
+                }
+
+                if view.show_exits {
+                    let size = f.size();
+                    let area = centered_rect(size.width * 3 / 4, size.height * 3 / 4, size);
+                    let text = if exits.is_empty() {
+                        "No process exited yet.".to_owned()
+                    } else {
+                        exits.to_string()
+                    };
+
+                    let report = Paragraph::new(text).style(theme.popup).block(
+                        Block::default()
+                            .title(format!("Exited Processes | {}", exits.processes().len()))
+                            .borders(Borders::ALL)
+                            .border_type(BorderType::Rounded),
+                    );
+
+                    f.render_widget(Clear, area);
+                    f.render_widget(report, area);
+                }
+
+                if view.show_help {
+                    let help = keymap.help();
+                    let area = centered_rect(60, help.len() as u16 + 2, f.size());
+                    let rows = help.into_iter().map(|(keys, description)| {
+                        Row::new(vec![
+                            Cell::from(keys).style(Style::default().add_modifier(Modifier::BOLD)),
+                            Cell::from(description),
+                        ])
+                    });
+                    let help_table = Table::new(rows)
+                        .widths(&[Constraint::Length(14), Constraint::Min(10)])
+                        .block(
+                            Block::default()
+                                .title("Keys")
+                                .borders(Borders::ALL)
+                                .border_type(BorderType::Rounded),
+                        )
+                        .style(theme.popup)
+                        .column_spacing(1);
+
+                    f.render_widget(Clear, area);
+                    f.render_widget(help_table, area);
+                }
             })
             .expect("Failed to draw frame.");
     }
@@ -142,19 +562,55 @@ impl DisplayTerminal {
             .recv()
             .expect("Failed to recieve input events.")
         {
-            DisplayEvent::Input(key) => {
-                if key.modifiers.is_empty() {
-                    match key.code {
-                        KeyCode::Char('q') => return RunnerEvent::Quit,
-                        KeyCode::Char('p') => return RunnerEvent::Pause,
-                        KeyCode::Char('r') => return RunnerEvent::Resume,
-                        KeyCode::Char('s') => return RunnerEvent::Step,
-                        _ => {}
-                    };
-                }
+            DisplayEvent::Input(key) => self.keymap.event(key),
+            // Returning right away redraws the frame at the new size
+            DisplayEvent::Resize | DisplayEvent::Tick => RunnerEvent::None,
+        }
+    }
+
+    /// The next key the user pressed, without waiting for one.
+    #[cfg(feature = "async")]
+    pub fn try_input(&self) -> Option<RunnerEvent> {
+        loop {
+            match self.input_rx.try_recv().ok()? {
+                DisplayEvent::Input(key) => return Some(self.keymap.event(key)),
+                DisplayEvent::Resize | DisplayEvent::Tick => continue,
             }
-            DisplayEvent::Tick => {}
         }
-        RunnerEvent::None
     }
 }
+
+/// Formats a duration like `top`'s TIME+ column: minutes, seconds and hundredths.
+fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!(
+        "{}:{:02}.{:02}",
+        seconds / 60,
+        seconds % 60,
+        time.subsec_millis() / 10
+    )
+}
+
+/// Puts a separator between each two items.
+fn separated<T>(items: impl IntoIterator<Item = T>, separator: impl Fn() -> T) -> Vec<T> {
+    let mut separated = Vec::new();
+    for item in items {
+        if !separated.is_empty() {
+            separated.push(separator());
+        }
+        separated.push(item);
+    }
+    separated
+}
+
+/// A rectangle of the given size in the middle of `area`, shrunk to fit inside it.
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
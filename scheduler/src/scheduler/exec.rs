@@ -0,0 +1,78 @@
+use super::{tasks::Task, Syscall};
+use std::{
+    io,
+    os::unix::process::ExitStatusExt,
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// Runs a real program, which is kept stopped (with SIGSTOP) except while the task is running.
+pub struct ExecTask {
+    child: Child,
+    exit_code: Option<i32>,
+}
+
+impl ExecTask {
+    /// How long the program is resumed for on every run.
+    const QUANTUM: Duration = Duration::from_millis(5);
+
+    pub fn spawn(program: &str, args: &[&str]) -> Result<Self, io::Error> {
+        // The program's output would garble the terminal-user-interface
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let task = Self {
+            child,
+            exit_code: None,
+        };
+        task.signal(libc::SIGSTOP)?;
+        Ok(task)
+    }
+
+    fn signal(&self, signal: libc::c_int) -> Result<(), io::Error> {
+        if unsafe { libc::kill(self.child.id() as libc::pid_t, signal) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+impl Task for ExecTask {
+    fn run(&mut self) -> String {
+        if let Ok(Some(status)) = self.child.try_wait() {
+            // Programs killed by a signal exit with 128 + the signal, like in a shell
+            self.exit_code = status
+                .code()
+                .or_else(|| status.signal().map(|signal| 128 + signal));
+            return format!("Exited ({status})");
+        }
+
+        // Let the program run for a single quantum
+        if self.signal(libc::SIGCONT).is_err() {
+            return "Failed to resume".to_owned();
+        }
+        thread::sleep(ExecTask::QUANTUM);
+        if self.signal(libc::SIGSTOP).is_err() {
+            return "Failed to stop".to_owned();
+        }
+
+        format!("Ran PID {}", self.child.id())
+    }
+
+    fn syscalls(&mut self) -> Vec<Syscall> {
+        self.exit_code.map(Syscall::Exit).into_iter().collect()
+    }
+}
+
+impl Drop for ExecTask {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,125 @@
+//! The processes whose tasks finished, and a report of how they did.
+
+use super::{clock, syscall, Process, Scheduler, Zombies};
+use std::{
+    fmt, thread,
+    time::{Duration, Instant},
+};
+
+/// A process whose task finished, and how it did.
+#[derive(Clone, Debug)]
+pub struct ExitedProcess {
+    pub pid: u32,
+    pub name: String,
+    pub exit_code: i32,
+    pub cpu_usage: Duration,
+    /// The process' share of the CPU time that passed before it exited
+    pub cpu_share: String,
+    /// How many times the process woke up and waited for the CPU
+    pub wake_ups: u64,
+    pub missed_deadlines: u64,
+    pub last_output: String,
+}
+
+/// The processes that exited, in the order they exited.
+#[derive(Clone, Default)]
+pub struct ExitReport {
+    processes: Vec<ExitedProcess>,
+}
+
+impl ExitReport {
+    pub fn record(&mut self, process: &Process, exit_code: i32, cpu_elapsed: Duration) {
+        self.processes.push(ExitedProcess {
+            pid: process.pid(),
+            name: process.name(),
+            exit_code,
+            cpu_usage: process.cpu_usage(),
+            cpu_share: process.cpu_usage_percentage(cpu_elapsed),
+            wake_ups: process.latencies().buckets().iter().sum(),
+            missed_deadlines: process.missed_deadlines(),
+            last_output: process.outputs().back().cloned().unwrap_or_default(),
+        });
+    }
+
+    pub fn processes(&self) -> &[ExitedProcess] {
+        &self.processes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+
+    /// The report as a table, a header and a line for every process.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{:>5} {:<16} {:>4} {:>9} {:>6} {:>8} {:>6}  Last output",
+            "PID", "Name", "Exit", "CPU time", "CPU %", "Wake-ups", "Missed"
+        )];
+        lines.extend(self.processes.iter().map(|process| {
+            format!(
+                "{:>5} {:<16} {:>4} {:>7}ms {:>6} {:>8} {:>6}  {}",
+                process.pid,
+                process.name,
+                process.exit_code,
+                process.cpu_usage.as_millis(),
+                process.cpu_share,
+                process.wake_ups,
+                process.missed_deadlines,
+                process.last_output,
+            )
+        }));
+        lines
+    }
+}
+
+impl fmt::Display for ExitReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.lines().join("\n"))
+    }
+}
+
+/// How long a headless run waits before trying again when no process can run.
+const IDLE_SLEEP: Duration = Duration::from_millis(1);
+
+/// Runs the scheduler's processes without the terminal-user-interface, until every process exited
+/// or `limit` passed, and returns the processes that exited. The children the tasks wait for are
+/// tracked in `zombies`.
+///
+/// Unlike `ProcessRunner`, the processes run right away regardless of their arrival time, and
+/// there's no watchdog.
+pub fn run_headless<S: Scheduler>(
+    scheduler: &mut S,
+    zombies: &Zombies,
+    limit: Duration,
+) -> ExitReport {
+    let mut report = ExitReport::default();
+    let start = Instant::now();
+
+    while !scheduler.process_table().is_empty() && start.elapsed() < limit {
+        zombies.track(scheduler.processes());
+        let Some(process) = scheduler.schedule() else {
+            thread::sleep(IDLE_SLEEP);
+            continue;
+        };
+
+        let pid = process.pid();
+        let start_time = clock::now();
+        process.run();
+        let elapsed = process.dilate(clock::elapsed(start_time));
+        scheduler.add_cpu_elapsed(elapsed);
+        syscall::service(scheduler, pid);
+        let exit_code = scheduler.process_mut(pid).and_then(|process| {
+            process.enforce_cpu_limit();
+            process.exit_code()
+        });
+
+        if let Some(exit_code) = exit_code {
+            let process = scheduler
+                .remove_pid(pid)
+                .expect("Failed to remove the exited process.");
+            zombies.exit(&process, exit_code);
+            report.record(&process, exit_code, scheduler.cpu_elapsed());
+        }
+    }
+    report
+}
@@ -0,0 +1,103 @@
+//! Fast userspace mutexes: words of shared memory that tasks read and change themselves, and wait
+//! queues keyed by their addresses, which tasks only go through when they need to block.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+#[derive(Default)]
+struct FutexTable {
+    /// The shared memory, where words that were never written are 0
+    words: HashMap<u64, i64>,
+    /// The PIDs of the processes waiting on every address, in the order they started waiting
+    queues: HashMap<u64, VecDeque<u32>>,
+}
+
+/// Shared memory words and the processes waiting on them, like Linux's `futex(2)`.
+///
+/// Blocking primitives are built on top of it: a task changes a word atomically, and only waits
+/// on its address (or wakes the processes waiting on it) when the word says it has to.
+#[derive(Clone, Default)]
+pub struct Futexes(Arc<Mutex<FutexTable>>);
+
+impl Futexes {
+    fn table(&self) -> MutexGuard<'_, FutexTable> {
+        self.0.lock().expect("Failed to lock the futex table.")
+    }
+
+    pub fn load(&self, address: u64) -> i64 {
+        self.table().words.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn store(&self, address: u64, value: i64) {
+        self.table().words.insert(address, value);
+    }
+
+    /// Sets the word to `new` if it's `current`. Returns the value it had, as `Ok` if it was
+    /// changed.
+    pub fn compare_exchange(&self, address: u64, current: i64, new: i64) -> Result<i64, i64> {
+        let mut table = self.table();
+        let word = table.words.entry(address).or_insert(0);
+        if *word == current {
+            *word = new;
+            Ok(current)
+        } else {
+            Err(*word)
+        }
+    }
+
+    /// Queues `pid` on the address if the word still is `expected` (`FUTEX_WAIT`). Returns false,
+    /// without queueing, if the word changed since the caller read it.
+    pub fn wait(&self, address: u64, expected: i64, pid: u32) -> bool {
+        let mut table = self.table();
+        if table.words.get(&address).copied().unwrap_or(0) != expected {
+            return false;
+        }
+        let queue = table.queues.entry(address).or_default();
+        if !queue.contains(&pid) {
+            queue.push_back(pid);
+        }
+        true
+    }
+
+    /// Wakes up to `count` of the processes waiting on the address, the longest waiting first
+    /// (`FUTEX_WAKE`). Returns the PIDs it woke.
+    pub fn wake(&self, address: u64, count: usize) -> Vec<u32> {
+        let mut table = self.table();
+        let Some(queue) = table.queues.get_mut(&address) else {
+            return Vec::new();
+        };
+        let woken = queue.drain(..count.min(queue.len())).collect();
+        if queue.is_empty() {
+            table.queues.remove(&address);
+        }
+        woken
+    }
+
+    /// Whether `pid` waits on the address and wasn't woken yet.
+    pub fn is_queued(&self, address: u64, pid: u32) -> bool {
+        self.table()
+            .queues
+            .get(&address)
+            .is_some_and(|queue| queue.contains(&pid))
+    }
+
+    /// The processes waiting on the address, the longest waiting first.
+    pub fn waiters(&self, address: u64) -> Vec<u32> {
+        self.table()
+            .queues
+            .get(&address)
+            .map(|queue| queue.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Stops a process that exited from waiting on any address.
+    pub fn remove(&self, pid: u32) {
+        let mut table = self.table();
+        for queue in table.queues.values_mut() {
+            queue.retain(|&waiter| waiter != pid);
+        }
+        table.queues.retain(|_, queue| !queue.is_empty());
+    }
+}
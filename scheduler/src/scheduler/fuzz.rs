@@ -0,0 +1,209 @@
+//! Random operations against a scheduler, checking its invariants after every one of them.
+
+use super::{
+    clock, syscall,
+    testing::{FixedTask, MockClock, IDLE_STEP},
+    Niceness, Process, Scheduler,
+};
+use std::{fmt, time::Duration};
+
+/// An operation on a scheduler. Processes are picked by their index, modulo the number of
+/// processes, so any index picks a process as long as there is one.
+#[derive(Clone, Debug)]
+pub enum FuzzOp {
+    /// Adds a process that computes for `runtime` per run, and sleeps after every run if it has a
+    /// `sleep`
+    Add {
+        niceness: i8,
+        runtime: Duration,
+        sleep: Option<Duration>,
+    },
+    /// Removes a process by its name
+    Remove(usize),
+    /// Removes a process by its PID
+    RemovePid(usize),
+    Renice(usize, i8),
+    /// Stops a process, blocking it until it's continued, or continues a stopped one
+    ToggleStopped(usize),
+    /// Makes scheduling decisions, running the chosen processes
+    Run(usize),
+}
+
+/// An invariant that broke, and the operation that broke it.
+#[derive(Debug)]
+pub struct Violation {
+    /// The index of the operation
+    pub step: usize,
+    pub op: FuzzOp,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Step {} ({:?}): {}", self.step, self.op, self.reason)
+    }
+}
+
+/// Applies `ops` to the scheduler that `scheduler` creates, on a mock clock, and checks that:
+/// - there's a current process whenever there are processes,
+/// - removing a process doesn't change which other process is the current one,
+/// - no process is lost or duplicated, and only processes that can run are scheduled,
+/// - the CPU time never goes backwards.
+pub fn check<S: Scheduler>(scheduler: impl FnOnce() -> S, ops: &[FuzzOp]) -> Result<(), Violation> {
+    // The scheduler is created after the clock, so it starts at the mock time
+    let mock_clock = MockClock::install();
+    let mut scheduler = scheduler();
+    let mut pids: Vec<u32> = Vec::new();
+    let mut next_pid = 0;
+    let mut cpu_elapsed = Duration::ZERO;
+
+    for (step, op) in ops.iter().enumerate() {
+        let violation = |reason: String| Violation {
+            step,
+            op: op.clone(),
+            reason,
+        };
+        let current = scheduler.current_process().map(Process::pid);
+        let pick = |index: usize| (!pids.is_empty()).then(|| pids[index % pids.len()]);
+
+        match *op {
+            FuzzOp::Add {
+                niceness,
+                runtime,
+                sleep,
+            } => {
+                let task = match sleep {
+                    Some(sleep) => FixedTask::sleeping(runtime, sleep),
+                    None => FixedTask::new(runtime),
+                };
+                let name = format!("Fuzz {next_pid}");
+                scheduler.add_process(Process::with_niceness(
+                    next_pid,
+                    &name,
+                    Box::new(task),
+                    Niceness::clamped(niceness),
+                ));
+                pids.push(next_pid);
+                next_pid += 1;
+            }
+            FuzzOp::Remove(index) | FuzzOp::RemovePid(index) => {
+                let Some(pid) = pick(index) else {
+                    continue;
+                };
+                let removed = match op {
+                    FuzzOp::Remove(_) => scheduler.remove_process(format!("Fuzz {pid}")),
+                    _ => scheduler.remove_pid(pid),
+                };
+                match removed {
+                    Some(process) if process.pid() == pid => {}
+                    Some(process) => {
+                        return Err(violation(format!(
+                            "Removing {pid} removed {} instead",
+                            process.pid()
+                        )))
+                    }
+                    None => return Err(violation(format!("{pid} couldn't be removed"))),
+                }
+                pids.retain(|&other| other != pid);
+
+                let now_current = scheduler.current_process().map(Process::pid);
+                if current.is_some_and(|current| current != pid) && now_current != current {
+                    return Err(violation(format!(
+                        "The current process changed from {current:?} to {now_current:?}"
+                    )));
+                }
+            }
+            FuzzOp::Renice(index, niceness) => {
+                if let Some(pid) = pick(index) {
+                    if !scheduler.renice(pid, Niceness::clamped(niceness)) {
+                        return Err(violation(format!("{pid} couldn't be reniced")));
+                    }
+                }
+            }
+            FuzzOp::ToggleStopped(index) => {
+                if let Some(pid) = pick(index) {
+                    let process = scheduler
+                        .process_mut(pid)
+                        .ok_or_else(|| violation(format!("{pid} is missing")))?;
+                    process.set_stopped(!process.is_stopped());
+                }
+            }
+            FuzzOp::Run(steps) => {
+                for _ in 0..steps {
+                    let Some(process) = scheduler.schedule() else {
+                        mock_clock.advance(IDLE_STEP);
+                        continue;
+                    };
+                    if !process.is_runnable() {
+                        return Err(violation(format!(
+                            "{} was scheduled while it can't run",
+                            process.pid()
+                        )));
+                    }
+                    if !pids.contains(&process.pid()) {
+                        return Err(violation(format!(
+                            "{} was scheduled after it was removed",
+                            process.pid()
+                        )));
+                    }
+
+                    let pid = process.pid();
+                    let start_time = clock::now();
+                    process.run();
+                    let elapsed = process.dilate(clock::elapsed(start_time));
+                    scheduler.add_cpu_elapsed(elapsed);
+                    syscall::service(&mut scheduler, pid);
+                }
+            }
+        }
+
+        let mut scheduled: Vec<u32> = scheduler.processes().map(Process::pid).collect();
+        scheduled.sort_unstable();
+        let mut expected = pids.clone();
+        expected.sort_unstable();
+        if scheduled != expected {
+            return Err(violation(format!(
+                "The scheduler has {scheduled:?} instead of {expected:?}"
+            )));
+        }
+        if !pids.is_empty() && scheduler.current_process().is_none() {
+            return Err(violation(
+                "There are processes but no current process".to_owned(),
+            ));
+        }
+        if scheduler.cpu_elapsed() < cpu_elapsed {
+            return Err(violation(format!(
+                "The CPU time went back from {cpu_elapsed:?} to {:?}",
+                scheduler.cpu_elapsed()
+            )));
+        }
+        cpu_elapsed = scheduler.cpu_elapsed();
+    }
+    Ok(())
+}
+
+/// `count` random operations, from a xorshift generator seeded with `seed`.
+pub fn random_ops(seed: u64, count: usize) -> Vec<FuzzOp> {
+    let mut state = seed.max(1);
+    let mut random = |bound: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % bound
+    };
+
+    (0..count)
+        .map(|_| match random(6) {
+            0 => FuzzOp::Add {
+                niceness: random(40) as i8 - 20,
+                runtime: Duration::from_millis(1 + random(5)),
+                sleep: (random(2) == 0).then(|| Duration::from_millis(1 + random(50))),
+            },
+            1 => FuzzOp::Remove(random(16) as usize),
+            2 => FuzzOp::RemovePid(random(16) as usize),
+            3 => FuzzOp::Renice(random(16) as usize, random(40) as i8 - 20),
+            4 => FuzzOp::ToggleStopped(random(16) as usize),
+            _ => FuzzOp::Run(1 + random(20) as usize),
+        })
+        .collect()
+}
@@ -0,0 +1,127 @@
+use super::Process;
+use std::{collections::VecDeque, fmt};
+
+/// Why a scheduler picked a process again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecisionReason {
+    QuantumExpired,
+    Blocked,
+    WokeUp,
+    PriorityChanged,
+    Yielded,
+}
+
+impl fmt::Display for DecisionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecisionReason::QuantumExpired => write!(f, "quantum expired"),
+            DecisionReason::Blocked => write!(f, "blocked"),
+            DecisionReason::WokeUp => write!(f, "woke up"),
+            DecisionReason::PriorityChanged => write!(f, "priority changed"),
+            DecisionReason::Yielded => write!(f, "yielded"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Decision {
+    pub tick: u64,
+    /// The PID and name of the picked process, None if nothing could run
+    pub process: Option<(u32, String)>,
+    pub reason: DecisionReason,
+    /// The number of runnable processes at the time of the decision
+    pub runqueue: usize,
+    /// What happened after the decision, for demos that explain themselves
+    pub notes: Vec<String>,
+}
+
+/// A ring buffer of the last scheduling decisions.
+pub struct DecisionHistory {
+    decisions: VecDeque<Decision>,
+    capacity: usize,
+    ticks: u64,
+}
+
+impl DecisionHistory {
+    pub const DEFAULT_CAPACITY: usize = 256;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            decisions: VecDeque::with_capacity(capacity),
+            capacity,
+            ticks: 0,
+        }
+    }
+
+    /// Records and logs a decision, forgetting the oldest one if the history is full.
+    pub fn record<'a>(
+        &mut self,
+        reason: DecisionReason,
+        processes: impl Iterator<Item = &'a Process>,
+        chosen: Option<&Process>,
+    ) {
+        let processes: Vec<&Process> = processes.collect();
+        self.ticks += 1;
+        let decision = Decision {
+            tick: self.ticks,
+            process: chosen.map(|process| (process.pid(), process.name())),
+            reason,
+            runqueue: processes
+                .iter()
+                .filter(|process| process.is_runnable())
+                .count(),
+            notes: Vec::new(),
+        };
+
+        let pid = chosen.map_or("none".to_owned(), |process| process.pid().to_string());
+        // The time dilation of every process, as `<pid>:<dilation>x`
+        let dilations: Vec<String> = processes
+            .iter()
+            .map(|process| format!("{}:{}x", process.pid(), process.time_dilation()))
+            .collect();
+        log::info!(
+            target: "scheduler",
+            "tick={} pid={pid} reason=\"{reason}\" runqueue={} dilations=\"{}\"",
+            decision.tick,
+            decision.runqueue,
+            dilations.join(" ")
+        );
+
+        if self.decisions.len() == self.capacity {
+            self.decisions.pop_front();
+        }
+        self.decisions.push_back(decision);
+    }
+
+    /// Adds a note to the newest decision. Notes made before the first decision are only logged.
+    pub fn annotate(&mut self, note: String) {
+        log::info!(target: "scheduler", "tick={} note=\"{note}\"", self.ticks);
+        if let Some(decision) = self.decisions.back_mut() {
+            decision.notes.push(note);
+        }
+    }
+
+    /// The number of decisions made so far, including forgotten ones.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// The remembered decisions, newest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Decision> {
+        self.decisions.iter().rev()
+    }
+
+    pub fn len(&self) -> usize {
+        self.decisions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decisions.is_empty()
+    }
+}
+
+impl Default for DecisionHistory {
+    fn default() -> Self {
+        DecisionHistory::new(DecisionHistory::DEFAULT_CAPACITY)
+    }
+}
@@ -0,0 +1,234 @@
+use super::runner::RunnerEvent;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::{fs, io, path::Path};
+
+/// The actions that can be bound to keys: their name in the config file, their event and their
+/// description in the help overlay.
+const ACTIONS: [(&str, RunnerEvent, &str); 21] = [
+    ("quit", RunnerEvent::Quit, "Quit"),
+    ("pause", RunnerEvent::Pause, "Pause"),
+    ("resume", RunnerEvent::Resume, "Resume"),
+    ("step", RunnerEvent::Step, "Step forward while paused"),
+    ("step-back", RunnerEvent::StepBack, "Step back while paused"),
+    (
+        "dilation-up",
+        RunnerEvent::IncreaseDilation,
+        "Double the current process' time dilation",
+    ),
+    (
+        "dilation-down",
+        RunnerEvent::DecreaseDilation,
+        "Halve the current process' time dilation",
+    ),
+    (
+        "sleeper-boost",
+        RunnerEvent::ToggleSleeperBoost,
+        "Toggle the sleeper boost",
+    ),
+    (
+        "autogroup",
+        RunnerEvent::ToggleAutogroup,
+        "Toggle autogrouping the children of every parent",
+    ),
+    (
+        "nice-up",
+        RunnerEvent::IncreaseNiceness,
+        "Raise the selected process' niceness, up to 19",
+    ),
+    (
+        "nice-down",
+        RunnerEvent::DecreaseNiceness,
+        "Lower the selected process' niceness, down to -20",
+    ),
+    (
+        "select-up",
+        RunnerEvent::SelectPrevious,
+        "Select the previous process",
+    ),
+    (
+        "select-down",
+        RunnerEvent::SelectNext,
+        "Select the next process",
+    ),
+    (
+        "stop",
+        RunnerEvent::ToggleStopped,
+        "Stop or continue the selected process",
+    ),
+    (
+        "tree",
+        RunnerEvent::ToggleTreeView,
+        "Toggle the process tree",
+    ),
+    (
+        "history-up",
+        RunnerEvent::ScrollHistoryUp,
+        "Scroll the decisions (or the output) back",
+    ),
+    (
+        "history-down",
+        RunnerEvent::ScrollHistoryDown,
+        "Scroll the decisions (or the output) forward",
+    ),
+    (
+        "output",
+        RunnerEvent::ToggleOutput,
+        "Show or hide the selected process' output",
+    ),
+    (
+        "exits",
+        RunnerEvent::ToggleExits,
+        "Show or hide the processes that exited",
+    ),
+    ("help", RunnerEvent::ToggleHelp, "Show or hide this help"),
+    ("none", RunnerEvent::None, "Do nothing"),
+];
+
+/// Maps keys to the runner's actions.
+///
+/// A config file overrides the keys of some actions, one `<action> = <key>[, <key>...]` per line.
+/// Keys are single characters or one of up, down, left, right, pageup, pagedown, home, end,
+/// enter, space, tab, backspace and esc. Lines starting with `#` are comments.
+#[derive(Clone)]
+pub struct Keymap {
+    bindings: Vec<(KeyCode, RunnerEvent)>,
+}
+
+impl Keymap {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        Keymap::parse(&fs::read_to_string(path)?)
+            .map_err(|reason| io::Error::new(io::ErrorKind::InvalidData, reason))
+    }
+
+    pub fn parse(config: &str) -> Result<Self, String> {
+        let mut keymap = Keymap::default();
+
+        for (index, line) in config.lines().map(str::trim).enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |reason: String| format!("Line {}: {reason}", index + 1);
+
+            let (action, keys) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `<action> = <key>[, <key>...]`".to_owned()))?;
+            let action = action.trim();
+            let &(_, event, _) = ACTIONS
+                .iter()
+                .find(|(name, ..)| *name == action)
+                .ok_or_else(|| error(format!("unknown action \"{action}\"")))?;
+
+            let keys = keys
+                .split(',')
+                .map(str::trim)
+                .map(|key| parse_key(key).ok_or_else(|| error(format!("unknown key \"{key}\""))))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // The action's keys replace its defaults, and are taken from any other action
+            keymap
+                .bindings
+                .retain(|(key, bound)| *bound != event && !keys.contains(key));
+            keymap
+                .bindings
+                .extend(keys.into_iter().map(|key| (key, event)));
+        }
+        Ok(keymap)
+    }
+
+    pub(super) fn event(&self, key: KeyEvent) -> RunnerEvent {
+        // Shift is part of the character itself (like `?`), other modifiers aren't bound
+        let modifiers = key.modifiers - KeyModifiers::SHIFT;
+        if !modifiers.is_empty() {
+            return RunnerEvent::None;
+        }
+
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == key.code)
+            .map_or(RunnerEvent::None, |&(_, event)| event)
+    }
+
+    /// The keys of every bound action and its description, for the help overlay.
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        ACTIONS
+            .iter()
+            .filter(|&&(_, event, _)| event != RunnerEvent::None)
+            .filter_map(|&(_, event, description)| {
+                let keys: Vec<String> = self
+                    .bindings
+                    .iter()
+                    .filter(|(_, bound)| *bound == event)
+                    .map(|&(key, _)| key_name(key))
+                    .collect();
+                (!keys.is_empty()).then(|| (keys.join(", "), description))
+            })
+            .collect()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            bindings: vec![
+                (KeyCode::Char('q'), RunnerEvent::Quit),
+                (KeyCode::Char('p'), RunnerEvent::Pause),
+                (KeyCode::Char('r'), RunnerEvent::Resume),
+                (KeyCode::Char('s'), RunnerEvent::Step),
+                (KeyCode::Right, RunnerEvent::Step),
+                (KeyCode::Left, RunnerEvent::StepBack),
+                (KeyCode::Char(']'), RunnerEvent::IncreaseDilation),
+                (KeyCode::Char('['), RunnerEvent::DecreaseDilation),
+                (KeyCode::Char('b'), RunnerEvent::ToggleSleeperBoost),
+                (KeyCode::Char('g'), RunnerEvent::ToggleAutogroup),
+                (KeyCode::Char('+'), RunnerEvent::IncreaseNiceness),
+                (KeyCode::Char('-'), RunnerEvent::DecreaseNiceness),
+                (KeyCode::Up, RunnerEvent::SelectPrevious),
+                (KeyCode::Down, RunnerEvent::SelectNext),
+                (KeyCode::Char('x'), RunnerEvent::ToggleStopped),
+                (KeyCode::Char('t'), RunnerEvent::ToggleTreeView),
+                (KeyCode::PageUp, RunnerEvent::ScrollHistoryUp),
+                (KeyCode::PageDown, RunnerEvent::ScrollHistoryDown),
+                (KeyCode::Char('o'), RunnerEvent::ToggleOutput),
+                (KeyCode::Char('e'), RunnerEvent::ToggleExits),
+                (KeyCode::Char('?'), RunnerEvent::ToggleHelp),
+            ],
+        }
+    }
+}
+
+const NAMED_KEYS: [(&str, KeyCode); 13] = [
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("enter", KeyCode::Enter),
+    ("space", KeyCode::Char(' ')),
+    ("tab", KeyCode::Tab),
+    ("backspace", KeyCode::Backspace),
+    ("esc", KeyCode::Esc),
+];
+
+fn parse_key(text: &str) -> Option<KeyCode> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(KeyCode::Char(c)),
+        _ => NAMED_KEYS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(text))
+            .map(|&(_, key)| key),
+    }
+}
+
+fn key_name(key: KeyCode) -> String {
+    match NAMED_KEYS.iter().find(|&&(_, named)| named == key) {
+        Some((name, _)) => name.to_string(),
+        None => match key {
+            KeyCode::Char(c) => c.to_string(),
+            _ => format!("{key:?}"),
+        },
+    }
+}
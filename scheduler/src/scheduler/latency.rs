@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+/// Counts wake-to-run latencies in buckets of increasing size.
+#[derive(Clone, Default)]
+pub struct LatencyHistogram {
+    buckets: [u64; LatencyHistogram::BUCKET_BOUNDS.len() + 1],
+}
+
+impl LatencyHistogram {
+    /// The upper bounds of the buckets, the last bucket holds everything above them.
+    pub const BUCKET_BOUNDS: [Duration; 7] = [
+        Duration::from_millis(1),
+        Duration::from_millis(2),
+        Duration::from_millis(5),
+        Duration::from_millis(10),
+        Duration::from_millis(50),
+        Duration::from_millis(100),
+        Duration::from_millis(500),
+    ];
+
+    pub fn record(&mut self, latency: Duration) {
+        let bucket = LatencyHistogram::BUCKET_BOUNDS
+            .iter()
+            .position(|&bound| latency < bound)
+            .unwrap_or(LatencyHistogram::BUCKET_BOUNDS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    pub fn labels() -> Vec<String> {
+        let mut labels: Vec<String> = LatencyHistogram::BUCKET_BOUNDS
+            .iter()
+            .map(|bound| format!("<{}ms", bound.as_millis()))
+            .collect();
+        labels.push(format!(
+            ">{}ms",
+            LatencyHistogram::BUCKET_BOUNDS.last().unwrap().as_millis()
+        ));
+        labels
+    }
+}
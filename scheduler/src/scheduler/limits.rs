@@ -0,0 +1,49 @@
+use super::script::parse_duration;
+use std::time::Duration;
+
+/// The signal a process gets when it goes over its CPU time limit.
+const SIGXCPU: i32 = 24;
+
+/// The resource limits of a process, like the `RLIMIT_CPU` and `RLIMIT_NPROC` of `setrlimit`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The CPU time the process can use before it's killed with `SIGXCPU`
+    pub cpu_time: Option<Duration>,
+    /// How many children the process can have at once
+    pub children: Option<usize>,
+}
+
+impl Limits {
+    /// The exit code of a process that was killed with `SIGXCPU`, the way a shell reports it.
+    pub const SIGXCPU_EXIT_CODE: i32 = 128 + SIGXCPU;
+
+    /// Parses limits like `cpu 2s children 4`, on top of `self`.
+    pub fn parse(self, text: &str) -> Result<Self, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.is_empty() || !words.len().is_multiple_of(2) {
+            return Err("expected `cpu <duration>` or `children <count>`".to_owned());
+        }
+
+        let mut limits = self;
+        for pair in words.chunks(2) {
+            match pair {
+                ["cpu", duration] => {
+                    limits.cpu_time = Some(
+                        parse_duration(duration)
+                            .ok_or_else(|| format!("invalid CPU time \"{duration}\""))?,
+                    );
+                }
+                ["children", count] => {
+                    limits.children = Some(
+                        count
+                            .parse()
+                            .map_err(|_| format!("invalid child count \"{count}\""))?,
+                    );
+                }
+                [limit, _] => return Err(format!("unknown limit \"{limit}\"")),
+                _ => unreachable!("Limits come in pairs"),
+            }
+        }
+        Ok(limits)
+    }
+}
@@ -0,0 +1,56 @@
+use super::clock;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Unix style load averages over the number of runnable (and running) processes.
+#[derive(Clone)]
+pub struct LoadAverage {
+    averages: [f64; 3],
+    last_sample: Instant,
+}
+
+impl LoadAverage {
+    /// The periods of the averages, in seconds rather than minutes to suit the simulation's pace.
+    const PERIODS: [Duration; 3] = [
+        Duration::from_secs(1),
+        Duration::from_secs(5),
+        Duration::from_secs(15),
+    ];
+
+    pub fn new() -> Self {
+        Self {
+            averages: [0.0; 3],
+            last_sample: clock::now(),
+        }
+    }
+
+    pub fn sample(&mut self, runnable: usize) {
+        let elapsed = clock::elapsed(self.last_sample);
+        self.last_sample = clock::now();
+
+        // Move each average towards the current load, the longer the period the slower it moves
+        for (average, period) in self.averages.iter_mut().zip(LoadAverage::PERIODS) {
+            let decay = (-elapsed.as_secs_f64() / period.as_secs_f64()).exp();
+            *average = *average * decay + runnable as f64 * (1.0 - decay);
+        }
+    }
+
+    pub fn averages(&self) -> [f64; 3] {
+        self.averages
+    }
+}
+
+impl Default for LoadAverage {
+    fn default() -> Self {
+        LoadAverage::new()
+    }
+}
+
+impl fmt::Display for LoadAverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [one, five, fifteen] = self.averages;
+        write!(f, "{one:.2} {five:.2} {fifteen:.2}")
+    }
+}
@@ -0,0 +1,414 @@
+//! Simulated mutexes which tasks lock and unlock, and the protocols that keep a low priority
+//! holder from making higher priority processes wait behind everyone else.
+
+use super::{
+    clock, syscall, testing::IDLE_STEP, Futexes, Niceness, Process, ProcessState, Scheduler,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// How a mutex raises the priority of the process holding it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockProtocol {
+    /// The holder keeps its niceness, so a process waiting for the lock can be held up by every
+    /// process that's more important than the holder (priority inversion)
+    None,
+    /// The holder runs with the niceness of the most important process waiting for the lock
+    Inheritance,
+    /// The holder runs with the given niceness from the moment it takes the lock (the immediate
+    /// priority ceiling protocol), which should be the niceness of its most important user
+    Ceiling(Niceness),
+}
+
+impl LockProtocol {
+    /// Parses `none`, `inherit` or `ceiling <niceness>`.
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.split_whitespace().collect::<Vec<_>>()[..] {
+            [] | ["none"] => Some(LockProtocol::None),
+            ["inherit"] => Some(LockProtocol::Inheritance),
+            ["ceiling", niceness] => niceness.parse().ok().map(LockProtocol::Ceiling),
+            _ => None,
+        }
+    }
+}
+
+struct LockState {
+    protocol: LockProtocol,
+    /// The address of the mutex' futex word, which holds the owner's PID + 1, or 0 while the mutex
+    /// is free
+    address: u64,
+}
+
+/// Where the futex words of the mutexes start, one word after the other.
+const MUTEX_BASE: u64 = 0x1000_0000;
+/// The size of a futex word.
+const WORD_SIZE: u64 = 4;
+
+/// A table of named mutexes, shared by the tasks that use them and the runner that applies their
+/// protocols. Mutexes that weren't declared use `LockProtocol::None`.
+///
+/// Every mutex is a futex word holding its owner. Taking a free mutex only changes the word, and
+/// a process only waits on the futex when the mutex is held, like a PI futex whose queue tells
+/// the protocol who to boost the owner for.
+#[derive(Clone, Default)]
+pub struct Locks {
+    table: Arc<Mutex<HashMap<String, LockState>>>,
+    futexes: Futexes,
+}
+
+impl Locks {
+    fn table(&self) -> MutexGuard<'_, HashMap<String, LockState>> {
+        self.table.lock().expect("Failed to lock the lock table.")
+    }
+
+    /// The address of a mutex' futex word, declaring the mutex if it doesn't exist yet.
+    fn address(&self, name: &str) -> u64 {
+        let mut table = self.table();
+        let address = next_address(&table);
+        table
+            .entry(name.to_owned())
+            .or_insert(LockState {
+                protocol: LockProtocol::None,
+                address,
+            })
+            .address
+    }
+
+    /// The futexes the mutexes are built on, which tasks can also use directly.
+    pub fn futexes(&self) -> Futexes {
+        self.futexes.clone()
+    }
+
+    /// Declares a mutex, or changes the protocol of an existing one.
+    pub fn declare(&self, name: &str, protocol: LockProtocol) {
+        let mut table = self.table();
+        let address = next_address(&table);
+        table
+            .entry(name.to_owned())
+            .or_insert(LockState { protocol, address })
+            .protocol = protocol;
+    }
+
+    /// Takes the mutex for `pid`. Returns false, and queues `pid` on the mutex' futex until the
+    /// mutex is unlocked, if another process holds it.
+    pub(super) fn try_lock(&self, name: &str, pid: u32) -> bool {
+        let address = self.address(name);
+        let owner = owner_word(pid);
+        loop {
+            match self.futexes.compare_exchange(address, 0, owner) {
+                Ok(_) => return true,
+                Err(word) if word == owner => return true,
+                // Wait unless the mutex was unlocked since the word was read
+                Err(word) => {
+                    if self.futexes.wait(address, word, pid) {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Frees the mutex if `pid` holds it, and wakes the process that waited for it the longest.
+    pub(super) fn unlock(&self, name: &str, pid: u32) {
+        let address = self.address(name);
+        if self
+            .futexes
+            .compare_exchange(address, owner_word(pid), 0)
+            .is_ok()
+        {
+            self.futexes.wake(address, 1);
+        }
+    }
+
+    /// Whether `pid` still waits for the mutex to be unlocked.
+    pub(super) fn is_waiting(&self, name: &str, pid: u32) -> bool {
+        self.futexes.is_queued(self.address(name), pid)
+    }
+
+    /// The name, owner and waiters of every mutex, by name.
+    fn mutexes(&self) -> Vec<(String, Option<u32>, Vec<u32>)> {
+        let mut mutexes: Vec<_> = self
+            .table()
+            .iter()
+            .map(|(name, lock)| {
+                let owner = owner_pid(self.futexes.load(lock.address));
+                (name.clone(), owner, self.futexes.waiters(lock.address))
+            })
+            .collect();
+        mutexes.sort_by(|a, b| a.0.cmp(&b.0));
+        mutexes
+    }
+
+    /// Releases the mutexes of a process that exited, and stops it from waiting for others.
+    pub fn release_all(&self, pid: u32) {
+        self.futexes.remove(pid);
+        let addresses: Vec<u64> = self.table().values().map(|lock| lock.address).collect();
+        for address in addresses {
+            if self
+                .futexes
+                .compare_exchange(address, owner_word(pid), 0)
+                .is_ok()
+            {
+                self.futexes.wake(address, 1);
+            }
+        }
+    }
+
+    /// Sets the niceness every process runs at because of the mutexes it holds.
+    pub fn apply<'a>(&self, processes: impl IntoIterator<Item = &'a mut Process>) {
+        let processes: Vec<&mut Process> = processes.into_iter().collect();
+        let niceness: HashMap<u32, Niceness> = processes
+            .iter()
+            .map(|process| (process.pid(), process.niceness()))
+            .collect();
+
+        let mut boosts: HashMap<u32, Niceness> = HashMap::new();
+        for lock in self.table().values() {
+            let Some(owner) = owner_pid(self.futexes.load(lock.address)) else {
+                continue;
+            };
+            let boost = match lock.protocol {
+                LockProtocol::None => None,
+                LockProtocol::Inheritance => self
+                    .futexes
+                    .waiters(lock.address)
+                    .iter()
+                    .filter_map(|waiter| niceness.get(waiter))
+                    .copied()
+                    .min(),
+                LockProtocol::Ceiling(ceiling) => Some(ceiling),
+            };
+            if let Some(boost) = boost {
+                let current = boosts.entry(owner).or_insert(boost);
+                *current = (*current).min(boost);
+            }
+        }
+
+        for process in processes {
+            process.set_lock_niceness(boosts.get(&process.pid()).copied());
+        }
+    }
+}
+
+/// Tells what the mutexes do to the processes as it happens: who took a mutex, who waits for it,
+/// who runs at another niceness because of it, and who runs while a more important process waits
+/// for a less important one (priority inversion).
+#[derive(Default)]
+pub struct LockNarrator {
+    /// The owner and waiters of every mutex when the narrator last looked
+    mutexes: HashMap<String, (Option<u32>, Vec<u32>)>,
+    /// The niceness of the processes that ran at a better niceness because of a mutex
+    boosts: HashMap<u32, Niceness>,
+    /// The process that was last noted for running during a priority inversion
+    inverted: Option<u32>,
+}
+
+impl LockNarrator {
+    pub fn new() -> Self {
+        LockNarrator::default()
+    }
+
+    /// What changed since the last call, one note per change.
+    pub fn narrate<S: Scheduler>(&mut self, locks: &Locks, scheduler: &S) -> Vec<String> {
+        let name = |pid: u32| {
+            scheduler
+                .process(pid)
+                .map_or_else(|| format!("Process {pid}"), Process::name)
+        };
+        let mut notes = Vec::new();
+
+        let mutexes = locks.mutexes();
+        for (mutex, owner, waiters) in &mutexes {
+            let (last_owner, last_waiters) = self.mutexes.remove(mutex).unwrap_or_default();
+            if last_owner != *owner {
+                if let Some(last_owner) = last_owner {
+                    notes.push(format!("{} released {mutex}", name(last_owner)));
+                }
+                if let Some(owner) = owner {
+                    notes.push(format!("{} took {mutex}", name(*owner)));
+                }
+            }
+            if let Some(owner) = owner {
+                for waiter in waiters.iter().filter(|pid| !last_waiters.contains(pid)) {
+                    notes.push(format!(
+                        "{} waits for {mutex}, held by {}",
+                        name(*waiter),
+                        name(*owner)
+                    ));
+                }
+            }
+        }
+
+        for process in scheduler.processes() {
+            let (pid, niceness) = (process.pid(), process.effective_niceness());
+            if niceness == process.niceness() {
+                if self.boosts.remove(&pid).is_some() {
+                    notes.push(format!("{} is back at niceness {niceness}", process.name()));
+                }
+                continue;
+            }
+            if self.boosts.insert(pid, niceness) == Some(niceness) {
+                continue;
+            }
+            // Inherited from a waiter with that niceness, or else the mutex' ceiling
+            let donor = mutexes
+                .iter()
+                .filter(|(_, owner, _)| *owner == Some(pid))
+                .flat_map(|(_, _, waiters)| waiters)
+                .find(|&&waiter| {
+                    scheduler.process(waiter).map(Process::niceness) == Some(niceness)
+                });
+            notes.push(match donor {
+                Some(&donor) => format!(
+                    "{} inherits niceness {niceness} from {}",
+                    process.name(),
+                    name(donor)
+                ),
+                None => format!("{} runs at the ceiling niceness {niceness}", process.name()),
+            });
+        }
+        self.boosts
+            .retain(|&pid, _| scheduler.process(pid).is_some());
+
+        let current = scheduler
+            .current_process()
+            .filter(|process| process.is_runnable());
+        let inversion = current.and_then(|current| {
+            mutexes.iter().find_map(|(_, owner, waiters)| {
+                let owner = (*owner)?;
+                let waiter = waiters
+                    .iter()
+                    .filter_map(|&pid| scheduler.process(pid))
+                    .find(|waiter| waiter.effective_niceness() < current.effective_niceness())?;
+                (owner != current.pid()).then_some((current, waiter, owner))
+            })
+        });
+        match inversion {
+            Some((current, waiter, owner)) if self.inverted != Some(current.pid()) => {
+                self.inverted = Some(current.pid());
+                notes.push(format!(
+                    "Priority inversion: {} runs while {} waits for {}",
+                    current.name(),
+                    waiter.name(),
+                    name(owner)
+                ));
+            }
+            Some(_) => {}
+            None => self.inverted = None,
+        }
+
+        self.mutexes = mutexes
+            .into_iter()
+            .map(|(mutex, owner, waiters)| (mutex, (owner, waiters)))
+            .collect();
+        notes
+    }
+}
+
+/// The address of the futex word of the next mutex.
+fn next_address(table: &HashMap<String, LockState>) -> u64 {
+    MUTEX_BASE + table.len() as u64 * WORD_SIZE
+}
+
+/// The futex word of a mutex held by `pid`.
+fn owner_word(pid: u32) -> i64 {
+    pid as i64 + 1
+}
+
+/// The PID of the process holding a mutex with the given futex word.
+fn owner_pid(word: i64) -> Option<u32> {
+    (word != 0).then(|| (word - 1) as u32)
+}
+
+/// How long a process waited for mutexes.
+#[derive(Clone, Debug)]
+pub struct LockWaits {
+    pub pid: u32,
+    pub name: String,
+    pub waits: u32,
+    pub total: Duration,
+    pub longest: Duration,
+}
+
+/// Runs the scheduler's processes for `duration` of mock time, applying the protocols of `locks`,
+/// and measures how long every process waited for them.
+///
+/// A mock clock must be installed, and the tasks must take mock time when they run.
+pub fn simulate<S: Scheduler>(
+    scheduler: &mut S,
+    locks: &Locks,
+    duration: Duration,
+) -> Vec<LockWaits> {
+    let start = clock::now();
+    let mut waits: Vec<LockWaits> = scheduler
+        .processes()
+        .map(|process| LockWaits {
+            pid: process.pid(),
+            name: process.name(),
+            waits: 0,
+            total: Duration::ZERO,
+            longest: Duration::ZERO,
+        })
+        .collect();
+    let mut waiting_since: HashMap<u32, Instant> = HashMap::new();
+
+    while clock::elapsed(start) < duration {
+        match scheduler.schedule() {
+            Some(process) => {
+                let pid = process.pid();
+                let start_time = clock::now();
+                process.run();
+                let elapsed = process.dilate(clock::elapsed(start_time));
+                scheduler.add_cpu_elapsed(elapsed);
+                syscall::service(scheduler, pid);
+            }
+            None => clock::advance_mock(IDLE_STEP),
+        }
+        locks.apply(scheduler.processes_mut());
+
+        for process in scheduler.processes() {
+            let waiting = matches!(process.state(), ProcessState::Waiting);
+            match waiting_since.get(&process.pid()) {
+                None if waiting => {
+                    waiting_since.insert(process.pid(), clock::now());
+                }
+                Some(&since) if !waiting => {
+                    waiting_since.remove(&process.pid());
+                    if let Some(waits) = waits.iter_mut().find(|waits| waits.pid == process.pid()) {
+                        let waited = clock::elapsed(since);
+                        waits.waits += 1;
+                        waits.total += waited;
+                        waits.longest = waits.longest.max(waited);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    waits
+}
+
+/// A table of how long every process waited for mutexes.
+pub fn report(waits: &[LockWaits]) -> String {
+    let mut report = format!(
+        "{:>4} {:<12} {:>6} {:>10} {:>10}\n",
+        "PID", "Name", "Waits", "Total", "Longest"
+    );
+    for process in waits {
+        writeln!(
+            report,
+            "{:>4} {:<12} {:>6} {:>8}ms {:>8}ms",
+            process.pid,
+            process.name,
+            process.waits,
+            process.total.as_millis(),
+            process.longest.as_millis(),
+        )
+        .expect("Failed to write the report.");
+    }
+    report
+}
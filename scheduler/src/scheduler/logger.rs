@@ -0,0 +1,108 @@
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A logger that appends records to a file, and rotates it once it grows too big.
+///
+/// Rotating renames `<file>` to `<file>.1`, `<file>.1` to `<file>.2` and so on, dropping the
+/// oldest file once there are `backups` of them.
+pub struct RotatingFileLogger {
+    path: PathBuf,
+    max_size: u64,
+    backups: u32,
+    file: Mutex<(File, u64)>,
+}
+
+impl RotatingFileLogger {
+    pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+    pub const DEFAULT_BACKUPS: u32 = 3;
+
+    pub fn new(path: impl AsRef<Path>, max_size: u64, backups: u32) -> Result<Self, io::Error> {
+        let path = path.as_ref().to_owned();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            max_size,
+            backups,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    /// Installs the logger as the global logger of the `log` crate.
+    pub fn init(self, level: LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    fn backup_path(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&self) -> Result<File, io::Error> {
+        for index in (1..self.backups).rev() {
+            let from = self.backup_path(index);
+            if from.exists() {
+                fs::rename(from, self.backup_path(index + 1))?;
+            }
+        }
+        if self.backups > 0 {
+            fs::rename(&self.path, self.backup_path(1))?;
+        }
+        File::create(&self.path)
+    }
+}
+
+impl Log for RotatingFileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let line = format!(
+            "{timestamp:.3} {} {}: {}\n",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        let mut file = self.file.lock().unwrap();
+        let (file, size) = &mut *file;
+        if *size > 0 && *size + line.len() as u64 > self.max_size {
+            // Keep logging to the old file if it can't be rotated
+            if let Ok(rotated) = self.rotate() {
+                *file = rotated;
+                *size = 0;
+            }
+        }
+
+        // There's nowhere to report a failure to log
+        if file.write_all(line.as_bytes()).is_ok() {
+            *size += line.len() as u64;
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.file.lock().unwrap().0.flush();
+    }
+}
@@ -1,29 +1,183 @@
+#[cfg(feature = "async")]
+mod async_runner;
+pub mod batch;
+mod clock;
+mod control;
+pub mod cores;
 mod display;
+#[cfg(unix)]
+mod exec;
+pub mod exits;
+mod futex;
+pub mod fuzz;
+mod history;
+mod keymap;
+mod latency;
+mod limits;
+mod load;
+pub mod locks;
+mod logger;
 mod niceness;
+mod observer;
 mod process;
+mod registry;
 mod round_robin;
 mod runner;
+mod runqueue;
+mod script;
+mod snapshot;
+pub mod sweep;
+mod syscall;
+mod sysctl;
+pub mod table;
 mod tasks;
+pub mod testing;
+mod theme;
+mod threads;
+mod tree;
+mod wait;
+mod watchdog;
+mod weight;
+pub mod workload;
 
 use std::time::Duration;
 
+#[cfg(feature = "async")]
+pub use async_runner::{AsyncProcessRunner, FutureTask, TaskOutput};
+pub use batch::{BatchExperiment, BatchResults, RunMetrics, Summary};
+pub use control::{Command, ControlServer};
+pub use cores::{BigLittle, Core, CoreUsage, Cpus, Placement};
+#[cfg(unix)]
+pub use exec::ExecTask;
+pub use exits::{ExitReport, ExitedProcess};
+pub use futex::Futexes;
+pub use history::{Decision, DecisionHistory, DecisionReason};
+pub use keymap::Keymap;
+pub use latency::LatencyHistogram;
+pub use limits::Limits;
+pub use load::LoadAverage;
+pub use locks::{LockNarrator, LockProtocol, Locks};
+pub use logger::RotatingFileLogger;
 pub use niceness::NicenessScheduler;
-pub use process::Process;
+pub use observer::SchedulerObserver;
+pub use process::{Process, ProcessState};
+pub use registry::{TaskFactory, TaskRegistry};
 pub use round_robin::RoundRobinScheduler;
 pub use runner::ProcessRunner;
-pub use tasks::CounterTask;
+pub use runqueue::{Runqueue, RunqueueEntry, RunqueueKind};
+pub use script::{GeneratorTask, ScriptError, ScriptedTask, Statement};
+pub use sweep::{QuantumSweep, SweepPoint};
+pub use syscall::Syscall;
+pub use sysctl::Sysctl;
+pub use table::{ProcessKey, ProcessTable};
+pub use tasks::{BurstyTask, CounterTask, InteractiveTask, IoBoundTask, MemoryHogTask, Task};
+pub use theme::Theme;
+pub use threads::TaskThreads;
+pub use wait::{WaitStatus, WaitTarget, Zombies};
+pub use watchdog::Watchdog;
+pub use weight::{niceness_to_weight, Niceness, NicenessOutOfRange, NICE_0_WEIGHT};
 
 const DEFAULT_TICK_RATE: Duration = Duration::from_millis(200);
+const DEFAULT_USAGE_HALF_LIFE: Duration = Duration::from_secs(2);
 
 pub trait Scheduler {
     const NAME: &'static str;
 
-    fn processes(&self) -> &Vec<Process>;
+    fn process_table(&self) -> &ProcessTable;
+    fn process_table_mut(&mut self) -> &mut ProcessTable;
     fn add_process(&mut self, process: Process);
-    fn remove_process(&mut self, process_name: String) -> Option<Process>;
+    /// Removes the process with the given PID, even if other processes have the same name.
+    fn remove_pid(&mut self, pid: u32) -> Option<Process>;
+
+    /// The processes, in the order they were added.
+    fn processes(&self) -> table::Iter<'_> {
+        self.process_table().iter()
+    }
+
+    /// The processes, in no particular order.
+    fn processes_mut(&mut self) -> table::IterMut<'_> {
+        self.process_table_mut().iter_mut()
+    }
+
+    fn process(&self, pid: u32) -> Option<&Process> {
+        self.process_table().by_pid(pid)
+    }
+
+    fn process_mut(&mut self, pid: u32) -> Option<&mut Process> {
+        self.process_table_mut().by_pid_mut(pid)
+    }
+
+    /// Removes the first process with the given name.
+    fn remove_process(&mut self, process_name: String) -> Option<Process> {
+        let pid = self
+            .processes()
+            .find(|process| process.name() == process_name)?
+            .pid();
+        self.remove_pid(pid)
+    }
+
     fn schedule(&mut self) -> Option<&mut Process>;
+    /// Makes the next call to `schedule` pick a process again, as if the current process' time
+    /// slice was over.
+    fn yield_current(&mut self);
     fn cpu_elapsed(&self) -> Duration;
     fn add_cpu_elapsed(&mut self, elapsed: Duration);
+    fn set_cpu_elapsed(&mut self, cpu_elapsed: Duration);
+
+    /// The decayed counterpart of `cpu_elapsed`, shared between the current processes.
+    fn recent_cpu_elapsed(&self) -> Duration {
+        self.processes().map(Process::recent_cpu_usage).sum()
+    }
+
+    /// The last scheduling decisions, and why they were made.
+    fn history(&self) -> &DecisionHistory;
+    fn history_mut(&mut self) -> &mut DecisionHistory;
+
+    /// The runnable processes in the order the scheduler would run them.
+    fn runqueue(&self) -> Runqueue;
+
+    /// Changes the niceness of a process. Returns false if there is no process with that PID.
+    fn renice(&mut self, pid: u32, niceness: Niceness) -> bool {
+        match self.process_mut(pid) {
+            Some(process) => {
+                process.set_niceness(niceness);
+                true
+            }
+            None => false,
+        }
+    }
+
     fn current_process(&self) -> Option<&Process>;
     fn current_process_mut(&mut self) -> Option<&mut Process>;
+    /// Makes the process with the given PID the current process, if it exists.
+    fn set_current_process(&mut self, pid: u32);
+
+    fn tick_rate(&self) -> Duration;
+    fn set_tick_rate(&mut self, tick_rate: Duration);
+    fn usage_half_life(&self) -> Duration;
+    /// Sets how fast the recent CPU usage decays, where zero turns the decay off.
+    fn set_usage_half_life(&mut self, half_life: Duration);
+
+    /// Whether processes waking up from sleep get a temporary priority boost.
+    fn sleeper_boost(&self) -> bool {
+        false
+    }
+
+    fn set_sleeper_boost(&mut self, _enabled: bool) {}
+
+    /// Whether the children of every parent share the weight of a single process.
+    fn autogroup(&self) -> bool {
+        false
+    }
+
+    fn set_autogroup(&mut self, _enabled: bool) {}
+}
+
+/// Wakes up every process whose sleep is over. Returns true if any process was woken up.
+fn wake_processes<'a>(processes: impl Iterator<Item = &'a mut Process>) -> bool {
+    let mut woke_up = false;
+    for process in processes {
+        woke_up |= process.wake();
+    }
+    woke_up
 }
@@ -1,92 +1,303 @@
-use super::{Process, Scheduler, DEFAULT_TICK_RATE};
-use std::time::{Duration, Instant};
+use super::{
+    clock, niceness_to_weight, wake_processes, DecisionHistory, DecisionReason, Niceness, Process,
+    ProcessKey, ProcessTable, Runqueue, RunqueueEntry, RunqueueKind, Scheduler, DEFAULT_TICK_RATE,
+    DEFAULT_USAGE_HALF_LIFE, NICE_0_WEIGHT,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
 pub struct NicenessScheduler {
-    processes: Vec<Process>,
-    current_process: usize,
+    processes: ProcessTable,
+    current_process: Option<ProcessKey>,
     tick_rate: Duration,
     cpu_elapsed: Duration,
+    usage_half_life: Duration,
+    sleeper_boost: bool,
+    autogroup: bool,
+    /// Set when a niceness changed, to pick a process with the new weights right away
+    reniced: bool,
     last_tick: Instant,
+    /// Set when the current process gave up the rest of its time slice
+    yielded: bool,
+    history: DecisionHistory,
 }
 
 impl NicenessScheduler {
-    pub const CPU_USAGE_SCALE: f64 = 256.0;
+    /// Fine enough that the small shares of heavily weighted processes don't round down to 0.
+    pub const CPU_USAGE_SCALE: f64 = (1 << 24) as f64;
+    /// The badness credit given to a process which woke up and hasn't run yet.
+    pub const SLEEPER_CREDIT: i64 = (NicenessScheduler::CPU_USAGE_SCALE / 2.0) as i64;
 
     pub fn new() -> Self {
         NicenessScheduler::with_processes(Vec::new(), DEFAULT_TICK_RATE)
     }
 
     pub fn with_processes(processes: Vec<Process>, tick_rate: Duration) -> Self {
+        let processes: ProcessTable = processes.into_iter().collect();
         Self {
+            current_process: processes.next_key(None),
             processes,
-            current_process: 0,
             tick_rate,
             cpu_elapsed: Duration::ZERO,
-            last_tick: Instant::now(),
+            usage_half_life: DEFAULT_USAGE_HALF_LIFE,
+            sleeper_boost: true,
+            autogroup: false,
+            reniced: false,
+            last_tick: clock::now(),
+            yielded: false,
+            history: DecisionHistory::default(),
         }
     }
 
     fn poll_process(&mut self) {
-        if self.processes.len() < 2 {
-            self.current_process = 0;
+        self.apply_autogroups();
+
+        // Make the runnable process with the least badness the current process
+        if let Some(&(key, _)) = self.sorted_runnable().first() {
+            self.current_process = Some(key);
+            let sleeper_boost = self.sleeper_boost;
+            if let Some(process) = self.processes.get_mut(key) {
+                let credited = sleeper_boost && NicenessScheduler::has_sleeper_credit(process);
+                process.set_used_sleeper_credit(credited);
+            }
+        }
+    }
+
+    /// Splits the weight of a nice 0 process between the children of every parent, by their own
+    /// weights, like Linux's autogroups do for the processes of a session. Processes without a
+    /// parent keep their weight.
+    fn apply_autogroups(&mut self) {
+        if !self.autogroup {
             return;
         }
 
-        // Find the process with the least badnass, and make it the current process
-        self.current_process = self
+        let mut group_weights: HashMap<u32, u64> = HashMap::new();
+        for process in self.processes.iter() {
+            if let Some(parent) = process.parent() {
+                *group_weights.entry(parent).or_default() +=
+                    niceness_to_weight(process.effective_niceness()) as u64;
+            }
+        }
+        for process in self.processes.iter_mut() {
+            let weight = process.parent().map(|parent| {
+                let own = niceness_to_weight(process.effective_niceness()) as u64;
+                (NICE_0_WEIGHT as u64 * own / group_weights[&parent]).max(1) as u32
+            });
+            process.set_autogroup_weight(weight);
+        }
+    }
+
+    /// The keys of the runnable processes and their effective badness, sorted by the badness. On
+    /// a tie, the process that didn't just wake up goes first.
+    fn sorted_runnable(&self) -> Vec<(ProcessKey, i64)> {
+        let recent_cpu_elapsed = self.recent_cpu_elapsed();
+        let mut runnable: Vec<(ProcessKey, i64)> = self
             .processes
+            .keys()
             .iter()
-            .enumerate()
-            .map(|(index, process)| (index, process.badness(self.cpu_elapsed)))
-            .min_by_key(|&(_, badnass)| badnass)
-            .unwrap()
-            .0;
+            .map(|&key| (key, &self.processes[key]))
+            .filter(|(_, process)| process.is_runnable())
+            .map(|(key, process)| (key, self.effective_badness(process, recent_cpu_elapsed)))
+            .collect();
+        runnable.sort_by_key(|&(key, badness)| (badness, self.processes[key].is_waking()));
+        runnable
+    }
+
+    /// The badness of a process, with the sleeper credit for waking processes.
+    fn effective_badness(&self, process: &Process, recent_cpu_elapsed: Duration) -> i64 {
+        let badness = process.badness(recent_cpu_elapsed);
+        if self.sleeper_boost && NicenessScheduler::has_sleeper_credit(process) {
+            badness - NicenessScheduler::SLEEPER_CREDIT
+        } else {
+            badness
+        }
+    }
+
+    /// Returns true if the process woke up and gets the sleeper credit. A process that ran on the
+    /// credit doesn't get it again until it ran without it, or processes which keep waking each
+    /// other up would starve the rest.
+    fn has_sleeper_credit(process: &Process) -> bool {
+        process.is_waking() && !process.used_sleeper_credit()
+    }
+}
+
+impl Default for NicenessScheduler {
+    fn default() -> Self {
+        NicenessScheduler::new()
     }
 }
 
 impl Scheduler for NicenessScheduler {
     const NAME: &'static str = "Niceness Scheduler";
 
-    fn processes(&self) -> &Vec<Process> {
+    fn process_table(&self) -> &ProcessTable {
         &self.processes
     }
 
+    fn process_table_mut(&mut self) -> &mut ProcessTable {
+        &mut self.processes
+    }
+
     fn add_process(&mut self, process: Process) {
-        self.processes.push(process);
+        let key = self.processes.insert(process);
+        self.current_process.get_or_insert(key);
     }
 
-    fn remove_process(&mut self, process_name: String) -> Option<Process> {
-        match self
-            .processes
-            .iter()
-            .position(|process| process.name() == process_name)
-        {
-            Some(index) => Some(self.processes.remove(index)),
-            None => None,
+    /// Removing the current process makes the process after it the current one, until the next
+    /// scheduling decision.
+    fn remove_pid(&mut self, pid: u32) -> Option<Process> {
+        let key = self.processes.key(pid)?;
+        if self.current_process == Some(key) {
+            self.current_process = self.processes.next_key(Some(key));
+        }
+        let process = self.processes.remove(key);
+        if self.processes.is_empty() {
+            self.current_process = None;
         }
+        process
     }
 
     fn schedule(&mut self) -> Option<&mut Process> {
-        if self.last_tick.elapsed() > self.tick_rate {
-            self.last_tick = Instant::now();
+        let woke_up = wake_processes(self.processes.iter_mut());
+        let current_blocked = !self.current_process().is_some_and(Process::is_runnable);
+
+        // Preempt the current process right away if a sleeper woke up and should get a boost
+        if clock::elapsed(self.last_tick) > self.tick_rate
+            || current_blocked
+            || (self.sleeper_boost && woke_up)
+            || self.reniced
+            || self.yielded
+        {
+            self.last_tick = clock::now();
             self.poll_process();
+
+            let reason = if current_blocked {
+                DecisionReason::Blocked
+            } else if self.sleeper_boost && woke_up {
+                DecisionReason::WokeUp
+            } else if self.reniced {
+                DecisionReason::PriorityChanged
+            } else if self.yielded {
+                DecisionReason::Yielded
+            } else {
+                DecisionReason::QuantumExpired
+            };
+            self.reniced = false;
+            self.yielded = false;
+            let chosen = self.current_process.and_then(|key| self.processes.get(key));
+            self.history.record(
+                reason,
+                self.processes.iter(),
+                chosen.filter(|process| process.is_runnable()),
+            );
         }
         self.current_process_mut()
+            .filter(|process| process.is_runnable())
+    }
+
+    fn yield_current(&mut self) {
+        self.yielded = true;
+    }
+
+    fn renice(&mut self, pid: u32, niceness: Niceness) -> bool {
+        match self.processes.by_pid_mut(pid) {
+            Some(process) => {
+                process.set_niceness(niceness);
+                self.reniced = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn history(&self) -> &DecisionHistory {
+        &self.history
+    }
+
+    fn history_mut(&mut self) -> &mut DecisionHistory {
+        &mut self.history
+    }
+
+    fn runqueue(&self) -> Runqueue {
+        let entries = self
+            .sorted_runnable()
+            .into_iter()
+            .map(|(key, badness)| RunqueueEntry::new(&self.processes[key], Some(badness)))
+            .collect();
+        Runqueue {
+            kind: RunqueueKind::Sorted,
+            entries,
+        }
     }
 
     fn current_process(&self) -> Option<&Process> {
-        self.processes.get(self.current_process)
+        self.processes.get(self.current_process?)
     }
 
     fn current_process_mut(&mut self) -> Option<&mut Process> {
-        self.processes.get_mut(self.current_process)
+        self.processes.get_mut(self.current_process?)
+    }
+
+    fn set_current_process(&mut self, pid: u32) {
+        if let Some(key) = self.processes.key(pid) {
+            self.current_process = Some(key);
+        }
     }
 
     fn cpu_elapsed(&self) -> Duration {
         self.cpu_elapsed
     }
 
+    fn set_cpu_elapsed(&mut self, cpu_elapsed: Duration) {
+        self.cpu_elapsed = cpu_elapsed;
+    }
+
+    fn tick_rate(&self) -> Duration {
+        self.tick_rate
+    }
+
+    fn set_tick_rate(&mut self, tick_rate: Duration) {
+        self.tick_rate = tick_rate;
+    }
+
+    fn usage_half_life(&self) -> Duration {
+        self.usage_half_life
+    }
+
+    fn set_usage_half_life(&mut self, half_life: Duration) {
+        self.usage_half_life = half_life;
+    }
+
     fn add_cpu_elapsed(&mut self, elapsed: Duration) {
         self.cpu_elapsed += elapsed;
+
+        for process in self.processes.iter_mut() {
+            process.decay_cpu_usage(elapsed, self.usage_half_life);
+        }
+    }
+
+    fn sleeper_boost(&self) -> bool {
+        self.sleeper_boost
+    }
+
+    fn set_sleeper_boost(&mut self, enabled: bool) {
+        self.sleeper_boost = enabled;
+    }
+
+    fn autogroup(&self) -> bool {
+        self.autogroup
+    }
+
+    fn set_autogroup(&mut self, enabled: bool) {
+        self.autogroup = enabled;
+        if !enabled {
+            for process in self.processes.iter_mut() {
+                process.set_autogroup_weight(None);
+            }
+        }
+        // Pick a process with the new weights right away
+        self.reniced = true;
     }
 }
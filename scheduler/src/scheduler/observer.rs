@@ -0,0 +1,18 @@
+use super::Process;
+
+/// Gets notified by `ProcessRunner` about scheduling events, regardless of the scheduler in use.
+///
+/// Every callback does nothing by default, so observers only implement the events they need.
+pub trait SchedulerObserver {
+    /// A process was picked to run.
+    fn on_schedule(&mut self, _process: &Process) {}
+
+    /// A process that could still run was switched out for another one.
+    fn on_preempt(&mut self, _process: &Process) {}
+
+    /// A process went to sleep after running.
+    fn on_block(&mut self, _process: &Process) {}
+
+    /// A process was removed from the scheduler.
+    fn on_exit(&mut self, _process: &Process) {}
+}
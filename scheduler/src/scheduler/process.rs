@@ -1,32 +1,164 @@
-use super::{niceness::NicenessScheduler, tasks::Task};
-use std::time::{Duration, Instant};
+use super::{
+    clock,
+    latency::LatencyHistogram,
+    limits::Limits,
+    niceness::NicenessScheduler,
+    syscall::Syscall,
+    tasks::Task,
+    wait::WaitTarget,
+    watchdog::Run,
+    weight::{niceness_to_weight, Niceness, NICE_0_WEIGHT},
+};
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ProcessState {
+    Ready,
+    Sleeping {
+        until: Instant,
+    },
+    /// Waiting for an event, until the task says otherwise
+    Waiting,
+}
+
+impl fmt::Display for ProcessState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessState::Ready => write!(f, "Ready"),
+            ProcessState::Sleeping { .. } => write!(f, "Sleeping"),
+            ProcessState::Waiting => write!(f, "Waiting"),
+        }
+    }
+}
+
+/// The counters of a process at some point in time, to be restored later.
+#[derive(Clone)]
+pub(super) struct ProcessCounters {
+    pid: u32,
+    niceness: Niceness,
+    cpu_usage: Duration,
+    recent_cpu_usage: Duration,
+    interval_start_usage: Duration,
+    interval_cpu_share: f64,
+    time_dilation: u32,
+    state: ProcessState,
+    stopped: bool,
+    woken_at: Option<Instant>,
+    used_sleeper_credit: bool,
+    latencies: LatencyHistogram,
+    missed_deadlines: u64,
+    outputs: VecDeque<String>,
+}
+
+impl ProcessCounters {
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+}
 
 pub struct Process {
     pid: u32,
+    parent: Option<u32>,
+    /// When the process joins the run queue, in the scheduler's CPU time
+    arrival: Option<Duration>,
     name: String,
-    task: Box<dyn Task>,
-    niceness: i8,
+    /// Taken away while a hung task is left running on a worker
+    task: Option<Box<dyn Task>>,
+    niceness: Niceness,
+    /// The niceness the process runs at while it holds a mutex that boosts it
+    lock_niceness: Option<Niceness>,
+    /// The process' part of its autogroup's weight, while autogrouping is enabled
+    autogroup_weight: Option<u32>,
+    limits: Limits,
+    /// When the process was created, in wall-clock time
+    created_at: Instant,
+    /// The CPU time the process ran for
     cpu_usage: Duration,
+    recent_cpu_usage: Duration,
+    /// The CPU usage when the current sampling interval started
+    interval_start_usage: Duration,
+    /// The fraction of the last sampling interval the process ran for
+    interval_cpu_share: f64,
+    time_dilation: u32,
+    state: ProcessState,
+    stopped: bool,
+    /// Set when the task went over the watchdog's budget
+    hung: bool,
+    woken_at: Option<Instant>,
+    /// Set when the process last ran on the sleeper credit, which it can't use again until it
+    /// runs without it
+    used_sleeper_credit: bool,
+    latencies: LatencyHistogram,
+    deadline: Option<Duration>,
+    missed_deadlines: u64,
+    /// The last outputs of the task, oldest first
+    outputs: VecDeque<String>,
+    /// The system calls the task made in its last run, until the kernel services them
+    syscalls: Vec<Syscall>,
+    /// Messages sent to the process that it didn't receive yet, oldest first
+    mailbox: VecDeque<String>,
+    /// Set while the process is blocked receiving a message
+    receiving: bool,
+    /// Messages the process received, which its task gets before its next run
+    received: Vec<String>,
+    /// Set once the task finished
+    exit_code: Option<i32>,
 }
 
 impl Process {
-    const DEFAULT_NICENESS: i8 = 0;
+    pub const MAX_TIME_DILATION: u32 = 64;
+    /// How many of the task's outputs are kept
+    pub const OUTPUT_HISTORY: usize = 100;
 
     pub fn new(pid: u32, task: Box<dyn Task>) -> Self {
         Process::named(pid, "", task)
     }
 
     pub fn named(pid: u32, name: &str, task: Box<dyn Task>) -> Self {
-        Process::with_niceness(pid, name, task, Process::DEFAULT_NICENESS)
+        Process::with_niceness(pid, name, task, Niceness::default())
     }
 
-    pub fn with_niceness(pid: u32, name: &str, task: Box<dyn Task>, niceness: i8) -> Self {
+    pub fn with_niceness(
+        pid: u32,
+        name: &str,
+        mut task: Box<dyn Task>,
+        niceness: Niceness,
+    ) -> Self {
+        task.set_pid(pid);
         Self {
             pid,
+            parent: None,
+            arrival: None,
             name: name.to_owned(),
-            task,
+            task: Some(task),
             niceness,
+            lock_niceness: None,
+            autogroup_weight: None,
+            limits: Limits::default(),
+            created_at: clock::now(),
             cpu_usage: Duration::ZERO,
+            recent_cpu_usage: Duration::ZERO,
+            interval_start_usage: Duration::ZERO,
+            interval_cpu_share: 0.0,
+            time_dilation: 1,
+            state: ProcessState::Ready,
+            stopped: false,
+            hung: false,
+            woken_at: None,
+            used_sleeper_credit: false,
+            latencies: LatencyHistogram::default(),
+            deadline: None,
+            missed_deadlines: 0,
+            outputs: VecDeque::with_capacity(Process::OUTPUT_HISTORY),
+            syscalls: Vec::new(),
+            mailbox: VecDeque::new(),
+            receiving: false,
+            received: Vec::new(),
+            exit_code: None,
         }
     }
 
@@ -38,27 +170,432 @@ impl Process {
         self.pid
     }
 
-    pub fn niceness(&self) -> i8 {
+    pub(super) fn set_pid(&mut self, pid: u32) {
+        self.pid = pid;
+        if let Some(task) = &mut self.task {
+            task.set_pid(pid);
+        }
+    }
+
+    /// The PID of the process' parent, if it has one.
+    pub fn parent(&self) -> Option<u32> {
+        self.parent
+    }
+
+    pub fn set_parent(&mut self, parent: Option<u32>) {
+        self.parent = parent;
+    }
+
+    /// When the process joins the run queue, measured in the scheduler's CPU time.
+    pub fn arrival(&self) -> Option<Duration> {
+        self.arrival
+    }
+
+    pub fn set_arrival(&mut self, arrival: Option<Duration>) {
+        self.arrival = arrival;
+    }
+
+    pub fn niceness(&self) -> Niceness {
         self.niceness
     }
 
-    pub fn badness(&self, cpu_elapsed: Duration) -> i64 {
-        (self.cpu_usage.as_micros() as f64 / cpu_elapsed.as_micros() as f64
-            * NicenessScheduler::CPU_USAGE_SCALE) as i64
-            + (self.niceness * 2) as i64
+    pub fn set_niceness(&mut self, niceness: Niceness) {
+        self.niceness = niceness;
+    }
+
+    /// The niceness the process runs at, which a mutex it holds may have boosted.
+    pub fn effective_niceness(&self) -> Niceness {
+        self.lock_niceness
+            .map_or(self.niceness, |boost| boost.min(self.niceness))
+    }
+
+    pub(super) fn set_lock_niceness(&mut self, niceness: Option<Niceness>) {
+        self.lock_niceness = niceness;
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.autogroup_weight
+            .unwrap_or_else(|| niceness_to_weight(self.effective_niceness()))
+    }
+
+    pub(super) fn set_autogroup_weight(&mut self, weight: Option<u32>) {
+        self.autogroup_weight = weight;
+    }
+
+    pub fn state(&self) -> ProcessState {
+        self.state
+    }
+
+    pub fn is_runnable(&self) -> bool {
+        self.state == ProcessState::Ready && !self.stopped && !self.hung
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Stops or continues the process. A stopped process keeps its state, but is never scheduled.
+    pub fn set_stopped(&mut self, stopped: bool) {
+        self.stopped = stopped;
+    }
+
+    pub fn is_hung(&self) -> bool {
+        self.hung
+    }
+
+    /// Marks the process as hung, which isn't scheduled like a stopped process.
+    ///
+    /// A process stays hung while its task is still left running on a watchdog's worker.
+    pub fn set_hung(&mut self, hung: bool) {
+        self.hung = hung || self.task.is_none();
+    }
+
+    /// Gives back a hung task which finished, along with its output.
+    pub(super) fn reattach(&mut self, task: Box<dyn Task>, output: String) {
+        self.task = Some(task);
+        self.hung = false;
+        self.record_output(output);
+    }
+
+    /// Returns true if the process has woken up and hasn't run since.
+    pub fn is_waking(&self) -> bool {
+        self.woken_at.is_some()
+    }
+
+    /// How long the process has been waiting for the CPU since it woke up.
+    pub fn wake_latency(&self) -> Option<Duration> {
+        self.woken_at.map(clock::elapsed)
+    }
+
+    /// Wakes the process up if its sleep is over, if a message it waits for arrived, or if its
+    /// task stopped waiting. Returns true if it was woken up.
+    pub fn wake(&mut self) -> bool {
+        let waiting = self.receiving || self.task.as_ref().is_some_and(|task| task.is_waiting());
+        match self.state {
+            ProcessState::Sleeping { until } if until <= clock::now() => {
+                self.state = ProcessState::Ready;
+                self.woken_at = Some(clock::now());
+                true
+            }
+            ProcessState::Waiting if !waiting => {
+                self.state = ProcessState::Ready;
+                self.woken_at = Some(clock::now());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if the process last ran on the sleeper credit, so it doesn't get it when it
+    /// wakes up again until it runs without it.
+    pub fn used_sleeper_credit(&self) -> bool {
+        self.used_sleeper_credit
+    }
+
+    pub(super) fn set_used_sleeper_credit(&mut self, used: bool) {
+        self.used_sleeper_credit = used;
+    }
+
+    /// The wake-to-run latencies of the process.
+    pub fn latencies(&self) -> &LatencyHistogram {
+        &self.latencies
+    }
+
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+
+    /// Sets how soon after waking up the process must run, a longer wait counts as a missed deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Duration>) {
+        self.deadline = deadline;
+    }
+
+    pub fn missed_deadlines(&self) -> u64 {
+        self.missed_deadlines
+    }
+
+    /// The last outputs of the task, oldest first. Empty outputs aren't kept.
+    pub fn outputs(&self) -> &VecDeque<String> {
+        &self.outputs
+    }
+
+    pub fn time_dilation(&self) -> u32 {
+        self.time_dilation
+    }
+
+    pub fn set_time_dilation(&mut self, time_dilation: u32) {
+        self.time_dilation = time_dilation.clamp(1, Process::MAX_TIME_DILATION);
+    }
+
+    /// Converts real elapsed time into the process' virtual time (N times longer when dilated).
+    pub fn dilate(&self, elapsed: Duration) -> Duration {
+        elapsed * self.time_dilation
+    }
+
+    pub fn cpu_usage(&self) -> Duration {
+        self.cpu_usage
+    }
+
+    pub fn recent_cpu_usage(&self) -> Duration {
+        self.recent_cpu_usage
+    }
+
+    /// How long the process has existed, in wall-clock time, whether it ran or not.
+    pub fn age(&self) -> Duration {
+        clock::elapsed(self.created_at)
+    }
+
+    /// Ends a sampling interval which took `interval` of wall-clock time, and starts the next one.
+    pub fn sample_cpu_usage(&mut self, interval: Duration) {
+        let usage = self.cpu_usage.saturating_sub(self.interval_start_usage);
+        self.interval_cpu_share = usage.as_secs_f64() / interval.as_secs_f64();
+        self.interval_start_usage = self.cpu_usage;
+    }
+
+    /// Exponentially decays the recent CPU usage, halving it every `half_life` of CPU time. A zero
+    /// half-life turns the decay off, so the recent usage is the whole usage.
+    pub fn decay_cpu_usage(&mut self, elapsed: Duration, half_life: Duration) {
+        if half_life.is_zero() {
+            return;
+        }
+        let factor = 0.5f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64());
+        self.recent_cpu_usage = self.recent_cpu_usage.mul_f64(factor);
+    }
+
+    /// The recent CPU share of the process, scaled down by its weight (like a vruntime).
+    pub fn badness(&self, recent_cpu_elapsed: Duration) -> i64 {
+        let share =
+            self.recent_cpu_usage.as_micros() as f64 / recent_cpu_elapsed.as_micros() as f64;
+        (share * NicenessScheduler::CPU_USAGE_SCALE * NICE_0_WEIGHT as f64 / self.weight() as f64)
+            as i64
     }
 
     pub fn cpu_usage_percentage(&self, cpu_elapsed: Duration) -> String {
+        Process::percentage(self.cpu_usage, cpu_elapsed)
+    }
+
+    pub fn recent_cpu_usage_percentage(&self, recent_cpu_elapsed: Duration) -> String {
+        Process::percentage(self.recent_cpu_usage, recent_cpu_elapsed)
+    }
+
+    /// The share of the last sampling interval's wall-clock time the process ran for, like `top`'s
+    /// %CPU.
+    pub fn interval_cpu_percentage(&self) -> String {
+        format!("{}%", (self.interval_cpu_share * 100.0).round())
+    }
+
+    pub(super) fn percentage(usage: Duration, elapsed: Duration) -> String {
         format!(
             "{}%",
-            (self.cpu_usage.as_micros() as f64 / cpu_elapsed.as_micros() as f64 * 100.0).round()
+            (usage.as_micros() as f64 / elapsed.as_micros() as f64 * 100.0).round()
         )
     }
 
+    pub(super) fn counters(&self) -> ProcessCounters {
+        ProcessCounters {
+            pid: self.pid,
+            niceness: self.niceness,
+            cpu_usage: self.cpu_usage,
+            recent_cpu_usage: self.recent_cpu_usage,
+            interval_start_usage: self.interval_start_usage,
+            interval_cpu_share: self.interval_cpu_share,
+            time_dilation: self.time_dilation,
+            state: self.state,
+            stopped: self.stopped,
+            woken_at: self.woken_at,
+            used_sleeper_credit: self.used_sleeper_credit,
+            latencies: self.latencies.clone(),
+            missed_deadlines: self.missed_deadlines,
+            outputs: self.outputs.clone(),
+        }
+    }
+
+    pub(super) fn restore_counters(&mut self, counters: &ProcessCounters) {
+        self.niceness = counters.niceness;
+        self.cpu_usage = counters.cpu_usage;
+        self.recent_cpu_usage = counters.recent_cpu_usage;
+        self.interval_start_usage = counters.interval_start_usage;
+        self.interval_cpu_share = counters.interval_cpu_share;
+        self.time_dilation = counters.time_dilation;
+        self.state = counters.state;
+        self.stopped = counters.stopped;
+        self.woken_at = counters.woken_at;
+        self.used_sleeper_credit = counters.used_sleeper_credit;
+        self.latencies = counters.latencies.clone();
+        self.missed_deadlines = counters.missed_deadlines;
+        self.outputs = counters.outputs.clone();
+    }
+
     pub fn run(&mut self) -> String {
-        let before_running = Instant::now();
-        let output = self.task.run();
-        self.cpu_usage += before_running.elapsed();
+        self.run_with(|mut task| {
+            let output = task.run();
+            Run::Finished(task, output)
+        })
+    }
+
+    /// Runs the task with `execute`, marking the process as hung if the task overran or hung.
+    pub(super) fn run_with(&mut self, execute: impl FnOnce(Box<dyn Task>) -> Run) -> String {
+        let Some(mut task) = self.task.take() else {
+            return String::new();
+        };
+        for message in self.take_received() {
+            task.received(message);
+        }
+
+        self.start_run();
+        let before_running = clock::now();
+        let output = match execute(task) {
+            Run::Finished(task, output) => {
+                self.task = Some(task);
+                output
+            }
+            Run::Overran(task, output) => {
+                self.task = Some(task);
+                self.hung = true;
+                output
+            }
+            Run::Hung => {
+                self.hung = true;
+                String::new()
+            }
+        };
+        let syscalls = self
+            .task
+            .as_mut()
+            .map_or_else(Vec::new, |task| task.syscalls());
+        self.finish_run(
+            clock::elapsed(before_running),
+            vec![output.clone()],
+            syscalls,
+        );
+        if self.task.as_ref().is_some_and(|task| task.is_waiting()) {
+            self.state = ProcessState::Waiting;
+        }
         output
     }
+
+    /// The exit code of the process' task, once it finished.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub(super) fn set_exit_code(&mut self, exit_code: Option<i32>) {
+        self.exit_code = exit_code;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Kills the process with `SIGXCPU` if it used more CPU time than its limit allows. Returns
+    /// true if it did.
+    pub(super) fn enforce_cpu_limit(&mut self) -> bool {
+        let over_limit = self
+            .limits
+            .cpu_time
+            .is_some_and(|limit| self.cpu_usage > limit);
+        if over_limit && self.exit_code.is_none() {
+            self.exit_code = Some(Limits::SIGXCPU_EXIT_CODE);
+            return true;
+        }
+        false
+    }
+
+    /// The child the process waits for to exit, while it waits for one.
+    pub fn waits_on(&self) -> Option<WaitTarget> {
+        self.task.as_ref().and_then(|task| task.waits_on())
+    }
+
+    /// Takes the system calls the task made, to be serviced by the kernel.
+    pub(super) fn take_syscalls(&mut self) -> Vec<Syscall> {
+        std::mem::take(&mut self.syscalls)
+    }
+
+    /// Puts the process to sleep for the duration.
+    pub(super) fn sleep(&mut self, duration: Duration) {
+        self.state = ProcessState::Sleeping {
+            until: clock::now() + duration,
+        };
+    }
+
+    /// Puts a message in the process' mailbox, handing it over right away if the process is
+    /// blocked receiving one.
+    pub(super) fn deliver(&mut self, message: String) {
+        self.mailbox.push_back(message);
+        if self.receiving {
+            self.receive();
+        }
+    }
+
+    /// Receives the oldest message in the mailbox, or blocks until one arrives.
+    pub(super) fn receive(&mut self) {
+        match self.mailbox.pop_front() {
+            Some(message) => {
+                self.received.push(message);
+                self.receiving = false;
+            }
+            None => {
+                self.receiving = true;
+                self.state = ProcessState::Waiting;
+            }
+        }
+    }
+
+    /// Takes the messages the process received, to be handed to its task.
+    pub(super) fn take_received(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.received)
+    }
+
+    /// How many messages wait in the process' mailbox.
+    pub fn mailbox_len(&self) -> usize {
+        self.mailbox.len()
+    }
+
+    /// Takes the task away from the process, to be run somewhere else.
+    pub(super) fn take_task(&mut self) -> Option<Box<dyn Task>> {
+        self.task.take()
+    }
+
+    /// Records how long the process waited since it woke up, as it starts running.
+    pub(super) fn start_run(&mut self) {
+        if let Some(latency) = self.wake_latency() {
+            self.latencies.record(latency);
+            if self.deadline.is_some_and(|deadline| latency > deadline) {
+                self.missed_deadlines += 1;
+            }
+        }
+    }
+
+    /// Accounts for the time the task ran and its outputs, keeping the system calls it made for
+    /// the kernel.
+    pub(super) fn finish_run(
+        &mut self,
+        elapsed: Duration,
+        outputs: Vec<String>,
+        syscalls: Vec<Syscall>,
+    ) {
+        let usage = self.dilate(elapsed);
+        self.cpu_usage += usage;
+        self.recent_cpu_usage += usage;
+        self.woken_at = None;
+
+        for output in outputs {
+            self.record_output(output);
+        }
+        self.syscalls.extend(syscalls);
+    }
+
+    fn record_output(&mut self, output: String) {
+        if output.is_empty() {
+            return;
+        }
+        if self.outputs.len() == Process::OUTPUT_HISTORY {
+            self.outputs.pop_front();
+        }
+        self.outputs.push_back(output);
+    }
 }
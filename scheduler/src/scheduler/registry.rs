@@ -0,0 +1,159 @@
+use super::{
+    script::parse_duration, BurstyTask, CounterTask, GeneratorTask, InteractiveTask, IoBoundTask,
+    Locks, MemoryHogTask, ScriptedTask, Task, Zombies,
+};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Spreads registry seeds apart, so the generators of one seed don't get the seeds of the next.
+const GENERATOR_SEED_SPREAD: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Builds a task from the arguments written after its name.
+pub trait TaskFactory {
+    fn create(&self, args: &str) -> Result<Box<dyn Task>, String>;
+}
+
+impl<F> TaskFactory for F
+where
+    F: Fn(&str) -> Result<Box<dyn Task>, String>,
+{
+    fn create(&self, args: &str) -> Result<Box<dyn Task>, String> {
+        self(args)
+    }
+}
+
+/// Constructs tasks by name, e.g. `counter` or `script compute 5ms; loop`.
+pub struct TaskRegistry {
+    factories: HashMap<String, Box<dyn TaskFactory>>,
+    /// The mutexes shared by the tasks the registry creates
+    locks: Locks,
+    /// The children the registry's tasks wait for
+    zombies: Zombies,
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self {
+            factories: HashMap::new(),
+            locks: Locks::default(),
+            zombies: Zombies::default(),
+        }
+    }
+
+    /// Creates a registry which already knows all of the tasks of this crate.
+    pub fn with_builtin_tasks() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |now| now.as_nanos() as u64);
+        TaskRegistry::with_seed(now)
+    }
+
+    /// Like `with_builtin_tasks`, but the generators it creates are seeded from `seed`, so the
+    /// same seed spawns the same processes.
+    pub fn with_seed(seed: u64) -> Self {
+        let mut registry = TaskRegistry::new();
+        registry.register("counter", without_args(|| Box::new(CounterTask::new())));
+        registry.register(
+            "interactive",
+            without_args(|| Box::new(InteractiveTask::new())),
+        );
+        registry.register("io-bound", without_args(|| Box::new(IoBoundTask::new())));
+        registry.register("bursty", without_args(|| Box::new(BurstyTask::new())));
+        registry.register(
+            "memory-hog",
+            without_args(|| Box::new(MemoryHogTask::new())),
+        );
+        let (locks, zombies) = (registry.locks(), registry.zombies());
+        registry.register("script", move |args: &str| {
+            let task = ScriptedTask::parse(args).map_err(|error| error.to_string())?;
+            Ok(
+                Box::new(task.with_locks(locks.clone()).with_zombies(zombies.clone()))
+                    as Box<dyn Task>,
+            )
+        });
+
+        // `generator [interval] [max]`, where every generator gets a seed of its own
+        let next_seed = Cell::new(seed.wrapping_mul(GENERATOR_SEED_SPREAD));
+        registry.register("generator", move |args: &str| {
+            let mut args = args.split_whitespace();
+            let interval = match args.next() {
+                Some(interval) => parse_duration(interval)
+                    .ok_or_else(|| format!("invalid interval \"{interval}\""))?,
+                None => GeneratorTask::DEFAULT_INTERVAL,
+            };
+            let max = match args.next() {
+                Some(max) => max
+                    .parse()
+                    .map_err(|_| format!("invalid maximum \"{max}\""))?,
+                None => GeneratorTask::DEFAULT_MAX,
+            };
+            let seed = next_seed.get();
+            next_seed.set(seed.wrapping_add(1));
+            Ok(Box::new(GeneratorTask::new(interval, max, seed)) as Box<dyn Task>)
+        });
+
+        #[cfg(unix)]
+        registry.register("exec", |args: &str| {
+            let mut words = args.split_whitespace();
+            let program = words.next().ok_or("exec requires a program")?;
+            let args: Vec<&str> = words.collect();
+
+            let task = super::ExecTask::spawn(program, &args)
+                .map_err(|error| format!("failed to run \"{program}\": {error}"))?;
+            Ok(Box::new(task) as Box<dyn Task>)
+        });
+
+        registry
+    }
+
+    /// Registers a factory under `name`, replacing any factory that was registered under it.
+    pub fn register(&mut self, name: &str, factory: impl TaskFactory + 'static) {
+        self.factories.insert(name.to_owned(), Box::new(factory));
+    }
+
+    /// Creates a task from a spec made of its name, optionally followed by arguments.
+    pub fn create(&self, spec: &str) -> Result<Box<dyn Task>, String> {
+        let (name, args) = match spec.trim().split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (spec.trim(), ""),
+        };
+
+        match self.factories.get(name) {
+            Some(factory) => factory.create(args),
+            None => Err(format!("unknown task \"{name}\"")),
+        }
+    }
+
+    /// The lock table of the mutexes the registry's scripts lock.
+    pub fn locks(&self) -> Locks {
+        self.locks.clone()
+    }
+
+    /// The table of the children the registry's scripts wait for.
+    pub fn zombies(&self) -> Zombies {
+        self.zombies.clone()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+}
+
+impl Default for TaskRegistry {
+    fn default() -> Self {
+        TaskRegistry::new()
+    }
+}
+
+fn without_args(new: impl Fn() -> Box<dyn Task>) -> impl Fn(&str) -> Result<Box<dyn Task>, String> {
+    move |args| {
+        if args.is_empty() {
+            Ok(new())
+        } else {
+            Err(format!("unexpected arguments \"{args}\""))
+        }
+    }
+}
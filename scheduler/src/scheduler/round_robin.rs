@@ -1,12 +1,19 @@
-use super::{Process, Scheduler, DEFAULT_TICK_RATE};
+use super::{
+    clock, wake_processes, DecisionHistory, DecisionReason, Process, ProcessKey, ProcessTable,
+    Runqueue, RunqueueEntry, RunqueueKind, Scheduler, DEFAULT_TICK_RATE, DEFAULT_USAGE_HALF_LIFE,
+};
 use std::time::{Duration, Instant};
 
 pub struct RoundRobinScheduler {
-    processes: Vec<Process>,
-    current_process: usize,
+    processes: ProcessTable,
+    current_process: Option<ProcessKey>,
     tick_rate: Duration,
     cpu_elapsed: Duration,
+    usage_half_life: Duration,
     last_tick: Instant,
+    /// Set when the current process gave up the rest of its time slice
+    yielded: bool,
+    history: DecisionHistory,
 }
 
 impl RoundRobinScheduler {
@@ -15,66 +22,171 @@ impl RoundRobinScheduler {
     }
 
     pub fn with_processes(processes: Vec<Process>, tick_rate: Duration) -> Self {
+        let processes: ProcessTable = processes.into_iter().collect();
         Self {
+            current_process: processes.next_key(None),
             processes,
-            current_process: 0,
             tick_rate,
             cpu_elapsed: Duration::ZERO,
-            last_tick: Instant::now(),
+            usage_half_life: DEFAULT_USAGE_HALF_LIFE,
+            last_tick: clock::now(),
+            yielded: false,
+            history: DecisionHistory::default(),
         }
     }
 
     fn poll_process(&mut self) {
-        self.current_process += 1;
-        if self.current_process >= self.processes.len() {
-            self.current_process = 0;
+        // Go over the processes in order, starting after the current one, until a runnable one is
+        // found
+        let runnable = self.queue().find(|&key| self.processes[key].is_runnable());
+        if runnable.is_some() {
+            self.current_process = runnable;
         }
     }
+
+    /// The keys of the processes in the order they take turns: the ones after the current
+    /// process, and then the current process at the tail.
+    fn queue(&self) -> impl Iterator<Item = ProcessKey> + '_ {
+        let keys = self.processes.keys();
+        let start = self
+            .processes
+            .next_key(self.current_process)
+            .and_then(|next| keys.iter().position(|&key| key == next))
+            .unwrap_or(0);
+        keys[start..].iter().chain(&keys[..start]).copied()
+    }
+}
+
+impl Default for RoundRobinScheduler {
+    fn default() -> Self {
+        RoundRobinScheduler::new()
+    }
 }
 
 impl Scheduler for RoundRobinScheduler {
     const NAME: &'static str = "Round Robin Scheduler";
 
-    fn processes(&self) -> &Vec<Process> {
+    fn process_table(&self) -> &ProcessTable {
         &self.processes
     }
 
+    fn process_table_mut(&mut self) -> &mut ProcessTable {
+        &mut self.processes
+    }
+
     fn add_process(&mut self, process: Process) {
-        self.processes.push(process);
+        let key = self.processes.insert(process);
+        self.current_process.get_or_insert(key);
     }
 
-    fn remove_process(&mut self, process_name: String) -> Option<Process> {
-        match self
-            .processes
-            .iter()
-            .position(|process| process.name() == process_name)
-        {
-            Some(index) => Some(self.processes.remove(index)),
-            None => None,
+    /// Removing the current process makes the process after it the current one.
+    fn remove_pid(&mut self, pid: u32) -> Option<Process> {
+        let key = self.processes.key(pid)?;
+        if self.current_process == Some(key) {
+            self.current_process = self.processes.next_key(Some(key));
         }
+        let process = self.processes.remove(key);
+        if self.processes.is_empty() {
+            self.current_process = None;
+        }
+        process
     }
 
     fn schedule(&mut self) -> Option<&mut Process> {
-        if self.last_tick.elapsed() > self.tick_rate {
-            self.last_tick = Instant::now();
+        wake_processes(self.processes.iter_mut());
+        let current_blocked = !self.current_process().is_some_and(Process::is_runnable);
+
+        if clock::elapsed(self.last_tick) > self.tick_rate || current_blocked || self.yielded {
+            self.last_tick = clock::now();
             self.poll_process();
+
+            let reason = if current_blocked {
+                DecisionReason::Blocked
+            } else if self.yielded {
+                DecisionReason::Yielded
+            } else {
+                DecisionReason::QuantumExpired
+            };
+            self.yielded = false;
+            let chosen = self.current_process.and_then(|key| self.processes.get(key));
+            self.history.record(
+                reason,
+                self.processes.iter(),
+                chosen.filter(|process| process.is_runnable()),
+            );
         }
         self.current_process_mut()
+            .filter(|process| process.is_runnable())
+    }
+
+    fn yield_current(&mut self) {
+        self.yielded = true;
+    }
+
+    fn history(&self) -> &DecisionHistory {
+        &self.history
+    }
+
+    fn history_mut(&mut self) -> &mut DecisionHistory {
+        &mut self.history
+    }
+
+    fn runqueue(&self) -> Runqueue {
+        let entries = self
+            .queue()
+            .map(|key| &self.processes[key])
+            .filter(|process| process.is_runnable())
+            .map(|process| RunqueueEntry::new(process, None))
+            .collect();
+        Runqueue {
+            kind: RunqueueKind::Fifo,
+            entries,
+        }
     }
 
     fn current_process(&self) -> Option<&Process> {
-        self.processes.get(self.current_process)
+        self.processes.get(self.current_process?)
     }
 
     fn current_process_mut(&mut self) -> Option<&mut Process> {
-        self.processes.get_mut(self.current_process)
+        self.processes.get_mut(self.current_process?)
+    }
+
+    fn set_current_process(&mut self, pid: u32) {
+        if let Some(key) = self.processes.key(pid) {
+            self.current_process = Some(key);
+        }
     }
 
     fn cpu_elapsed(&self) -> Duration {
         self.cpu_elapsed
     }
 
+    fn set_cpu_elapsed(&mut self, cpu_elapsed: Duration) {
+        self.cpu_elapsed = cpu_elapsed;
+    }
+
+    fn tick_rate(&self) -> Duration {
+        self.tick_rate
+    }
+
+    fn set_tick_rate(&mut self, tick_rate: Duration) {
+        self.tick_rate = tick_rate;
+    }
+
+    fn usage_half_life(&self) -> Duration {
+        self.usage_half_life
+    }
+
+    fn set_usage_half_life(&mut self, half_life: Duration) {
+        self.usage_half_life = half_life;
+    }
+
     fn add_cpu_elapsed(&mut self, elapsed: Duration) {
         self.cpu_elapsed += elapsed;
+
+        for process in self.processes.iter_mut() {
+            process.decay_cpu_usage(elapsed, self.usage_half_life);
+        }
     }
 }
@@ -1,12 +1,46 @@
-use std::time::Instant;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
 
-use super::{display::DisplayTerminal, Scheduler};
+use super::{
+    clock,
+    display::{DisplayTerminal, View},
+    keymap::Keymap,
+    snapshot::TickSnapshot,
+    syscall, tree, workload, Command, ControlServer, ExitReport, LatencyHistogram, LoadAverage,
+    LockNarrator, Locks, Process, Scheduler, SchedulerObserver, Sysctl, TaskRegistry, TaskThreads,
+    Theme, Watchdog, Zombies,
+};
 
+const SYSCTL_ROOT: &str = "proc/sys";
+/// How often the processes' %CPU is sampled
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// How many ticks can be stepped back through
+const RECORDED_TICKS: usize = 256;
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum RunnerEvent {
     Quit,
     Pause,
     Resume,
     Step,
+    StepBack,
+    IncreaseDilation,
+    DecreaseDilation,
+    ToggleSleeperBoost,
+    ToggleAutogroup,
+    IncreaseNiceness,
+    DecreaseNiceness,
+    SelectPrevious,
+    SelectNext,
+    ToggleStopped,
+    ToggleTreeView,
+    ScrollHistoryUp,
+    ScrollHistoryDown,
+    ToggleOutput,
+    ToggleExits,
+    ToggleHelp,
     None,
 }
 
@@ -14,45 +48,591 @@ pub struct ProcessRunner<S> {
     terminal: DisplayTerminal,
     scheduler: S,
     paused: bool,
+    view: View,
+    /// The output of the last process that ran
+    output: String,
+    /// Snapshots of the last ticks, oldest first
+    ticks: VecDeque<TickSnapshot>,
+    /// The state from before stepping back, to return to
+    present: Option<TickSnapshot>,
+    /// Wake-to-run latencies with the sleeper boost disabled (0) and enabled (1)
+    latencies: [LatencyHistogram; 2],
+    load_average: LoadAverage,
+    /// When the current %CPU sampling interval started
+    cpu_sampled_at: Instant,
+    sysctl: Sysctl,
+    observers: Vec<Box<dyn SchedulerObserver>>,
+    /// The PID of the last process that ran
+    last_pid: Option<u32>,
+    control: Option<ControlServer>,
+    /// Creates the tasks of processes added through the control socket
+    registry: TaskRegistry,
+    watchdog: Watchdog,
+    /// Set when every task runs on a thread of its own, instead of through the watchdog
+    threads: Option<TaskThreads>,
+    /// The processes that haven't arrived yet, by their arrival time
+    arrivals: Vec<Process>,
+    /// The mutexes the tasks lock, whose protocols boost the processes holding them
+    locks: Locks,
+    /// Notes what the mutexes do in the decision history, when set
+    narrator: Option<LockNarrator>,
+    /// The children that exited, until their parents wait for them
+    zombies: Zombies,
+    exits: ExitReport,
 }
 
 impl<S: Scheduler> ProcessRunner<S> {
     pub fn new(scheduler: S) -> Self {
         let terminal = DisplayTerminal::new().expect("Failed to create a terminal.");
+        let mut sysctl = Sysctl::new(SYSCTL_ROOT).expect("Failed to create the sysctl directory.");
+        sysctl
+            .publish(&scheduler)
+            .expect("Failed to publish the sysctl tunables.");
 
         Self {
             terminal,
             scheduler,
             paused: false,
+            view: View::default(),
+            output: String::new(),
+            ticks: VecDeque::with_capacity(RECORDED_TICKS),
+            present: None,
+            latencies: Default::default(),
+            load_average: LoadAverage::new(),
+            cpu_sampled_at: clock::now(),
+            sysctl,
+            observers: Vec::new(),
+            last_pid: None,
+            control: None,
+            registry: TaskRegistry::new(),
+            watchdog: Watchdog::default(),
+            threads: None,
+            arrivals: Vec::new(),
+            locks: Locks::default(),
+            narrator: None,
+            zombies: Zombies::default(),
+            exits: ExitReport::default(),
+        }
+    }
+
+    /// Executes the commands sent to `server`, creating tasks for new processes with `registry`.
+    pub fn listen(&mut self, server: ControlServer, registry: TaskRegistry) {
+        self.control = Some(server);
+        self.registry = registry;
+    }
+
+    fn execute(&mut self, command: &Command) -> Result<String, String> {
+        match command {
+            Command::Add(line) => {
+                let process = workload::parse_process(line, &self.registry)?;
+                if self.scheduler.process(process.pid()).is_some()
+                    || self
+                        .arrivals
+                        .iter()
+                        .any(|other| other.pid() == process.pid())
+                {
+                    return Err(format!("pid {} is already in use", process.pid()));
+                }
+                self.add_process(process);
+            }
+            Command::Kill(name) => {
+                self.remove_process(name.clone())
+                    .ok_or_else(|| format!("no process named \"{name}\""))?;
+            }
+            Command::Renice { pid, niceness } => {
+                if !self.scheduler.renice(*pid, *niceness) {
+                    return Err(format!("no process with pid {pid}"));
+                }
+            }
+            Command::Dilate { pid, dilation } => {
+                self.scheduler
+                    .process_mut(*pid)
+                    .ok_or_else(|| format!("no process with pid {pid}"))?
+                    .set_time_dilation(*dilation);
+            }
+            Command::Pause => self.paused = true,
+            Command::Resume => self.paused = false,
+            Command::Stats => return Ok(self.stats()),
+        }
+        Ok(String::new())
+    }
+
+    /// Describes the runner and each of its processes as `key=value` lines.
+    fn stats(&self) -> String {
+        let cpu_elapsed = self.scheduler.cpu_elapsed();
+        let recent_cpu_elapsed = self.scheduler.recent_cpu_elapsed();
+
+        let mut lines = vec![format!(
+            "scheduler={} paused={} load=\"{}\"",
+            S::NAME,
+            self.paused,
+            self.load_average
+        )];
+        lines.extend(self.scheduler.processes().map(|process| {
+            format!(
+                "pid={} name=\"{}\" niceness={} dilation={} state={} stopped={} hung={} cpu={} \
+                 recent={} interval_cpu={} cpu_time_ms={} age_ms={}",
+                process.pid(),
+                process.name(),
+                process.niceness(),
+                process.time_dilation(),
+                process.state(),
+                process.is_stopped(),
+                process.is_hung(),
+                process.cpu_usage_percentage(cpu_elapsed),
+                process.recent_cpu_usage_percentage(recent_cpu_elapsed),
+                process.interval_cpu_percentage(),
+                process.cpu_usage().as_millis(),
+                process.age().as_millis(),
+            )
+        }));
+        lines.join("\n")
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.terminal.set_keymap(keymap);
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.terminal.set_theme(theme);
+    }
+
+    pub fn set_watchdog(&mut self, watchdog: Watchdog) {
+        self.watchdog = watchdog;
+    }
+
+    /// Runs every task on a thread of its own, which gets the CPU for `slice` when scheduled.
+    pub fn use_threads(&mut self, slice: Duration) {
+        self.threads = Some(TaskThreads::new(slice));
+    }
+
+    /// Applies the protocols of the mutexes in `locks`, e.g. the lock table of a `TaskRegistry`.
+    pub fn set_locks(&mut self, locks: Locks) {
+        self.locks = locks;
+    }
+
+    /// Notes who takes, waits for and gets boosted by the mutexes in the decision history, to
+    /// explain a demo as it runs.
+    pub fn narrate_locks(&mut self) {
+        self.narrator = Some(LockNarrator::new());
+    }
+
+    /// Tracks the children the tasks wait for in `zombies`, e.g. the table of a `TaskRegistry`.
+    pub fn set_zombies(&mut self, zombies: Zombies) {
+        self.zombies = zombies;
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn SchedulerObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Adds a process to the scheduler, or holds it until its arrival time if that's still ahead.
+    pub fn add_process(&mut self, process: Process) {
+        match process.arrival() {
+            Some(arrival) if arrival > self.scheduler.cpu_elapsed() => {
+                let index = self
+                    .arrivals
+                    .partition_point(|other| other.arrival() <= Some(arrival));
+                self.arrivals.insert(index, process);
+            }
+            _ => self.scheduler.add_process(process),
+        }
+    }
+
+    /// Moves the processes whose arrival time was reached into the scheduler.
+    fn admit_arrivals(&mut self) {
+        let cpu_elapsed = self.scheduler.cpu_elapsed();
+        let arrived = self
+            .arrivals
+            .partition_point(|process| process.arrival() <= Some(cpu_elapsed));
+        for process in self.arrivals.drain(..arrived) {
+            log::info!("Process {} ({}) arrived", process.pid(), process.name());
+            self.scheduler.add_process(process);
+        }
+    }
+
+    /// Adds the processes a task spawned as children of its process, with the next free PIDs.
+    /// Processes over the parent's limit of children are dropped.
+    fn adopt_spawned(&mut self, parent: u32, spawned: Vec<Process>) {
+        let max_children = self
+            .scheduler
+            .process(parent)
+            .and_then(|process| process.limits().children);
+        let children = self
+            .scheduler
+            .processes()
+            .filter(|process| process.parent() == Some(parent))
+            .count();
+        let mut spawned = spawned;
+        if let Some(max_children) = max_children {
+            let allowed = max_children.saturating_sub(children);
+            if spawned.len() > allowed {
+                log::warn!(
+                    "Process {parent} can't have more than {max_children} children, dropped {}",
+                    spawned.len() - allowed
+                );
+                spawned.truncate(allowed);
+            }
+        }
+
+        for mut process in spawned {
+            let pid = self
+                .scheduler
+                .processes()
+                .chain(&self.arrivals)
+                .map(|process| process.pid() + 1)
+                .max()
+                .unwrap_or(0);
+            process.set_pid(pid);
+            process.set_parent(Some(parent));
+            self.scheduler.add_process(process);
         }
     }
 
+    /// Removes a process from the scheduler, and lets the observers know it exited.
+    ///
+    /// The process' children are adopted by its own parent, or become roots if it has none.
+    pub fn remove_process(&mut self, process_name: String) -> Option<Process> {
+        let process = self.scheduler.remove_process(process_name)?;
+        self.forget(&process);
+        Some(process)
+    }
+
+    /// Cleans up after a process that left the scheduler.
+    fn forget(&mut self, process: &Process) {
+        if let Some(threads) = &mut self.threads {
+            threads.remove(process.pid());
+        }
+        self.locks.release_all(process.pid());
+        for child in self.scheduler.processes_mut() {
+            if child.parent() == Some(process.pid()) {
+                child.set_parent(process.parent());
+            }
+        }
+        for observer in &mut self.observers {
+            observer.on_exit(process);
+        }
+    }
+
+    /// The processes whose tasks finished.
+    pub fn exits(&self) -> &ExitReport {
+        &self.exits
+    }
+
     fn run_process(&mut self) -> String {
-        if let Some(process) = self.scheduler.schedule() {
-            let start_time = Instant::now();
-            let output = process.run();
-            self.scheduler.add_cpu_elapsed(start_time.elapsed());
-            output
+        let sleeper_boost = self.scheduler.sleeper_boost();
+        self.zombies.track(self.scheduler.processes());
+        let Some(pid) = self.scheduler.schedule().map(|process| process.pid()) else {
+            return String::new();
+        };
+
+        // The last process was preempted if it could have kept running
+        if self.last_pid.is_some_and(|last_pid| last_pid != pid) {
+            let previous = self
+                .scheduler
+                .processes()
+                .find(|process| Some(process.pid()) == self.last_pid);
+            if let Some(previous) = previous.filter(|previous| previous.is_runnable()) {
+                for observer in &mut self.observers {
+                    observer.on_preempt(previous);
+                }
+            }
+        }
+        self.last_pid = Some(pid);
+
+        let process = self
+            .scheduler
+            .current_process_mut()
+            .expect("Failed to get the scheduled process.");
+        for observer in &mut self.observers {
+            observer.on_schedule(process);
+        }
+
+        if let Some(latency) = process.wake_latency() {
+            self.latencies[usize::from(sleeper_boost)].record(latency);
+        }
+
+        let start_time = clock::now();
+        let output = match &mut self.threads {
+            Some(threads) => threads.run(process),
+            None => self.watchdog.run(process),
+        };
+        let elapsed = process.dilate(clock::elapsed(start_time));
+
+        // Service the system calls the task made, as the kernel
+        let spawned = syscall::service(&mut self.scheduler, pid);
+        let process = self
+            .scheduler
+            .process_mut(pid)
+            .expect("Failed to get the process that ran.");
+
+        if process.is_hung() {
+            log::warn!(
+                "Process {} ({}) went over the watchdog's budget of {:?}",
+                pid,
+                process.name(),
+                self.watchdog.budget()
+            );
+        }
+
+        if !process.is_runnable() {
+            for observer in &mut self.observers {
+                observer.on_block(process);
+            }
+        }
+
+        if process.enforce_cpu_limit() {
+            log::warn!(
+                "Process {pid} ({}) went over its CPU time limit and got SIGXCPU",
+                process.name()
+            );
+        }
+
+        let exit_code = process.exit_code();
+        self.scheduler.add_cpu_elapsed(elapsed);
+        self.adopt_spawned(pid, spawned);
+
+        if let Some(exit_code) = exit_code {
+            let process = self
+                .scheduler
+                .remove_pid(pid)
+                .expect("Failed to remove the exited process.");
+            log::info!(
+                "Process {pid} ({}) exited with code {exit_code}",
+                process.name()
+            );
+            self.forget(&process);
+            self.zombies.exit(&process, exit_code);
+            self.exits
+                .record(&process, exit_code, self.scheduler.cpu_elapsed());
+        }
+        self.locks.apply(self.scheduler.processes_mut());
+        if let Some(narrator) = &mut self.narrator {
+            for note in narrator.narrate(&self.locks, &self.scheduler) {
+                self.scheduler.history_mut().annotate(note);
+            }
+        }
+
+        if self.ticks.len() == RECORDED_TICKS {
+            self.ticks.pop_front();
+        }
+        let snapshot = TickSnapshot::capture(
+            &self.scheduler,
+            output.clone(),
+            &self.latencies,
+            &self.load_average,
+        );
+        self.ticks.push_back(snapshot);
+        output
+    }
+
+    fn restore(&mut self, snapshot: TickSnapshot) {
+        snapshot.restore(&mut self.scheduler);
+        self.output = snapshot.output;
+        self.latencies = snapshot.latencies;
+        self.load_average = snapshot.load_average;
+    }
+
+    /// Goes back one recorded tick, remembering the present when leaving it.
+    fn step_back(&mut self) {
+        if self.view.rewound + 1 >= self.ticks.len() {
+            return;
+        }
+        if self.view.rewound == 0 {
+            self.present = Some(TickSnapshot::capture(
+                &self.scheduler,
+                self.output.clone(),
+                &self.latencies,
+                &self.load_average,
+            ));
+        }
+
+        self.view.rewound += 1;
+        let snapshot = self.ticks[self.ticks.len() - 1 - self.view.rewound].clone();
+        self.restore(snapshot);
+    }
+
+    /// Goes forward one recorded tick, or all the way back to the present.
+    fn step_forward(&mut self, to_present: bool) {
+        if self.view.rewound == 0 {
+            return;
+        }
+
+        self.view.rewound = if to_present { 0 } else { self.view.rewound - 1 };
+        let snapshot = match self.view.rewound {
+            0 => self.present.take(),
+            rewound => self.ticks.get(self.ticks.len() - 1 - rewound).cloned(),
+        };
+        if let Some(snapshot) = snapshot {
+            self.restore(snapshot);
+        }
+    }
+
+    /// The index of the process in the selected row, which depends on the table's order.
+    fn selected_index(&self) -> usize {
+        if self.view.tree_view {
+            let processes: Vec<&Process> = self.scheduler.processes().collect();
+            tree::tree_rows(&processes)
+                .get(self.view.selected)
+                .map_or(self.view.selected, |row| row.index)
         } else {
-            String::new()
+            self.view.selected
+        }
+    }
+
+    /// The process in the selected row.
+    fn selected_process(&self) -> Option<&Process> {
+        self.scheduler.processes().nth(self.selected_index())
+    }
+
+    /// Renices the selected process by `delta` levels, stopping at the ends of the niceness range.
+    fn change_niceness(&mut self, delta: i8) {
+        let selected = self
+            .selected_process()
+            .map(|process| (process.pid(), process.niceness().saturating_add(delta)));
+        if let Some((pid, niceness)) = selected {
+            self.scheduler.renice(pid, niceness);
+        }
+    }
+
+    fn change_dilation(&mut self, change: impl Fn(u32) -> u32) {
+        if let Some(process) = self.scheduler.current_process_mut() {
+            process.set_time_dilation(change(process.time_dilation()));
         }
     }
 
     // Returns false if the program should quit
     pub fn run(&mut self) -> bool {
-        let process_output = if !self.paused {
-            self.run_process()
-        } else {
-            String::new()
-        };
-        self.terminal.draw(&self.scheduler, process_output);
+        // Pick up tunables changed from the outside, and publish the ones changed from the inside
+        self.sysctl.apply(&mut self.scheduler);
+        self.sysctl
+            .publish(&self.scheduler)
+            .expect("Failed to publish the sysctl tunables.");
+
+        // Give hung tasks that finished back to their processes
+        for (pid, task, output) in self.watchdog.reclaim() {
+            if let Some(process) = self.scheduler.process_mut(pid) {
+                process.reattach(task, output);
+                log::info!("Process {pid} ({}) recovered", process.name());
+            }
+        }
+
+        // Execute the commands sent over the control socket
+        let requests: Vec<_> = self
+            .control
+            .iter()
+            .flat_map(ControlServer::requests)
+            .collect();
+        for request in requests {
+            let result = self.execute(&request.command);
+            request.reply(result);
+        }
+
+        if !self.paused {
+            self.admit_arrivals();
+            self.output = self.run_process();
+        }
+
+        // The past's load average stays as it was
+        if self.view.rewound == 0 {
+            let runnable = self
+                .scheduler
+                .processes()
+                .filter(|process| process.is_runnable())
+                .count();
+            self.load_average.sample(runnable);
+
+            let interval = clock::elapsed(self.cpu_sampled_at);
+            if interval >= CPU_SAMPLE_INTERVAL {
+                self.cpu_sampled_at = clock::now();
+                for process in self.scheduler.processes_mut() {
+                    process.sample_cpu_usage(interval);
+                }
+            }
+        }
+
+        // Keep the selection inside the table, in case processes were removed
+        self.view.selected = self
+            .view
+            .selected
+            .min(self.scheduler.processes().len().saturating_sub(1));
+        self.view.history_scroll = self
+            .view
+            .history_scroll
+            .min(self.scheduler.history().len().saturating_sub(1));
+        let outputs = self
+            .selected_process()
+            .map_or(0, |process| process.outputs().len());
+        self.view.output_scroll = self.view.output_scroll.min(outputs.saturating_sub(1));
+
+        self.terminal.draw(
+            &self.scheduler,
+            self.output.clone(),
+            &self.view,
+            &self.latencies,
+            &self.load_average,
+            &self.exits,
+        );
 
         match self.terminal.get_input() {
             RunnerEvent::Quit => return false,
             RunnerEvent::Pause if !self.paused => self.paused = true,
-            RunnerEvent::Resume if self.paused => self.paused = false,
+            RunnerEvent::Resume if self.paused => {
+                self.step_forward(true);
+                self.paused = false;
+            }
+            RunnerEvent::Step if self.paused && self.view.rewound > 0 => self.step_forward(false),
             RunnerEvent::Step if self.paused => {
-                self.run_process();
+                self.admit_arrivals();
+                self.output = self.run_process();
+            }
+            RunnerEvent::StepBack if self.paused => self.step_back(),
+            RunnerEvent::IncreaseDilation => self.change_dilation(|dilation| dilation * 2),
+            RunnerEvent::DecreaseDilation => self.change_dilation(|dilation| dilation / 2),
+            RunnerEvent::ToggleSleeperBoost => {
+                let enabled = self.scheduler.sleeper_boost();
+                self.scheduler.set_sleeper_boost(!enabled);
+            }
+            RunnerEvent::ToggleAutogroup => {
+                let enabled = self.scheduler.autogroup();
+                self.scheduler.set_autogroup(!enabled);
+            }
+            RunnerEvent::IncreaseNiceness => self.change_niceness(1),
+            RunnerEvent::DecreaseNiceness => self.change_niceness(-1),
+            RunnerEvent::SelectPrevious => {
+                self.view.selected = self.view.selected.saturating_sub(1);
+                self.view.output_scroll = 0;
+            }
+            RunnerEvent::SelectNext => {
+                self.view.selected += 1;
+                self.view.output_scroll = 0;
+            }
+            RunnerEvent::ToggleStopped => {
+                let pid = self.selected_process().map(Process::pid);
+                // Continue hung processes, as long as their task came back
+                if let Some(process) = pid.and_then(|pid| self.scheduler.process_mut(pid)) {
+                    if process.is_hung() {
+                        process.set_hung(false);
+                    } else {
+                        process.set_stopped(!process.is_stopped());
+                    }
+                }
+            }
+            RunnerEvent::ToggleTreeView => self.view.tree_view = !self.view.tree_view,
+            RunnerEvent::ToggleOutput => {
+                self.view.show_output = !self.view.show_output;
+                self.view.output_scroll = 0;
+            }
+            RunnerEvent::ToggleExits => self.view.show_exits = !self.view.show_exits,
+            RunnerEvent::ToggleHelp => self.view.show_help = !self.view.show_help,
+            // The output popup takes over the scrolling while it's open
+            RunnerEvent::ScrollHistoryUp if self.view.show_output => self.view.output_scroll += 1,
+            RunnerEvent::ScrollHistoryDown if self.view.show_output => {
+                self.view.output_scroll = self.view.output_scroll.saturating_sub(1)
+            }
+            RunnerEvent::ScrollHistoryUp => self.view.history_scroll += 1,
+            RunnerEvent::ScrollHistoryDown => {
+                self.view.history_scroll = self.view.history_scroll.saturating_sub(1)
             }
             _ => {}
         }
@@ -0,0 +1,90 @@
+//! The runnable processes the way a scheduler keeps them, to show its internal state.
+
+use super::Process;
+
+/// How a scheduler keeps its runnable processes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunqueueKind {
+    /// A first-in first-out queue, where the process at the head runs next and the process that
+    /// used up its quantum goes to the tail
+    Fifo,
+    /// Sorted by a key, where the process with the least key runs next, like the red-black tree
+    /// that CFS keeps sorted by virtual runtime
+    Sorted,
+}
+
+#[derive(Clone, Debug)]
+pub struct RunqueueEntry {
+    pub pid: u32,
+    pub name: String,
+    /// What the scheduler sorts the processes by, if anything
+    pub key: Option<i64>,
+}
+
+impl RunqueueEntry {
+    pub fn new(process: &Process, key: Option<i64>) -> Self {
+        Self {
+            pid: process.pid(),
+            name: process.name(),
+            key,
+        }
+    }
+}
+
+/// The runnable processes in the order the scheduler would run them.
+#[derive(Clone, Debug)]
+pub struct Runqueue {
+    pub kind: RunqueueKind,
+    pub entries: Vec<RunqueueEntry>,
+}
+
+impl Runqueue {
+    /// The lines that draw the runqueue, the process that runs next first.
+    ///
+    /// A FIFO queue is drawn from its head to its tail. A sorted runqueue is drawn as a balanced
+    /// search tree lying on its side, indented by depth, so reading it from the top is the tree's
+    /// in-order walk and the leftmost node is at the top.
+    pub fn lines(&self) -> Vec<String> {
+        let depths = match self.kind {
+            RunqueueKind::Fifo => vec![0; self.entries.len()],
+            RunqueueKind::Sorted => tree_depths(self.entries.len()),
+        };
+
+        self.entries
+            .iter()
+            .zip(depths)
+            .enumerate()
+            .map(|(position, (entry, depth))| {
+                let marker = match (self.kind, position) {
+                    (_, 0) => "next",
+                    (RunqueueKind::Fifo, position) if position + 1 == self.entries.len() => "tail",
+                    _ => "",
+                };
+                let key = entry.key.map(|key| format!(" ({key})")).unwrap_or_default();
+                format!(
+                    "{marker:>4} {}{} {}{key}",
+                    "  ".repeat(depth),
+                    entry.pid,
+                    entry.name
+                )
+            })
+            .collect()
+    }
+}
+
+/// The depth of every position in a balanced search tree over `count` sorted keys, where the
+/// middle key of every range is the root of its subtree.
+fn tree_depths(count: usize) -> Vec<usize> {
+    let mut depths = vec![0; count];
+    let mut pending = vec![(0, count, 0)];
+    while let Some((start, end, depth)) = pending.pop() {
+        if start >= end {
+            continue;
+        }
+        let middle = (start + end) / 2;
+        depths[middle] = depth;
+        pending.push((start, middle, depth + 1));
+        pending.push((middle + 1, end, depth + 1));
+    }
+    depths
+}
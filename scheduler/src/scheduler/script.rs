@@ -0,0 +1,441 @@
+use super::{
+    tasks::{compute, Task},
+    Locks, Niceness, Process, Syscall, WaitStatus, WaitTarget, Zombies,
+};
+use std::{error::Error, fmt, time::Duration};
+
+/// A single statement of a task script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    Compute(Duration),
+    Sleep(Duration),
+    Print(String),
+    Lock(String),
+    Unlock(String),
+    Wait(WaitTarget),
+    /// Waits on the futex at the address while its word holds the value
+    FutexWait(u64, i64),
+    /// Wakes up to the given number of processes waiting on the futex at the address
+    FutexWake(u64, usize),
+    /// Sets the futex word at the address
+    FutexSet(u64, i64),
+    /// Sends a message to the process with the PID
+    Send(u32, String),
+    Recv,
+    Yield,
+    Loop,
+    Exit(i32),
+}
+
+#[derive(Debug)]
+pub struct ScriptError {
+    statement: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid statement \"{}\": {}",
+            self.statement, self.reason
+        )
+    }
+}
+
+impl Error for ScriptError {}
+
+/// A task whose behavior is described by a small script, e.g.
+/// `compute 5ms; sleep 20ms; print "x"; loop`.
+///
+/// Every run executes statements until it computes or sleeps once. A script without a `loop`
+/// only runs once, and then exits with code 0, unless it exits earlier with `exit <code>`.
+/// `lock <mutex>` waits until the mutex is free, and `wait [pid]` waits until a child (or the one
+/// with that PID) exits, printing its exit code. `futex wait <address> <value>`, `futex wake
+/// <address> [count]` and `futex set <address> <value>` use the futexes the mutexes are built on.
+/// `send <pid> "text"` sends a message to another process, `recv` waits for one and prints it,
+/// and `yield` gives up the rest of the time slice.
+pub struct ScriptedTask {
+    statements: Vec<Statement>,
+    next: usize,
+    syscalls: Vec<Syscall>,
+    /// The message the last `recv` received, printed by the next run
+    received: Option<String>,
+    locks: Locks,
+    pid: u32,
+    /// The mutex the task waits for
+    waiting_for: Option<String>,
+    /// The address of the futex the task waits on
+    futex_wait: Option<u64>,
+    zombies: Zombies,
+    /// The children the task waits for
+    waiting_on: Option<WaitTarget>,
+    exited: bool,
+}
+
+impl ScriptedTask {
+    const FOREVER: Duration = Duration::from_secs(60 * 60 * 24 * 365);
+
+    pub fn new(statements: Vec<Statement>) -> Self {
+        Self {
+            statements,
+            next: 0,
+            syscalls: Vec::new(),
+            received: None,
+            locks: Locks::default(),
+            pid: 0,
+            waiting_for: None,
+            futex_wait: None,
+            zombies: Zombies::default(),
+            waiting_on: None,
+            exited: false,
+        }
+    }
+
+    /// Uses the mutexes of `locks`, instead of mutexes of its own.
+    pub fn with_locks(self, locks: Locks) -> Self {
+        Self { locks, ..self }
+    }
+
+    /// Waits for the children tracked by `zombies`, instead of a table of its own.
+    pub fn with_zombies(self, zombies: Zombies) -> Self {
+        Self { zombies, ..self }
+    }
+
+    pub fn parse(script: &str) -> Result<Self, ScriptError> {
+        let statements = split_statements(script)
+            .into_iter()
+            .map(parse_statement)
+            .collect::<Result<_, _>>()?;
+        Ok(ScriptedTask::new(statements))
+    }
+}
+
+impl Task for ScriptedTask {
+    fn run(&mut self) -> String {
+        let mut output = self
+            .received
+            .take()
+            .map_or_else(String::new, |message| format!("Received \"{message}\""));
+        let mut looped = false;
+        if self.exited {
+            return output;
+        }
+
+        loop {
+            match self.statements.get(self.next) {
+                Some(Statement::Compute(duration)) => {
+                    compute(*duration);
+                    self.next += 1;
+                    return output;
+                }
+                Some(Statement::Sleep(duration)) => {
+                    self.syscalls.push(Syscall::Sleep(*duration));
+                    self.next += 1;
+                    return output;
+                }
+                Some(Statement::Print(text)) => {
+                    output = text.clone();
+                    self.next += 1;
+                }
+                Some(Statement::Lock(mutex)) => {
+                    if !self.locks.try_lock(mutex, self.pid) {
+                        self.waiting_for = Some(mutex.clone());
+                        return output;
+                    }
+                    self.waiting_for = None;
+                    self.next += 1;
+                }
+                Some(Statement::Unlock(mutex)) => {
+                    self.locks.unlock(mutex, self.pid);
+                    self.next += 1;
+                }
+                Some(&Statement::FutexWait(address, expected)) => {
+                    self.next += 1;
+                    if self.locks.futexes().wait(address, expected, self.pid) {
+                        self.futex_wait = Some(address);
+                        return output;
+                    }
+                }
+                Some(&Statement::FutexWake(address, count)) => {
+                    let woken = self.locks.futexes().wake(address, count);
+                    output = format!("Woke {} process(es)", woken.len());
+                    self.next += 1;
+                }
+                Some(&Statement::FutexSet(address, value)) => {
+                    self.locks.futexes().store(address, value);
+                    self.next += 1;
+                }
+                Some(&Statement::Wait(target)) => {
+                    match self.zombies.wait(self.pid, target) {
+                        WaitStatus::Running => {
+                            self.waiting_on = Some(target);
+                            return output;
+                        }
+                        WaitStatus::Exited(pid, exit_code) => {
+                            output = format!("Child {pid} exited with code {exit_code}");
+                        }
+                        WaitStatus::NoChildren => output = "No child to wait for".to_owned(),
+                    }
+                    self.waiting_on = None;
+                    self.next += 1;
+                }
+                Some(Statement::Send(pid, message)) => {
+                    self.syscalls.push(Syscall::Send {
+                        to: *pid,
+                        message: message.clone(),
+                    });
+                    self.next += 1;
+                }
+                Some(Statement::Recv) => {
+                    self.syscalls.push(Syscall::Recv);
+                    self.next += 1;
+                    return output;
+                }
+                Some(Statement::Yield) => {
+                    self.syscalls.push(Syscall::Yield);
+                    self.next += 1;
+                    return output;
+                }
+                Some(Statement::Loop) => {
+                    // Don't spin forever on a script that never computes or sleeps
+                    if looped {
+                        return output;
+                    }
+                    looped = true;
+                    self.next = 0;
+                }
+                Some(Statement::Exit(code)) => {
+                    self.exited = true;
+                    self.syscalls.push(Syscall::Exit(*code));
+                    return output;
+                }
+                None => {
+                    self.exited = true;
+                    self.syscalls.push(Syscall::Exit(0));
+                    return output;
+                }
+            }
+        }
+    }
+
+    fn syscalls(&mut self) -> Vec<Syscall> {
+        std::mem::take(&mut self.syscalls)
+    }
+
+    fn received(&mut self, message: String) {
+        self.received = Some(message);
+    }
+
+    fn is_waiting(&self) -> bool {
+        self.waiting_for
+            .as_ref()
+            .is_some_and(|mutex| self.locks.is_waiting(mutex, self.pid))
+            || self
+                .futex_wait
+                .is_some_and(|address| self.locks.futexes().is_queued(address, self.pid))
+            || self
+                .waiting_on
+                .is_some_and(|target| self.zombies.would_block(self.pid, target))
+    }
+
+    fn waits_on(&self) -> Option<WaitTarget> {
+        self.waiting_on
+    }
+
+    fn set_pid(&mut self, pid: u32) {
+        self.pid = pid;
+    }
+}
+
+/// Spawns processes with random scripts and niceness, every `interval` on average.
+///
+/// The spawned processes compute for a few milliseconds and then sleep for a while, in a loop.
+/// The generator stops after spawning `max` of them.
+pub struct GeneratorTask {
+    interval: Duration,
+    max: usize,
+    generated: usize,
+    /// The state of a xorshift generator, which is never 0
+    random: u64,
+    syscalls: Vec<Syscall>,
+}
+
+impl GeneratorTask {
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+    pub const DEFAULT_MAX: usize = 8;
+
+    pub fn new(interval: Duration, max: usize, seed: u64) -> Self {
+        Self {
+            interval,
+            max,
+            generated: 0,
+            random: seed.max(1),
+            syscalls: Vec::new(),
+        }
+    }
+
+    /// A random number in `range`.
+    fn random(&mut self, range: std::ops::RangeInclusive<u64>) -> u64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        range.start() + self.random % (range.end() - range.start() + 1)
+    }
+}
+
+impl Task for GeneratorTask {
+    fn run(&mut self) -> String {
+        if self.generated == self.max {
+            self.syscalls.push(Syscall::Sleep(ScriptedTask::FOREVER));
+            return format!("Spawned all {} processes", self.max);
+        }
+        self.generated += 1;
+
+        let statements = vec![
+            Statement::Compute(Duration::from_millis(self.random(1..=10))),
+            Statement::Sleep(Duration::from_millis(self.random(0..=200))),
+            Statement::Loop,
+        ];
+        let niceness = Niceness::clamped(self.random(0..=15) as i8 - 5);
+        let name = format!("Generated {}", self.generated);
+
+        let output = format!("Spawned \"{name}\" with niceness {niceness}");
+        self.syscalls
+            .push(Syscall::Spawn(Box::new(Process::with_niceness(
+                0,
+                &name,
+                Box::new(ScriptedTask::new(statements)),
+                niceness,
+            ))));
+
+        // Wait anywhere between nothing and twice the interval, for an average of the interval
+        let interval = self.interval.as_millis() as u64;
+        let sleep = Duration::from_millis(self.random(0..=interval * 2));
+        self.syscalls.push(Syscall::Sleep(sleep));
+        output
+    }
+
+    fn syscalls(&mut self) -> Vec<Syscall> {
+        std::mem::take(&mut self.syscalls)
+    }
+}
+
+/// Splits a script into statements on semicolons and new lines, except inside quotes.
+fn split_statements(script: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+
+    for (index, c) in script.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' | '\n' if !quoted => {
+                statements.push(&script[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    statements.push(&script[start..]);
+
+    statements
+        .into_iter()
+        .map(str::trim)
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+fn parse_statement(statement: &str) -> Result<Statement, ScriptError> {
+    let error = |reason| ScriptError {
+        statement: statement.to_owned(),
+        reason,
+    };
+
+    let (command, argument) = match statement.split_once(char::is_whitespace) {
+        Some((command, argument)) => (command, argument.trim()),
+        None => (statement, ""),
+    };
+
+    match command {
+        "compute" => Ok(Statement::Compute(
+            parse_duration(argument).ok_or_else(|| error("expected a duration"))?,
+        )),
+        "sleep" => Ok(Statement::Sleep(
+            parse_duration(argument).ok_or_else(|| error("expected a duration"))?,
+        )),
+        "print" => argument
+            .strip_prefix('"')
+            .and_then(|text| text.strip_suffix('"'))
+            .map(|text| Statement::Print(text.to_owned()))
+            .ok_or_else(|| error("expected a quoted string")),
+        "lock" if !argument.is_empty() => Ok(Statement::Lock(argument.to_owned())),
+        "unlock" if !argument.is_empty() => Ok(Statement::Unlock(argument.to_owned())),
+        "lock" | "unlock" => Err(error("expected a mutex")),
+        "wait" if argument.is_empty() => Ok(Statement::Wait(WaitTarget::Any)),
+        "wait" => argument
+            .parse()
+            .map(|pid| Statement::Wait(WaitTarget::Child(pid)))
+            .map_err(|_| error("expected a PID")),
+        "exit" if argument.is_empty() => Ok(Statement::Exit(0)),
+        "exit" => argument
+            .parse()
+            .map(Statement::Exit)
+            .map_err(|_| error("expected an exit code")),
+        "send" => parse_send(argument).ok_or_else(|| error("expected a PID and a quoted string")),
+        "recv" if argument.is_empty() => Ok(Statement::Recv),
+        "yield" if argument.is_empty() => Ok(Statement::Yield),
+        "recv" | "yield" => Err(error("doesn't take an argument")),
+        "futex" => parse_futex(argument).ok_or_else(|| {
+            error("expected `wait <address> <value>`, `wake <address> [count]` or `set <address> <value>`")
+        }),
+        "loop" if argument.is_empty() => Ok(Statement::Loop),
+        "loop" => Err(error("loop doesn't take an argument")),
+        _ => Err(error("unknown command")),
+    }
+}
+
+/// Parses the arguments of a `send` statement, like `3 "hello"`.
+fn parse_send(argument: &str) -> Option<Statement> {
+    let (pid, message) = argument.split_once(char::is_whitespace)?;
+    let message = message.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some(Statement::Send(pid.parse().ok()?, message.to_owned()))
+}
+
+/// Parses the arguments of a `futex` statement. Addresses are decimal or hex like `0x10`.
+fn parse_futex(argument: &str) -> Option<Statement> {
+    let parse_address = |address: &str| match address.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => address.parse().ok(),
+    };
+
+    match argument.split_whitespace().collect::<Vec<_>>()[..] {
+        ["wait", address, value] => Some(Statement::FutexWait(
+            parse_address(address)?,
+            value.parse().ok()?,
+        )),
+        ["wake", address] => Some(Statement::FutexWake(parse_address(address)?, 1)),
+        ["wake", address, count] => Some(Statement::FutexWake(
+            parse_address(address)?,
+            count.parse().ok()?,
+        )),
+        ["set", address, value] => Some(Statement::FutexSet(
+            parse_address(address)?,
+            value.parse().ok()?,
+        )),
+        _ => None,
+    }
+}
+
+/// Parses durations like `500us`, `5ms` or `2s`.
+pub(super) fn parse_duration(text: &str) -> Option<Duration> {
+    let unit_start = text.find(|c: char| !c.is_ascii_digit())?;
+    let value = text[..unit_start].parse().ok()?;
+
+    match &text[unit_start..] {
+        "us" => Some(Duration::from_micros(value)),
+        "ms" => Some(Duration::from_millis(value)),
+        "s" => Some(Duration::from_secs(value)),
+        _ => None,
+    }
+}
@@ -0,0 +1,54 @@
+use super::{process::ProcessCounters, LatencyHistogram, LoadAverage, Scheduler};
+use std::time::Duration;
+
+/// Everything the runner shows after a tick, so it can go back to it later.
+///
+/// Tasks can't be rewound, so only the counters are recorded, not what the tasks were doing.
+#[derive(Clone)]
+pub struct TickSnapshot {
+    cpu_elapsed: Duration,
+    current_pid: Option<u32>,
+    processes: Vec<ProcessCounters>,
+    pub output: String,
+    pub latencies: [LatencyHistogram; 2],
+    pub load_average: LoadAverage,
+}
+
+impl TickSnapshot {
+    pub fn capture<S: Scheduler>(
+        scheduler: &S,
+        output: String,
+        latencies: &[LatencyHistogram; 2],
+        load_average: &LoadAverage,
+    ) -> Self {
+        Self {
+            cpu_elapsed: scheduler.cpu_elapsed(),
+            current_pid: scheduler.current_process().map(|process| process.pid()),
+            processes: scheduler
+                .processes()
+                .map(|process| process.counters())
+                .collect(),
+            output,
+            latencies: latencies.clone(),
+            load_average: load_average.clone(),
+        }
+    }
+
+    /// Restores the scheduler's counters. Processes that didn't exist at the time are left as is.
+    pub fn restore<S: Scheduler>(&self, scheduler: &mut S) {
+        scheduler.set_cpu_elapsed(self.cpu_elapsed);
+        if let Some(pid) = self.current_pid {
+            scheduler.set_current_process(pid);
+        }
+
+        for process in scheduler.processes_mut() {
+            if let Some(counters) = self
+                .processes
+                .iter()
+                .find(|counters| counters.pid() == process.pid())
+            {
+                process.restore_counters(counters);
+            }
+        }
+    }
+}
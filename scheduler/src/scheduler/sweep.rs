@@ -0,0 +1,159 @@
+//! Sweeps the quantum (the schedulers' tick rate) over headless runs of a workload, to show the
+//! trade-off between throughput and responsiveness.
+
+use super::{
+    clock, syscall,
+    testing::{MockClock, IDLE_STEP},
+    Scheduler,
+};
+use std::{fmt::Write, time::Duration};
+
+/// How a workload did with one quantum.
+#[derive(Clone, Debug)]
+pub struct SweepPoint {
+    pub quantum: Duration,
+    /// The fraction of the run spent running tasks, rather than switching between them or idling
+    pub throughput: f64,
+    pub context_switches: u64,
+    pub mean_latency: Duration,
+    pub max_latency: Duration,
+}
+
+/// Runs a workload once for every quantum, on a mock clock, and measures how it did.
+///
+/// Every context switch costs `switch_cost` of CPU time which no task gets, so short quanta waste
+/// more of the CPU, while long quanta make processes that wake up wait longer for their turn.
+///
+/// The workload's tasks must take mock time when they run, like `FixedTask`.
+pub struct QuantumSweep {
+    quanta: Vec<Duration>,
+    duration: Duration,
+    switch_cost: Duration,
+}
+
+impl QuantumSweep {
+    pub const DEFAULT_QUANTA: [Duration; 7] = [
+        Duration::from_millis(1),
+        Duration::from_millis(2),
+        Duration::from_millis(5),
+        Duration::from_millis(10),
+        Duration::from_millis(20),
+        Duration::from_millis(50),
+        Duration::from_millis(100),
+    ];
+    pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+    pub const DEFAULT_SWITCH_COST: Duration = Duration::from_micros(500);
+
+    /// A sweep over `quanta`, with every run taking `duration` of mock time.
+    pub fn new(quanta: Vec<Duration>, duration: Duration, switch_cost: Duration) -> Self {
+        Self {
+            quanta,
+            duration,
+            switch_cost,
+        }
+    }
+
+    /// Runs the scheduler that `scheduler` creates with every quantum.
+    pub fn run<S: Scheduler>(&self, mut scheduler: impl FnMut(Duration) -> S) -> Vec<SweepPoint> {
+        self.quanta
+            .iter()
+            .map(|&quantum| self.run_once(quantum, &mut scheduler))
+            .collect()
+    }
+
+    fn run_once<S: Scheduler>(
+        &self,
+        quantum: Duration,
+        scheduler: &mut impl FnMut(Duration) -> S,
+    ) -> SweepPoint {
+        // The scheduler is created after the clock, so it starts at the mock time
+        let clock = MockClock::install();
+        let mut scheduler = scheduler(quantum);
+
+        let mut busy = Duration::ZERO;
+        let mut context_switches = 0;
+        let mut previous = None;
+        let (mut latencies, mut total_latency, mut max_latency) =
+            (0, Duration::ZERO, Duration::ZERO);
+        while clock.elapsed() < self.duration {
+            let Some(process) = scheduler.schedule() else {
+                clock.advance(IDLE_STEP);
+                continue;
+            };
+
+            if previous != Some(process.pid()) {
+                previous = Some(process.pid());
+                context_switches += 1;
+                clock.advance(self.switch_cost);
+            }
+            if let Some(latency) = process.wake_latency() {
+                latencies += 1;
+                total_latency += latency;
+                max_latency = max_latency.max(latency);
+            }
+
+            let pid = process.pid();
+            let start_time = clock::now();
+            process.run();
+            let elapsed = clock::elapsed(start_time);
+            busy += elapsed;
+            let elapsed = process.dilate(elapsed);
+            scheduler.add_cpu_elapsed(elapsed);
+            syscall::service(&mut scheduler, pid);
+        }
+
+        SweepPoint {
+            quantum,
+            throughput: busy.as_secs_f64() / clock.elapsed().as_secs_f64(),
+            context_switches,
+            mean_latency: total_latency.checked_div(latencies).unwrap_or_default(),
+            max_latency,
+        }
+    }
+}
+
+impl Default for QuantumSweep {
+    fn default() -> Self {
+        QuantumSweep::new(
+            QuantumSweep::DEFAULT_QUANTA.to_vec(),
+            QuantumSweep::DEFAULT_DURATION,
+            QuantumSweep::DEFAULT_SWITCH_COST,
+        )
+    }
+}
+
+/// The width of the longest bar in a report.
+const BAR_WIDTH: usize = 30;
+
+/// A table of the sweep's results, with bars that draw the throughput and mean latency curves.
+pub fn report(points: &[SweepPoint]) -> String {
+    // Latencies are drawn against the longest quantum too, so tiny latencies get tiny bars
+    let longest_latency = points
+        .iter()
+        .flat_map(|point| [point.mean_latency, point.quantum])
+        .max()
+        .unwrap_or_default()
+        .max(Duration::from_nanos(1));
+    let bar = |fraction: f64| "#".repeat((fraction * BAR_WIDTH as f64).round() as usize);
+
+    let mut report = format!(
+        "{:>8} {:>10} {:>9} {:>9} {:>9}  {:<BAR_WIDTH$}  Mean latency\n",
+        "Quantum", "Throughput", "Switches", "Mean", "Max", "Throughput"
+    );
+    for point in points {
+        let latency = point.mean_latency.as_secs_f64() / longest_latency.as_secs_f64();
+        writeln!(
+            report,
+            "{:>6}ms {:>9.1}% {:>9} {:>7.2}ms {:>7.2}ms  {:<BAR_WIDTH$}  {}",
+            point.quantum.as_millis(),
+            point.throughput * 100.0,
+            point.context_switches,
+            point.mean_latency.as_secs_f64() * 1000.0,
+            point.max_latency.as_secs_f64() * 1000.0,
+            bar(point.throughput),
+            bar(latency),
+        )
+        .expect("Failed to write the report.");
+    }
+    report
+}
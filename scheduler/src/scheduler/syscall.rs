@@ -0,0 +1,67 @@
+//! The system calls tasks make, and the kernel side that services them between runs.
+
+use super::{Process, Scheduler};
+use std::time::Duration;
+
+/// A request a task makes to the kernel, instead of changing its process or the scheduler itself.
+///
+/// A task issues system calls during a run, and the runner services them after the run, in order.
+pub enum Syscall {
+    /// Blocks the process for the duration
+    Sleep(Duration),
+    /// Starts a child process, which gets the next free PID
+    Spawn(Box<Process>),
+    /// Ends the process with the exit code
+    Exit(i32),
+    /// Puts a message in the mailbox of the process with the PID
+    Send { to: u32, message: String },
+    /// Takes the oldest message from the process' mailbox, blocking until there is one
+    Recv,
+    /// Gives up the rest of the process' time slice
+    Yield,
+}
+
+impl Syscall {
+    /// Whether the process gives up the rest of its time slice when it makes the call.
+    pub(super) fn ends_slice(&self) -> bool {
+        !matches!(self, Syscall::Spawn(_) | Syscall::Send { .. })
+    }
+}
+
+/// Services the system calls the process made in its last run. Returns the processes it spawned,
+/// for the caller to adopt.
+///
+/// Messages sent to processes that don't exist are dropped.
+pub fn service<S: Scheduler>(scheduler: &mut S, pid: u32) -> Vec<Process> {
+    let Some(process) = scheduler.process_mut(pid) else {
+        return Vec::new();
+    };
+
+    let mut spawned = Vec::new();
+    for syscall in process.take_syscalls() {
+        match syscall {
+            Syscall::Sleep(duration) => {
+                if let Some(process) = scheduler.process_mut(pid) {
+                    process.sleep(duration);
+                }
+            }
+            Syscall::Spawn(child) => spawned.push(*child),
+            Syscall::Exit(exit_code) => {
+                if let Some(process) = scheduler.process_mut(pid) {
+                    process.set_exit_code(Some(exit_code));
+                }
+            }
+            Syscall::Send { to, message } => match scheduler.process_mut(to) {
+                Some(receiver) => receiver.deliver(message),
+                None => log::warn!("Process {pid} sent a message to missing process {to}"),
+            },
+            Syscall::Recv => {
+                if let Some(process) = scheduler.process_mut(pid) {
+                    process.receive();
+                }
+            }
+            Syscall::Yield => scheduler.yield_current(),
+        }
+    }
+    spawned
+}
@@ -0,0 +1,105 @@
+use super::Scheduler;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The tunables, named by their path under the sysctl directory.
+const TUNABLES: [&str; 4] = [
+    "sched/tick_rate_ms",
+    "sched/usage_half_life_ms",
+    "sched/sleeper_boost",
+    "sched/autogroup",
+];
+
+/// Exposes the scheduler's tunables as files in a `/proc/sys` style directory.
+///
+/// Every tunable is a file holding its current value, so it can be read with `cat` and changed
+/// live with `echo <value> > <file>` from any shell.
+pub struct Sysctl {
+    root: PathBuf,
+    /// The last value written to each file, used to detect changes made from the outside
+    published: HashMap<&'static str, String>,
+}
+
+impl Sysctl {
+    pub fn new(root: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let root = root.as_ref().to_owned();
+        for key in TUNABLES {
+            fs::create_dir_all(root.join(key).parent().unwrap())?;
+        }
+
+        Ok(Self {
+            root,
+            published: HashMap::new(),
+        })
+    }
+
+    /// Applies the values that were changed in the files since they were last published.
+    pub fn apply<S: Scheduler>(&mut self, scheduler: &mut S) {
+        for key in TUNABLES {
+            let Ok(value) = fs::read_to_string(self.root.join(key)) else {
+                continue;
+            };
+            let value = value.trim();
+
+            // Forget invalid values, so the next publish overwrites them with the current value
+            if self.published.get(key).map(String::as_str) != Some(value) {
+                if let Err(error) = set(scheduler, key, value) {
+                    log::warn!(target: "sysctl", "Ignoring {key}: {error}");
+                    self.published.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Writes the current values of the tunables to their files.
+    pub fn publish<S: Scheduler>(&mut self, scheduler: &S) -> Result<(), io::Error> {
+        for key in TUNABLES {
+            let value = get(scheduler, key);
+            if self.published.get(key) != Some(&value) {
+                fs::write(self.root.join(key), format!("{value}\n"))?;
+                self.published.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn get<S: Scheduler>(scheduler: &S, key: &str) -> String {
+    match key {
+        "sched/tick_rate_ms" => scheduler.tick_rate().as_millis().to_string(),
+        "sched/usage_half_life_ms" => scheduler.usage_half_life().as_millis().to_string(),
+        "sched/sleeper_boost" => u8::from(scheduler.sleeper_boost()).to_string(),
+        "sched/autogroup" => u8::from(scheduler.autogroup()).to_string(),
+        _ => unreachable!("Unknown tunable: {key}"),
+    }
+}
+
+pub fn set<S: Scheduler>(scheduler: &mut S, key: &str, value: &str) -> Result<(), String> {
+    let millis = || match value.parse() {
+        Ok(millis) if millis > 0 => Ok(Duration::from_millis(millis)),
+        _ => Err(format!(
+            "Expected a positive number of milliseconds, got \"{value}\""
+        )),
+    };
+
+    match key {
+        "sched/tick_rate_ms" => scheduler.set_tick_rate(millis()?),
+        "sched/usage_half_life_ms" => scheduler.set_usage_half_life(millis()?),
+        "sched/sleeper_boost" => match value {
+            "0" => scheduler.set_sleeper_boost(false),
+            "1" => scheduler.set_sleeper_boost(true),
+            _ => return Err(format!("Expected 0 or 1, got \"{value}\"")),
+        },
+        "sched/autogroup" => match value {
+            "0" => scheduler.set_autogroup(false),
+            "1" => scheduler.set_autogroup(true),
+            _ => return Err(format!("Expected 0 or 1, got \"{value}\"")),
+        },
+        _ => return Err(format!("Unknown tunable: {key}")),
+    }
+    Ok(())
+}
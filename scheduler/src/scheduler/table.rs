@@ -0,0 +1,146 @@
+//! The processes of a scheduler, in a slot map that finds them by their PIDs.
+
+use super::Process;
+use slotmap::{basic::ValuesMut, new_key_type, SlotMap};
+use std::{collections::HashMap, ops::Index, slice};
+
+new_key_type! {
+    /// Where a process is kept in a `ProcessTable`. The key of a removed process never finds
+    /// another process, even one that got its slot.
+    pub struct ProcessKey;
+}
+
+/// The processes of a scheduler, in the order they were added.
+///
+/// Removing a process doesn't move the others, so a key held by a scheduler (like its current
+/// process) keeps finding the same process, and finding a process by its PID doesn't search.
+#[derive(Default)]
+pub struct ProcessTable {
+    processes: SlotMap<ProcessKey, Process>,
+    /// The keys of the processes, in the order they were added
+    order: Vec<ProcessKey>,
+    pids: HashMap<u32, ProcessKey>,
+}
+
+impl ProcessTable {
+    pub fn new() -> Self {
+        ProcessTable::default()
+    }
+
+    pub fn insert(&mut self, process: Process) -> ProcessKey {
+        let pid = process.pid();
+        let key = self.processes.insert(process);
+        self.order.push(key);
+        self.pids.insert(pid, key);
+        key
+    }
+
+    pub fn remove(&mut self, key: ProcessKey) -> Option<Process> {
+        let process = self.processes.remove(key)?;
+        self.order.retain(|&other| other != key);
+        // Another process may have taken the PID, if it was added with the same one
+        if self.pids.get(&process.pid()) == Some(&key) {
+            self.pids.remove(&process.pid());
+        }
+        Some(process)
+    }
+
+    pub fn get(&self, key: ProcessKey) -> Option<&Process> {
+        self.processes.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: ProcessKey) -> Option<&mut Process> {
+        self.processes.get_mut(key)
+    }
+
+    /// The key of the process with the given PID.
+    pub fn key(&self, pid: u32) -> Option<ProcessKey> {
+        self.pids.get(&pid).copied()
+    }
+
+    pub fn by_pid(&self, pid: u32) -> Option<&Process> {
+        self.get(self.key(pid)?)
+    }
+
+    pub fn by_pid_mut(&mut self, pid: u32) -> Option<&mut Process> {
+        self.get_mut(self.key(pid)?)
+    }
+
+    /// The key that comes after `key`, going back to the first one after the last one. Without a
+    /// key, or with a key of a process that was removed, it's the first key.
+    pub fn next_key(&self, key: Option<ProcessKey>) -> Option<ProcessKey> {
+        let next = key
+            .and_then(|key| self.order.iter().position(|&other| other == key))
+            .map_or(0, |index| (index + 1) % self.order.len());
+        self.order.get(next).copied()
+    }
+
+    /// The keys of the processes, in the order they were added.
+    pub fn keys(&self) -> &[ProcessKey] {
+        &self.order
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// The processes, in the order they were added.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            keys: self.order.iter(),
+            processes: &self.processes,
+        }
+    }
+
+    /// The processes, in no particular order.
+    pub fn iter_mut(&mut self) -> IterMut<'_> {
+        self.processes.values_mut()
+    }
+}
+
+impl Index<ProcessKey> for ProcessTable {
+    type Output = Process;
+
+    fn index(&self, key: ProcessKey) -> &Process {
+        &self.processes[key]
+    }
+}
+
+impl FromIterator<Process> for ProcessTable {
+    fn from_iter<I: IntoIterator<Item = Process>>(processes: I) -> Self {
+        let mut table = ProcessTable::new();
+        for process in processes {
+            table.insert(process);
+        }
+        table
+    }
+}
+
+/// The processes of a `ProcessTable`, in the order they were added.
+#[derive(Clone)]
+pub struct Iter<'a> {
+    keys: slice::Iter<'a, ProcessKey>,
+    processes: &'a SlotMap<ProcessKey, Process>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Process;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.keys.next()?;
+        Some(&self.processes[*key])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+/// The processes of a `ProcessTable`, in no particular order.
+pub type IterMut<'a> = ValuesMut<'a, ProcessKey, Process>;
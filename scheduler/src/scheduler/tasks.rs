@@ -1,7 +1,46 @@
-use std::time::Duration;
+use super::{clock, Syscall, WaitTarget};
+use std::time::{Duration, Instant};
 
-pub trait Task {
+/// Tasks are `Send` so a watchdog can run them on a worker thread.
+pub trait Task: Send {
     fn run(&mut self) -> String;
+
+    /// The system calls the task made in its last run, which the kernel services after the run.
+    fn syscalls(&mut self) -> Vec<Syscall> {
+        Vec::new()
+    }
+
+    /// Hands the task a message that its `Recv` received, before its next run.
+    fn received(&mut self, _message: String) {}
+
+    /// Whether the task is waiting for an event (e.g. a timer or a message) after its last run.
+    /// The process wakes up once it isn't.
+    fn is_waiting(&self) -> bool {
+        false
+    }
+
+    /// The child the task waits for to exit, while it waits for one.
+    fn waits_on(&self) -> Option<WaitTarget> {
+        None
+    }
+
+    /// Tells the task the PID of the process it runs in.
+    fn set_pid(&mut self, _pid: u32) {}
+}
+
+/// Keeps the CPU busy for `duration`, unlike sleeping which would let it idle.
+pub(super) fn compute(duration: Duration) {
+    let duration = clock::at_core_speed(duration);
+    // There's no need to spin when the time is simulated
+    if clock::is_mock() {
+        clock::advance_mock(duration);
+        return;
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        std::hint::spin_loop();
+    }
 }
 
 pub struct CounterTask {
@@ -14,6 +53,12 @@ impl CounterTask {
     }
 }
 
+impl Default for CounterTask {
+    fn default() -> Self {
+        CounterTask::new()
+    }
+}
+
 impl Task for CounterTask {
     fn run(&mut self) -> String {
         self.counter += 1;
@@ -21,3 +66,144 @@ impl Task for CounterTask {
         self.counter.to_string()
     }
 }
+
+/// Simulates an interactive program, which handles a short input event and then waits for the next one.
+pub struct InteractiveTask {
+    events: u32,
+}
+
+impl InteractiveTask {
+    const INPUT_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new() -> Self {
+        Self { events: 0 }
+    }
+}
+
+impl Default for InteractiveTask {
+    fn default() -> Self {
+        InteractiveTask::new()
+    }
+}
+
+impl Task for InteractiveTask {
+    fn run(&mut self) -> String {
+        self.events += 1;
+        format!("Handled event {}", self.events)
+    }
+
+    fn syscalls(&mut self) -> Vec<Syscall> {
+        vec![Syscall::Sleep(InteractiveTask::INPUT_INTERVAL)]
+    }
+}
+
+/// Simulates a program that spends most of its time waiting for slow I/O, like reading a disk.
+pub struct IoBoundTask {
+    blocks_read: u32,
+}
+
+impl IoBoundTask {
+    const IO_TIME: Duration = Duration::from_millis(300);
+
+    pub fn new() -> Self {
+        Self { blocks_read: 0 }
+    }
+}
+
+impl Default for IoBoundTask {
+    fn default() -> Self {
+        IoBoundTask::new()
+    }
+}
+
+impl Task for IoBoundTask {
+    fn run(&mut self) -> String {
+        self.blocks_read += 1;
+        format!("Read block {}", self.blocks_read)
+    }
+
+    fn syscalls(&mut self) -> Vec<Syscall> {
+        vec![Syscall::Sleep(IoBoundTask::IO_TIME)]
+    }
+}
+
+/// Alternates between bursts of heavy computation and long sleeps.
+pub struct BurstyTask {
+    runs_in_burst: u32,
+    bursts: u32,
+}
+
+impl BurstyTask {
+    const BURST_LENGTH: u32 = 50;
+    const COMPUTE_TIME: Duration = Duration::from_millis(5);
+    const SLEEP_TIME: Duration = Duration::from_secs(2);
+
+    pub fn new() -> Self {
+        Self {
+            runs_in_burst: 0,
+            bursts: 0,
+        }
+    }
+}
+
+impl Default for BurstyTask {
+    fn default() -> Self {
+        BurstyTask::new()
+    }
+}
+
+impl Task for BurstyTask {
+    fn run(&mut self) -> String {
+        compute(BurstyTask::COMPUTE_TIME);
+        self.runs_in_burst += 1;
+        format!(
+            "Burst {}: {}/{}",
+            self.bursts + 1,
+            self.runs_in_burst,
+            BurstyTask::BURST_LENGTH
+        )
+    }
+
+    fn syscalls(&mut self) -> Vec<Syscall> {
+        // Sleep once the current burst is over
+        if self.runs_in_burst < BurstyTask::BURST_LENGTH {
+            return Vec::new();
+        }
+
+        self.runs_in_burst = 0;
+        self.bursts += 1;
+        vec![Syscall::Sleep(BurstyTask::SLEEP_TIME)]
+    }
+}
+
+/// Allocates memory aggressively, and starts over once it holds too much.
+pub struct MemoryHogTask {
+    chunks: Vec<Vec<u8>>,
+}
+
+impl MemoryHogTask {
+    const CHUNK_SIZE: usize = 1024 * 1024;
+    const MAX_CHUNKS: usize = 256;
+
+    pub fn new() -> Self {
+        Self { chunks: Vec::new() }
+    }
+}
+
+impl Default for MemoryHogTask {
+    fn default() -> Self {
+        MemoryHogTask::new()
+    }
+}
+
+impl Task for MemoryHogTask {
+    fn run(&mut self) -> String {
+        if self.chunks.len() >= MemoryHogTask::MAX_CHUNKS {
+            self.chunks.clear();
+        }
+
+        // Touch every byte so the memory is actually committed
+        self.chunks.push(vec![0xAA; MemoryHogTask::CHUNK_SIZE]);
+        format!("Holding {} MiB", self.chunks.len())
+    }
+}
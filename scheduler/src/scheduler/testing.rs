@@ -0,0 +1,135 @@
+//! Deterministic building blocks for testing schedulers: a mock clock, tasks with known runtimes,
+//! and assertions over the resulting schedule.
+
+use super::{clock, syscall, LatencyHistogram, Process, Scheduler, Syscall, Task};
+use std::time::{Duration, Instant};
+
+/// Replaces the schedulers' clock on the current thread until it is dropped.
+///
+/// The mock clock only moves when it is advanced, either explicitly or by running a `FixedTask`.
+pub struct MockClock {
+    start: Instant,
+}
+
+impl MockClock {
+    pub fn install() -> Self {
+        let start = Instant::now();
+        clock::set_mock(Some(start));
+        Self { start }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        clock::advance_mock(duration);
+    }
+
+    /// The mock time that passed since the clock was installed.
+    pub fn elapsed(&self) -> Duration {
+        clock::elapsed(self.start)
+    }
+}
+
+impl Drop for MockClock {
+    fn drop(&mut self) {
+        clock::set_mock(None);
+    }
+}
+
+/// A task that takes exactly `runtime` of mock time per run (at a core speed of 1), and may block
+/// after every run.
+pub struct FixedTask {
+    runtime: Duration,
+    sleep: Option<Duration>,
+}
+
+impl FixedTask {
+    pub fn new(runtime: Duration) -> Self {
+        Self {
+            runtime,
+            sleep: None,
+        }
+    }
+
+    pub fn sleeping(runtime: Duration, sleep: Duration) -> Self {
+        Self {
+            runtime,
+            sleep: Some(sleep),
+        }
+    }
+}
+
+impl Task for FixedTask {
+    fn run(&mut self) -> String {
+        clock::advance_mock(clock::at_core_speed(self.runtime));
+        String::new()
+    }
+
+    fn syscalls(&mut self) -> Vec<Syscall> {
+        self.sleep.map(Syscall::Sleep).into_iter().collect()
+    }
+}
+
+/// How far the clock skips ahead when no process can run.
+pub const IDLE_STEP: Duration = Duration::from_millis(1);
+
+/// Makes `steps` scheduling decisions the way `ProcessRunner` does, and returns the PID that ran
+/// in every step, or None if every process was asleep.
+pub fn run_schedule<S: Scheduler>(scheduler: &mut S, steps: usize) -> Vec<Option<u32>> {
+    (0..steps)
+        .map(|_| match scheduler.schedule() {
+            Some(process) => {
+                let pid = process.pid();
+                let start_time = clock::now();
+                process.run();
+                let elapsed = process.dilate(clock::elapsed(start_time));
+                scheduler.add_cpu_elapsed(elapsed);
+                syscall::service(scheduler, pid);
+                Some(pid)
+            }
+            None => {
+                clock::advance_mock(IDLE_STEP);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The fraction of the non-idle steps in which `pid` ran.
+pub fn share(schedule: &[Option<u32>], pid: u32) -> f64 {
+    let runs: Vec<u32> = schedule.iter().flatten().copied().collect();
+    runs.iter().filter(|&&run| run == pid).count() as f64 / runs.len() as f64
+}
+
+pub fn assert_share(schedule: &[Option<u32>], pid: u32, expected: f64, tolerance: f64) {
+    let share = share(schedule, pid);
+    assert!(
+        (share - expected).abs() <= tolerance,
+        "PID {pid} got {share:.3} of the CPU, expected {expected:.3} ± {tolerance}"
+    );
+}
+
+/// Asserts the processes took turns in `expected` order, ignoring how long each turn was.
+pub fn assert_order(schedule: &[Option<u32>], expected: &[u32]) {
+    let mut turns: Vec<u32> = schedule.iter().flatten().copied().collect();
+    turns.dedup();
+    assert!(
+        turns.starts_with(expected),
+        "Expected the turns to start with {expected:?}, got {turns:?}"
+    );
+}
+
+/// Asserts the process always ran less than `bound` after waking up.
+///
+/// `bound` must be one of `LatencyHistogram::BUCKET_BOUNDS`.
+pub fn assert_wake_latency_below(process: &Process, bound: Duration) {
+    let bucket = LatencyHistogram::BUCKET_BOUNDS
+        .iter()
+        .position(|&bucket_bound| bucket_bound == bound)
+        .expect("The bound must be a bucket bound.");
+    let late: u64 = process.latencies().buckets()[bucket + 1..].iter().sum();
+    assert!(
+        late == 0,
+        "PID {} ran {late} time(s) {}ms or more after waking up",
+        process.pid(),
+        bound.as_millis()
+    );
+}
@@ -0,0 +1,170 @@
+use std::{fs, io, path::Path};
+use tui::style::{Color, Style};
+
+const NAMED_COLORS: [(&str, Color); 16] = [
+    ("black", Color::Black),
+    ("red", Color::Red),
+    ("green", Color::Green),
+    ("yellow", Color::Yellow),
+    ("blue", Color::Blue),
+    ("magenta", Color::Magenta),
+    ("cyan", Color::Cyan),
+    ("gray", Color::Gray),
+    ("darkgray", Color::DarkGray),
+    ("lightred", Color::LightRed),
+    ("lightgreen", Color::LightGreen),
+    ("lightyellow", Color::LightYellow),
+    ("lightblue", Color::LightBlue),
+    ("lightmagenta", Color::LightMagenta),
+    ("lightcyan", Color::LightCyan),
+    ("white", Color::White),
+];
+
+/// The colors of the display's widgets.
+///
+/// A config file picks a preset with `preset = <name>` (default, dark, high-contrast or
+/// colorblind-safe), and then recolors single widgets with `<widget> = <color>`. The widgets are
+/// header, table, decisions, runqueue, latency and popup. Colors are named (like `lightgreen`) or `#rrggbb`.
+/// Lines starting with `#` are comments.
+#[derive(Clone)]
+pub struct Theme {
+    pub header: Style,
+    pub table: Style,
+    pub decisions: Style,
+    pub runqueue: Style,
+    pub latency: Style,
+    pub popup: Style,
+}
+
+impl Theme {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        Theme::parse(&fs::read_to_string(path)?)
+            .map_err(|reason| io::Error::new(io::ErrorKind::InvalidData, reason))
+    }
+
+    pub fn parse(config: &str) -> Result<Self, String> {
+        let mut theme = Theme::default();
+
+        for (index, line) in config.lines().map(str::trim).enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |reason: String| format!("Line {}: {reason}", index + 1);
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `<widget> = <color>`".to_owned()))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            if key == "preset" {
+                theme = Theme::preset(value)
+                    .ok_or_else(|| error(format!("unknown preset \"{value}\"")))?;
+                continue;
+            }
+
+            let color =
+                parse_color(value).ok_or_else(|| error(format!("unknown color \"{value}\"")))?;
+            let style = theme
+                .style_mut(key)
+                .ok_or_else(|| error(format!("unknown widget \"{key}\"")))?;
+            *style = style.fg(color);
+        }
+        Ok(theme)
+    }
+
+    /// The preset theme with the given name.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Theme::light()),
+            "dark" => Some(Theme::dark()),
+            "high-contrast" => Some(Theme::high_contrast()),
+            "colorblind-safe" => Some(Theme::colorblind_safe()),
+            _ => None,
+        }
+    }
+
+    fn style_mut(&mut self, element: &str) -> Option<&mut Style> {
+        match element {
+            "header" => Some(&mut self.header),
+            "table" => Some(&mut self.table),
+            "decisions" => Some(&mut self.decisions),
+            "runqueue" => Some(&mut self.runqueue),
+            "latency" => Some(&mut self.latency),
+            "popup" => Some(&mut self.popup),
+            _ => None,
+        }
+    }
+
+    /// The original light colors, for terminals with a dark background.
+    fn light() -> Self {
+        Self {
+            header: Style::default().fg(Color::LightBlue),
+            table: Style::default().fg(Color::LightGreen),
+            decisions: Style::default().fg(Color::LightCyan),
+            runqueue: Style::default().fg(Color::LightMagenta),
+            latency: Style::default().fg(Color::LightYellow),
+            popup: Style::default().fg(Color::White),
+        }
+    }
+
+    /// Darker colors, for terminals with a light background.
+    fn dark() -> Self {
+        Self {
+            header: Style::default().fg(Color::Blue),
+            table: Style::default().fg(Color::Green),
+            decisions: Style::default().fg(Color::Cyan),
+            runqueue: Style::default().fg(Color::Red),
+            latency: Style::default().fg(Color::Magenta),
+            popup: Style::default().fg(Color::Black),
+        }
+    }
+
+    /// White and yellow on black, regardless of the terminal's colors.
+    fn high_contrast() -> Self {
+        let style = Style::default().fg(Color::White).bg(Color::Black);
+        Self {
+            header: style.fg(Color::Yellow),
+            table: style,
+            decisions: style,
+            runqueue: style,
+            latency: style,
+            popup: style.fg(Color::Yellow),
+        }
+    }
+
+    /// Colors from the Okabe-Ito palette, which stay apart with every kind of color blindness.
+    fn colorblind_safe() -> Self {
+        Self {
+            header: Style::default().fg(Color::Rgb(86, 180, 233)),
+            table: Style::default().fg(Color::Rgb(230, 159, 0)),
+            decisions: Style::default().fg(Color::Rgb(0, 158, 115)),
+            runqueue: Style::default().fg(Color::Rgb(204, 121, 167)),
+            latency: Style::default().fg(Color::Rgb(240, 228, 66)),
+            popup: Style::default().fg(Color::White),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::light()
+    }
+}
+
+fn parse_color(text: &str) -> Option<Color> {
+    if let Some(hex) = text.strip_prefix('#') {
+        let channel = |index: usize| {
+            hex.get(index..index + 2)
+                .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+        };
+        return match hex.len() {
+            6 => Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?)),
+            _ => None,
+        };
+    }
+
+    NAMED_COLORS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(text))
+        .map(|&(_, color)| color)
+}
@@ -0,0 +1,171 @@
+use super::{Process, Syscall, Task};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// One run of a task on its thread.
+struct TaskRun {
+    output: String,
+    elapsed: Duration,
+    syscalls: Vec<Syscall>,
+}
+
+/// A thread that keeps running a process' task while it's allowed to, and parks otherwise.
+struct TaskThread {
+    handle: JoinHandle<()>,
+    /// Set while the process has the CPU
+    running: Arc<AtomicBool>,
+    exiting: Arc<AtomicBool>,
+    runs: Receiver<TaskRun>,
+    /// The messages the process received, for the task to get before its next run
+    messages: Sender<String>,
+}
+
+impl TaskThread {
+    fn spawn(mut task: Box<dyn Task>) -> Self {
+        let running = Arc::new(AtomicBool::new(false));
+        let exiting = Arc::new(AtomicBool::new(false));
+        let (runs_tx, runs) = mpsc::channel();
+        let (messages, messages_rx) = mpsc::channel();
+
+        let handle = {
+            let running = Arc::clone(&running);
+            let exiting = Arc::clone(&exiting);
+            thread::spawn(move || loop {
+                while !running.load(Ordering::Acquire) {
+                    if exiting.load(Ordering::Acquire) {
+                        return;
+                    }
+                    thread::park();
+                }
+
+                for message in messages_rx.try_iter() {
+                    task.received(message);
+                }
+                let start = Instant::now();
+                let output = task.run();
+                let elapsed = start.elapsed();
+                let syscalls = task.syscalls();
+
+                // A blocked, yielding or finished task gives up the rest of its slice
+                if syscalls.iter().any(Syscall::ends_slice) {
+                    running.store(false, Ordering::Release);
+                }
+                let run = TaskRun {
+                    output,
+                    elapsed,
+                    syscalls,
+                };
+                if runs_tx.send(run).is_err() {
+                    return;
+                }
+            })
+        };
+
+        Self {
+            handle,
+            running,
+            exiting,
+            runs,
+            messages,
+        }
+    }
+
+    fn resume(&self) {
+        self.running.store(true, Ordering::Release);
+        self.handle.thread().unpark();
+    }
+
+    fn suspend(&self) {
+        self.running.store(false, Ordering::Release);
+    }
+}
+
+impl Drop for TaskThread {
+    fn drop(&mut self) {
+        self.exiting.store(true, Ordering::Release);
+        self.suspend();
+        self.handle.thread().unpark();
+    }
+}
+
+/// Runs every process' task on a thread of its own, which is resumed for a time slice when the
+/// process is scheduled and suspended when the slice is over.
+///
+/// The runner doesn't wait for a task to return, so a slow task can't hold up the schedule. Safe
+/// Rust can't stop a thread in the middle of `run()` though, so a run in progress finishes in the
+/// background before the thread parks, and is accounted for in the process' next slice.
+pub struct TaskThreads {
+    slice: Duration,
+    threads: HashMap<u32, TaskThread>,
+}
+
+impl TaskThreads {
+    pub fn new(slice: Duration) -> Self {
+        Self {
+            slice,
+            threads: HashMap::new(),
+        }
+    }
+
+    pub fn slice(&self) -> Duration {
+        self.slice
+    }
+
+    /// Lets the process run on its thread for a slice, and returns its last output.
+    pub(super) fn run(&mut self, process: &mut Process) -> String {
+        let pid = process.pid();
+        if let Entry::Vacant(entry) = self.threads.entry(pid) {
+            let Some(task) = process.take_task() else {
+                return String::new();
+            };
+            entry.insert(TaskThread::spawn(task));
+        }
+        let thread = &self.threads[&pid];
+        for message in process.take_received() {
+            // A thread that's gone has no task to give the message to
+            let _ = thread.messages.send(message);
+        }
+
+        process.start_run();
+        thread.resume();
+
+        // Collect the task's runs until its slice is over, or until it blocks
+        let deadline = Instant::now() + self.slice;
+        let mut elapsed = Duration::ZERO;
+        let mut outputs = Vec::new();
+        let mut syscalls = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match thread.runs.recv_timeout(remaining) {
+                Ok(run) => {
+                    elapsed += run.elapsed;
+                    outputs.push(run.output);
+                    let ends_slice = run.syscalls.iter().any(Syscall::ends_slice);
+                    syscalls.extend(run.syscalls);
+                    if ends_slice {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        thread.suspend();
+
+        let output = outputs.last().cloned().unwrap_or_default();
+        process.finish_run(elapsed, outputs, syscalls);
+        output
+    }
+
+    /// Stops the thread of a process that exited.
+    pub(super) fn remove(&mut self, pid: u32) {
+        self.threads.remove(&pid);
+    }
+}
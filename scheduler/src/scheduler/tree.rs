@@ -0,0 +1,89 @@
+use super::Process;
+use std::time::Duration;
+
+/// A row of the process tree: the index of its process, and the prefix that draws its branch.
+pub struct TreeRow {
+    pub index: usize,
+    pub prefix: String,
+}
+
+/// Orders the processes depth-first like `pstree`, children under their parents.
+///
+/// Processes whose parent doesn't exist are roots.
+pub fn tree_rows(processes: &[&Process]) -> Vec<TreeRow> {
+    let mut rows = Vec::new();
+    let mut visited = vec![false; processes.len()];
+
+    let roots: Vec<usize> = (0..processes.len())
+        .filter(|&index| parent_index(processes, index).is_none())
+        .collect();
+    for index in roots {
+        push_subtree(processes, index, &mut Vec::new(), &mut visited, &mut rows);
+    }
+
+    // Processes in a parent cycle are never reached from a root
+    for index in 0..processes.len() {
+        if !visited[index] {
+            push_subtree(processes, index, &mut Vec::new(), &mut visited, &mut rows);
+        }
+    }
+    rows
+}
+
+/// The CPU usage of a process and all of its descendants.
+pub fn subtree_cpu_usage(processes: &[&Process], index: usize) -> Duration {
+    let mut visited = vec![false; processes.len()];
+    let mut pending = vec![index];
+    let mut usage = Duration::ZERO;
+
+    while let Some(index) = pending.pop() {
+        if visited[index] {
+            continue;
+        }
+        visited[index] = true;
+        usage += processes[index].cpu_usage();
+        pending.extend(children(processes, index));
+    }
+    usage
+}
+
+fn parent_index(processes: &[&Process], index: usize) -> Option<usize> {
+    let parent = processes[index].parent()?;
+    processes.iter().position(|process| process.pid() == parent)
+}
+
+fn children(processes: &[&Process], index: usize) -> Vec<usize> {
+    (0..processes.len())
+        .filter(|&child| parent_index(processes, child) == Some(index))
+        .collect()
+}
+
+/// `last_children` holds, for every ancestor below the root, whether it's its parent's last child.
+fn push_subtree(
+    processes: &[&Process],
+    index: usize,
+    last_children: &mut Vec<bool>,
+    visited: &mut [bool],
+    rows: &mut Vec<TreeRow>,
+) {
+    if visited[index] {
+        return;
+    }
+    visited[index] = true;
+
+    let mut prefix = String::new();
+    if let Some((&is_last, ancestors)) = last_children.split_last() {
+        for &ancestor_is_last in ancestors {
+            prefix.push_str(if ancestor_is_last { "   " } else { "│  " });
+        }
+        prefix.push_str(if is_last { "└─ " } else { "├─ " });
+    }
+    rows.push(TreeRow { index, prefix });
+
+    let children = children(processes, index);
+    for (position, &child) in children.iter().enumerate() {
+        last_children.push(position == children.len() - 1);
+        push_subtree(processes, child, last_children, visited, rows);
+        last_children.pop();
+    }
+}
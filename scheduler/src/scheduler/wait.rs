@@ -0,0 +1,122 @@
+//! Waiting for child processes to exit, and collecting their exit codes.
+
+use super::Process;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Which children a parent waits for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitTarget {
+    Any,
+    Child(u32),
+}
+
+impl WaitTarget {
+    fn matches(self, pid: u32) -> bool {
+        match self {
+            WaitTarget::Any => true,
+            WaitTarget::Child(child) => child == pid,
+        }
+    }
+}
+
+impl fmt::Display for WaitTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WaitTarget::Any => write!(f, "any"),
+            WaitTarget::Child(pid) => write!(f, "{pid}"),
+        }
+    }
+}
+
+/// What waiting for a child found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitStatus {
+    /// The child with this PID exited with this code, and was reaped
+    Exited(u32, i32),
+    /// The children that match are still running
+    Running,
+    /// There are no children that match
+    NoChildren,
+}
+
+#[derive(Default)]
+struct Family {
+    /// The children that are still running
+    running: Vec<u32>,
+    /// The children that exited and weren't waited for yet, and their exit codes
+    zombies: Vec<(u32, i32)>,
+}
+
+/// The children of every process, shared by the tasks that wait for them and the runner that
+/// tracks them.
+#[derive(Clone, Default)]
+pub struct Zombies(Arc<Mutex<HashMap<u32, Family>>>);
+
+impl Zombies {
+    fn families(&self) -> MutexGuard<'_, HashMap<u32, Family>> {
+        self.0.lock().expect("Failed to lock the zombie table.")
+    }
+
+    /// Records which processes are running, and whose children they are.
+    pub fn track<'a>(&self, processes: impl IntoIterator<Item = &'a Process>) {
+        let mut families = self.families();
+        for family in families.values_mut() {
+            family.running.clear();
+        }
+        for process in processes {
+            if let Some(parent) = process.parent() {
+                families
+                    .entry(parent)
+                    .or_default()
+                    .running
+                    .push(process.pid());
+            }
+        }
+    }
+
+    /// Records that a process exited, keeping its exit code until its parent waits for it. Its
+    /// own zombies are never reaped, so they're dropped.
+    pub fn exit(&self, process: &Process, exit_code: i32) {
+        let mut families = self.families();
+        families.remove(&process.pid());
+        if let Some(parent) = process.parent() {
+            let family = families.entry(parent).or_default();
+            family.running.retain(|&pid| pid != process.pid());
+            family.zombies.push((process.pid(), exit_code));
+        }
+    }
+
+    /// Reaps a child of `parent` that exited, if there's one that matches `target`.
+    pub fn wait(&self, parent: u32, target: WaitTarget) -> WaitStatus {
+        let mut families = self.families();
+        let Some(family) = families.get_mut(&parent) else {
+            return WaitStatus::NoChildren;
+        };
+
+        if let Some(index) = family
+            .zombies
+            .iter()
+            .position(|&(pid, _)| target.matches(pid))
+        {
+            let (pid, exit_code) = family.zombies.remove(index);
+            return WaitStatus::Exited(pid, exit_code);
+        }
+        if family.running.iter().any(|&pid| target.matches(pid)) {
+            WaitStatus::Running
+        } else {
+            WaitStatus::NoChildren
+        }
+    }
+
+    /// Whether waiting for `target` would block, without reaping anything.
+    pub(super) fn would_block(&self, parent: u32, target: WaitTarget) -> bool {
+        self.families().get(&parent).is_some_and(|family| {
+            !family.zombies.iter().any(|&(pid, _)| target.matches(pid))
+                && family.running.iter().any(|&pid| target.matches(pid))
+        })
+    }
+}
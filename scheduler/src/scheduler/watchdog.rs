@@ -0,0 +1,137 @@
+use super::{clock, Process, Task};
+use std::{
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+/// What came of running a task under the watchdog.
+pub(super) enum Run {
+    Finished(Box<dyn Task>, String),
+    /// The task finished, but took longer than the budget
+    Overran(Box<dyn Task>, String),
+    /// The task is still running on a worker, which hands it back when it finishes
+    Hung,
+}
+
+/// A task finished by a worker, with its process' PID and output.
+type Finished = (u32, Box<dyn Task>, String);
+
+/// A thread that runs tasks sent to it, one at a time.
+struct Worker {
+    tasks: Sender<(u32, Box<dyn Task>)>,
+    finished: Receiver<Finished>,
+}
+
+impl Worker {
+    fn spawn() -> Self {
+        let (tasks, tasks_rx) = mpsc::channel::<(u32, Box<dyn Task>)>();
+        let (finished_tx, finished) = mpsc::channel();
+        thread::spawn(move || {
+            // Stops once the watchdog gives up on the worker, after its last task finishes
+            for (pid, mut task) in tasks_rx {
+                let output = task.run();
+                if finished_tx.send((pid, task, output)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { tasks, finished }
+    }
+}
+
+/// Catches tasks that run for longer than a budget, and marks their processes as hung.
+///
+/// By default tasks still run on the runner's thread, so they're only caught once they return.
+/// An isolated watchdog runs them on a worker thread instead, and stops waiting for a task when
+/// its budget is over, so a task that never returns doesn't freeze the display.
+pub struct Watchdog {
+    budget: Duration,
+    isolated: bool,
+    worker: Option<Worker>,
+    /// The workers left running hung tasks, oldest first
+    hung_workers: Vec<Receiver<Finished>>,
+}
+
+impl Watchdog {
+    pub const DEFAULT_BUDGET: Duration = Duration::from_millis(100);
+
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            isolated: false,
+            worker: None,
+            hung_workers: Vec::new(),
+        }
+    }
+
+    /// A watchdog which runs the tasks on a worker thread.
+    pub fn isolated(budget: Duration) -> Self {
+        Self {
+            isolated: true,
+            ..Watchdog::new(budget)
+        }
+    }
+
+    pub fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    pub fn is_isolated(&self) -> bool {
+        self.isolated
+    }
+
+    /// Runs the process, marking it as hung if its task went over the budget.
+    pub(super) fn run(&mut self, process: &mut Process) -> String {
+        let pid = process.pid();
+        process.run_with(|task| self.execute(pid, task))
+    }
+
+    fn execute(&mut self, pid: u32, mut task: Box<dyn Task>) -> Run {
+        if !self.isolated {
+            let start = clock::now();
+            let output = task.run();
+            return if clock::elapsed(start) > self.budget {
+                Run::Overran(task, output)
+            } else {
+                Run::Finished(task, output)
+            };
+        }
+
+        let worker = self.worker.get_or_insert_with(Worker::spawn);
+        worker
+            .tasks
+            .send((pid, task))
+            .expect("Failed to send a task to the worker.");
+        match worker.finished.recv_timeout(self.budget) {
+            Ok((_, task, output)) => Run::Finished(task, output),
+            Err(_) => {
+                // Leave the hung task to its worker, the next tasks get a new one
+                let worker = self.worker.take().expect("Failed to get the worker.");
+                self.hung_workers.push(worker.finished);
+                Run::Hung
+            }
+        }
+    }
+
+    /// The hung tasks which finished since they were given up on.
+    pub(super) fn reclaim(&mut self) -> Vec<Finished> {
+        let mut finished = Vec::new();
+        self.hung_workers.retain(|worker| match worker.try_recv() {
+            Ok(task) => {
+                finished.push(task);
+                false
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Disconnected) => false,
+        });
+        finished
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog::new(Watchdog::DEFAULT_BUDGET)
+    }
+}
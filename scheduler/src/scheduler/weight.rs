@@ -0,0 +1,105 @@
+use std::{fmt, str::FromStr};
+
+/// The weight of a process with a niceness of 0.
+pub const NICE_0_WEIGHT: u32 = 1024;
+
+/// Linux's `sched_prio_to_weight`, indexed by niceness + 20.
+/// Every niceness level is worth about 1.25 times the CPU share of the next one.
+#[rustfmt::skip]
+const NICE_TO_WEIGHT: [u32; 40] = [
+    /* -20 */ 88761, 71755, 56483, 46273, 36291,
+    /* -15 */ 29154, 23254, 18705, 14949, 11916,
+    /* -10 */ 9548, 7620, 6100, 4904, 3906,
+    /*  -5 */ 3121, 2501, 1991, 1586, 1277,
+    /*   0 */ 1024, 820, 655, 526, 423,
+    /*   5 */ 335, 272, 215, 172, 137,
+    /*  10 */ 110, 87, 70, 56, 45,
+    /*  15 */ 36, 29, 23, 18, 15,
+];
+
+/// A niceness, from -20 (the most important) to 19 (the least important).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Niceness(i8);
+
+impl Niceness {
+    pub const MIN: Niceness = Niceness(-20);
+    pub const MAX: Niceness = Niceness(19);
+
+    /// The niceness closest to `niceness` that's in range.
+    pub const fn clamped(niceness: i8) -> Self {
+        if niceness < Niceness::MIN.0 {
+            Niceness::MIN
+        } else if niceness > Niceness::MAX.0 {
+            Niceness::MAX
+        } else {
+            Niceness(niceness)
+        }
+    }
+
+    pub const fn get(self) -> i8 {
+        self.0
+    }
+
+    /// The niceness `delta` levels away, stopping at the ends of the range.
+    pub fn saturating_add(self, delta: i8) -> Self {
+        Niceness::clamped(self.0.saturating_add(delta))
+    }
+}
+
+impl TryFrom<i8> for Niceness {
+    type Error = NicenessOutOfRange;
+
+    fn try_from(niceness: i8) -> Result<Self, Self::Error> {
+        if (Niceness::MIN.0..=Niceness::MAX.0).contains(&niceness) {
+            Ok(Niceness(niceness))
+        } else {
+            Err(NicenessOutOfRange(niceness))
+        }
+    }
+}
+
+impl FromStr for Niceness {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let niceness: i8 = text
+            .parse()
+            .map_err(|_| format!("invalid niceness \"{text}\""))?;
+        Niceness::try_from(niceness).map_err(|error| error.to_string())
+    }
+}
+
+impl From<Niceness> for i8 {
+    fn from(niceness: Niceness) -> Self {
+        niceness.0
+    }
+}
+
+impl fmt::Display for Niceness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A niceness outside of -20..=19.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NicenessOutOfRange(pub i8);
+
+impl fmt::Display for NicenessOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "niceness {} is out of range {}..={}",
+            self.0,
+            Niceness::MIN,
+            Niceness::MAX
+        )
+    }
+}
+
+impl std::error::Error for NicenessOutOfRange {}
+
+/// Returns the weight of a niceness.
+pub fn niceness_to_weight(niceness: Niceness) -> u32 {
+    NICE_TO_WEIGHT[(niceness.0 - Niceness::MIN.0) as usize]
+}
@@ -0,0 +1,113 @@
+use super::{script::parse_duration, LockProtocol, Niceness, Process, TaskRegistry};
+use std::{fs, io, path::Path};
+
+/// Loads processes from a workload file.
+///
+/// Every non-empty line that doesn't start with `#` describes a process:
+/// `<pid> | <name> | <niceness> | <task> [args]`, where the task is created through `registry`.
+/// The PID can be followed by the PID of the process' parent, like `<pid>:<parent>`, and by the
+/// time the process arrives at, like `<pid> @ 2s`. Processes that arrive later only join the run
+/// queue once the scheduler's CPU time reaches their arrival.
+///
+/// Lines like `mutex <name> [none | inherit | ceiling <niceness>]` declare the mutexes the
+/// processes' scripts lock, and how they boost the processes holding them. Lines like
+/// `limit <pid> [cpu <duration>] [children <count>]` set the resource limits of a process.
+pub fn load(path: impl AsRef<Path>, registry: &TaskRegistry) -> Result<Vec<Process>, io::Error> {
+    parse(&fs::read_to_string(path)?, registry)
+}
+
+/// Parses the contents of a workload file, see `load`.
+pub fn parse(workload: &str, registry: &TaskRegistry) -> Result<Vec<Process>, io::Error> {
+    let mut processes = Vec::new();
+    // Limits can come before the process they limit, so they're applied at the end
+    let mut limits = Vec::new();
+    for (index, line) in workload.lines().map(str::trim).enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |reason: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Line {}: {reason}", index + 1),
+            )
+        };
+
+        if let Some(mutex) = line.strip_prefix("mutex ") {
+            parse_mutex(mutex, registry).map_err(error)?;
+        } else if let Some(limit) = line.strip_prefix("limit ") {
+            limits.push((index, limit));
+        } else {
+            processes.push(parse_process(line, registry).map_err(error)?);
+        }
+    }
+
+    for (index, limit) in limits {
+        apply_limit(limit, &mut processes).map_err(|reason| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Line {}: {reason}", index + 1),
+            )
+        })?;
+    }
+    Ok(processes)
+}
+
+/// Sets the limits of a `limit` line on its process.
+fn apply_limit(limit: &str, processes: &mut [Process]) -> Result<(), String> {
+    let limit = limit.trim();
+    let (pid, limits) = limit.split_once(char::is_whitespace).unwrap_or((limit, ""));
+    let pid: u32 = pid.parse().map_err(|_| format!("invalid pid \"{pid}\""))?;
+    let process = processes
+        .iter_mut()
+        .find(|process| process.pid() == pid)
+        .ok_or_else(|| format!("no process with pid {pid}"))?;
+    process.set_limits(process.limits().parse(limits)?);
+    Ok(())
+}
+
+/// Declares the mutex of a `mutex` line in the registry's lock table.
+fn parse_mutex(mutex: &str, registry: &TaskRegistry) -> Result<(), String> {
+    let mutex = mutex.trim();
+    let (name, protocol) = mutex.split_once(char::is_whitespace).unwrap_or((mutex, ""));
+    let protocol = LockProtocol::parse(protocol)
+        .ok_or_else(|| format!("invalid lock protocol \"{}\"", protocol.trim()))?;
+    registry.locks().declare(name, protocol);
+    Ok(())
+}
+
+/// Parses a single process line of a workload file.
+pub(super) fn parse_process(line: &str, registry: &TaskRegistry) -> Result<Process, String> {
+    let fields: Vec<&str> = line.splitn(4, '|').map(str::trim).collect();
+    let [pid, name, niceness, task] = fields[..] else {
+        return Err("expected `<pid> | <name> | <niceness> | <task> [args]`".to_owned());
+    };
+
+    let (pid, arrival) = match pid.split_once('@') {
+        Some((pid, arrival)) => (pid.trim(), Some(arrival.trim())),
+        None => (pid, None),
+    };
+    let arrival = arrival
+        .map(|arrival| {
+            parse_duration(arrival).ok_or_else(|| format!("invalid arrival time \"{arrival}\""))
+        })
+        .transpose()?;
+    let (pid, parent) = match pid.split_once(':') {
+        Some((pid, parent)) => (pid.trim(), Some(parent.trim())),
+        None => (pid, None),
+    };
+    let pid = pid.parse().map_err(|_| format!("invalid pid \"{pid}\""))?;
+    let parent = parent
+        .map(|parent| {
+            parent
+                .parse()
+                .map_err(|_| format!("invalid parent pid \"{parent}\""))
+        })
+        .transpose()?;
+    let niceness: Niceness = niceness.parse()?;
+    let task = registry.create(task)?;
+
+    let mut process = Process::with_niceness(pid, name, task, niceness);
+    process.set_parent(parent);
+    process.set_arrival(arrival);
+    Ok(process)
+}
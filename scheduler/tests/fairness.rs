@@ -0,0 +1,138 @@
+use completely_fair_scheduler::{
+    testing::{run_schedule, share, FixedTask, MockClock},
+    Niceness, NicenessScheduler, Process, RoundRobinScheduler, Scheduler,
+};
+use proptest::prelude::*;
+use std::time::Duration;
+
+const TICK_RATE: Duration = Duration::from_millis(10);
+const MAX_RUNTIME_MS: u64 = 5;
+
+/// A randomly generated process: its niceness, runtime per run, and how long it blocks after each
+/// run (if at all).
+#[derive(Clone, Debug)]
+struct ProcessSpec {
+    niceness: i8,
+    runtime_ms: u64,
+    sleep_ms: Option<u64>,
+}
+
+fn process_spec() -> impl Strategy<Value = ProcessSpec> {
+    (-20i8..=19, 1..=MAX_RUNTIME_MS, prop::option::of(1u64..=50)).prop_map(
+        |(niceness, runtime_ms, sleep_ms)| ProcessSpec {
+            niceness,
+            runtime_ms,
+            sleep_ms,
+        },
+    )
+}
+
+fn workload(max_processes: usize) -> impl Strategy<Value = Vec<ProcessSpec>> {
+    prop::collection::vec(process_spec(), 1..=max_processes)
+}
+
+fn spawn(specs: &[ProcessSpec]) -> Vec<Process> {
+    specs
+        .iter()
+        .enumerate()
+        .map(|(pid, spec)| {
+            let runtime = Duration::from_millis(spec.runtime_ms);
+            let task = match spec.sleep_ms {
+                Some(sleep_ms) => FixedTask::sleeping(runtime, Duration::from_millis(sleep_ms)),
+                None => FixedTask::new(runtime),
+            };
+            let niceness = Niceness::clamped(spec.niceness);
+            Process::with_niceness(pid as u32, "", Box::new(task), niceness)
+        })
+        .collect()
+}
+
+/// Every process must run within a tick's worth of steps per process.
+fn check_no_starvation<S: Scheduler>(
+    new_scheduler: fn(Vec<Process>, Duration) -> S,
+    specs: &[ProcessSpec],
+) -> Result<(), TestCaseError> {
+    let _clock = MockClock::install();
+    let mut scheduler = new_scheduler(spawn(specs), TICK_RATE);
+
+    // A tick ends after the first run that passes the tick rate
+    let runs_per_tick = TICK_RATE.as_millis() as usize + 1;
+    let schedule = run_schedule(&mut scheduler, specs.len() * runs_per_tick);
+
+    for pid in 0..specs.len() as u32 {
+        prop_assert!(
+            schedule.contains(&Some(pid)),
+            "PID {} never ran: {:?}",
+            pid,
+            schedule
+        );
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn round_robin_never_starves(specs in workload(8)) {
+        check_no_starvation(RoundRobinScheduler::with_processes, &specs)?;
+    }
+
+    #[test]
+    fn niceness_never_starves(specs in workload(8)) {
+        check_no_starvation(NicenessScheduler::with_processes, &specs)?;
+    }
+
+    #[test]
+    fn round_robin_cycles_in_order(runtimes in prop::collection::vec(1..=MAX_RUNTIME_MS, 1..=8)) {
+        let _clock = MockClock::install();
+        let processes = runtimes
+            .iter()
+            .enumerate()
+            .map(|(pid, &runtime_ms)| {
+                let task = FixedTask::new(Duration::from_millis(runtime_ms));
+                Process::new(pid as u32, Box::new(task))
+            })
+            .collect();
+        let mut scheduler = RoundRobinScheduler::with_processes(processes, TICK_RATE);
+
+        let mut turns: Vec<u32> = run_schedule(&mut scheduler, 500).into_iter().flatten().collect();
+        turns.dedup();
+
+        // With a single process there is only one turn
+        let count = runtimes.len() as u32;
+        for (index, &pid) in turns.iter().enumerate() {
+            prop_assert_eq!(pid, index as u32 % count);
+        }
+    }
+
+    #[test]
+    fn niceness_shares_follow_weights(nicenesses in prop::collection::vec(-5i8..=5, 2..=4)) {
+        let _clock = MockClock::install();
+        let processes: Vec<Process> = nicenesses
+            .iter()
+            .enumerate()
+            .map(|(pid, &niceness)| {
+                let task = FixedTask::new(Duration::from_millis(1));
+                Process::with_niceness(pid as u32, "", Box::new(task), Niceness::clamped(niceness))
+            })
+            .collect();
+        let weights: Vec<u32> = processes.iter().map(Process::weight).collect();
+        let total_weight: u32 = weights.iter().sum();
+        let mut scheduler = NicenessScheduler::with_processes(processes, TICK_RATE);
+
+        let schedule = run_schedule(&mut scheduler, 5000);
+
+        for (pid, &weight) in weights.iter().enumerate() {
+            let expected = weight as f64 / total_weight as f64;
+            let share = share(&schedule, pid as u32);
+            prop_assert!(
+                (share - expected).abs() <= 0.05,
+                "PID {} got {:.3} of the CPU, expected {:.3}",
+                pid,
+                share,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use completely_fair_scheduler::{
+    fuzz::{self, FuzzOp},
+    NicenessScheduler, RoundRobinScheduler,
+};
+use proptest::prelude::*;
+use std::time::Duration;
+
+const TICK_RATE: Duration = Duration::from_millis(10);
+
+fn op() -> impl Strategy<Value = FuzzOp> {
+    prop_oneof![
+        (-20i8..=19, 1u64..=5, prop::option::of(1u64..=50)).prop_map(
+            |(niceness, runtime_ms, sleep_ms)| FuzzOp::Add {
+                niceness,
+                runtime: Duration::from_millis(runtime_ms),
+                sleep: sleep_ms.map(Duration::from_millis),
+            }
+        ),
+        any::<usize>().prop_map(FuzzOp::Remove),
+        any::<usize>().prop_map(FuzzOp::RemovePid),
+        (any::<usize>(), -20i8..=19).prop_map(|(index, niceness)| FuzzOp::Renice(index, niceness)),
+        any::<usize>().prop_map(FuzzOp::ToggleStopped),
+        (1usize..=20).prop_map(FuzzOp::Run),
+    ]
+}
+
+fn ops() -> impl Strategy<Value = Vec<FuzzOp>> {
+    prop::collection::vec(op(), 1..=100)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn round_robin_keeps_its_invariants(ops in ops()) {
+        let result = fuzz::check(|| RoundRobinScheduler::with_processes(Vec::new(), TICK_RATE), &ops);
+        prop_assert!(result.is_ok(), "{}", result.unwrap_err());
+    }
+
+    #[test]
+    fn niceness_keeps_its_invariants(ops in ops()) {
+        let result = fuzz::check(|| NicenessScheduler::with_processes(Vec::new(), TICK_RATE), &ops);
+        prop_assert!(result.is_ok(), "{}", result.unwrap_err());
+    }
+}
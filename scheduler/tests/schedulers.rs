@@ -0,0 +1,160 @@
+use completely_fair_scheduler::{
+    testing::{
+        assert_order, assert_share, assert_wake_latency_below, run_schedule, FixedTask, MockClock,
+    },
+    Niceness, NicenessScheduler, Process, RoundRobinScheduler, Scheduler, NICE_0_WEIGHT,
+};
+use std::time::Duration;
+
+const RUNTIME: Duration = Duration::from_millis(1);
+const TICK_RATE: Duration = Duration::from_millis(10);
+
+fn fixed(pid: u32, niceness: i8) -> Process {
+    let niceness = Niceness::try_from(niceness).expect("Failed to make a niceness.");
+    Process::with_niceness(pid, "", Box::new(FixedTask::new(RUNTIME)), niceness)
+}
+
+fn sleeper(pid: u32, sleep: Duration) -> Process {
+    Process::named(pid, "", Box::new(FixedTask::sleeping(RUNTIME, sleep)))
+}
+
+#[test]
+fn round_robin_takes_turns() {
+    let _clock = MockClock::install();
+    let mut scheduler =
+        RoundRobinScheduler::with_processes(vec![fixed(0, 0), fixed(1, 0), fixed(2, 0)], TICK_RATE);
+
+    let schedule = run_schedule(&mut scheduler, 330);
+
+    assert_order(&schedule, &[0, 1, 2, 0, 1, 2]);
+    for pid in 0..3 {
+        assert_share(&schedule, pid, 1.0 / 3.0, 0.01);
+    }
+}
+
+#[test]
+fn round_robin_ignores_niceness() {
+    let _clock = MockClock::install();
+    let mut scheduler =
+        RoundRobinScheduler::with_processes(vec![fixed(0, -10), fixed(1, 10)], TICK_RATE);
+
+    let schedule = run_schedule(&mut scheduler, 1000);
+
+    assert_share(&schedule, 0, 0.5, 0.02);
+}
+
+#[test]
+fn round_robin_switches_away_from_blocked_processes() {
+    let _clock = MockClock::install();
+    let sleep = Duration::from_millis(100);
+    let mut scheduler =
+        RoundRobinScheduler::with_processes(vec![sleeper(0, sleep), fixed(1, 0)], TICK_RATE);
+
+    let schedule = run_schedule(&mut scheduler, 2);
+
+    // The sleeper runs once and blocks, so the other process runs right after it
+    assert_eq!(schedule, [Some(0), Some(1)]);
+}
+
+#[test]
+fn niceness_shares_equally_between_equal_processes() {
+    let _clock = MockClock::install();
+    let mut scheduler =
+        NicenessScheduler::with_processes(vec![fixed(0, 0), fixed(1, 0), fixed(2, 0)], TICK_RATE);
+
+    let schedule = run_schedule(&mut scheduler, 3000);
+
+    for pid in 0..3 {
+        assert_share(&schedule, pid, 1.0 / 3.0, 0.02);
+    }
+}
+
+#[test]
+fn niceness_shares_follow_weights() {
+    let _clock = MockClock::install();
+    let processes = vec![fixed(0, 0), fixed(1, 5)];
+    let weights: Vec<u32> = processes.iter().map(Process::weight).collect();
+    let mut scheduler = NicenessScheduler::with_processes(processes, TICK_RATE);
+
+    let schedule = run_schedule(&mut scheduler, 5000);
+
+    assert_eq!(weights[0], NICE_0_WEIGHT);
+    let expected = weights[0] as f64 / weights.iter().sum::<u32>() as f64;
+    assert_share(&schedule, 0, expected, 0.03);
+}
+
+#[test]
+fn sleeper_boost_preempts_for_waking_processes() {
+    let _clock = MockClock::install();
+    let mut scheduler = NicenessScheduler::with_processes(
+        vec![fixed(0, 0), sleeper(1, Duration::from_millis(5))],
+        TICK_RATE,
+    );
+    scheduler.set_sleeper_boost(true);
+
+    run_schedule(&mut scheduler, 1000);
+
+    assert_wake_latency_below(scheduler.process(1).unwrap(), Duration::from_millis(1));
+}
+
+#[test]
+fn sleeper_credit_lets_waking_processes_preempt_less_bad_hogs() {
+    let _clock = MockClock::install();
+    // Between its sleeps, the I/O-bound process runs for longer than the hog at a lower weight, so
+    // its badness stays above the hog's and it would wait for the hog's tick without the credit
+    let io_bound = Process::with_niceness(
+        1,
+        "",
+        Box::new(FixedTask::sleeping(RUNTIME * 2, Duration::from_millis(1))),
+        Niceness::try_from(8).expect("Failed to make a niceness."),
+    );
+    let mut scheduler = NicenessScheduler::with_processes(vec![fixed(0, 0), io_bound], TICK_RATE);
+    scheduler.set_sleeper_boost(true);
+
+    run_schedule(&mut scheduler, 1000);
+
+    // It runs on the credit every other time it wakes up
+    let latencies = scheduler.process(1).unwrap().latencies().buckets();
+    assert!(latencies[0] * 3 >= latencies.iter().sum(), "{latencies:?}");
+}
+
+#[test]
+fn waking_processes_wait_for_the_tick_without_sleeper_boost() {
+    let _clock = MockClock::install();
+    let mut scheduler = NicenessScheduler::with_processes(
+        vec![fixed(0, 0), sleeper(1, Duration::from_millis(5))],
+        TICK_RATE,
+    );
+    scheduler.set_sleeper_boost(false);
+
+    run_schedule(&mut scheduler, 1000);
+
+    let sleeper = scheduler.process(1).unwrap();
+    assert_wake_latency_below(sleeper, Duration::from_millis(50));
+    assert!(sleeper.latencies().buckets()[0] < sleeper.latencies().buckets().iter().sum());
+}
+
+#[test]
+fn zero_usage_half_life_turns_the_decay_off() {
+    let _clock = MockClock::install();
+    let mut scheduler =
+        NicenessScheduler::with_processes(vec![fixed(0, 0), fixed(1, 0)], TICK_RATE);
+    scheduler.set_usage_half_life(Duration::ZERO);
+
+    run_schedule(&mut scheduler, 100);
+
+    for process in scheduler.processes() {
+        assert_eq!(process.recent_cpu_usage(), process.cpu_usage());
+    }
+}
+
+#[test]
+fn mock_clock_only_moves_when_tasks_run() {
+    let clock = MockClock::install();
+    let mut scheduler = RoundRobinScheduler::with_processes(vec![fixed(0, 0)], TICK_RATE);
+
+    run_schedule(&mut scheduler, 10);
+
+    assert_eq!(clock.elapsed(), RUNTIME * 10);
+    assert_eq!(scheduler.cpu_elapsed(), RUNTIME * 10);
+}